@@ -0,0 +1,111 @@
+/**
+ * CapsuleWebhook - 把收到的胶囊推送给外部系统
+ * 有界队列 + 指数退避重试，慢的webhook不会阻塞入站处理
+ */
+
+class CapsuleWebhook {
+    constructor(options = {}) {
+        this.url = options.url;
+        this.nodeId = options.nodeId || null;
+        this.tags = Array.isArray(options.tags) ? options.tags.filter(Boolean) : [];
+        this.types = Array.isArray(options.types) ? options.types.filter(Boolean) : [];
+        this.maxQueue = options.maxQueue || 100;
+        this.maxAttempts = options.maxAttempts || 5;
+        this.baseDelayMs = options.baseDelayMs || 1000;
+        this.maxDelayMs = options.maxDelayMs || 60000;
+        this.timeoutMs = options.timeoutMs || 5000;
+        this.queue = [];
+        this.sending = false;
+        this.retryTimer = null;
+        this.stopped = false;
+        this.stats = { delivered: 0, dropped: 0, failed: 0 };
+    }
+
+    matches(capsule) {
+        if (!capsule) return false;
+        const type = capsule.type || capsule.content?.capsule?.type;
+        const tags = [
+            ...(Array.isArray(capsule.tags) ? capsule.tags : []),
+            ...(Array.isArray(capsule.content?.capsule?.blast_radius) ? capsule.content.capsule.blast_radius : [])
+        ];
+        if (this.types.length > 0 && !this.types.includes(type)) return false;
+        if (this.tags.length > 0 && !tags.some(tag => this.tags.includes(tag))) return false;
+        return true;
+    }
+
+    // 队列满时丢弃最旧的一条
+    enqueue(capsule) {
+        if (this.stopped || !this.url || !this.matches(capsule)) return false;
+        if (this.queue.length >= this.maxQueue) {
+            const dropped = this.queue.shift();
+            this.stats.dropped++;
+            console.warn(`⚠️  Capsule webhook queue full, dropped ${dropped.body.capsule?.asset_id}`);
+        }
+        this.queue.push({
+            attempts: 0,
+            body: { event: 'capsule.received', nodeId: this.nodeId, receivedAt: new Date().toISOString(), capsule }
+        });
+        this.drain();
+        return true;
+    }
+
+    async drain() {
+        if (this.sending || this.retryTimer || this.stopped) return;
+        this.sending = true;
+        try {
+            while (this.queue.length > 0 && !this.stopped) {
+                const item = this.queue[0];
+                try {
+                    await this.post(item.body);
+                    this.queue.shift();
+                    this.stats.delivered++;
+                } catch (e) {
+                    item.attempts++;
+                    if (item.attempts >= this.maxAttempts) {
+                        this.queue.shift();
+                        this.stats.failed++;
+                        console.error(`Capsule webhook gave up on ${item.body.capsule?.asset_id}:`, e.message);
+                        continue;
+                    }
+                    const delay = Math.min(this.baseDelayMs * 2 ** (item.attempts - 1), this.maxDelayMs);
+                    this.retryTimer = setTimeout(() => {
+                        this.retryTimer = null;
+                        this.drain();
+                    }, delay);
+                    this.retryTimer.unref?.();
+                    return;
+                }
+            }
+        } finally {
+            this.sending = false;
+        }
+    }
+
+    async post(body) {
+        const controller = new AbortController();
+        const timer = setTimeout(() => controller.abort(), this.timeoutMs);
+        try {
+            const response = await fetch(this.url, {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify(body),
+                signal: controller.signal
+            });
+            if (!response.ok) {
+                throw new Error(`Webhook responded ${response.status}`);
+            }
+        } finally {
+            clearTimeout(timer);
+        }
+    }
+
+    stop() {
+        this.stopped = true;
+        if (this.retryTimer) {
+            clearTimeout(this.retryTimer);
+            this.retryTimer = null;
+        }
+    }
+}
+
+module.exports = CapsuleWebhook;
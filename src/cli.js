@@ -72,14 +72,20 @@ OpenClaw Mesh - 去中心化技能共享网络
   status               查看节点状态
   publish <file>       发布记忆胶囊
   memories [filter]    列出记忆
-  search <query>       搜索记忆
+  search <query>       搜索记忆（--similar 按相似度排序）
   task publish         发布任务
   task list            列出任务
   task submit <id>     提交解决方案
+  task import <file>   从JSON文件批量导入任务
+  run-task             发布任务并等待完成（通过本地节点API）
   account export       导出账户JSON
   account import <file>导入账户JSON
   account transfer     账本转账
+  account reconcile-index [--dry-run] 按账户记录重建账户索引
+  capsule export       导出胶囊包和完整性清单（--out，--ids 逗号分隔）
+  capsule import <file>按清单校验后导入胶囊包（--manifest，--require-signature，--trusted-exporter）
   sync                 同步网络记忆
+  verify [--repair]    校验账本链（ledger.json 和 tx_log），--repair 备份后截断断裂处之后的记录
   webui                打开WebUI
   config               查看配置
 
@@ -88,9 +94,15 @@ OpenClaw Mesh - 去中心化技能共享网络
   --port <number>      设置P2P端口
   --web-port <number>  设置WebUI端口
   --bootstrap <addr>   添加引导节点
+  --dns-seed <host>    通过DNS种子（TXT/A记录）发现引导节点
   --tags <tags>        设置标签（逗号分隔）
+  --derived-from <ids> 发布胶囊时声明来源胶囊（逗号分隔的asset_id）
+  --acl <nodeIds>      发布胶囊时只允许这些节点查看完整内容（逗号分隔）
+  --deadline-ms <ms>   发布任务的期限（毫秒，默认 1 天），到期无人完成时退回托管
+  --topics <topics>    订阅的胶囊/任务标签或类型（逗号分隔，默认全部）
   --master <url>       设置主节点URL
   --genesis            标记为主节点
+  --roles <roles>      节点角色（genesis,worker,storage,relay，逗号分隔）
 
 示例:
   openclaw-mesh init MyNode
@@ -99,9 +111,14 @@ OpenClaw Mesh - 去中心化技能共享网络
   openclaw-mesh publish ./skill.json --tags trading,api
   openclaw-mesh search "JSON parse error"
   openclaw-mesh task publish --description "优化性能" --bounty 100
+  openclaw-mesh task publish --description "优化性能" --bounty 100 --fallback-account acct_xxx
+  openclaw-mesh run-task --description "生成报告" --bounty 50 --timeout 600
+  openclaw-mesh capsule export --out capsules.json
+  openclaw-mesh capsule import ./capsules.json --manifest ./capsules.manifest.json
   openclaw-mesh account export --out account.json
   openclaw-mesh account import ./account.json
   openclaw-mesh account transfer --to-account acct_xxx --amount 100
+  openclaw-mesh account transfer --to-account acct_xxx --amount 100 --memo "invoice-42"
   openclaw-mesh account transfer --to-account acct_xxx --amount 100 --bootstrap localhost:4000
 `);
 }
@@ -116,6 +133,7 @@ async function init(args) {
     const webPort = parseInt(getArg(args, '--web-port')) || 3457;
     const bootstrap = getArg(args, '--bootstrap');
     const tags = getArg(args, '--tags', '');
+    const topics = getArg(args, '--topics', '');
     const masterUrl = getArg(args, '--master', '');
     const isGenesisNode = args.includes('--genesis');
     
@@ -129,6 +147,7 @@ async function init(args) {
         webPort,
         bootstrapNodes,
         tags: nodeTags,
+        topics: topics ? topics.split(',').map(t => t.trim()).filter(Boolean) : [],
         dataDir: './data',
         masterUrl,
         isGenesisNode,
@@ -172,8 +191,19 @@ async function start(args, configPath = null) {
         dataDir: config.dataDir || './data',
         masterUrl: getArg(args, '--master') || config.masterUrl || null,
         isGenesisNode: args.includes('--genesis') || config.isGenesisNode || false,
-        genesisOperatorAccountId: config.genesisOperatorAccountId || null
+        genesisOperatorAccountId: config.genesisOperatorAccountId || null,
+        topics: config.topics || [],
+        dnsSeed: getArg(args, '--dns-seed') || config.dnsSeed || null,
+        capsuleWebhook: config.capsuleWebhook || null,
+        webTls: config.webTls || null,
+        genesisTokens: config.genesisTokens || undefined,
+        // init --tags 设置的节点标签同时用作自动竞标的擅长标签
+        workerTags: Array.isArray(config.tags) && config.tags.length > 0 ? config.tags : undefined
     };
+    const roles = getArg(args, '--roles') || config.roles;
+    if (roles) {
+        options.roles = Array.isArray(roles) ? roles : roles.split(',');
+    }
     
     // 如果有bootstrap参数
     const bootstrap = getArg(args, '--bootstrap');
@@ -190,10 +220,22 @@ async function start(args, configPath = null) {
     // 保持运行
     console.log('\n⏳ Node is running... Press Ctrl+C to stop\n');
     
-    process.on('SIGINT', async () => {
-        await mesh.stop();
+    // 停止时先排空：完成进行中的任务后再退出；再次按 Ctrl+C 立即退出
+    const shutdown = async () => {
+        if (mesh.isDraining()) {
+            console.log('⚠️  Forced exit, pending writes may be lost');
+            process.exit(1);
+        }
+        try {
+            await mesh.stop();
+        } catch (e) {
+            console.error('❌ Shutdown failed:', e.message);
+            process.exit(1);
+        }
         process.exit(0);
-    });
+    };
+    process.on('SIGINT', shutdown);
+    process.on('SIGTERM', shutdown);
 }
 
 // 查看状态
@@ -258,6 +300,17 @@ async function publish(args) {
     if (tags) {
         capsule.content.capsule.blast_radius = tags.split(',');
     }
+
+    // 来源胶囊（写入签名的来源链）
+    const derivedFrom = getArg(args, '--derived-from');
+    if (derivedFrom) {
+        capsule.derivedFrom = derivedFrom.split(',').map(id => id.trim()).filter(Boolean);
+    }
+    // 访问控制：只有列出的节点和创建者能看到完整内容
+    const acl = getArg(args, '--acl');
+    if (acl) {
+        capsule.acl = acl.split(',').map(id => id.trim()).filter(Boolean);
+    }
     
     if (!global.meshInstance) {
         console.error('❌ Node not running. Start with: openclaw-mesh start');
@@ -266,7 +319,7 @@ async function publish(args) {
     
     const result = await global.meshInstance.publishCapsule(capsule);
     const assetId = result.assetId || result;
-    console.log(`✅ Published: ${assetId}`);
+    console.log(result.alreadyExists ? `♻️  Already published: ${assetId}` : `✅ Published: ${assetId}`);
 }
 
 // 列出记忆
@@ -307,7 +360,11 @@ async function search(args) {
         return;
     }
     
-    const results = global.meshInstance.memoryStore.searchMemories(query);
+    // --similar：按词项重合度排序，部分命中的胶囊也会列出
+    const similar = args.includes('--similar');
+    const results = similar
+        ? global.meshInstance.memoryStore.queryCapsules({ mode: 'similarity', query, limit: 20 })
+        : global.meshInstance.memoryStore.searchMemories(query);
     
     console.log(`\n🔍 Search: "${query}" (${results.length} results)`);
     console.log('=' .repeat(60));
@@ -315,6 +372,9 @@ async function search(args) {
     results.forEach((r, i) => {
         console.log(`\n${i + 1}. ${r.asset_id.slice(0, 20)}...`);
         console.log(`   Confidence: ${(r.confidence * 100).toFixed(0)}%`);
+        if (similar) {
+            console.log(`   Similarity: ${r.similarity}`);
+        }
     });
 }
 
@@ -330,8 +390,11 @@ async function taskCommand(subcommand, args) {
         case 'submit':
             await submitSolution(args);
             break;
+        case 'import':
+            await importTasks(args);
+            break;
         default:
-            console.log('Usage: openclaw-mesh task <publish|list|submit>');
+            console.log('Usage: openclaw-mesh task <publish|list|submit|import>');
     }
 }
 
@@ -356,7 +419,8 @@ async function publishTask(args) {
             amount: bounty,
             token: 'CLAW'
         },
-        deadline: new Date(Date.now() + 86400000).toISOString()
+        deadlineMs: Number(getArg(args, '--deadline-ms')) || 86400000,
+        fallbackAccountId: getArg(args, '--fallback-account') || undefined
     };
     
     const result = await global.meshInstance.publishTask(task);
@@ -364,6 +428,53 @@ async function publishTask(args) {
     console.log(`✅ Task published: ${taskId}`);
 }
 
+// 从文件批量导入任务（通过本地节点API）
+async function importTasks(args) {
+    const file = args[0] && !args[0].startsWith('--') ? args[0] : getArg(args, '--file');
+    const config = loadConfig();
+    const api = (getArg(args, '--api') || `http://localhost:${config.webPort || 3457}`).replace(/\/+$/, '');
+    if (!file || !fs.existsSync(file)) {
+        console.error(`❌ ${file ? `File not found: ${file}` : 'Please specify a file'}`);
+        console.error('Usage: openclaw-mesh task import <file.json> [--api <url>]');
+        process.exitCode = 1;
+        return;
+    }
+    let specs;
+    try {
+        specs = JSON.parse(fs.readFileSync(file, 'utf8'));
+    } catch (e) {
+        console.error(`❌ Invalid JSON: ${e.message}`);
+        process.exitCode = 1;
+        return;
+    }
+    try {
+        const res = await fetch(`${api}/api/tasks/import`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(specs)
+        });
+        const data = await res.json();
+        if (data.error) {
+            throw new Error(data.error);
+        }
+        for (const r of data.results) {
+            const label = r.externalId ? `${r.index} (${r.externalId})` : `${r.index}`;
+            if (r.status === 'published') {
+                console.log(`✅ ${label}: ${r.taskId}`);
+            } else {
+                console.log(`${r.status === 'skipped' ? '⏭️ ' : '❌'} ${label}: ${r.status} - ${r.reason}`);
+            }
+        }
+        console.log(`\n📥 Published ${data.published}/${data.results.length}, escrowed ${data.totalEscrowed} CLAW`);
+        if (data.results.some(r => r.status === 'failed')) {
+            process.exitCode = 2;
+        }
+    } catch (e) {
+        console.error(`❌ Import failed: ${e.message}`);
+        process.exitCode = 1;
+    }
+}
+
 async function listTasks() {
     if (!global.meshInstance) {
         console.error('❌ Node not running');
@@ -412,6 +523,83 @@ async function submitSolution(args) {
     }
 }
 
+// 发布任务并等待完成
+// 退出码: 0 完成, 1 参数/发布错误, 2 任务失败, 3 超时（无获胜者）
+async function runTask(args, configPath = null) {
+    const config = loadConfig(configPath);
+    const description = getArg(args, '--description');
+    const bounty = parseInt(getArg(args, '--bounty')) || 100;
+    const tags = getArg(args, '--tags', '');
+    const timeoutSec = Number(getArg(args, '--timeout', '300'));
+    const intervalMs = Number(getArg(args, '--interval', '2000'));
+    const api = (getArg(args, '--api') || `http://localhost:${config.webPort || 3457}`).replace(/\/+$/, '');
+
+    if (!description) {
+        console.error('❌ Please specify --description');
+        console.error('Usage: openclaw-mesh run-task --description <text> [--bounty <n>] [--tags a,b] [--timeout <sec>] [--api <url>]');
+        process.exitCode = 1;
+        return;
+    }
+
+    let taskId;
+    try {
+        const res = await fetch(`${api}/api/task/publish`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+                description,
+                bounty,
+                tags: tags ? tags.split(',').map(t => t.trim()).filter(Boolean) : []
+            })
+        });
+        const data = await res.json();
+        if (data.error || !data.taskId) {
+            throw new Error(data.error || 'No taskId returned');
+        }
+        taskId = data.taskId;
+    } catch (e) {
+        console.error(`❌ Failed to publish task: ${e.message}`);
+        process.exitCode = 1;
+        return;
+    }
+    console.log(`🎯 Task published: ${taskId}`);
+    console.log(`⏳ Waiting for completion (timeout ${timeoutSec}s)...`);
+
+    const deadline = Date.now() + timeoutSec * 1000;
+    let lastStatus = null;
+    while (Date.now() < deadline) {
+        try {
+            const tasks = await fetch(`${api}/api/tasks`).then(r => r.json());
+            const task = Array.isArray(tasks) ? tasks.find(t => t.taskId === taskId) : null;
+            if (task && task.status !== lastStatus) {
+                lastStatus = task.status;
+                console.log(`   Status: ${task.status}${task.assignedTo ? ` (assigned to ${task.assignedTo})` : ''}`);
+            }
+            if (task?.status === 'completed') {
+                console.log(`🏆 Winner: ${task.completedBy || task.assignedTo || 'unknown'}`);
+                const downloadUrl = `${api}/api/tasks/${taskId}/download`;
+                const head = await fetch(downloadUrl, { method: 'HEAD' }).catch(() => null);
+                if (head?.ok) {
+                    console.log(`📦 Result: ${downloadUrl}`);
+                }
+                process.exitCode = 0;
+                return;
+            }
+            if (task?.status === 'failed') {
+                console.error(`❌ Task failed: ${taskId}`);
+                process.exitCode = 2;
+                return;
+            }
+        } catch (e) {
+            console.error(`⚠️  Poll failed: ${e.message}`);
+        }
+        await new Promise(resolve => setTimeout(resolve, intervalMs));
+    }
+
+    console.error(`⌛ Timed out waiting for task ${taskId} (last status: ${lastStatus || 'unknown'})`);
+    process.exitCode = 3;
+}
+
 // 同步记忆
 async function sync(args) {
     if (!global.meshInstance) {
@@ -421,7 +609,109 @@ async function sync(args) {
     
     console.log('🔄 Syncing memories from network...');
     const count = await global.meshInstance.syncMemories();
-    console.log(`✅ Synced ${count} memories`);
+    const rejected = global.meshInstance.lastMemorySync?.rejected || 0;
+    console.log(`✅ Synced ${count} memories${rejected > 0 ? `, ${rejected} rejected` : ''}`);
+}
+
+// 校验账本链（读取磁盘上的 ledger.json 和 ledger.sqlite 的 tx_log，节点无需运行）；--repair 截断并隔离断裂处之后的记录
+async function verify(args, configPath = null) {
+    const config = ensureNodeConfig(loadConfig(configPath));
+    const dataDir = config.dataDir || './data';
+    if (args.includes('--repair')) {
+        const store = new MemoryStore(dataDir, { nodeId: config.nodeId });
+        // 只连接 LanceDB 以便同时改写其中的账本表，不做完整初始化（加载时会重算链hash）
+        await store.initLance();
+        const result = await store.repairLedger();
+        console.log(JSON.stringify(result, null, 2));
+        return;
+    }
+    const store = new MemoryStore(dataDir, { nodeId: config.nodeId, useLance: false });
+    const txLedger = new LedgerStore(dataDir);
+    let result;
+    try {
+        result = store.verifyPersistedLedger(txLedger.openExisting() ? txLedger : null);
+    } finally {
+        txLedger.close();
+    }
+    console.log(JSON.stringify(result, null, 2));
+    if (result.valid) {
+        console.log(`✅ Ledger chain verified (${result.length} entries${result.txLog ? `, tx_log ${result.txLog.length} entries` : ''})`);
+        return;
+    }
+    if (result.brokenIndex !== null) {
+        console.error(`❌ Ledger chain broken at index ${result.brokenIndex}: ${result.reason}`);
+    }
+    if (result.txLog && !result.txLog.valid) {
+        console.error(`❌ tx_log chain broken at seq ${result.txLog.brokenSeq ?? 'head'}: ${result.txLog.reason}`);
+    }
+    process.exitCode = 1;
+}
+
+function manifestPathFor(bundlePath) {
+    return bundlePath.replace(/\.json$/i, '') + '.manifest.json';
+}
+
+async function capsuleCommand(subcommand, args, configPath = null) {
+    const config = ensureNodeConfig(loadConfig(configPath));
+    const dataDir = config.dataDir || './data';
+    if (subcommand !== 'export' && subcommand !== 'import') {
+        console.log('Usage: openclaw-mesh capsule <export|import>');
+        return;
+    }
+    const store = new MemoryStore(dataDir, { nodeId: config.nodeId });
+    await store.init();
+    try {
+        if (subcommand === 'export') {
+            const outPath = path.resolve(getArg(args, '--out') || 'capsules.json');
+            const manifestPath = path.resolve(getArg(args, '--manifest') || manifestPathFor(outPath));
+            const ids = getArg(args, '--ids');
+            const { bundle, manifest } = store.exportCapsules({
+                assetIds: ids ? ids.split(',').map(id => id.trim()).filter(Boolean) : null,
+                signer: args.includes('--unsigned') ? null : loadOrCreateWallet(dataDir)
+            });
+            fs.writeFileSync(outPath, JSON.stringify(bundle, null, 2));
+            fs.writeFileSync(manifestPath, JSON.stringify(manifest, null, 2));
+            console.log(`✅ Exported ${manifest.count} capsules: ${outPath}`);
+            console.log(`   Manifest: ${manifestPath}`);
+            return;
+        }
+        const filePath = args[0] && !args[0].startsWith('--') ? args[0] : getArg(args, '--file');
+        if (!filePath) {
+            console.error('❌ Missing bundle file. Usage: openclaw-mesh capsule import <file> [--manifest <file>]');
+            process.exitCode = 1;
+            return;
+        }
+        const bundlePath = path.resolve(filePath);
+        const manifestPath = path.resolve(getArg(args, '--manifest') || manifestPathFor(bundlePath));
+        if (!fs.existsSync(manifestPath)) {
+            console.error(`❌ Manifest not found: ${manifestPath}`);
+            process.exitCode = 1;
+            return;
+        }
+        const requireSignature = args.includes('--require-signature');
+        const trustedExporters = (getArg(args, '--trusted-exporter') || '')
+            .split(',').map(id => id.trim()).filter(Boolean);
+        if (requireSignature && trustedExporters.length === 0) {
+            console.error('❌ --require-signature needs --trusted-exporter <accountId[,accountId...]>');
+            process.exitCode = 1;
+            return;
+        }
+        const bundle = JSON.parse(fs.readFileSync(bundlePath, 'utf8'));
+        const manifest = JSON.parse(fs.readFileSync(manifestPath, 'utf8'));
+        const result = await store.importCapsules(bundle, manifest, {
+            requireSignature,
+            trustedExporters: trustedExporters.length > 0 ? trustedExporters : null
+        });
+        console.log(JSON.stringify(result, null, 2));
+        if (result.valid) {
+            console.log(`✅ Imported ${result.imported} capsules (${result.skipped} already present)`);
+        } else {
+            console.error(`❌ Bundle rejected: ${result.reason}`);
+            process.exitCode = 1;
+        }
+    } finally {
+        await store.close();
+    }
 }
 
 // 查看配置
@@ -435,10 +725,11 @@ async function config() {
 async function accountCommand(subcommand, args, configPath = null) {
     const config = ensureNodeConfig(loadConfig(configPath));
     const dataDir = config.dataDir || './data';
+    let ledger = null;
     try {
         if (subcommand === 'export') {
             const wallet = loadOrCreateWallet(dataDir);
-            const ledger = new LedgerStore(dataDir);
+            ledger = new LedgerStore(dataDir);
             ledger.init({ isGenesis: config.isGenesisNode || false, genesisAccountId: wallet.accountId, genesisSupply: 1000000, genesisPublicKeyPem: wallet.publicKeyPem, genesisPrivateKeyPem: wallet.privateKeyPem });
             const payload = {
                 version: 2,
@@ -473,9 +764,21 @@ async function accountCommand(subcommand, args, configPath = null) {
             console.log(`✅ Account imported: ${wallet.accountId}`);
             return;
         }
+        if (subcommand === 'reconcile-index') {
+            const store = new MemoryStore(dataDir, { nodeId: config.nodeId, useLance: false });
+            await store.init();
+            try {
+                // 启动时若已自动修复，直接报告那次的结果
+                const result = store.lastAccountIndexReconcile || await store.reconcileAccountIndex({ dryRun: args.includes('--dry-run') });
+                console.log(JSON.stringify(result, null, 2));
+            } finally {
+                await store.close();
+            }
+            return;
+        }
         if (subcommand === 'transfer') {
             const wallet = loadOrCreateWallet(dataDir);
-            const ledger = new LedgerStore(dataDir);
+            ledger = new LedgerStore(dataDir);
             ledger.init({ isGenesis: config.isGenesisNode || false, genesisAccountId: wallet.accountId, genesisSupply: 1000000, genesisPublicKeyPem: wallet.publicKeyPem, genesisPrivateKeyPem: wallet.privateKeyPem });
             const toAccountIdRaw = getArg(args, '--to-account') || getArg(args, '--to');
             const amount = Number(getArg(args, '--amount'));
@@ -497,13 +800,15 @@ async function accountCommand(subcommand, args, configPath = null) {
                 return;
             }
             const nonce = ledger.getNonce(wallet.accountId) + 1;
+            const memo = LedgerStore.sanitizeMemo(getArg(args, '--memo'));
             const payload = {
                 type: 'transfer',
                 from: wallet.accountId,
                 to: toAccountIdRaw,
                 amount: Number(amount),
                 nonce,
-                timestamp: Date.now()
+                timestamp: Date.now(),
+                ...(memo ? { meta: { memo } } : {})
             };
             const signature = signPayload(wallet.privateKeyPem, payload);
             const tx = {
@@ -532,9 +837,9 @@ async function accountCommand(subcommand, args, configPath = null) {
             console.log(JSON.stringify({ submitted: true, txId: tx.txId }, null, 2));
             return;
         }
-        console.log('Usage: openclaw-mesh account <export|import|transfer>');
+        console.log('Usage: openclaw-mesh account <export|import|transfer|reconcile-index>');
     } finally {
-        ledger.close();
+        ledger?.close();
     }
 }
 
@@ -587,12 +892,21 @@ async function main() {
         case 'task':
             await taskCommand(subArgs[0], subArgs.slice(1));
             break;
+        case 'run-task':
+            await runTask(subArgs, configArg);
+            break;
         case 'account':
             await accountCommand(subArgs[0], subArgs.slice(1), configArg);
             break;
+        case 'capsule':
+            await capsuleCommand(subArgs[0], subArgs.slice(1), configArg);
+            break;
         case 'sync':
             await sync(subArgs);
             break;
+        case 'verify':
+            await verify(subArgs, configArg);
+            break;
         case 'config':
             await config();
             break;
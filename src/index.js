@@ -9,11 +9,73 @@ const TaskBazaar = require('./task-bazaar');
 const WebUIServer = require('../web/server');
 const TaskWorker = require('./task-worker');
 const LedgerStore = require('./ledger-store');
-const { loadOrCreateWallet, signPayload, accountIdFromPublicKey, importWallet } = require('./wallet');
+const { loadOrCreateWallet, signPayload, verifyPayload, accountIdFromPublicKey, nodeIdFromPublicKey, importWallet } = require('./wallet');
 const crypto = require('crypto');
 const RatingStore = require('./rating-store');
+const CapsuleWebhook = require('./capsule-webhook');
 const fs = require('fs').promises;
 const path = require('path');
+const { version: PACKAGE_VERSION } = require('../package.json');
+
+// 构建标识：优先取 OPENCLAW_BUILD_HASH，否则读取仓库的 git HEAD
+let cachedBuildHash;
+function readBuildHash() {
+    if (cachedBuildHash !== undefined) return cachedBuildHash;
+    cachedBuildHash = process.env.OPENCLAW_BUILD_HASH || null;
+    if (!cachedBuildHash) {
+        try {
+            const gitDir = path.join(__dirname, '..', '.git');
+            const head = require('fs').readFileSync(path.join(gitDir, 'HEAD'), 'utf8').trim();
+            cachedBuildHash = head.startsWith('ref: ')
+                ? require('fs').readFileSync(path.join(gitDir, head.slice(5)), 'utf8').trim()
+                : head;
+        } catch (e) {
+            cachedBuildHash = null;
+        }
+    }
+    return cachedBuildHash;
+}
+
+// OPENCLAW_GENESIS_TOKENS 是 JSON 数组；解析失败时给出指明环境变量的配置错误，而不是裸的 SyntaxError
+function parseGenesisTokensEnv(raw = process.env.OPENCLAW_GENESIS_TOKENS) {
+    if (!raw) return [];
+    let tokens;
+    try {
+        tokens = JSON.parse(raw);
+    } catch (e) {
+        throw new Error(`Invalid OPENCLAW_GENESIS_TOKENS: expected a JSON array of token definitions (${e.message})`);
+    }
+    if (!Array.isArray(tokens)) {
+        throw new Error('Invalid OPENCLAW_GENESIS_TOKENS: expected a JSON array of token definitions');
+    }
+    return tokens;
+}
+
+// 节点角色：genesis（主节点账本）、worker（自动争单执行任务）、storage（存储胶囊并响应查询）、relay（转发消息）
+const NODE_ROLES = ['genesis', 'worker', 'storage', 'relay'];
+
+// 未配置 roles 时由旧开关推导：默认 storage + worker，isGenesisNode 追加 genesis，enableWorker=false 去掉 worker
+function resolveNodeRoles(options = {}) {
+    const configured = options.roles ?? (process.env.OPENCLAW_ROLES ? process.env.OPENCLAW_ROLES.split(',') : null);
+    let roles;
+    if (configured) {
+        roles = (Array.isArray(configured) ? configured : String(configured).split(','))
+            .map(role => String(role).trim().toLowerCase())
+            .filter(Boolean);
+        const unknown = roles.filter(role => !NODE_ROLES.includes(role));
+        if (unknown.length > 0) {
+            throw new Error(`Unknown node role: ${unknown.join(', ')}`);
+        }
+    } else {
+        roles = ['storage'];
+        if (options.enableWorker ?? process.env.OPENCLAW_ENABLE_WORKER !== '0') roles.push('worker');
+    }
+    if (options.isGenesisNode ?? process.env.OPENCLAW_IS_GENESIS === '1') roles.push('genesis');
+    if (roles.length === 0) {
+        throw new Error('At least one node role is required');
+    }
+    return NODE_ROLES.filter(role => roles.includes(role));
+}
 
 class OpenClawMesh {
     constructor(options = {}) {
@@ -30,6 +92,99 @@ class OpenClawMesh {
             capsuleCreatorShare: Number(options.capsuleCreatorShare ?? process.env.OPENCLAW_CAPSULE_CREATOR_SHARE ?? 0.9),
             capsulePublishFee: Number(options.capsulePublishFee ?? process.env.OPENCLAW_CAPSULE_PUBLISH_FEE ?? 1),
             taskPublishFee: Number(options.taskPublishFee ?? process.env.OPENCLAW_TASK_PUBLISH_FEE ?? 0),
+            minTaskPeers: Number(options.minTaskPeers ?? process.env.OPENCLAW_MIN_TASK_PEERS ?? 0),
+            votingPeriodMs: Number(options.votingPeriodMs ?? process.env.OPENCLAW_VOTING_PERIOD_MS ?? 5000),
+            tieBreakSeed: options.tieBreakSeed || process.env.OPENCLAW_TIE_BREAK_SEED || '',
+            votingQuietMs: Number(options.votingQuietMs ?? process.env.OPENCLAW_VOTING_QUIET_MS ?? 3000),
+            bidStrategy: options.bidStrategy || process.env.OPENCLAW_BID_STRATEGY || 'fixed',
+            bidPercent: Number(options.bidPercent ?? process.env.OPENCLAW_BID_PERCENT ?? 0.9),
+            bidMinPercent: Number(options.bidMinPercent ?? process.env.OPENCLAW_BID_MIN_PERCENT ?? 0.7),
+            bidMaxPercent: Number(options.bidMaxPercent ?? process.env.OPENCLAW_BID_MAX_PERCENT ?? 0.95),
+            workerTags: options.workerTags || (process.env.OPENCLAW_WORKER_TAGS || '').split(',').map(t => t.trim()).filter(Boolean),
+            requireAllTags: options.requireAllTags ?? process.env.OPENCLAW_REQUIRE_ALL_TAGS === '1',
+            taskExecutor: options.taskExecutor || null,
+            taskCommand: options.taskCommand || process.env.OPENCLAW_TASK_COMMAND || null,
+            taskCommandTimeoutMs: Number(options.taskCommandTimeoutMs ?? process.env.OPENCLAW_TASK_COMMAND_TIMEOUT_MS ?? 600000),
+            genesisTokens: options.genesisTokens || parseGenesisTokensEnv(),
+            requireSignedHandshake: options.requireSignedHandshake ?? process.env.OPENCLAW_REQUIRE_SIGNED_HANDSHAKE === '1',
+            peerKeys: options.peerKeys || (process.env.OPENCLAW_PEER_KEYS ? JSON.parse(require('fs').readFileSync(process.env.OPENCLAW_PEER_KEYS, 'utf8')) : null),
+            signMessages: options.signMessages ?? process.env.OPENCLAW_SIGN_MESSAGES === '1',
+            capsuleWebhook: options.capsuleWebhook || (process.env.OPENCLAW_CAPSULE_WEBHOOK_URL ? {
+                url: process.env.OPENCLAW_CAPSULE_WEBHOOK_URL,
+                tags: (process.env.OPENCLAW_CAPSULE_WEBHOOK_TAGS || '').split(',').map(t => t.trim()).filter(Boolean),
+                types: (process.env.OPENCLAW_CAPSULE_WEBHOOK_TYPES || '').split(',').map(t => t.trim()).filter(Boolean)
+            } : null),
+            benchMode: options.benchMode ?? process.env.OPENCLAW_BENCH === '1',
+            strictProvenance: options.strictProvenance ?? process.env.OPENCLAW_STRICT_PROVENANCE === '1',
+            confidenceHalfLifeMs: Number(options.confidenceHalfLifeMs ?? process.env.OPENCLAW_CONFIDENCE_HALF_LIFE_MS ?? 0),
+            // 本节点发布的胶囊的空闲保留时长（0 表示不因空闲被清理），收到的胶囊沿用全局 OPENCLAW_PRUNE_IDLE_MS
+            publishedCapsuleTtlMs: Number(options.publishedCapsuleTtlMs ?? process.env.OPENCLAW_PUBLISHED_CAPSULE_TTL_MS ?? 0),
+            peerBanThreshold: Number(options.peerBanThreshold ?? process.env.OPENCLAW_PEER_BAN_THRESHOLD ?? 5),
+            peerBanDurationMs: Number(options.peerBanDurationMs ?? process.env.OPENCLAW_PEER_BAN_MS ?? 600000),
+            supportedKeyAlgorithms: options.supportedKeyAlgorithms || (process.env.OPENCLAW_KEY_ALGORITHMS ? process.env.OPENCLAW_KEY_ALGORITHMS.split(',').map(a => a.trim()).filter(Boolean) : ['ed25519']),
+            maxMessageBytes: Number(options.maxMessageBytes ?? process.env.OPENCLAW_MAX_MESSAGE_BYTES ?? 4 * 1024 * 1024),
+            wireFraming: options.wireFraming || process.env.OPENCLAW_WIRE_FRAMING || 'line',
+            maxJsonDepth: Number(options.maxJsonDepth ?? process.env.OPENCLAW_MAX_JSON_DEPTH ?? 64),
+            webTls: options.webTls || (process.env.OPENCLAW_WEB_TLS_CERT ? {
+                certPath: process.env.OPENCLAW_WEB_TLS_CERT,
+                keyPath: process.env.OPENCLAW_WEB_TLS_KEY,
+                caPath: process.env.OPENCLAW_WEB_TLS_CA || null
+            } : null),
+            adminToken: options.adminToken || process.env.OPENCLAW_ADMIN_TOKEN || null,
+            auditPersist: options.auditPersist ?? process.env.OPENCLAW_AUDIT_PERSIST === '1',
+            maxAuditEntries: Number(options.maxAuditEntries ?? process.env.OPENCLAW_AUDIT_MAX_ENTRIES ?? 1000),
+            storeLockTimeoutMs: Number(options.storeLockTimeoutMs ?? process.env.OPENCLAW_STORE_LOCK_TIMEOUT_MS ?? 5000),
+            storeBreakerThreshold: Number(options.storeBreakerThreshold ?? process.env.OPENCLAW_STORE_BREAKER_THRESHOLD ?? 3),
+            storeBreakerCooldownMs: Number(options.storeBreakerCooldownMs ?? process.env.OPENCLAW_STORE_BREAKER_COOLDOWN_MS ?? 30000),
+            webShutdownTimeoutMs: Number(options.webShutdownTimeoutMs ?? process.env.OPENCLAW_WEB_SHUTDOWN_TIMEOUT_MS ?? 5000),
+            wsStatusIntervalMs: Number(options.wsStatusIntervalMs ?? process.env.OPENCLAW_WS_STATUS_MS ?? 5000),
+            wsPingIntervalMs: Number(options.wsPingIntervalMs ?? process.env.OPENCLAW_WS_PING_MS ?? 20000),
+            reconnectPeers: options.reconnectPeers ?? process.env.OPENCLAW_RECONNECT_PEERS !== '0',
+            maxReconnectPeers: Number(options.maxReconnectPeers ?? process.env.OPENCLAW_MAX_RECONNECT_PEERS ?? 8),
+            sharePeerList: options.sharePeerList ?? process.env.OPENCLAW_SHARE_PEER_LIST === '1',
+            persistAddressBook: options.persistAddressBook ?? process.env.OPENCLAW_PERSIST_ADDRESS_BOOK === '1',
+            addressCooldownMs: Number(options.addressCooldownMs ?? process.env.OPENCLAW_ADDRESS_COOLDOWN_MS ?? 300000),
+            dnsSeed: options.dnsSeed || process.env.OPENCLAW_DNS_SEED || null,
+            dnsSeedPort: Number(options.dnsSeedPort ?? process.env.OPENCLAW_DNS_SEED_PORT ?? 4000),
+            taskArchiveAfterMs: Number(options.taskArchiveAfterMs ?? process.env.OPENCLAW_TASK_ARCHIVE_AFTER_MS ?? 7 * 24 * 60 * 60 * 1000),
+            maxBidsPerTask: Number(options.maxBidsPerTask ?? process.env.OPENCLAW_MAX_BIDS_PER_TASK ?? 50),
+            taskExpiryIntervalMs: Number(options.taskExpiryIntervalMs ?? process.env.OPENCLAW_TASK_EXPIRY_INTERVAL_MS ?? 30000),
+            taskExpiryGraceMs: Number(options.taskExpiryGraceMs ?? process.env.OPENCLAW_TASK_EXPIRY_GRACE_MS ?? 60000),
+            reannounceK: Number(options.reannounceK ?? process.env.OPENCLAW_REANNOUNCE_K ?? 3),
+            reannounceDelayMs: Number(options.reannounceDelayMs ?? process.env.OPENCLAW_REANNOUNCE_DELAY_MS ?? 10000),
+            reannounceBatchSize: Number(options.reannounceBatchSize ?? process.env.OPENCLAW_REANNOUNCE_BATCH_SIZE ?? 100),
+            reannounceBatchIntervalMs: Number(options.reannounceBatchIntervalMs ?? process.env.OPENCLAW_REANNOUNCE_BATCH_INTERVAL_MS ?? 1000),
+            maxRelayPerSec: Number(options.maxRelayPerSec ?? process.env.OPENCLAW_MAX_RELAY_PER_SEC ?? 0),
+            maxInboundPerSec: Number(options.maxInboundPerSec ?? process.env.OPENCLAW_MAX_INBOUND_PER_SEC ?? 0),
+            seenBloomBits: Number(options.seenBloomBits ?? process.env.OPENCLAW_SEEN_BLOOM_BITS ?? 0),
+            maxPublishPerSec: Number(options.maxPublishPerSec ?? process.env.OPENCLAW_MAX_PUBLISH_PER_SEC ?? 0),
+            maxPublishQueue: Number(options.maxPublishQueue ?? process.env.OPENCLAW_MAX_PUBLISH_QUEUE ?? 1000),
+            keyPlacement: options.keyPlacement || process.env.OPENCLAW_KEY_PLACEMENT || 'xor',
+            relayStrategy: options.relayStrategy || process.env.OPENCLAW_RELAY_STRATEGY || 'flood',
+            gossipEagerFanout: Number(options.gossipEagerFanout ?? process.env.OPENCLAW_GOSSIP_EAGER_FANOUT ?? 2),
+            gossipCacheMaxBytes: Number(options.gossipCacheMaxBytes ?? process.env.OPENCLAW_GOSSIP_CACHE_MAX_BYTES ?? 16 * 1024 * 1024),
+            maxPeers: Number(options.maxPeers ?? process.env.OPENCLAW_MAX_PEERS ?? 128),
+            maxSendQueue: Number(options.maxSendQueue ?? process.env.OPENCLAW_MAX_SEND_QUEUE ?? 1000),
+            sendQueueStallMs: Number(options.sendQueueStallMs ?? process.env.OPENCLAW_SEND_QUEUE_STALL_MS ?? 30000),
+            tcpNoDelay: options.tcpNoDelay ?? process.env.OPENCLAW_TCP_NODELAY !== '0',
+            tcpKeepAliveMs: Number(options.tcpKeepAliveMs ?? process.env.OPENCLAW_TCP_KEEPALIVE_MS ?? 60000),
+            ringVirtualNodes: Number(options.ringVirtualNodes ?? process.env.OPENCLAW_RING_VIRTUAL_NODES ?? 64),
+            capacityWeight: Number(options.capacityWeight ?? process.env.OPENCLAW_CAPACITY_WEIGHT ?? 1),
+            maxPeerCapacity: Number(options.maxPeerCapacity ?? process.env.OPENCLAW_MAX_PEER_CAPACITY ?? 16),
+            peerScoreWeights: options.peerScoreWeights || (process.env.OPENCLAW_PEER_SCORE_WEIGHTS ? JSON.parse(process.env.OPENCLAW_PEER_SCORE_WEIGHTS) : null),
+            // JSON 文件：{ "capsule": <schema>, "task": <schema> }
+            messageSchemas: options.messageSchemas || (process.env.OPENCLAW_MESSAGE_SCHEMAS ? JSON.parse(require('fs').readFileSync(process.env.OPENCLAW_MESSAGE_SCHEMAS, 'utf8')) : null),
+            minAcceptConfidence: Number(options.minAcceptConfidence ?? process.env.OPENCLAW_MIN_ACCEPT_CONFIDENCE ?? 0),
+            logDrops: options.logDrops ?? process.env.OPENCLAW_LOG_DROPS === '1',
+            suppressStoredCapsules: options.suppressStoredCapsules ?? process.env.OPENCLAW_SUPPRESS_STORED_CAPSULES !== '0',
+            syncBarrierPeer: options.syncBarrierPeer || process.env.OPENCLAW_SYNC_BARRIER_PEER || null,
+            drainTimeoutMs: Number(options.drainTimeoutMs ?? process.env.OPENCLAW_DRAIN_TIMEOUT_MS ?? 30000),
+            syncBarrierTimeoutMs: Number(options.syncBarrierTimeoutMs ?? process.env.OPENCLAW_SYNC_BARRIER_TIMEOUT_MS ?? 120000),
+            maxConcurrentQueries: Number(options.maxConcurrentQueries ?? process.env.OPENCLAW_MAX_CONCURRENT_QUERIES ?? 8),
+            maxQueryResults: Number(options.maxQueryResults ?? process.env.OPENCLAW_MAX_QUERY_RESULTS ?? 200),
+            queryBusyMode: options.queryBusyMode || process.env.OPENCLAW_QUERY_BUSY_MODE || 'wait',
+            disabledMessageTypes: options.disabledMessageTypes || (process.env.OPENCLAW_DISABLED_MESSAGES ? process.env.OPENCLAW_DISABLED_MESSAGES.split(',').map(t => t.trim()).filter(Boolean) : []),
+            topics: options.topics || (process.env.OPENCLAW_TOPICS ? process.env.OPENCLAW_TOPICS.split(',').map(t => t.trim()).filter(Boolean) : []),
             txConfirmations: options.txConfirmations || {
                 transfer: 1,
                 capsulePublish: 1,
@@ -46,8 +201,12 @@ class OpenClawMesh {
             },
             ...options
         };
+        this.options.roles = resolveNodeRoles(options);
+        this.options.isGenesisNode = this.options.roles.includes('genesis');
         
         this.node = null;
+        this.taskImportLock = Promise.resolve();
+        this.capsuleWebhook = null;
         this.memoryStore = null;
         this.taskBazaar = null;
         this.webUI = null;
@@ -57,14 +216,42 @@ class OpenClawMesh {
         this.initialized = false;
         this.pendingTxs = new Map();
         this.pendingTxInterval = null;
+        // 关闭前的排空阶段：不再接受新任务/竞标/发布，超时后通过 drainController 取消剩余工作
+        this.draining = false;
+        this.drainController = new AbortController();
+        this.nodeIdConfigured = Boolean(options.nodeId);
     }
     
     generateNodeId() {
         const crypto = require('crypto');
         return 'node_' + crypto.randomBytes(8).toString('hex');
     }
+
+    // 消息签名要求节点ID由钱包公钥派生；显式配置的ID必须与之一致
+    async deriveNodeIdFromWallet() {
+        await fs.mkdir(this.options.dataDir, { recursive: true });
+        const wallet = loadOrCreateWallet(this.options.dataDir);
+        const derived = nodeIdFromPublicKey(wallet.publicKeyPem);
+        if (this.nodeIdConfigured && this.options.nodeId !== derived) {
+            throw new Error(`nodeId ${this.options.nodeId} does not match wallet key (expected ${derived})`);
+        }
+        this.options.nodeId = derived;
+    }
+
+    hasRole(role) {
+        return this.options.roles.includes(role);
+    }
+
+    // 只转发的节点不存储胶囊、不执行任务，也不接受写操作
+    isRelayOnly() {
+        return !this.options.roles.some(role => role !== 'relay');
+    }
     
     async init() {
+        // 签名握手同样要求 nodeId 与钱包公钥绑定；显式配置了 nodeId 时需在对端用 peerKeys 固定公钥
+        if (this.options.signMessages || (this.options.requireSignedHandshake && !this.nodeIdConfigured)) {
+            await this.deriveNodeIdFromWallet();
+        }
         console.log(`🚀 Initializing OpenClaw Mesh...`);
         console.log(`   Node ID: ${this.options.nodeId}`);
         
@@ -73,7 +260,9 @@ class OpenClawMesh {
             nodeId: this.options.nodeId,
             isGenesisNode: this.options.isGenesisNode,
             masterUrl: this.options.masterUrl,
-            genesisOperatorAccountId: this.options.genesisOperatorAccountId
+            genesisOperatorAccountId: this.options.genesisOperatorAccountId,
+            confidenceHalfLifeMs: this.options.confidenceHalfLifeMs,
+            strictProvenance: this.options.strictProvenance
         });
         await this.memoryStore.init();
         this.wallet = loadOrCreateWallet(this.options.dataDir);
@@ -82,6 +271,7 @@ class OpenClawMesh {
             isGenesis: this.options.isGenesisNode,
             genesisAccountId: this.wallet.accountId,
             genesisSupply: this.memoryStore.genesisSupply,
+            genesisTokens: this.options.genesisTokens,
             genesisPublicKeyPem: this.wallet.publicKeyPem,
             genesisPrivateKeyPem: this.wallet.privateKeyPem
         });
@@ -92,16 +282,78 @@ class OpenClawMesh {
             threshold: 10
         });
         this.ratingStore.init();
+        this.memoryStore.capsuleRatings = () => this.ratingStore.getCapsuleRatings();
         
         // 初始化P2P节点
         this.node = new MeshNode({
             nodeId: this.options.nodeId,
             port: this.options.port,
-            bootstrapNodes: this.options.bootstrapNodes
+            bootstrapNodes: this.options.bootstrapNodes,
+            dnsSeed: this.options.dnsSeed,
+            dnsSeedPort: this.options.dnsSeedPort,
+            sharePeerList: this.options.sharePeerList,
+            addressBookPath: this.options.persistAddressBook ? path.join(this.options.dataDir, 'address-book.json') : null,
+            rttHistogramPath: path.join(this.options.dataDir, 'rtt-histogram.json'),
+            addressCooldownMs: this.options.addressCooldownMs,
+            reconnectPeers: this.options.reconnectPeers,
+            maxReconnectPeers: this.options.maxReconnectPeers,
+            identity: {
+                publicKeyPem: this.wallet.publicKeyPem,
+                privateKeyPem: this.wallet.privateKeyPem
+            },
+            requireSignedHandshake: this.options.requireSignedHandshake,
+            peerKeys: this.options.peerKeys,
+            signMessages: this.options.signMessages,
+            banThreshold: this.options.peerBanThreshold,
+            banDurationMs: this.options.peerBanDurationMs,
+            maxMessageBytes: this.options.maxMessageBytes,
+            wireFraming: this.options.wireFraming,
+            maxJsonDepth: this.options.maxJsonDepth,
+            topics: this.options.topics,
+            disabledMessageTypes: this.options.disabledMessageTypes,
+            maxConcurrentQueries: this.options.maxConcurrentQueries,
+            maxQueryResults: this.options.maxQueryResults,
+            benchMode: this.options.benchMode,
+            maxRelayPerSec: this.options.maxRelayPerSec,
+            maxInboundPerSec: this.options.maxInboundPerSec,
+            seenBloomBits: this.options.seenBloomBits,
+            maxPublishPerSec: this.options.maxPublishPerSec,
+            maxPublishQueue: this.options.maxPublishQueue,
+            keyPlacement: this.options.keyPlacement,
+            relayStrategy: this.options.relayStrategy,
+            gossipEagerFanout: this.options.gossipEagerFanout,
+            gossipCacheMaxBytes: this.options.gossipCacheMaxBytes,
+            maxPeers: this.options.maxPeers,
+            maxSendQueue: this.options.maxSendQueue,
+            sendQueueStallMs: this.options.sendQueueStallMs,
+            tcpNoDelay: this.options.tcpNoDelay,
+            tcpKeepAliveMs: this.options.tcpKeepAliveMs,
+            ringVirtualNodes: this.options.ringVirtualNodes,
+            capacityWeight: this.options.capacityWeight,
+            maxPeerCapacity: this.options.maxPeerCapacity,
+            peerScoreWeights: this.options.peerScoreWeights,
+            messageSchemas: this.options.messageSchemas,
+            minAcceptConfidence: this.options.minAcceptConfidence,
+            logDrops: this.options.logDrops,
+            suppressStoredCapsules: this.options.suppressStoredCapsules,
+            queryBusyMode: this.options.queryBusyMode,
+            roles: this.options.roles
         });
+        // 响应peer的记忆查询：只返回脱敏后的胶囊；没有 storage 角色时不提供数据。limit 已由 MeshNode 按 maxQueryResults 限定
+        this.node.queryHandler = (filter) => !this.hasRole('storage') ? [] : this.memoryStore
+            .queryCapsules(filter)
+            .map(capsule => this.memoryStore.redactCapsule(capsule));
+        this.node.hasCapsule = (capsule) => this.memoryStore.hasCapsule(capsule);
+        this.node.balanceHandler = ({ accountId, token }) => this.createBalanceStatement(accountId, token);
         await this.node.init();
+        if (this.options.capsuleWebhook?.url) {
+            this.capsuleWebhook = new CapsuleWebhook({ ...this.options.capsuleWebhook, nodeId: this.options.nodeId });
+        }
+
+        this.startReannounce();
 
         // 账本广播由主节点处理 tx -> tx_log
+        this.startSyncBarrier();
         this.startLedgerSync();
         this.startPendingTxRelay();
         
@@ -112,17 +364,34 @@ class OpenClawMesh {
             ledger: this.ledger,
             walletAccountId: this.wallet.accountId,
             ratingStore: this.ratingStore,
-            dataDir: this.options.dataDir
+            dataDir: this.options.dataDir,
+            archiveAfterMs: this.options.taskArchiveAfterMs,
+            maxBidsPerTask: this.options.maxBidsPerTask
         });
+        this.startTaskArchiving();
+        this.startTaskExpiry();
         
         // 初始化任务处理器 (自动争单)
         this.taskWorker = new TaskWorker(this);
-        this.taskWorker.startAutoBidding();
+        this.taskBazaar.compareBids = (a, b, taskId) => this.taskWorker.compareBids(a, b, taskId);
+        if (this.hasRole('worker')) {
+            this.taskWorker.startAutoBidding();
+        }
         
         // 初始化WebUI
         this.webUI = new WebUIServer({
             port: this.options.webPort,
-            mesh: this
+            mesh: this,
+            wsStatusIntervalMs: this.options.wsStatusIntervalMs,
+            wsPingIntervalMs: this.options.wsPingIntervalMs,
+            adminToken: this.options.adminToken,
+            tls: this.options.webTls,
+            maxAuditEntries: this.options.maxAuditEntries,
+            storeLockTimeoutMs: this.options.storeLockTimeoutMs,
+            storeBreakerThreshold: this.options.storeBreakerThreshold,
+            storeBreakerCooldownMs: this.options.storeBreakerCooldownMs,
+            shutdownTimeoutMs: this.options.webShutdownTimeoutMs,
+            auditLogPath: this.options.auditPersist ? path.join(this.options.dataDir, 'audit.jsonl') : null
         });
         await this.webUI.start();
         
@@ -151,6 +420,7 @@ class OpenClawMesh {
             const sinceSeq = forceFull ? 0 : this.ledger.getLastSeq();
             console.log(`🔄 Ledger sync request: sinceSeq=${sinceSeq} peers=${peers.length}`);
             for (const peer of peers) {
+                this.requestSyncBarrierHead(peer.nodeId);
                 const ok = this.node.sendToPeer(peer.nodeId, {
                     type: 'tx_log_request',
                     payload: { sinceSeq },
@@ -165,6 +435,295 @@ class OpenClawMesh {
         this.ledgerSyncInterval = setInterval(request, 5000);
     }
 
+    // 应用一条同步来的账本记录；分叉记录来源peer并告警
+    applyLedgerEntry(entry, peerId = null) {
+        const result = this.ledger.applyLogEntry(entry);
+        // 账本只接受主节点签名的停用交易，同步到后本地账户记录同样标记为停用
+        if (result?.applied && entry.type === 'account_deactivate') {
+            this.memoryStore?.markAccountInactive(entry.from, Number(entry.timestamp) || Date.now());
+        }
+        if (result?.reason === 'Fork') {
+            result.fork.peerId = peerId;
+            console.warn(`⚠️  Ledger fork at seq ${result.fork.seq} with ${peerId || 'unknown peer'}: local=${result.fork.localTxId} remote=${result.fork.remoteTxId}`);
+        }
+        return result;
+    }
+
+    requestLedgerSince(peerId, sinceSeq) {
+        return this.node.sendToPeer(peerId, {
+            type: 'tx_log_request',
+            payload: { sinceSeq: Number(sinceSeq) || 0 },
+            timestamp: Date.now()
+        });
+    }
+
+    // 启动同步屏障：追上可信节点的账本高度前拒绝写操作
+    startSyncBarrier() {
+        // 只接受具名的可信节点；'*' 会让任意 peer 虚报高度把本节点卡在屏障上，直接忽略
+        const configured = this.options.syncBarrierPeer;
+        const peers = (Array.isArray(configured) ? configured : String(configured || '').split(','))
+            .map(p => String(p).trim())
+            .filter(Boolean);
+        if (peers.includes('*')) {
+            console.warn('⚠️  Sync barrier ignores "*": name the trusted peer nodeIds instead');
+        }
+        const trusted = peers.filter(p => p !== '*');
+        const required = trusted.length > 0 && !this.options.isGenesisNode;
+        this.syncBarrier = {
+            required,
+            ready: !required,
+            peers: required ? trusted : [],
+            targetSeq: null,
+            startedAt: Date.now(),
+            readyAt: required ? null : Date.now(),
+            reason: required ? null : 'disabled'
+        };
+        if (!required) return;
+        console.log(`⏳ Sync barrier: waiting for ledger head of ${trusted.join(', ')}`);
+        for (const p of this.node.getPeers()) {
+            this.requestSyncBarrierHead(p.nodeId);
+        }
+        if (this.options.syncBarrierTimeoutMs > 0) {
+            this.syncBarrierTimer = setTimeout(() => {
+                this.markSyncReady('timeout');
+            }, this.options.syncBarrierTimeoutMs);
+            this.syncBarrierTimer.unref?.();
+        }
+    }
+
+    isSyncBarrierPeer(peerId) {
+        return !!this.syncBarrier?.peers.includes(peerId);
+    }
+
+    requestSyncBarrierHead(peerId) {
+        if (this.syncBarrier?.ready || !this.isSyncBarrierPeer(peerId)) return;
+        this.node.sendToPeer(peerId, {
+            type: 'ledger_head_request',
+            payload: {},
+            timestamp: Date.now()
+        });
+    }
+
+    checkSyncBarrier() {
+        const barrier = this.syncBarrier;
+        if (!barrier || barrier.ready || barrier.targetSeq === null) return;
+        if (this.ledger.getLastSeq() >= barrier.targetSeq) {
+            this.markSyncReady('synced');
+        }
+    }
+
+    markSyncReady(reason) {
+        const barrier = this.syncBarrier;
+        if (!barrier || barrier.ready) return;
+        barrier.ready = true;
+        barrier.readyAt = Date.now();
+        barrier.reason = reason;
+        if (this.syncBarrierTimer) {
+            clearTimeout(this.syncBarrierTimer);
+            this.syncBarrierTimer = null;
+        }
+        if (reason === 'timeout') {
+            console.warn(`⚠️  Sync barrier timed out after ${this.options.syncBarrierTimeoutMs}ms (lastSeq=${this.ledger.getLastSeq()}, target=${barrier.targetSeq ?? 'unknown'})`);
+        } else {
+            console.log(`✅ Sync barrier satisfied (${reason}) at seq ${this.ledger.getLastSeq()}`);
+        }
+    }
+
+    isSyncReady() {
+        return !this.syncBarrier || this.syncBarrier.ready;
+    }
+
+    getSyncStatus() {
+        const barrier = this.syncBarrier || { required: false, ready: true, peers: [], targetSeq: null };
+        return {
+            ready: barrier.ready,
+            required: barrier.required,
+            peers: barrier.peers,
+            targetSeq: barrier.targetSeq,
+            lastSeq: this.ledger ? this.ledger.getLastSeq() : 0,
+            reason: barrier.reason || null,
+            waitedMs: (barrier.readyAt || Date.now()) - (barrier.startedAt || Date.now())
+        };
+    }
+
+    startTaskArchiving() {
+        if (this.taskArchiveInterval) {
+            clearInterval(this.taskArchiveInterval);
+        }
+        if (!(this.options.taskArchiveAfterMs > 0)) return;
+        this.taskArchiveInterval = setInterval(() => {
+            this.taskBazaar?.archiveTasks();
+        }, 10 * 60 * 1000);
+        this.taskArchiveInterval.unref?.();
+    }
+
+    // 启动后等待连接建立，再把本地胶囊重新通告给离各自 asset_id 最近的 k 个peer
+    startReannounce() {
+        if (this.reannounceTimer) {
+            clearTimeout(this.reannounceTimer);
+        }
+        if (!(this.options.reannounceK > 0) || !this.hasRole('storage')) return;
+        this.reannounceTimer = setTimeout(() => {
+            this.reannounceTimer = null;
+            this.reannounceCapsules();
+        }, this.options.reannounceDelayMs);
+        this.reannounceTimer.unref?.();
+    }
+
+    // 分批通告，每批之间间隔 reannounceBatchIntervalMs，避免胶囊很多时启动瞬间发出大量消息
+    reannounceCapsules(assetIds = Array.from(this.memoryStore.capsules.keys()), offset = 0) {
+        const batchSize = this.options.reannounceBatchSize > 0 ? this.options.reannounceBatchSize : assetIds.length;
+        const batch = assetIds.slice(offset, offset + batchSize);
+        let capsules = 0;
+        let sent = 0;
+        for (const assetId of batch) {
+            const capsule = this.memoryStore.capsules.get(assetId);
+            if (!capsule) continue;
+            capsules++;
+            sent += this.node.announceToClosestPeers(capsule.asset_id, {
+                type: 'capsule',
+                payload: this.buildCapsuleAnnouncement(capsule),
+                timestamp: Date.now()
+            }, this.options.reannounceK);
+        }
+        const remaining = Math.max(0, assetIds.length - offset - batch.length);
+        if (capsules > 0) {
+            console.log(`📣 Re-announced ${capsules} capsules (${sent} messages, ${remaining} remaining)`);
+        }
+        if (remaining > 0) {
+            this.reannounceTimer = setTimeout(() => {
+                this.reannounceTimer = null;
+                this.reannounceCapsules(assetIds, offset + batch.length);
+            }, Math.max(0, this.options.reannounceBatchIntervalMs || 0));
+            this.reannounceTimer.unref?.();
+        }
+        return { capsules, sent, remaining };
+    }
+
+    // 广播用的胶囊元数据：不含内容、本地保留时长与置顶状态；收到的胶囊没有内容，沿用其通告时的标签
+    buildCapsuleAnnouncement(capsule) {
+        return {
+            ...capsule,
+            retentionMs: undefined,
+            pinned: undefined,
+            localOrigin: undefined,
+            content: null,
+            contentHash: capsule.asset_id,
+            tags: capsule.content ? (capsule.content.capsule?.blast_radius || []) : (capsule.tags || [])
+        };
+    }
+
+    startTaskExpiry() {
+        if (this.taskExpiryInterval) {
+            clearInterval(this.taskExpiryInterval);
+        }
+        if (!(this.options.taskExpiryIntervalMs > 0)) return;
+        this.taskExpiryInterval = setInterval(() => {
+            this.expireTasks();
+        }, this.options.taskExpiryIntervalMs);
+        this.taskExpiryInterval.unref?.();
+    }
+
+    // 截止时间已过且无获胜者的任务标记为过期并广播 task_expired；托管由主节点签名转给备用账户或退回发布者
+    expireTasks(now = Date.now()) {
+        const expired = this.taskBazaar?.expireTasks(now, task => this.isExpiryAuthority(task, now)) || [];
+        for (const task of expired) {
+            console.log(`⌛ Task expired: ${task.taskId} (escrow ${task.escrowDisposition.type} -> ${task.escrowDisposition.to || 'unknown'})`);
+            this.node?.broadcast({
+                type: 'task_expired',
+                payload: { taskId: task.taskId, expiredAt: task.expiredAt, expiredBy: task.expiredBy },
+                timestamp: Date.now()
+            });
+            this.taskWorker?.biddingTasks?.delete(task.taskId);
+            this.refundExpiredTask(task);
+        }
+        return expired;
+    }
+
+    // 本地托管记录只在发布者节点上，由发布者退回；账本上的托管由主节点结算。两者都只执行一次
+    refundExpiredTask(task) {
+        const local = task.publisher === this.options.nodeId && task.escrowDisposition?.type === 'refund'
+            ? this.memoryStore?.refundEscrow(task.taskId, { reason: 'task_expired' })
+            : null;
+        const ledger = this.options.isGenesisNode ? this.settleTaskEscrow(task) : null;
+        return { local, ledger };
+    }
+
+    // 宣布过期的权限：发布者在截止时间即可；其余节点的接手时间只由任务本身决定，各节点看到的相同：
+    // 任务参与者（被分配节点和投标节点，不含发布者）按 sha256(taskId:nodeId) 排序，第 r 名在截止时间后
+    // (r + 1) × taskExpiryGraceMs 接手；不在其中的节点排在所有参与者之后，彼此按哈希值的比例错开
+    isExpiryAuthority(task, now = Date.now()) {
+        const takeoverAt = this.getExpiryTakeoverAt(task);
+        return takeoverAt !== null && now >= takeoverAt;
+    }
+
+    getExpiryTakeoverAt(task, nodeId = this.options.nodeId) {
+        const deadline = this.taskBazaar.getTaskDeadline(task);
+        if (!deadline) return null;
+        if (task.publisher === nodeId) return deadline;
+        const rankKey = (id) => crypto.createHash('sha256').update(`${task.taskId}:${id}`).digest('hex');
+        const participants = Array.from(new Set([
+            task.assignedTo,
+            ...(Array.isArray(task.bids) ? task.bids.map(bid => bid?.nodeId) : [])
+        ].filter(id => typeof id === 'string' && id && id !== task.publisher)))
+            .sort((a, b) => (rankKey(a) < rankKey(b) ? -1 : 1));
+        const index = participants.indexOf(nodeId);
+        const rank = index >= 0
+            ? index
+            : participants.length + parseInt(rankKey(nodeId).slice(0, 8), 16) / 0x100000000;
+        return deadline + this.options.taskExpiryGraceMs * (rank + 1);
+    }
+
+    // 信誉只记录本地已知任务真正发生的完成/失败转换，且必须由被分配节点本人签名报告；
+    // 未知任务、重复消息和其他节点代报的结果都不计
+    recordTaskOutcome(previousStatus, task, origin, success) {
+        const status = success ? 'completed' : 'failed';
+        const assignee = task?.assignedTo;
+        if (!assignee || origin !== assignee || !previousStatus || previousStatus === status || task.status !== status) {
+            return false;
+        }
+        if (success) {
+            // 时间戳已由 TaskBazaar 统一为毫秒
+            const assignedAt = task.assignedAt || null;
+            const completedAt = task.completedAt || Date.now();
+            if (assignedAt && completedAt >= assignedAt) {
+                this.ratingStore?.recordCompletion(assignee, completedAt - assignedAt);
+            }
+        } else {
+            this.ratingStore?.recordFailure(assignee);
+        }
+        this.ratingStore?.recordOutcome(assignee, success);
+        return true;
+    }
+
+    applyTaskExpiry(payload, now = Date.now()) {
+        const result = this.taskBazaar.applyRemoteExpiry(payload?.taskId, payload?.expiredAt, payload?.expiredBy, now);
+        if (!result.applied) return result;
+        this.taskWorker?.biddingTasks?.delete(result.task.taskId);
+        this.refundExpiredTask(result.task);
+        return result;
+    }
+
+    // 托管去向写入交易备注（task:<taskId>:fallback|refund），账本中可查；已结算过的不再重复
+    settleTaskEscrow(task) {
+        const disposition = task.escrowDisposition;
+        if (disposition?.txId && disposition.accepted) return null;
+        const token = task.bounty?.token || LedgerStore.DEFAULT_TOKEN;
+        const amount = task.escrowAccountId ? this.ledger.getBalance(task.escrowAccountId, token) : 0;
+        // 退款只退到签名托管证明中的发布者账户
+        const to = disposition?.type === 'refund' ? this.taskBazaar.getPublisherAccountId(task) : disposition?.to;
+        if (!to || !(amount > 0)) return null;
+        const tx = this.createSignedEscrowRelease(task.escrowAccountId, to, amount, {
+            memo: `task:${task.taskId}:${disposition.type}`,
+            token
+        });
+        const result = this.submitTx(tx);
+        this.taskBazaar.updateTask(task.taskId, {
+            escrowDisposition: { ...disposition, to, amount, txId: tx.txId, accepted: Boolean(result?.accepted) }
+        });
+        return result;
+    }
+
     startPendingTxRelay() {
         if (this.pendingTxInterval) {
             clearInterval(this.pendingTxInterval);
@@ -208,7 +767,9 @@ class OpenClawMesh {
                 throw new Error('Genesis wallet cannot be changed after initialization');
             }
         }
-        const wallet = importWallet(this.options.dataDir, payload);
+        const wallet = importWallet(this.options.dataDir, payload, {
+            supportedAlgorithms: this.options.supportedKeyAlgorithms
+        });
         this.wallet = wallet;
         if (this.taskBazaar) {
             this.taskBazaar.walletAccountId = wallet.accountId;
@@ -220,7 +781,30 @@ class OpenClawMesh {
         // 监听新记忆
         this.node.on('memory:received', async (capsule) => {
             console.log(`📦 New capsule received: ${capsule.asset_id}`);
-            await this.memoryStore.storeCapsule(capsule);
+            if (!this.hasRole('storage')) return;
+            try {
+                await this.memoryStore.storeCapsule(capsule);
+            } catch (e) {
+                console.error(`Rejected capsule ${capsule?.asset_id}:`, e.message);
+                return;
+            }
+            if (this.capsuleWebhook) {
+                this.capsuleWebhook.enqueue(capsule);
+            }
+        });
+
+        this.node.on('capsule:rating', (rating) => {
+            const result = this.applyCapsuleRating(rating);
+            if (!result.ok && result.reason !== 'Capsule already rated by this node') {
+                console.warn(`⚠️  Ignored rating for ${rating?.assetId}: ${result.reason}`);
+            }
+        });
+
+        this.node.on('capsule:acl', async (update) => {
+            const result = await this.memoryStore.applyAclUpdate(update);
+            if (!result.applied && result.reason !== 'Capsule not found' && result.reason !== 'Stale ACL update') {
+                console.warn(`⚠️  Ignored ACL update for ${update?.assetId}: ${result.reason}`);
+            }
         });
         
         // 监听新任务
@@ -241,16 +825,7 @@ class OpenClawMesh {
                         if (task.status === 'assigned' || task.status === 'completed') {
                             return;
                         }
-                        task.bids = task.bids || [];
-                        // Avoid duplicate bids
-                        if (!task.bids.find(b => b.nodeId === bid.nodeId)) {
-                            task.bids.push(bid);
-                            this.taskBazaar.updateTask(taskId, { 
-                                bids: task.bids,
-                                status: task.status === 'open' ? 'voting' : task.status,
-                                votingStartedAt: task.votingStartedAt || bid.timestamp || Date.now()
-                            });
-                        }
+                        this.taskBazaar.addBid(taskId, bid);
                     }
                 }
             } catch (err) {
@@ -258,16 +833,21 @@ class OpenClawMesh {
             }
         });
 
-        this.node.on('task:assigned', async (payload) => {
+        this.node.on('task:assigned', async (payload, origin) => {
             try {
                 if (!payload) return;
-                const { taskId, assignedTo, assignedAt } = payload;
+                const { taskId, assignedTo, assignedAt, version } = payload;
                 if (!taskId || !assignedTo) return;
-                const updatedTask = this.taskBazaar.updateTask(taskId, { 
+                const update = this.taskBazaar.applyRemoteUpdate(taskId, {
                     status: 'assigned',
                     assignedTo,
                     assignedAt: assignedAt || Date.now()
-                });
+                }, version, origin);
+                if (!update.applied) {
+                    console.log(`⏭️  Ignored task_assigned for ${taskId.slice(0, 16)}: ${update.reason}`);
+                    return;
+                }
+                const updatedTask = update.task;
                 if (this.taskWorker?.biddingTasks) {
                     this.taskWorker.biddingTasks.delete(taskId);
                 }
@@ -280,25 +860,25 @@ class OpenClawMesh {
         });
         
         // 监听任务完成
-        this.node.on('task:completed', async (payload) => {
+        this.node.on('task:completed', async (payload, origin) => {
             try {
                 if (!payload) return;
-                const { taskId, nodeId, result, package: taskPackage } = payload;
+                const { taskId, nodeId, result, package: taskPackage, version } = payload;
                 console.log(`✅ Task completed by node: ${nodeId?.slice(0, 16)} for task: ${taskId?.slice(0, 16)}`);
                 if (taskId) {
-                    this.taskBazaar.updateTask(taskId, { 
+                    const previousStatus = this.taskBazaar.getTask(taskId)?.status;
+                    const update = this.taskBazaar.applyRemoteUpdate(taskId, {
                         status: 'completed',
                         completedBy: nodeId,
                         completedAt: result?.completedAt || Date.now(),
                         result
-                    });
-                    const task = this.taskBazaar.getTask(taskId);
-                    const assignedAt = task?.assignedAt ? Number(task.assignedAt) : null;
-                    const completedAtRaw = result?.completedAt || Date.now();
-                    const completedAt = Number(completedAtRaw) || Date.parse(completedAtRaw) || Date.now();
-                    if (assignedAt && completedAt && completedAt >= assignedAt) {
-                        const duration = completedAt - assignedAt;
-                        this.ratingStore?.recordCompletion(nodeId, duration);
+                    }, version, origin);
+                    if (!update.applied && update.reason !== 'Task not found') {
+                        console.log(`⏭️  Ignored task_completed for ${taskId.slice(0, 16)}: ${update.reason}`);
+                        return;
+                    }
+                    if (update.applied) {
+                        this.recordTaskOutcome(previousStatus, update.task, origin, true);
                     }
                 }
                 if (taskId && nodeId && taskPackage?.data) {
@@ -315,16 +895,67 @@ class OpenClawMesh {
             }
         });
 
-        this.node.on('task:failed', async (payload) => {
+        this.node.on('task:cancelled', async (notice) => {
             try {
-                if (!payload) return;
-                const { taskId, nodeId } = payload;
-                if (taskId) {
-                    this.taskBazaar.updateTask(taskId, { status: 'failed' });
+                const result = this.applyTaskCancellation(notice);
+                if (!result.success) {
+                    console.warn(`⚠️  Ignored cancellation for ${notice?.statement?.taskId}: ${result.reason}`);
+                }
+            } catch (err) {
+                console.error('Error handling task:cancelled:', err.message);
+            }
+        });
+
+        this.node.on('task:submission', async (notice) => {
+            try {
+                const result = await this.applyTaskSubmission(notice);
+                const { taskId, solverId } = notice?.statement || {};
+                if (result.success) {
+                    console.log(`📝 Submission from ${solverId?.slice(0, 16)} for task: ${taskId?.slice(0, 16)} (${result.submissionCount} total)`);
+                } else if (result.reason !== 'Task not found') {
+                    console.warn(`⚠️  Ignored submission for ${taskId}: ${result.reason}`);
+                }
+            } catch (err) {
+                console.error('Error handling task:submission:', err.message);
+            }
+        });
+
+        this.node.on('task:awarded', async (notice) => {
+            try {
+                const result = this.applyTaskAward(notice);
+                if (!result.success) {
+                    console.warn(`⚠️  Ignored award for ${notice?.statement?.taskId}: ${result.reason}`);
                 }
-                if (nodeId) {
-                    this.ratingStore?.recordFailure(nodeId);
+            } catch (err) {
+                console.error('Error handling task:awarded:', err.message);
+            }
+        });
+
+        this.node.on('task:expired', async (payload) => {
+            try {
+                const result = this.applyTaskExpiry(payload);
+                if (result.applied) {
+                    console.log(`⌛ Task expired by ${payload.expiredBy}: ${payload.taskId}`);
                 }
+            } catch (err) {
+                console.error('Error handling task:expired:', err.message);
+            }
+        });
+
+        this.node.on('task:failed', async (payload, origin) => {
+            try {
+                if (!payload) return;
+                const { taskId, version } = payload;
+                if (!taskId) return;
+                const previousStatus = this.taskBazaar.getTask(taskId)?.status;
+                const update = this.taskBazaar.applyRemoteUpdate(taskId, { status: 'failed' }, version, origin);
+                if (!update.applied) {
+                    if (update.reason !== 'Task not found') {
+                        console.log(`⏭️  Ignored task_failed for ${taskId.slice(0, 16)}: ${update.reason}`);
+                    }
+                    return;
+                }
+                this.recordTaskOutcome(previousStatus, update.task, origin, false);
             } catch (err) {
                 console.error('Error handling task:failed:', err.message);
             }
@@ -343,6 +974,7 @@ class OpenClawMesh {
         // 监听节点连接
         this.node.on('peer:connected', (peerId) => {
             console.log(`🌐 Peer connected: ${peerId}`);
+            this.requestSyncBarrierHead(peerId);
             if (!this.options.isGenesisNode) {
                 console.log(`🔄 Ledger sync request (on connect): sinceSeq=0 -> ${peerId}`);
                 const ok = this.node.sendToPeer(peerId, {
@@ -379,7 +1011,8 @@ class OpenClawMesh {
                             nonce: tx.nonce,
                             timestamp: tx.timestamp,
                             pubkeyPem: tx.pubkeyPem,
-                            signature: tx.signature
+                            signature: tx.signature,
+                            ...(tx.meta ? { meta: tx.meta } : {})
                         },
                         timestamp: Date.now()
                     });
@@ -388,12 +1021,17 @@ class OpenClawMesh {
         });
         
         // 监听交易日志同步
-        this.node.on('tx:log', (entry) => {
+        this.node.on('tx:log', (entry, peerId) => {
             if (!entry) return;
-            this.ledger.applyLogEntry(entry);
-            if (entry.txId) {
+            const result = this.applyLedgerEntry(entry, peerId);
+            if (result?.reason === 'Gap' && peerId) {
+                // 缺少前面的条目：从本地链尾向该peer补齐
+                this.requestLedgerSince(peerId, this.ledger.getLastSeq());
+            }
+            if (entry.txId && result?.reason !== 'Fork') {
                 this.pendingTxs.delete(entry.txId);
             }
+            this.checkSyncBarrier();
             if (this.taskBazaar?.tryActivatePendingTasks) {
                 this.taskBazaar.tryActivatePendingTasks();
             }
@@ -426,19 +1064,32 @@ class OpenClawMesh {
                 const lastSeq = entries[entries.length - 1]?.seq;
                 console.log(`📥 tx_log_batch from ${peerId} count=${entries.length} seq=${firstSeq}..${lastSeq}`);
             }
+            let interrupted = null;
             for (const entry of entries) {
-                this.ledger.applyLogEntry(entry);
+                const result = this.applyLedgerEntry(entry, peerId);
+                if (result?.reason === 'Fork' || result?.reason === 'Gap') {
+                    interrupted = result.reason;
+                    break;
+                }
                 if (entry?.txId) {
                     this.pendingTxs.delete(entry.txId);
                 }
             }
-            if (payload?.hasMore && Number.isFinite(payload?.lastSeq)) {
+            if (interrupted === 'Gap') {
+                this.requestLedgerSince(peerId, this.ledger.getLastSeq());
+            } else if (interrupted === 'Fork') {
+                // 分叉无法自动合并，不再跟进该peer的后续批次
+            } else if (payload?.hasMore && Number.isFinite(payload?.lastSeq)) {
                 this.node.sendToPeer(peerId, {
                     type: 'tx_log_request',
                     payload: { sinceSeq: Number(payload.lastSeq) },
                     timestamp: Date.now()
                 });
+            } else if (this.isSyncBarrierPeer(peerId) && this.syncBarrier.targetSeq === null) {
+                // 可信节点没有更多日志可发：视为已静止
+                this.markSyncReady('quiescent');
             }
+            this.checkSyncBarrier();
             if (this.taskBazaar?.tryActivatePendingTasks) {
                 this.taskBazaar.tryActivatePendingTasks();
             }
@@ -456,18 +1107,49 @@ class OpenClawMesh {
             });
         });
 
-        // ledger_head_* handlers are no longer used in forced tx_log sync mode.
+        this.node.on('ledger:head_response', (payload, peerId) => {
+            if (!this.isSyncBarrierPeer(peerId) || this.syncBarrier.ready) return;
+            const lastSeq = Number(payload?.lastSeq);
+            if (!Number.isFinite(lastSeq)) return;
+            this.syncBarrier.targetSeq = Math.max(this.syncBarrier.targetSeq ?? 0, lastSeq);
+            console.log(`⏳ Sync barrier target: seq ${this.syncBarrier.targetSeq} from ${peerId}`);
+            this.checkSyncBarrier();
+        });
     }
     
-    createSignedTransfer(toAccountId, amount) {
+    createSignedTransfer(toAccountId, amount, options = {}) {
         const nonce = this.ledger.getNonce(this.wallet.accountId) + 1;
+        const memo = LedgerStore.sanitizeMemo(options.memo);
+        const token = options.token && options.token !== LedgerStore.DEFAULT_TOKEN ? options.token : null;
+        const meta = memo || token ? { ...(token ? { token } : {}), ...(memo ? { memo } : {}) } : null;
         const payload = {
             type: 'transfer',
             from: this.wallet.accountId,
             to: toAccountId,
             amount: Number(amount),
             nonce,
-            timestamp: Date.now()
+            timestamp: Date.now(),
+            ...(meta ? { meta } : {})
+        };
+        const signature = signPayload(this.wallet.privateKeyPem, payload);
+        return {
+            ...payload,
+            pubkeyPem: this.wallet.publicKeyPem,
+            signature,
+            txId: crypto.createHash('sha256').update(JSON.stringify({ ...payload, signature })).digest('hex')
+        };
+    }
+
+    // 增发已定义的代币，只有该代币的 minters 签名才会被账本接受
+    createSignedMint(token, toAccountId, amount) {
+        const payload = {
+            type: 'mint',
+            from: this.wallet.accountId,
+            to: toAccountId,
+            amount: Number(amount),
+            nonce: this.ledger.getNonce(this.wallet.accountId) + 1,
+            timestamp: Date.now(),
+            meta: { token }
         };
         const signature = signPayload(this.wallet.privateKeyPem, payload);
         return {
@@ -478,6 +1160,21 @@ class OpenClawMesh {
         };
     }
 
+    mintToken(token, toAccountId, amount) {
+        const definition = this.ledger.getToken(token);
+        if (!definition) {
+            const err = new Error(`Unknown token: ${token}`);
+            err.code = 'UNKNOWN_TOKEN';
+            throw err;
+        }
+        if (!definition.minters.includes(this.wallet.accountId)) {
+            const err = new Error(`Account ${this.wallet.accountId} is not a minter of ${token}`);
+            err.code = 'NOT_MINTER';
+            throw err;
+        }
+        return this.submitTx(this.createSignedMint(token, toAccountId, amount));
+    }
+
     getPlatformAccountId() {
         const masterPub = this.ledger.getMeta('master_pubkey');
         if (!masterPub) return null;
@@ -517,15 +1214,19 @@ class OpenClawMesh {
         return { txId, confirmations, confirmed: confirmations > 0 };
     }
 
-    createSignedEscrowRelease(escrowAccountId, toAccountId, amount) {
+    createSignedEscrowRelease(escrowAccountId, toAccountId, amount, options = {}) {
         const nonce = this.ledger.getNonce(escrowAccountId) + 1;
+        const memo = LedgerStore.sanitizeMemo(options.memo);
+        const token = options.token && options.token !== LedgerStore.DEFAULT_TOKEN ? options.token : null;
+        const meta = memo || token ? { ...(token ? { token } : {}), ...(memo ? { memo } : {}) } : null;
         const payload = {
             type: 'escrow_release',
             from: escrowAccountId,
             to: toAccountId,
             amount: Number(amount),
             nonce,
-            timestamp: Date.now()
+            timestamp: Date.now(),
+            ...(meta ? { meta } : {})
         };
         const signature = signPayload(this.wallet.privateKeyPem, payload);
         return {
@@ -536,6 +1237,71 @@ class OpenClawMesh {
         };
     }
 
+    // 停用账户：主节点签发 account_deactivate 账本交易（指定 sweepTo 时同时转走余额，其他代币各签一笔），
+    // 同步后所有节点都拒绝该账户的转入转出；本地账户记录同样标记为停用
+    deactivateAccount(accountId, { sweepTo = null } = {}) {
+        if (!this.options.isGenesisNode) {
+            throw new Error('Only the genesis node can deactivate accounts');
+        }
+        const deactivate = (token, amount) => {
+            const tx = this.ledger.createDeactivateTx({
+                accountId,
+                sweepTo,
+                amount,
+                token,
+                nonce: this.ledger.getNonce(accountId) + 1,
+                publicKeyPem: this.wallet.publicKeyPem,
+                privateKeyPem: this.wallet.privateKeyPem
+            });
+            const result = this.submitTx(tx);
+            if (!result.accepted) {
+                throw new Error(token ? `${token}: ${result.reason}` : result.reason);
+            }
+            return { tx, result };
+        };
+        const { tx, result: ledger } = deactivate(null, sweepTo ? this.ledger.getBalance(accountId) : 0);
+        const tokens = {};
+        if (sweepTo) {
+            for (const { symbol } of this.ledger.getTokens()) {
+                const balance = this.ledger.getBalance(accountId, symbol);
+                if (balance > 0) {
+                    deactivate(symbol, balance);
+                    tokens[symbol] = balance;
+                }
+            }
+        }
+        const store = this.memoryStore;
+        const local = store.isAccountActive(store.accounts.get(accountId))
+            ? store.deactivateAccount(accountId, { sweepTo: store.accounts.has(sweepTo) ? sweepTo : null })
+            : null;
+        return {
+            accountId,
+            status: 'inactive',
+            swept: sweepTo ? { to: sweepTo, amount: tx.amount, tokens } : null,
+            ledger,
+            local
+        };
+    }
+
+    // 发布者签名的托管证明，随任务广播，竞标方据此确认赏金已锁定
+    createEscrowProof(task, txId, amount) {
+        const statement = {
+            taskId: task.taskId,
+            escrowAccountId: task.escrowAccountId,
+            amount: Number(amount),
+            token: task.bounty?.token || 'CLAW',
+            txId,
+            publisher: this.wallet.accountId,
+            ...(task.fallbackAccountId ? { fallbackAccountId: task.fallbackAccountId } : {}),
+            issuedAt: Date.now()
+        };
+        return {
+            statement,
+            publicKeyPem: this.wallet.publicKeyPem,
+            signature: signPayload(this.wallet.privateKeyPem, statement)
+        };
+    }
+
     getEscrowAccountId(taskId) {
         const hash = crypto.createHash('sha256').update(String(taskId)).digest('hex').slice(0, 24);
         return `escrow_${hash}`;
@@ -558,7 +1324,9 @@ class OpenClawMesh {
                         nonce: tx.nonce,
                         timestamp: tx.timestamp,
                         pubkeyPem: tx.pubkeyPem,
-                        signature: tx.signature
+                        signature: tx.signature,
+                        headHash: result.headHash,
+                        ...(tx.meta ? { meta: tx.meta } : {})
                     },
                     timestamp: Date.now()
                 });
@@ -575,10 +1343,12 @@ class OpenClawMesh {
     }
 
     // 发布记忆胶囊
-    async publishCapsule(capsule) {
+    async publishCapsule(capsule, { ttlMs } = {}) {
         if (!this.initialized) {
             throw new Error('Mesh not initialized');
         }
+        this.assertNotDraining();
+        const retentionMs = ttlMs ?? this.options.publishedCapsuleTtlMs;
         
         if (!capsule.price) {
             capsule.price = {
@@ -599,6 +1369,18 @@ class OpenClawMesh {
         
         // 计算asset_id
         capsule.asset_id = this.computeAssetId(capsule);
+        const derivedFrom = Array.isArray(capsule.derivedFrom) ? capsule.derivedFrom.filter(Boolean) : [];
+        delete capsule.derivedFrom;
+
+        // 相同内容已发布过：不重复收费、不重复广播，保留原始元数据
+        if (this.memoryStore.getCapsule(capsule.asset_id)) {
+            await this.memoryStore.extendCapsuleTtl(capsule.asset_id, retentionMs);
+            console.log(`♻️  Capsule already published: ${capsule.asset_id}`);
+            return { assetId: capsule.asset_id, txReceipts: [], alreadyExists: true };
+        }
+
+        // 广播队列已满时在收费和存储之前拒绝，调用方稍后重试
+        this.node.assertPublishCapacity();
 
         const txReceipts = [];
         if (this.options.capsulePublishFee > 0) {
@@ -621,31 +1403,245 @@ class OpenClawMesh {
             txReceipts.push({ txId: feeTx.txId, ...feeConfirm });
         }
         
+        capsule.provenance = this.createProvenance(capsule.asset_id, derivedFrom);
+        if (capsule.acl !== undefined) {
+            Object.assign(capsule, this.signCapsuleAcl(capsule.asset_id, capsule.acl));
+        }
+
         // 本地存储
-        await this.memoryStore.storeCapsule(capsule);
+        await this.memoryStore.storeCapsule(capsule, { ttlMs: retentionMs, localOrigin: true });
         
         // 广播到网络
-        const capsuleMeta = {
-            ...capsule,
-            content: null,
-            contentHash: capsule.asset_id
-        };
-        await this.node.broadcastCapsule(capsuleMeta);
+        await this.node.broadcastCapsule(this.buildCapsuleAnnouncement(capsule));
         
         console.log(`✅ Capsule published: ${capsule.asset_id}`);
         return { assetId: capsule.asset_id, txReceipts };
     }
     
+    // 批量发布：逐个发布，单个失败不影响其余；广播受 maxPublishPerSec 限速
+    async publishCapsules(capsules, { ttlMs } = {}) {
+        const results = [];
+        for (const capsule of capsules) {
+            try {
+                const result = await this.publishCapsule(capsule, { ttlMs });
+                results.push({ assetId: result.assetId, alreadyExists: Boolean(result.alreadyExists), txReceipts: result.txReceipts || [] });
+            } catch (e) {
+                results.push({ error: e.message, ...(e.code ? { code: e.code } : {}) });
+            }
+        }
+        return { results, published: results.filter(r => r.assetId && !r.alreadyExists).length, publishQueue: this.node.getPublishQueueStats() };
+    }
+
+    // 压测：发布合成胶囊（标签 bench，结束后删除），统计发布吞吐与peer回执的往返延迟
+    async runGossipBench(options = {}) {
+        if (!this.options.benchMode) {
+            throw new Error('Bench mode disabled');
+        }
+        const count = Math.min(Math.max(Math.floor(Number(options.count) || 100), 1), 10000);
+        const size = Math.min(Math.max(Math.floor(Number(options.size) || 1024), 1), 65536);
+        const waitMs = Math.min(Math.max(Number(options.waitMs) || 2000, 0), 30000);
+        const benchId = 'bench_' + crypto.randomBytes(6).toString('hex');
+        const sentAt = new Map();
+        const latencies = [];
+        const responders = new Set();
+        const onAck = (ack) => {
+            if (ack.benchId !== benchId || !sentAt.has(ack.seq)) return;
+            latencies.push(Date.now() - sentAt.get(ack.seq));
+            responders.add(ack.nodeId);
+        };
+        this.node.on('bench:ack', onAck);
+        const filler = 'x'.repeat(size);
+        const assetIds = [];
+        const started = Date.now();
+        try {
+            for (let seq = 0; seq < count; seq++) {
+                const capsule = {
+                    content: { capsule: { type: 'bench', code: filler, blast_radius: ['bench'] }, bench: { benchId, seq } },
+                    attribution: { creator: this.options.nodeId, created_at: new Date().toISOString() }
+                };
+                capsule.asset_id = this.computeAssetId(capsule);
+                capsule.bench = { benchId, seq, origin: this.options.nodeId };
+                capsule.provenance = this.createProvenance(capsule.asset_id);
+                await this.memoryStore.storeCapsule(capsule);
+                assetIds.push(capsule.asset_id);
+                sentAt.set(seq, Date.now());
+                await this.node.broadcastCapsule({ ...capsule, content: null, tags: ['bench'] });
+            }
+            const publishMs = Date.now() - started;
+            await new Promise(resolve => setTimeout(resolve, waitMs));
+            latencies.sort((a, b) => a - b);
+            const pick = (q) => latencies.length ? latencies[Math.min(latencies.length - 1, Math.floor(q * latencies.length))] : null;
+            return {
+                benchId,
+                count,
+                size,
+                peers: this.node.peers.size,
+                publishMs,
+                publishPerSec: publishMs > 0 ? Math.round(count / publishMs * 1000) : count * 1000,
+                acks: latencies.length,
+                responders: responders.size,
+                latencyMs: { p50: pick(0.5), p95: pick(0.95), max: latencies.length ? latencies[latencies.length - 1] : null }
+            };
+        } finally {
+            this.node.off('bench:ack', onAck);
+            for (const assetId of assetIds) {
+                await this.memoryStore.removeCapsule(assetId);
+            }
+        }
+    }
+
+    // 在第一个来源胶囊的来源链后追加本节点签名的一步
+    createProvenance(assetId, derivedFrom = []) {
+        const parent = derivedFrom.length > 0 ? this.memoryStore.getCapsule(derivedFrom[0]) : null;
+        const chain = Array.isArray(parent?.provenance) ? parent.provenance : [];
+        const statement = {
+            assetId,
+            creator: this.options.nodeId,
+            accountId: this.wallet.accountId,
+            derivedFrom,
+            prevSignature: chain.length > 0 ? chain[chain.length - 1].signature : null,
+            signedAt: new Date().toISOString()
+        };
+        return [...chain, {
+            ...statement,
+            publicKeyPem: this.wallet.publicKeyPem,
+            signature: signPayload(this.wallet.privateKeyPem, statement)
+        }];
+    }
+
+    signCapsuleAcl(assetId, acl, aclUpdatedAt = Date.now()) {
+        const statement = {
+            assetId,
+            acl: Array.from(new Set((Array.isArray(acl) ? acl : []).map(String).filter(Boolean))),
+            aclUpdatedAt
+        };
+        return {
+            acl: statement.acl,
+            aclUpdatedAt: statement.aclUpdatedAt,
+            aclSignature: signPayload(this.wallet.privateKeyPem, statement)
+        };
+    }
+
+    // 创建者授权/撤销节点对胶囊完整内容的访问，签名后广播
+    async updateCapsuleAcl(assetId, { grant = [], revoke = [] } = {}) {
+        const capsule = this.memoryStore.getCapsule(assetId);
+        if (!capsule) {
+            throw new Error('Capsule not found');
+        }
+        if (capsule.attribution?.creator !== this.options.nodeId) {
+            throw new Error('Only the creator can change the ACL');
+        }
+        const acl = new Set(Array.isArray(capsule.acl) ? capsule.acl : []);
+        grant.forEach(nodeId => acl.add(nodeId));
+        revoke.forEach(nodeId => acl.delete(nodeId));
+        const updatedAt = Math.max(Date.now(), Number(capsule.aclUpdatedAt || 0) + 1);
+        const update = { assetId, ...this.signCapsuleAcl(assetId, Array.from(acl), updatedAt) };
+        const result = await this.memoryStore.applyAclUpdate(update);
+        if (!result.applied) {
+            throw new Error(result.reason);
+        }
+        this.node.broadcast({ type: 'capsule_acl', payload: update, timestamp: Date.now() });
+        return { assetId, acl: update.acl, aclUpdatedAt: update.aclUpdatedAt };
+    }
+
+    // 对胶囊评分（赞/踩 + 1~5 分），签名后广播；同一节点对同一胶囊只能评一次
+    rateCapsule(assetId, { thumb, score = 5 } = {}) {
+        if (!this.memoryStore.getCapsule(assetId)) {
+            throw new Error('Capsule not found');
+        }
+        // 评分者ID总是由钱包公钥派生，收到的节点才能验证评分确实出自该节点
+        const statement = {
+            assetId,
+            raterNodeId: nodeIdFromPublicKey(this.wallet.publicKeyPem),
+            thumb,
+            score: Number(score),
+            ratedAt: Date.now()
+        };
+        const rating = {
+            ...statement,
+            publicKeyPem: this.wallet.publicKeyPem,
+            signature: signPayload(this.wallet.privateKeyPem, statement)
+        };
+        const result = this.applyCapsuleRating(rating);
+        if (!result.ok) {
+            throw new Error(result.reason);
+        }
+        this.node.broadcast({ type: 'capsule_rating', payload: rating, timestamp: Date.now() });
+        return { assetId, rating: result.rating };
+    }
+
+    applyCapsuleRating(rating) {
+        const check = this.verifyCapsuleRating(rating);
+        if (!check.valid) return { ok: false, reason: check.reason };
+        return this.ratingStore.addCapsuleRating(rating);
+    }
+
+    verifyCapsuleRating(rating) {
+        const { assetId, raterNodeId, thumb, score, ratedAt, publicKeyPem, signature } = rating || {};
+        if (!assetId || !raterNodeId || !publicKeyPem || !signature) {
+            return { valid: false, reason: 'Missing rating fields' };
+        }
+        if (thumb !== 'up' && thumb !== 'down') {
+            return { valid: false, reason: 'Invalid thumb' };
+        }
+        if (!Number.isInteger(score) || score < 1 || score > 5) {
+            return { valid: false, reason: 'Score must be an integer from 1 to 5' };
+        }
+        let signatureOk = false;
+        try {
+            signatureOk = verifyPayload(publicKeyPem, { assetId, raterNodeId, thumb, score, ratedAt }, signature);
+        } catch (e) {
+            signatureOk = false;
+        }
+        if (!signatureOk) {
+            return { valid: false, reason: 'Invalid signature' };
+        }
+        // 签名公钥必须属于声明的评分节点
+        const knownKey = raterNodeId === this.options.nodeId
+            ? this.wallet.publicKeyPem
+            : (this.node?.peerAuth?.get(raterNodeId)?.verified ? this.node.peerAuth.get(raterNodeId).publicKeyPem : null);
+        if (knownKey && knownKey !== publicKeyPem) {
+            return { valid: false, reason: 'Signer key does not match the rater' };
+        }
+        // 不论是否开启消息签名，评分节点ID都必须由签名公钥派生，否则换一把钥匙就能冒充任意节点重复评分
+        if (nodeIdFromPublicKey(publicKeyPem) !== raterNodeId) {
+            return { valid: false, reason: 'Signer key does not match the rater' };
+        }
+        return { valid: true };
+    }
+
     // 发布任务
     async publishTask(task) {
         if (!this.initialized) {
             throw new Error('Mesh not initialized');
         }
+        this.assertNotDraining();
         
         task.publisher = task.publisher || this.options.nodeId;
-        task.published_at = new Date().toISOString();
+        task.published_at = Date.now();
         task.taskId = this.computeTaskId(task);
         task.escrowAccountId = this.getEscrowAccountId(task.taskId);
+        // 过期无人完成时托管的去向：fallbackAccountId（如公益或销毁地址），未设置则退回发布者账户
+        task.publisherAccountId = this.wallet.accountId;
+        if (task.fallbackAccountId !== undefined && task.fallbackAccountId !== null && task.fallbackAccountId !== '') {
+            if (typeof task.fallbackAccountId !== 'string' || task.fallbackAccountId.startsWith('escrow_')) {
+                throw new Error('Invalid fallback account');
+            }
+        } else {
+            delete task.fallbackAccountId;
+        }
+        // 锁定托管之前先检查选取方式
+        if (task.selectionMode !== undefined && task.selectionMode !== null && !TaskBazaar.SELECTION_MODES.includes(task.selectionMode)) {
+            throw new Error(`Invalid task: selectionMode must be one of ${TaskBazaar.SELECTION_MODES.join(', ')}`);
+        }
+
+        // 孤立节点不锁定托管：连接的peer不足时直接拒绝发布
+        const minPeers = Number(this.options.minTaskPeers) || 0;
+        if (minPeers > 0 && this.node.getLivePeerCount() < minPeers) {
+            const err = new Error(`Not enough peers to publish task (have ${this.node.getLivePeerCount()}, need ${minPeers})`);
+            err.code = 'INSUFFICIENT_PEERS';
+            throw err;
+        }
 
         const txReceipts = [];
         if (this.options.taskPublishFee > 0) {
@@ -669,12 +1665,7 @@ class OpenClawMesh {
         }
 
         const bountyAmount = Number(task.bounty?.amount || 0);
-        if (bountyAmount > 0) {
-            const available = this.ledger.getBalance(this.wallet.accountId);
-            if (available < bountyAmount) {
-                throw new Error('Insufficient balance to lock escrow');
-            }
-            const escrowTx = this.createSignedTransfer(task.escrowAccountId, bountyAmount);
+        const lockEscrow = async (escrowTx) => {
             const escrowResult = this.submitTx(escrowTx);
             if (this.options.isGenesisNode && !escrowResult.accepted) {
                 throw new Error(escrowResult.reason || 'Failed to lock escrow');
@@ -682,14 +1673,225 @@ class OpenClawMesh {
             const cfg = this.getConfirmConfig('taskEscrow');
             const escrowConfirm = await this.waitForTxConfirmations(escrowTx.txId, cfg.target, cfg.timeoutMs);
             txReceipts.push({ type: 'task_escrow_lock', txId: escrowTx.txId, ...escrowConfirm });
+        };
+        // 非主节点无法自己签发托管退款：要求送达peer时，先确认任务广播出去了再提交锁定交易，
+        // 广播无人接收时锁定交易从未提交，不会留下退不回来的托管
+        const deferLock = minPeers > 0 && !this.options.isGenesisNode;
+        let escrowTx = null;
+        if (bountyAmount > 0) {
+            const available = this.ledger.getBalance(this.wallet.accountId);
+            if (available < bountyAmount) {
+                throw new Error('Insufficient balance to lock escrow');
+            }
+            escrowTx = this.createSignedTransfer(task.escrowAccountId, bountyAmount);
+            if (!deferLock) {
+                await lockEscrow(escrowTx);
+            }
+            task.escrowProof = this.createEscrowProof(task, escrowTx.txId, bountyAmount);
         }
 
+        // traceId 随任务传播，后续竞标/分配/完成消息沿用
+        task.traceId = task.traceId || crypto.randomUUID();
         const taskId = await this.taskBazaar.publishTask(task);
-        await this.node.broadcastTask(task);
+        const reached = await this.node.broadcastTask(task);
+        if (minPeers > 0 && reached === 0) {
+            await this.rollbackUnreachableTask(task, deferLock ? 0 : bountyAmount, txReceipts);
+        }
+        if (escrowTx && deferLock) {
+            await lockEscrow(escrowTx);
+        }
         console.log(`🎯 Task published: ${taskId}`);
         return { taskId, txReceipts };
     }
 
+    // 发布者取消任务（仅限 open/voting）：广播签名的取消通知，托管退回发布者
+    cancelTask(taskId, { reason } = {}) {
+        const result = this.taskBazaar.cancelTask(taskId, this.options.nodeId, { reason });
+        if (!result.success) {
+            throw new Error(result.reason);
+        }
+        const task = result.task;
+        const statement = {
+            taskId: task.taskId,
+            publisher: task.publisher,
+            cancelledAt: task.cancelledAt,
+            reason: task.cancelReason
+        };
+        this.node.broadcast({
+            type: 'task_cancelled',
+            payload: {
+                statement,
+                publicKeyPem: this.wallet.publicKeyPem,
+                signature: signPayload(this.wallet.privateKeyPem, statement)
+            },
+            timestamp: Date.now()
+        });
+        console.log(`🚫 Task cancelled: ${task.taskId}`);
+        return { taskId: task.taskId, status: task.status, refund: this.refundCancelledTask(task) };
+    }
+
+    // 发布者评选获胜者：广播签名的评选结果，之后才释放托管
+    awardTask(taskId, solverId) {
+        const result = this.taskBazaar.awardTask(taskId, this.options.nodeId, solverId);
+        if (!result.success) {
+            throw new Error(result.reason);
+        }
+        const task = result.task;
+        const statement = {
+            taskId: task.taskId,
+            publisher: task.publisher,
+            winner: task.winner,
+            accountId: task.escrowDisposition.to,
+            awardedAt: task.awardedAt
+        };
+        this.node.broadcast({
+            type: 'task_awarded',
+            payload: {
+                statement,
+                publicKeyPem: this.wallet.publicKeyPem,
+                signature: signPayload(this.wallet.privateKeyPem, statement)
+            },
+            timestamp: Date.now()
+        });
+        return { taskId: task.taskId, status: task.status, winner: task.winner, payout: this.payoutAwardedTask(task) };
+    }
+
+    // 网络上收到的解：先验证解题节点的签名，再与本地提交一样校验解的内容
+    async applyTaskSubmission(notice) {
+        const check = this.taskBazaar.verifyTaskSubmission(notice);
+        if (!check.valid) {
+            return { success: false, reason: check.reason };
+        }
+        const { taskId, solverId, accountId, solution, submittedAt } = notice.statement;
+        if (!solution || !(await this.taskBazaar.validateSolution(this.taskBazaar.getTask(taskId), solution))) {
+            return { success: false, reason: 'Invalid solution' };
+        }
+        return this.taskBazaar.addSubmission(taskId, { solverId, accountId, solution, submittedAt });
+    }
+
+    applyTaskAward(notice) {
+        const check = this.taskBazaar.verifyTaskAward(notice);
+        if (!check.valid) {
+            return { success: false, reason: check.reason };
+        }
+        const { taskId, publisher, winner, accountId, awardedAt } = notice.statement;
+        if (!accountId) {
+            return { success: false, reason: 'Award is missing the winner account' };
+        }
+        const result = this.taskBazaar.awardTask(taskId, publisher, winner, { accountId, awardedAt });
+        if (!result.success) {
+            return result;
+        }
+        return { success: true, payout: this.payoutAwardedTask(result.task) };
+    }
+
+    // 本地托管记录直接付给获胜者；账本上的托管账户由主节点签名释放到获胜者账户
+    payoutAwardedTask(task) {
+        const local = this.memoryStore.releaseEscrow(task.taskId, task.winner, { awardedBy: task.publisher });
+        const result = this.options.isGenesisNode ? this.settleTaskEscrow(task) : null;
+        return {
+            released: local.released,
+            ledger: result ? { accepted: Boolean(result.accepted), ...this.taskBazaar.getTask(task.taskId)?.escrowDisposition } : null
+        };
+    }
+
+    applyTaskCancellation(notice) {
+        const check = this.taskBazaar.verifyTaskCancellation(notice);
+        if (!check.valid) {
+            return { success: false, reason: check.reason };
+        }
+        const { taskId, publisher, cancelledAt, reason } = notice.statement;
+        const result = this.taskBazaar.cancelTask(taskId, publisher, { cancelledAt, reason });
+        if (!result.success) {
+            return result;
+        }
+        this.taskWorker?.biddingTasks?.delete(taskId);
+        return { success: true, refund: this.refundCancelledTask(result.task) };
+    }
+
+    // 本地托管记录直接退回；账本上的托管账户由主节点签名释放回发布者账户
+    refundCancelledTask(task) {
+        const local = this.memoryStore.refundEscrow(task.taskId, { cancelledBy: task.cancelledBy });
+        const result = this.options.isGenesisNode ? this.settleTaskEscrow(task) : null;
+        return {
+            refunded: local.refunded,
+            ledger: result ? { accepted: Boolean(result.accepted), ...this.taskBazaar.getTask(task.taskId)?.escrowDisposition } : null
+        };
+    }
+
+    // 广播没有送达任何peer：取消任务；主节点已锁定的托管立即退回，非主节点此时尚未提交锁定交易
+    async rollbackUnreachableTask(task, bountyAmount, txReceipts) {
+        let refunded = false;
+        if (bountyAmount > 0 && this.options.isGenesisNode) {
+            const refundTx = this.createSignedEscrowRelease(task.escrowAccountId, this.wallet.accountId, bountyAmount, {
+                memo: `task:${task.taskId}:refund`
+            });
+            const refundResult = this.submitTx(refundTx);
+            refunded = Boolean(refundResult?.accepted);
+            txReceipts.push({ type: 'task_escrow_refund', txId: refundTx.txId, accepted: refunded });
+        }
+        this.taskBazaar.updateTask(task.taskId, {
+            status: 'cancelled',
+            cancelledAt: Date.now(),
+            cancelReason: 'broadcast reached no peers'
+        });
+        const err = new Error(`Task broadcast reached no peers; task cancelled${refunded ? ' and escrow refunded' : ''}`);
+        err.code = 'UNREACHABLE';
+        err.taskId = task.taskId;
+        err.txReceipts = txReceipts;
+        throw err;
+    }
+
+    // 批量导入任务：逐个发布并锁定托管，按 externalId 去重
+    async importTasks(specs) {
+        if (!Array.isArray(specs)) {
+            throw new Error('Invalid import: expected an array of tasks');
+        }
+        // 串行化并发导入，避免同一 externalId 被重复发布
+        const run = this.taskImportLock.then(() => this.runTaskImport(specs));
+        this.taskImportLock = run.catch(() => {});
+        return run;
+    }
+
+    async runTaskImport(specs) {
+        const seen = new Set();
+        for (const task of this.taskBazaar.getTasks()) {
+            if (task.externalId) seen.add(String(task.externalId));
+        }
+        const results = [];
+        let totalEscrowed = 0;
+        for (let index = 0; index < specs.length; index++) {
+            const spec = specs[index] || {};
+            const externalId = spec.externalId != null ? String(spec.externalId) : null;
+            const amount = Number(typeof spec.bounty === 'object' ? spec.bounty?.amount : spec.bounty);
+            if (externalId && seen.has(externalId)) {
+                results.push({ index, externalId, status: 'skipped', reason: 'duplicate externalId' });
+                continue;
+            }
+            if (!spec.description || !Number.isFinite(amount) || amount <= 0) {
+                results.push({ index, externalId, status: 'failed', reason: 'missing description or bounty' });
+                continue;
+            }
+            try {
+                const { taskId, txReceipts } = await this.publishTask({
+                    description: spec.description,
+                    type: spec.type,
+                    tags: Array.isArray(spec.tags) ? spec.tags : [],
+                    bounty: { amount, token: 'CLAW' },
+                    ...(externalId ? { externalId } : {})
+                });
+                if (externalId) seen.add(externalId);
+                totalEscrowed += amount;
+                results.push({ index, externalId, status: 'published', taskId, txReceipts });
+            } catch (e) {
+                results.push({ index, externalId, status: 'failed', reason: e.message });
+            }
+        }
+        const published = results.filter(r => r.status === 'published').length;
+        console.log(`📥 Imported ${published}/${specs.length} tasks, escrowed ${totalEscrowed}`);
+        return { results, published, totalEscrowed };
+    }
+
     async purchaseCapsule(assetId, buyerNodeId = null) {
         if (!this.initialized) {
             throw new Error('Mesh not initialized');
@@ -699,6 +1901,9 @@ class OpenClawMesh {
         if (!capsule) {
             throw new Error('Capsule not found');
         }
+        if (!this.memoryStore.canRead(capsule, buyer)) {
+            throw new Error('Capsule is restricted by its ACL');
+        }
         const price = capsule.price?.amount || 0;
         if (price > 0 && buyer !== capsule.attribution?.creator) {
             const share = typeof capsule.price?.creatorShare === 'number' ? capsule.price.creatorShare : this.options.capsuleCreatorShare;
@@ -740,7 +1945,27 @@ class OpenClawMesh {
     
     // 提交任务解决方案
     async submitSolution(taskId, solution) {
-        const result = await this.taskBazaar.submitSolution(taskId, solution, this.options.nodeId);
+        const task = this.taskBazaar.getTask(taskId);
+        // 评选模式的解由本节点钱包签名，其他节点据此把 solverId/accountId 绑定到签名公钥
+        const reviewMode = task && this.taskBazaar.getSelectionMode(task) === 'review';
+        if (reviewMode && (!this.wallet || nodeIdFromPublicKey(this.wallet.publicKeyPem) !== this.options.nodeId)) {
+            throw new Error('Review submissions require a nodeId derived from the wallet key');
+        }
+        const result = await this.taskBazaar.submitSolution(taskId, solution, this.options.nodeId, { accountId: this.wallet?.accountId });
+        // 评选模式：把签名的解广播出去，由发布者节点收集
+        if (result?.underReview) {
+            const statement = { taskId, ...result.submission };
+            this.node.broadcast({
+                type: 'task_submission',
+                payload: {
+                    statement,
+                    publicKeyPem: this.wallet.publicKeyPem,
+                    signature: signPayload(this.wallet.privateKeyPem, statement)
+                },
+                timestamp: Date.now()
+            });
+            return result;
+        }
         if (result?.winner && this.options.isGenesisNode) {
             const task = this.taskBazaar.getTask(taskId);
             const escrowId = task?.escrowAccountId;
@@ -753,26 +1978,217 @@ class OpenClawMesh {
         return result;
     }
     
+    // 本节点账本中的签名余额声明；账户不在本地账本中时返回 null
+    createBalanceStatement(accountId, token = LedgerStore.DEFAULT_TOKEN) {
+        if (!accountId || !this.ledger || !this.wallet) return null;
+        const symbol = token || LedgerStore.DEFAULT_TOKEN;
+        const known = symbol === LedgerStore.DEFAULT_TOKEN
+            ? !!this.ledger.getAccount(accountId)
+            : this.ledger.getBalance(accountId, symbol) > 0;
+        if (!known) return null;
+        const statement = {
+            accountId,
+            token: symbol,
+            balance: this.ledger.getBalance(accountId, symbol),
+            nonce: this.ledger.getNonce(accountId),
+            lastSeq: this.ledger.getLastSeq(),
+            headHash: this.ledger.getHeadHash(),
+            nodeId: this.options.nodeId,
+            issuedAt: Date.now()
+        };
+        return {
+            ...statement,
+            publicKeyPem: this.wallet.publicKeyPem,
+            signature: signPayload(this.wallet.privateKeyPem, statement)
+        };
+    }
+
+    // 校验余额声明：签名、签名者与握手公钥一致，且本地账本处于同一高度时头hash与余额一致；
+    // 高度不同时无法比对，只接受主节点（账本 master 公钥）签名的声明
+    verifyBalanceStatement(statement) {
+        if (!statement?.publicKeyPem || !statement?.signature) {
+            return { valid: false, reason: 'Missing signature' };
+        }
+        const { accountId, token, balance, nonce, lastSeq, headHash, nodeId, issuedAt } = statement;
+        const payload = { accountId, token, balance, nonce, lastSeq, headHash, nodeId, issuedAt };
+        let signatureOk = false;
+        try {
+            signatureOk = verifyPayload(statement.publicKeyPem, payload, statement.signature);
+        } catch (e) {
+            signatureOk = false;
+        }
+        if (!signatureOk) {
+            return { valid: false, reason: 'Invalid signature' };
+        }
+        const auth = this.node?.peerAuth?.get(nodeId);
+        if (auth?.verified && auth.publicKeyPem !== statement.publicKeyPem) {
+            return { valid: false, reason: 'Signer key does not match the peer handshake' };
+        }
+        const localSeq = this.ledger ? this.ledger.getLastSeq() : 0;
+        if (localSeq !== Number(lastSeq)) {
+            if (this.ledger?.getMeta('master_pubkey') === statement.publicKeyPem) {
+                return { valid: true, ledger: 'master' };
+            }
+            // 任何节点都能声明更高的 lastSeq，高度不同且不是主节点签名的声明不可信
+            return {
+                valid: false,
+                ledger: localSeq < Number(lastSeq) ? 'ahead' : 'stale',
+                reason: `Ledger height ${lastSeq} differs from local ${localSeq}`
+            };
+        }
+        if (this.ledger.getHeadHash() !== headHash) {
+            return { valid: false, reason: 'Ledger head mismatch' };
+        }
+        if (this.ledger.getBalance(accountId, token) !== Number(balance)) {
+            return { valid: false, reason: 'Balance does not match local ledger entries' };
+        }
+        return { valid: true, ledger: 'match' };
+    }
+
+    // 余额查询：本地账本有该账户且未要求远程时直接返回，否则路由到持有账本的peer
+    async getAccountBalance(accountId, options = {}) {
+        const token = options.token || LedgerStore.DEFAULT_TOKEN;
+        if (!options.remote) {
+            const local = this.createBalanceStatement(accountId, token);
+            if (local) {
+                return { accountId, token, balance: local.balance, nonce: local.nonce, source: 'local' };
+            }
+        }
+        let verification = null;
+        let rejected = null;
+        const statement = await this.node.requestBalance(accountId, {
+            token,
+            accept: (candidate) => {
+                verification = this.verifyBalanceStatement(candidate);
+                if (!verification.valid) rejected = verification;
+                return verification.valid;
+            }
+        });
+        if (!statement && rejected) {
+            const err = new Error(`No verifiable balance statement: ${rejected.reason}`);
+            err.code = 'BALANCE_UNVERIFIED';
+            throw err;
+        }
+        if (!statement) {
+            const err = new Error('Account not found on any reachable node');
+            err.code = 'ACCOUNT_NOT_FOUND';
+            throw err;
+        }
+        return {
+            accountId,
+            token: statement.token,
+            balance: statement.balance,
+            nonce: statement.nonce,
+            source: 'remote',
+            statement,
+            verification
+        };
+    }
+
+    isDraining() {
+        return this.draining;
+    }
+
+    assertNotDraining() {
+        if (this.draining) {
+            const err = new Error('Node is draining');
+            err.code = 'DRAINING';
+            throw err;
+        }
+    }
+
+    // 排空中仍需等待的工作：正在执行的任务和尚未确认的托管释放
+    getInFlightWork() {
+        const activeTasks = this.taskWorker ? Array.from(this.taskWorker.activeTasks.keys()) : [];
+        // 没有 peer 时托管释放无法被确认，不再等待
+        const canConfirm = !this.options.isGenesisNode && (this.node?.getPeers().length || 0) > 0;
+        const pendingReleases = canConfirm
+            ? Array.from(this.pendingTxs.values()).filter(item => item.tx?.type === 'escrow_release').map(item => item.tx.txId)
+            : [];
+        return { activeTasks, pendingReleases };
+    }
+
+    // 停止接受新工作，等待进行中的工作在超时内完成；超时后触发取消
+    async drain(timeoutMs = this.options.drainTimeoutMs) {
+        this.draining = true;
+        if (this.taskWorker) {
+            this.taskWorker.stopAutoBidding();
+        }
+        const deadline = Date.now() + Math.max(0, Number(timeoutMs) || 0);
+        let work = this.getInFlightWork();
+        if (work.activeTasks.length > 0 || work.pendingReleases.length > 0) {
+            console.log(`⏳ Draining: ${work.activeTasks.length} active task(s), ${work.pendingReleases.length} pending escrow release(s)`);
+        }
+        while ((work.activeTasks.length > 0 || work.pendingReleases.length > 0) && Date.now() < deadline) {
+            await new Promise(resolve => setTimeout(resolve, Math.min(100, Math.max(1, deadline - Date.now()))));
+            work = this.getInFlightWork();
+        }
+        const drained = work.activeTasks.length === 0 && work.pendingReleases.length === 0;
+        if (!drained) {
+            console.warn(`⚠️  Drain timed out after ${timeoutMs}ms, cancelling ${work.activeTasks.length} task(s)`);
+            this.drainController.abort();
+        }
+        return { drained, ...work };
+    }
+
     // 获取网络统计
     getStats() {
         return {
             nodeId: this.options.nodeId,
             peers: this.node.getPeers(),
+            connections: this.node.getConnectionStats(),
             memoryCount: this.memoryStore.getCount(),
             taskCount: this.taskBazaar.getTaskCount(),
+            roles: this.options.roles,
+            sync: this.getSyncStatus(),
             uptime: process.uptime()
         };
     }
     
+    // 版本与兼容性信息，供滚动升级前检查集群是否一致
+    getVersionInfo() {
+        const capabilities = ['gossip', 'ledger-sync', 'multi-token', 'query-pagination', 'capsule-provenance', 'escrow-proof', 'length-framing'];
+        if (this.options.requireSignedHandshake) capabilities.push('signed-handshake');
+        if (this.options.signMessages) capabilities.push('signed-messages');
+        if (this.options.topics?.length > 0) capabilities.push('topics');
+        if (this.options.webTls) capabilities.push('tls');
+        if (this.options.capsuleWebhook?.url) capabilities.push('capsule-webhook');
+        if (this.options.benchMode) capabilities.push('bench');
+        return {
+            version: PACKAGE_VERSION,
+            build: readBuildHash(),
+            protocolVersion: MeshNode.PROTOCOL_VERSION,
+            capabilities,
+            messageTypes: this.node
+                ? Array.from(this.node.messageHandlers.keys()).filter(type => !this.node.isMessageTypeDisabled(type)).sort()
+                : []
+        };
+    }
+
     // 同步网络记忆
     async syncMemories(filter = {}) {
         console.log('🔄 Syncing memories from network...');
-        const memories = await this.node.queryMemories(filter);
+        let memories = await this.node.queryMemories(filter);
+        if (filter.minConfidence) {
+            // 远端结果同样按本节点的衰减规则过滤
+            const now = Date.now();
+            memories = memories.filter(c => this.memoryStore.getEffectiveConfidence(c, now) >= filter.minConfidence);
+        }
+        // 严格来源链模式下单个胶囊校验失败只计数，不中断整批同步
+        let stored = 0;
+        let rejected = 0;
         for (const capsule of memories) {
-            await this.memoryStore.storeCapsule(capsule);
+            try {
+                await this.memoryStore.storeCapsule(capsule);
+                stored++;
+            } catch (e) {
+                rejected++;
+                console.warn(`⚠️  Rejected synced capsule ${capsule?.asset_id}: ${e.message}`);
+            }
         }
-        console.log(`✅ Synced ${memories.length} memories`);
-        return memories.length;
+        this.lastMemorySync = { stored, rejected, at: Date.now() };
+        console.log(`✅ Synced ${stored} memories${rejected > 0 ? ` (${rejected} rejected)` : ''}`);
+        return stored;
     }
     
     computeAssetId(capsule) {
@@ -791,14 +2207,28 @@ class OpenClawMesh {
     async stop() {
         console.log('👋 Stopping OpenClaw Mesh...');
         
+        if (!this.draining) {
+            await this.drain();
+        }
+        if (this.taskWorker) {
+            this.taskWorker.stopAutoBidding();
+        }
+
+        if (this.capsuleWebhook) {
+            this.capsuleWebhook.stop();
+        }
+        
         if (this.webUI) {
             await this.webUI.stop();
         }
         
+        let peersClosed = 0;
         if (this.node) {
-            await this.node.stop();
+            peersClosed = (await this.node.stop())?.peersClosed || 0;
         }
         
+        // 关闭时把胶囊、账户、账本和托管全部写盘；账本高度取 SQLite 账本的链尾 seq
+        const ledgerHeight = this.ledger?.db ? this.ledger.getLastSeq() : 0;
         if (this.memoryStore) {
             await this.memoryStore.close();
         }
@@ -817,11 +2247,24 @@ class OpenClawMesh {
         if (this.ledgerSyncInterval) {
             clearInterval(this.ledgerSyncInterval);
         }
+        if (this.syncBarrierTimer) {
+            clearTimeout(this.syncBarrierTimer);
+        }
         if (this.pendingTxInterval) {
             clearInterval(this.pendingTxInterval);
         }
+        if (this.taskArchiveInterval) {
+            clearInterval(this.taskArchiveInterval);
+        }
+        if (this.taskExpiryInterval) {
+            clearInterval(this.taskExpiryInterval);
+        }
+        if (this.reannounceTimer) {
+            clearTimeout(this.reannounceTimer);
+        }
         
-        console.log('✅ OpenClaw Mesh stopped');
+        console.log(`✅ OpenClaw Mesh stopped (closed ${peersClosed} peer(s), ledger height ${ledgerHeight})`);
+        return { peersClosed, ledgerHeight };
     }
 }
 
@@ -1,3 +1,4 @@
+const fs = require('fs');
 const path = require('path');
 const crypto = require('crypto');
 const Database = require('better-sqlite3');
@@ -7,6 +8,16 @@ function sha256Hex(input) {
     return crypto.createHash('sha256').update(input).digest('hex');
 }
 
+const MAX_MEMO_LENGTH = 256;
+// 默认代币：余额记在 accounts_state，其他代币记在 token_balances
+const DEFAULT_TOKEN = 'CLAW';
+const TOKEN_SYMBOL_RE = /^[A-Z][A-Z0-9]{1,11}$/;
+
+function txToken(tx) {
+    return tx?.meta?.token || DEFAULT_TOKEN;
+}
+
+// meta 仅在存在时参与签名，兼容旧交易
 function canonicalPayload(tx) {
     return {
         type: tx.type,
@@ -14,23 +25,54 @@ function canonicalPayload(tx) {
         to: tx.to || null,
         amount: Number(tx.amount),
         nonce: Number(tx.nonce),
-        timestamp: Number(tx.timestamp)
+        timestamp: Number(tx.timestamp),
+        ...(tx.meta ? { meta: tx.meta } : {})
     };
 }
 
+// 去除控制字符、首尾空白，超长则拒绝
+function sanitizeMemo(memo) {
+    if (memo === undefined || memo === null) return null;
+    const clean = String(memo).replace(/[\u0000-\u001f\u007f]/g, ' ').trim();
+    if (!clean) return null;
+    if (clean.length > MAX_MEMO_LENGTH) {
+        throw new Error(`Memo too long (max ${MAX_MEMO_LENGTH} chars)`);
+    }
+    return clean;
+}
+
+function parseMeta(row) {
+    if (!row) return row;
+    if (typeof row.meta === 'string') {
+        try {
+            row.meta = JSON.parse(row.meta);
+        } catch (e) {
+            row.meta = null;
+        }
+    }
+    if (!row.meta) delete row.meta;
+    return row;
+}
+
 function nextHeadHash(prevHash, seq, txId) {
     const input = `${prevHash || ''}:${seq}:${txId}`;
     return sha256Hex(input);
 }
 
 class LedgerStore {
-    constructor(dataDir) {
+    constructor(dataDir, options = {}) {
         this.dataDir = dataDir;
         this.dbPath = path.join(dataDir, 'ledger.sqlite');
         this.db = null;
+        // 同步时发现的分叉：seq -> 报告（同一位置的条目或链hash与本地冲突，无法自动合并）
+        this.forks = new Map();
+        this.maxForkReports = 100;
+        // 收益排行榜缓存：链头hash变化时失效，否则按 leaderboardCacheMs 复用
+        this.leaderboardCacheMs = Number(options.leaderboardCacheMs ?? process.env.OPENCLAW_LEADERBOARD_CACHE_MS ?? 30000);
+        this.leaderboardCache = { head: null, entries: new Map() }; // `${windowMs}|${token}` -> { computedAt, since, items }
     }
 
-    init({ isGenesis = false, genesisAccountId = null, genesisSupply = 1000000, genesisPublicKeyPem = null, genesisPrivateKeyPem = null } = {}) {
+    init({ isGenesis = false, genesisAccountId = null, genesisSupply = 1000000, genesisTokens = [], genesisPublicKeyPem = null, genesisPrivateKeyPem = null } = {}) {
         this.db = new Database(this.dbPath);
         this.db.exec(`
             CREATE TABLE IF NOT EXISTS tx_log (
@@ -45,7 +87,8 @@ class LedgerStore {
                 signature TEXT,
                 timestamp INTEGER,
                 status TEXT,
-                reason TEXT
+                reason TEXT,
+                meta TEXT
             );
             CREATE TABLE IF NOT EXISTS accounts_state (
                 account_id TEXT PRIMARY KEY,
@@ -56,7 +99,32 @@ class LedgerStore {
                 key TEXT PRIMARY KEY,
                 value TEXT
             );
+            CREATE TABLE IF NOT EXISTS tokens (
+                symbol TEXT PRIMARY KEY,
+                minters TEXT,
+                supply REAL
+            );
+            CREATE TABLE IF NOT EXISTS token_balances (
+                account_id TEXT,
+                token TEXT,
+                balance REAL,
+                PRIMARY KEY (account_id, token)
+            );
+            CREATE TABLE IF NOT EXISTS inactive_accounts (
+                account_id TEXT PRIMARY KEY,
+                deactivated_at INTEGER
+            );
         `);
+        try {
+            // 旧库补充 meta 列
+            this.db.exec('ALTER TABLE tx_log ADD COLUMN meta TEXT');
+        } catch (e) {
+        }
+        try {
+            // 每条记录之后的链hash，同步时用来校验链接关系
+            this.db.exec('ALTER TABLE tx_log ADD COLUMN head_hash TEXT');
+        } catch (e) {
+        }
 
         if (isGenesis) {
             this.setMeta('master_pubkey', genesisPublicKeyPem);
@@ -73,13 +141,64 @@ class LedgerStore {
                     privateKeyPem: genesisPrivateKeyPem
                 });
                 this.appendAsMaster(mintTx);
+                // 创世时定义的其他代币：定义本身就是一条带 minters 的 mint 记录
+                let nonce = 1;
+                for (const token of genesisTokens || []) {
+                    const defineTx = this.createMintTx({
+                        to: token.holder || genesisAccountId,
+                        amount: token.supply,
+                        nonce: ++nonce,
+                        meta: {
+                            token: token.symbol,
+                            minters: Array.isArray(token.minters) ? token.minters : []
+                        },
+                        publicKeyPem: genesisPublicKeyPem,
+                        privateKeyPem: genesisPrivateKeyPem
+                    });
+                    const result = this.appendAsMaster(defineTx);
+                    if (!result.accepted) {
+                        throw new Error(`Invalid genesis token ${token.symbol}: ${result.reason}`);
+                    }
+                }
             }
         }
-        if (!this.getMeta('head_hash')) {
+        const unhashed = this.db.prepare('SELECT COUNT(1) as cnt FROM tx_log WHERE head_hash IS NULL').get();
+        if (!this.getMeta('head_hash') || (unhashed && unhashed.cnt > 0)) {
             this.rebuildHeadHash();
         }
     }
 
+    // 只读打开已有的账本库，不建表、不写创世交易、不重算链hash，供节点未运行时离线校验
+    openExisting() {
+        if (!fs.existsSync(this.dbPath)) return false;
+        this.db = new Database(this.dbPath, { readonly: true, fileMustExist: true });
+        return true;
+    }
+
+    // 按 seq 顺序校验 tx_log 的链hash：seq 从 1 连续，每行的 head_hash 由上一行的链hash、seq 和 tx_id 算出，
+    // 最后与 meta 中记录的链头一致。返回第一处断裂的 seq 和其之前已校验通过的链头
+    verifyChain() {
+        const rows = this.db.prepare('SELECT seq, tx_id as txId, head_hash as headHash FROM tx_log ORDER BY seq ASC').all();
+        let head = '';
+        for (let i = 0; i < rows.length; i += 1) {
+            const row = rows[i];
+            let reason = null;
+            if (Number(row.seq) !== i + 1) {
+                reason = 'seq mismatch';
+            } else if ((row.headHash || '') !== nextHeadHash(head, row.seq, row.txId)) {
+                reason = 'head hash mismatch';
+            }
+            if (reason) {
+                return { valid: false, length: rows.length, verified: i, brokenSeq: Number(row.seq), reason, headHash: head };
+            }
+            head = row.headHash;
+        }
+        if (this.getHeadHash() !== head) {
+            return { valid: false, length: rows.length, verified: rows.length, brokenSeq: null, reason: 'stored head hash mismatch', headHash: head };
+        }
+        return { valid: true, length: rows.length, verified: rows.length, brokenSeq: null, reason: null, headHash: head };
+    }
+
     close() {
         if (this.db) {
             this.db.close();
@@ -91,11 +210,28 @@ class LedgerStore {
         return this.db.prepare('SELECT account_id, balance, nonce FROM accounts_state WHERE account_id = ?').get(accountId) || null;
     }
 
-    getBalance(accountId) {
+    getBalance(accountId, token = DEFAULT_TOKEN) {
+        if (token && token !== DEFAULT_TOKEN) {
+            const row = this.db.prepare('SELECT balance FROM token_balances WHERE account_id = ? AND token = ?').get(accountId, token);
+            return row ? Number(row.balance) : 0;
+        }
         const row = this.getAccount(accountId);
         return row ? Number(row.balance) : 0;
     }
 
+    getToken(symbol) {
+        const row = this.db.prepare('SELECT symbol, minters, supply FROM tokens WHERE symbol = ?').get(symbol);
+        return row ? { symbol: row.symbol, minters: JSON.parse(row.minters || '[]'), supply: Number(row.supply) } : null;
+    }
+
+    getTokens() {
+        return this.db.prepare('SELECT symbol FROM tokens ORDER BY symbol ASC').all().map(row => this.getToken(row.symbol));
+    }
+
+    isAccountActive(accountId) {
+        return !this.db.prepare('SELECT 1 FROM inactive_accounts WHERE account_id = ?').get(accountId);
+    }
+
     getNonce(accountId) {
         const row = this.getAccount(accountId);
         return row ? Number(row.nonce) : 0;
@@ -108,12 +244,13 @@ class LedgerStore {
 
     getTxById(txId) {
         if (!txId) return null;
-        return this.db.prepare(`
+        const row = this.db.prepare(`
             SELECT seq, tx_id as txId, type, from_account as "from", to_account as "to",
-                   amount, nonce, timestamp, pubkey_pem as pubkeyPem, signature
+                   amount, nonce, timestamp, pubkey_pem as pubkeyPem, signature, meta
             FROM tx_log
             WHERE tx_id = ?
-        `).get(txId) || null;
+        `).get(txId);
+        return row ? parseMeta(row) : null;
     }
 
     getConfirmations(txId) {
@@ -126,12 +263,25 @@ class LedgerStore {
     getRecentTxs(limit = 20) {
         const rows = this.db.prepare(`
             SELECT seq, tx_id as txId, type, from_account as "from", to_account as "to",
-                   amount, nonce, timestamp
+                   amount, nonce, timestamp, meta
             FROM tx_log
             ORDER BY seq DESC
             LIMIT ?
         `).all(limit);
-        return rows || [];
+        return (rows || []).map(parseMeta);
+    }
+
+    // 按 seq 升序分页读取账本，total 为账本条目总数
+    getTxPage(offset = 0, limit = 50) {
+        const rows = this.db.prepare(`
+            SELECT seq, tx_id as txId, type, from_account as "from", to_account as "to",
+                   amount, nonce, timestamp, pubkey_pem as pubkeyPem, signature, meta, head_hash as headHash
+            FROM tx_log
+            ORDER BY seq ASC
+            LIMIT ? OFFSET ?
+        `).all(limit, offset);
+        const total = this.db.prepare('SELECT COUNT(*) as count FROM tx_log').get().count;
+        return { items: (rows || []).map(parseMeta), total: Number(total), offset, limit };
     }
 
     getMeta(key) {
@@ -154,27 +304,99 @@ class LedgerStore {
 
     rebuildHeadHash() {
         const rows = this.db.prepare('SELECT seq, tx_id as txId FROM tx_log ORDER BY seq ASC').all();
+        const setRowHash = this.db.prepare('UPDATE tx_log SET head_hash = ? WHERE seq = ?');
         let head = '';
         for (const row of rows) {
             head = nextHeadHash(head, row.seq, row.txId);
+            setRowHash.run(head, row.seq);
         }
         this.setMeta('head_hash', head);
         return head;
     }
 
+    // seq 处（含该条）的链hash，seq 为 0 时是空链
+    getHeadHashAt(seq) {
+        if (!(Number(seq) > 0)) return '';
+        const row = this.db.prepare('SELECT head_hash FROM tx_log WHERE seq = ?').get(Number(seq));
+        return row ? row.head_hash || '' : null;
+    }
+
+    recordFork(entry, local) {
+        const report = {
+            seq: Number(entry.seq),
+            localTxId: local?.txId || null,
+            remoteTxId: entry.txId || null,
+            localHeadHash: local?.headHash || null,
+            remoteHeadHash: entry.headHash || null,
+            detectedAt: Date.now()
+        };
+        this.forks.delete(report.seq);
+        this.forks.set(report.seq, report);
+        while (this.forks.size > this.maxForkReports) {
+            this.forks.delete(this.forks.keys().next().value);
+        }
+        return { applied: false, reason: 'Fork', fork: report };
+    }
+
+    getForks() {
+        return Array.from(this.forks.values()).sort((a, b) => a.seq - b.seq);
+    }
+
+    // 按 tx_log 中主节点签名的 escrow_release 交易统计各账户的任务收益（指定代币）与完成任务数；
+    // 退款和转入回退账户的释放（备注 task:<taskId>:refund|fallback）不算收益。windowMs 为 0 时统计全部账本
+    getLeaderboard({ windowMs = 0, limit = 20, token = DEFAULT_TOKEN, now = Date.now() } = {}) {
+        const head = this.getHeadHash();
+        if (this.leaderboardCache.head !== head) {
+            this.leaderboardCache = { head, entries: new Map() };
+        }
+        const key = `${windowMs}|${token}`;
+        let cached = this.leaderboardCache.entries.get(key);
+        if (!cached || now - cached.computedAt >= this.leaderboardCacheMs) {
+            const since = windowMs > 0 ? now - windowMs : 0;
+            const rows = this.db.prepare(`
+                SELECT to_account as "to", amount, timestamp, meta
+                FROM tx_log
+                WHERE type = 'escrow_release' AND timestamp >= ?
+                ORDER BY seq ASC
+            `).all(since).map(parseMeta);
+            const totals = new Map(); // accountId -> { earnings, tasksCompleted, lastEarnedAt }
+            for (const row of rows) {
+                if (!row.to || /:(refund|fallback)$/.test(row.meta?.memo || '')) continue;
+                const total = totals.get(row.to) || { accountId: row.to, earnings: 0, tasksCompleted: 0, lastEarnedAt: 0 };
+                if (txToken(row) === token) total.earnings += Number(row.amount) || 0;
+                total.tasksCompleted++;
+                total.lastEarnedAt = Math.max(total.lastEarnedAt, Number(row.timestamp) || 0);
+                totals.set(row.to, total);
+            }
+            const items = Array.from(totals.values())
+                .sort((a, b) => b.earnings - a.earnings || b.tasksCompleted - a.tasksCompleted || (a.accountId < b.accountId ? -1 : 1))
+                .map((row, i) => ({ rank: i + 1, ...row }));
+            cached = { computedAt: now, since, items };
+            this.leaderboardCache.entries.set(key, cached);
+        }
+        return {
+            windowMs,
+            since: cached.since,
+            token,
+            computedAt: cached.computedAt,
+            total: cached.items.length,
+            items: cached.items.slice(0, limit)
+        };
+    }
+
     getTxLogSince(seq, limit = 500) {
         const rows = this.db.prepare(`
             SELECT seq, tx_id as txId, type, from_account as "from", to_account as "to",
-                   amount, nonce, timestamp, pubkey_pem as pubkeyPem, signature
+                   amount, nonce, timestamp, pubkey_pem as pubkeyPem, signature, meta, head_hash as headHash
             FROM tx_log
             WHERE seq > ?
             ORDER BY seq ASC
             LIMIT ?
         `).all(seq, limit);
-        return rows || [];
+        return (rows || []).map(parseMeta);
     }
 
-    createTransferTx({ from, to, amount, nonce, publicKeyPem, signature }) {
+    createTransferTx({ from, to, amount, nonce, publicKeyPem, signature, meta = null }) {
         const tx = {
             type: 'transfer',
             from,
@@ -182,6 +404,7 @@ class LedgerStore {
             amount: Number(amount),
             nonce: Number(nonce),
             timestamp: Date.now(),
+            ...(meta ? { meta } : {}),
             pubkeyPem: publicKeyPem,
             signature
         };
@@ -204,7 +427,26 @@ class LedgerStore {
         return tx;
     }
 
-    createMintTx({ to, amount, nonce, publicKeyPem, privateKeyPem, signature }) {
+    // 主节点签名的停用交易：from 为被停用的账户，to 为余额转入的账户（不转移时为账户本身），amount 为转移的余额；
+    // 非默认代币的余额用带 meta.token 的停用交易逐个转走
+    createDeactivateTx({ accountId, sweepTo = null, amount = 0, token = null, nonce, publicKeyPem, privateKeyPem }) {
+        const tx = {
+            type: 'account_deactivate',
+            from: accountId,
+            to: sweepTo || accountId,
+            amount: Number(amount),
+            nonce: Number(nonce),
+            timestamp: Date.now(),
+            ...(token && token !== DEFAULT_TOKEN ? { meta: { token } } : {}),
+            pubkeyPem: publicKeyPem,
+            signature: null
+        };
+        tx.signature = require('./wallet').signPayload(privateKeyPem, canonicalPayload(tx));
+        tx.txId = sha256Hex(JSON.stringify({ ...canonicalPayload(tx), signature: tx.signature }));
+        return tx;
+    }
+
+    createMintTx({ to, amount, nonce, meta = null, publicKeyPem, privateKeyPem, signature }) {
         const tx = {
             type: 'mint',
             from: accountIdFromPublicKey(publicKeyPem),
@@ -212,6 +454,7 @@ class LedgerStore {
             amount: Number(amount),
             nonce: Number(nonce),
             timestamp: Date.now(),
+            ...(meta ? { meta } : {}),
             pubkeyPem: publicKeyPem,
             signature: signature || null
         };
@@ -227,7 +470,9 @@ class LedgerStore {
         if (!tx || !tx.type || !tx.pubkeyPem || !tx.signature) {
             return { ok: false, reason: 'Missing tx fields' };
         }
-        if (!Number.isFinite(tx.amount) || tx.amount <= 0) {
+        // 停用交易在不转移余额时 amount 为 0
+        const minAmount = tx.type === 'account_deactivate' ? 0 : Number.MIN_VALUE;
+        if (!Number.isFinite(tx.amount) || tx.amount < minAmount) {
             return { ok: false, reason: 'Invalid amount' };
         }
         const derived = accountIdFromPublicKey(tx.pubkeyPem);
@@ -245,9 +490,41 @@ class LedgerStore {
                 return { ok: false, reason: 'Invalid escrow account' };
             }
         }
+        if (tx.type === 'account_deactivate') {
+            const masterPub = this.getMeta('master_pubkey');
+            if (!masterPub || tx.pubkeyPem !== masterPub) {
+                return { ok: false, reason: 'Account deactivation not signed by master' };
+            }
+            if (!tx.from) {
+                return { ok: false, reason: 'Missing from' };
+            }
+        }
         if (!tx.to) {
             return { ok: false, reason: 'Missing to' };
         }
+        if (tx.meta !== undefined && tx.meta !== null) {
+            if (typeof tx.meta !== 'object' || Array.isArray(tx.meta)) {
+                return { ok: false, reason: 'Invalid meta' };
+            }
+            if (tx.meta.token !== undefined && !TOKEN_SYMBOL_RE.test(String(tx.meta.token))) {
+                return { ok: false, reason: 'Invalid token' };
+            }
+            if (tx.meta.memo !== undefined) {
+                let memo;
+                try {
+                    memo = sanitizeMemo(tx.meta.memo);
+                } catch (e) {
+                    return { ok: false, reason: e.message };
+                }
+                if (memo !== tx.meta.memo) {
+                    return { ok: false, reason: 'Invalid memo' };
+                }
+            }
+        }
+        if (tx.type === 'mint') {
+            const authority = this.checkMintAuthority(tx);
+            if (!authority.ok) return authority;
+        }
         const payload = canonicalPayload(tx);
         const ok = verifyPayload(tx.pubkeyPem, payload, tx.signature);
         if (!ok) {
@@ -256,49 +533,118 @@ class LedgerStore {
         return { ok: true };
     }
 
+    // 默认代币和代币定义只能由主节点铸造；已定义代币的增发只能由其 minters 签名
+    checkMintAuthority(tx) {
+        const masterPub = this.getMeta('master_pubkey');
+        const byMaster = !!masterPub && tx.pubkeyPem === masterPub;
+        const token = txToken(tx);
+        if (tx.meta?.minters !== undefined) {
+            if (!byMaster) return { ok: false, reason: 'Token definition not signed by master' };
+            if (token === DEFAULT_TOKEN || this.getToken(token)) return { ok: false, reason: 'Token already defined' };
+            if (!Array.isArray(tx.meta.minters) || tx.meta.minters.some(m => typeof m !== 'string' || !m)) {
+                return { ok: false, reason: 'Invalid minters' };
+            }
+            return { ok: true };
+        }
+        if (token === DEFAULT_TOKEN) {
+            return byMaster ? { ok: true } : { ok: false, reason: 'Mint not signed by master' };
+        }
+        const definition = this.getToken(token);
+        if (!definition) return { ok: false, reason: 'Unknown token' };
+        if (!definition.minters.includes(tx.from)) {
+            return { ok: false, reason: 'Not an authorized minter' };
+        }
+        return { ok: true };
+    }
+
+    // 停用的账户不能再转出、接收或被增发；主节点账户不能被停用
+    checkAccountStatus(tx) {
+        const masterPub = this.getMeta('master_pubkey');
+        if (tx.type === 'account_deactivate' && masterPub && tx.from === accountIdFromPublicKey(masterPub)) {
+            return { ok: false, reason: 'Genesis account cannot be deactivated' };
+        }
+        // 停用交易本身可以作用于已停用的账户：主节点借此转走其余代币的余额
+        if (tx.type !== 'account_deactivate' && !this.isAccountActive(tx.from)) {
+            return { ok: false, reason: 'Account is inactive' };
+        }
+        if (tx.to !== tx.from && !this.isAccountActive(tx.to)) {
+            return { ok: false, reason: 'Recipient account is inactive' };
+        }
+        return { ok: true };
+    }
+
     appendAsMaster(tx) {
         const verification = this.verifyTx(tx);
         if (!verification.ok) {
             return { accepted: false, reason: verification.reason };
         }
+        const status = this.checkAccountStatus(tx);
+        if (!status.ok) {
+            return { accepted: false, reason: status.reason };
+        }
         const fromAccount = this.getAccount(tx.from) || { balance: 0, nonce: 0 };
         const expectedNonce = fromAccount.nonce + 1;
         if (tx.nonce !== expectedNonce) {
             return { accepted: false, reason: 'Invalid nonce' };
         }
-        if ((tx.type === 'transfer' || tx.type === 'escrow_release') && fromAccount.balance < tx.amount) {
+        if ((tx.type === 'transfer' || tx.type === 'escrow_release' || tx.type === 'account_deactivate') && this.getBalance(tx.from, txToken(tx)) < tx.amount) {
             return { accepted: false, reason: 'Insufficient balance' };
         }
         const seq = this.getLastSeq() + 1;
+        const headHash = nextHeadHash(this.getMeta('head_hash'), seq, tx.txId);
         const insertTx = this.db.prepare(`
-            INSERT INTO tx_log (seq, tx_id, type, from_account, to_account, amount, nonce, pubkey_pem, signature, timestamp, status, reason)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO tx_log (seq, tx_id, type, from_account, to_account, amount, nonce, pubkey_pem, signature, timestamp, status, reason, meta, head_hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         `);
-        insertTx.run(
-            seq,
-            tx.txId,
-            tx.type,
-            tx.from,
-            tx.to,
-            tx.amount,
-            tx.nonce,
-            tx.pubkeyPem,
-            tx.signature,
-            tx.timestamp,
-            'accepted',
-            null
-        );
-        this.applyToState(tx);
-        this.setMeta('head_hash', nextHeadHash(this.getMeta('head_hash'), seq, tx.txId));
-        return { accepted: true, seq };
+        // 日志条目、双方余额和链头hash在同一个事务里写入，中途失败时全部回滚
+        this.db.transaction(() => {
+            insertTx.run(
+                seq,
+                tx.txId,
+                tx.type,
+                tx.from,
+                tx.to,
+                tx.amount,
+                tx.nonce,
+                tx.pubkeyPem,
+                tx.signature,
+                tx.timestamp,
+                'accepted',
+                null,
+                tx.meta ? JSON.stringify(tx.meta) : null,
+                headHash
+            );
+            this.applyToState(tx);
+            this.setMeta('head_hash', headHash);
+        })();
+        return { accepted: true, seq, headHash };
     }
 
     applyLogEntry(entry) {
         if (!entry || !entry.seq) {
             return { applied: false, reason: 'Missing seq' };
         }
-        const exists = this.db.prepare('SELECT 1 FROM tx_log WHERE seq = ? OR tx_id = ?').get(entry.seq, entry.txId);
-        if (exists) return { applied: false, reason: 'Duplicate' };
+        // 同一位置已有条目：相同则是重复，不同（或链hash不同）则是分叉
+        const atSeq = this.db.prepare('SELECT tx_id as txId, head_hash as headHash FROM tx_log WHERE seq = ?').get(entry.seq);
+        if (atSeq) {
+            if (atSeq.txId !== entry.txId || (entry.headHash && atSeq.headHash && entry.headHash !== atSeq.headHash)) {
+                return this.recordFork(entry, atSeq);
+            }
+            return { applied: false, reason: 'Duplicate' };
+        }
+        const elsewhere = this.getTxById(entry.txId);
+        if (elsewhere) {
+            return this.recordFork(entry, { txId: elsewhere.txId, headHash: this.getHeadHashAt(elsewhere.seq) });
+        }
+        // 只接在当前链尾之后，缺口由同步补齐
+        const lastSeq = this.getLastSeq();
+        if (Number(entry.seq) !== lastSeq + 1) {
+            return { applied: false, reason: 'Gap', expectedSeq: lastSeq + 1 };
+        }
+        const headHash = nextHeadHash(this.getHeadHash(), entry.seq, entry.txId);
+        if (entry.headHash && entry.headHash !== headHash) {
+            return this.recordFork(entry, { txId: null, headHash: this.getHeadHash() });
+        }
         const tx = {
             type: entry.type,
             from: entry.from,
@@ -308,7 +654,8 @@ class LedgerStore {
             timestamp: entry.timestamp,
             pubkeyPem: entry.pubkeyPem,
             signature: entry.signature,
-            txId: entry.txId
+            txId: entry.txId,
+            ...(entry.meta ? { meta: entry.meta } : {})
         };
         if (!this.getMeta('master_pubkey') && entry.type === 'mint' && entry.pubkeyPem) {
             this.setMeta('master_pubkey', entry.pubkeyPem);
@@ -318,25 +665,29 @@ class LedgerStore {
             return { applied: false, reason: verification.reason };
         }
         const insertTx = this.db.prepare(`
-            INSERT INTO tx_log (seq, tx_id, type, from_account, to_account, amount, nonce, pubkey_pem, signature, timestamp, status, reason)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO tx_log (seq, tx_id, type, from_account, to_account, amount, nonce, pubkey_pem, signature, timestamp, status, reason, meta, head_hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         `);
-        insertTx.run(
-            entry.seq,
-            entry.txId,
-            entry.type,
-            entry.from,
-            entry.to,
-            entry.amount,
-            entry.nonce,
-            entry.pubkeyPem,
-            entry.signature,
-            entry.timestamp,
-            'accepted',
-            null
-        );
-        this.applyToState(tx);
-        this.setMeta('head_hash', nextHeadHash(this.getMeta('head_hash'), entry.seq, entry.txId));
+        this.db.transaction(() => {
+            insertTx.run(
+                entry.seq,
+                entry.txId,
+                entry.type,
+                entry.from,
+                entry.to,
+                entry.amount,
+                entry.nonce,
+                entry.pubkeyPem,
+                entry.signature,
+                entry.timestamp,
+                'accepted',
+                null,
+                tx.meta ? JSON.stringify(tx.meta) : null,
+                headHash
+            );
+            this.applyToState(tx);
+            this.setMeta('head_hash', headHash);
+        })();
         return { applied: true };
     }
 
@@ -350,7 +701,15 @@ class LedgerStore {
         `);
         const fromState = this.getAccount(tx.from) || { balance: 0, nonce: 0 };
         const toState = this.getAccount(tx.to) || { balance: 0, nonce: 0 };
-        if (tx.type === 'transfer' || tx.type === 'escrow_release') {
+        const token = txToken(tx);
+        if (tx.type === 'account_deactivate') {
+            this.db.prepare('INSERT OR IGNORE INTO inactive_accounts (account_id, deactivated_at) VALUES (?, ?)').run(tx.from, Number(tx.timestamp));
+        }
+        if (token !== DEFAULT_TOKEN) {
+            this.applyTokenToState(tx, token, fromState, upsert);
+            return;
+        }
+        if (tx.type === 'transfer' || tx.type === 'escrow_release' || tx.type === 'account_deactivate') {
             if (tx.from === tx.to) {
                 upsert.run(tx.from, Number(fromState.balance), Number(tx.nonce));
                 return;
@@ -366,6 +725,32 @@ class LedgerStore {
             upsert.run(tx.to, Number(toState.balance) + Number(tx.amount), Number(toState.nonce));
         }
     }
+
+    // 非默认代币：nonce 仍记在 accounts_state，余额记在 token_balances
+    applyTokenToState(tx, token, fromState, upsert) {
+        const setBalance = this.db.prepare(`
+            INSERT INTO token_balances (account_id, token, balance)
+            VALUES (?, ?, ?)
+            ON CONFLICT(account_id, token) DO UPDATE SET balance = excluded.balance
+        `);
+        const amount = Number(tx.amount);
+        upsert.run(tx.from, Number(fromState.balance), Number(tx.nonce));
+        if (tx.type === 'mint') {
+            if (Array.isArray(tx.meta.minters)) {
+                this.db.prepare('INSERT INTO tokens (symbol, minters, supply) VALUES (?, ?, 0)').run(token, JSON.stringify(tx.meta.minters));
+            }
+            this.db.prepare('UPDATE tokens SET supply = supply + ? WHERE symbol = ?').run(amount, token);
+            setBalance.run(tx.to, token, this.getBalance(tx.to, token) + amount);
+            return;
+        }
+        if (tx.from === tx.to) return;
+        setBalance.run(tx.from, token, this.getBalance(tx.from, token) - amount);
+        setBalance.run(tx.to, token, this.getBalance(tx.to, token) + amount);
+    }
 }
 
+LedgerStore.sanitizeMemo = sanitizeMemo;
+LedgerStore.MAX_MEMO_LENGTH = MAX_MEMO_LENGTH;
+LedgerStore.DEFAULT_TOKEN = DEFAULT_TOKEN;
+
 module.exports = LedgerStore;
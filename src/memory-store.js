@@ -6,11 +6,77 @@
 const fs = require('fs');
 const path = require('path');
 const crypto = require('crypto');
+const { signPayload, verifyPayload, accountIdFromPublicKey } = require('./wallet');
+
+// 标签倒排表：按胶囊插入序号追加写入 Uint32Array（每条 4 字节），容量翻倍增长，写入摊还 O(1)，从不重写整个列表。
+// 移除只减计数，失效条目留在原处由查询跳过，失效条目超过一半时才压缩一次
+class PostingList {
+    constructor() {
+        this.seqs = new Uint32Array(4);
+        this.length = 0;
+        this.size = 0;
+        this.sorted = true;
+    }
+
+    append(seq) {
+        if (this.length === this.seqs.length) {
+            const grown = new Uint32Array(this.seqs.length * 2);
+            grown.set(this.seqs);
+            this.seqs = grown;
+        }
+        if (this.length > 0 && seq < this.seqs[this.length - 1]) this.sorted = false;
+        this.seqs[this.length++] = seq;
+        this.size++;
+    }
+
+    remove() {
+        this.size--;
+    }
+
+    // 按插入序号升序的全部条目（含失效条目），已有胶囊新增标签时才需要重新排序
+    entries() {
+        const view = this.seqs.subarray(0, this.length);
+        if (!this.sorted) {
+            view.sort();
+            this.sorted = true;
+        }
+        return view;
+    }
+
+    needsCompaction() {
+        return this.length - this.size > this.length / 2;
+    }
+
+    compact(isLive) {
+        const kept = new Uint32Array(Math.max(4, this.size));
+        let length = 0;
+        for (const seq of this.entries()) {
+            if ((length === 0 || kept[length - 1] !== seq) && isLive(seq)) kept[length++] = seq;
+        }
+        this.seqs = kept;
+        this.length = length;
+    }
+}
 
 class MemoryStore {
     constructor(dataDir = './data', options = {}) {
         this.dataDir = dataDir;
         this.capsules = new Map();
+        // 标签倒排索引：tag -> PostingList（插入序号），写入为 O(标签数)，不重写整个列表
+        this.tagIndex = new Map();
+        this.indexedTags = new Map(); // asset_id -> [tag]
+        this.indexSeq = new Map(); // asset_id -> 插入序号（与 capsules 的迭代顺序一致）
+        this.indexIds = []; // 插入序号 -> asset_id（已删除的胶囊为空）
+        this.nextIndexSeq = 0;
+        // 词项倒排索引：token -> Map(asset_id -> 词频)，用于相似度查询
+        this.tokenIndex = new Map();
+        this.indexedTokens = new Map(); // asset_id -> { tokens, norm }（norm 为词频向量的模）
+        // 'content-type'：按 content.content_type 选择索引内容；'naive'：索引内容中的所有字符串
+        this.indexMode = (options.indexMode ?? process.env.OPENCLAW_INDEX_MODE) === 'naive' ? 'naive' : 'content-type';
+        // 结构化（JSON）胶囊默认索引的字段路径，胶囊可用 content.index_fields 覆盖
+        this.indexFields = options.indexFields
+            || (process.env.OPENCLAW_INDEX_FIELDS ? process.env.OPENCLAW_INDEX_FIELDS.split(',').map(f => f.trim()).filter(Boolean) : null)
+            || ['summary', 'description', 'text', 'gene.summary', 'gene.solution', 'capsule.summary', 'capsule.code'];
         this.accounts = new Map();
         this.accountIndex = new Map();
         this.ledger = [];
@@ -19,7 +85,6 @@ class MemoryStore {
         this.isGenesisNode = Boolean(options.isGenesisNode);
         this.masterUrl = options.masterUrl || null;
         this.genesisOperatorAccountId = options.genesisOperatorAccountId || null;
-        this.onLedgerEntry = typeof options.onLedgerEntry === 'function' ? options.onLedgerEntry : null;
         const envDisable = process.env.OPENCLAW_DISABLE_LANCE === '1' || process.env.OPENCLAW_USE_LANCE === '0';
         this.useLance = options.useLance !== false && !envDisable;
         this.lancePath = path.join(this.dataDir, 'lancedb');
@@ -31,6 +96,32 @@ class MemoryStore {
         this.genesisNodeId = 'node_genesis';
         this.genesisSeed = 'genesis';
         this.genesisSupply = Number(process.env.OPENCLAW_GENESIS_SUPPLY) || 1000000;
+        // 胶囊写入批量落盘（0 = 每次写入立即落盘）；账户/账本/托管始终同步fsync
+        this.capsuleFlushIntervalMs = Number(options.capsuleFlushIntervalMs ?? process.env.OPENCLAW_CAPSULE_FLUSH_MS ?? 1000);
+        this.capsuleFlushTimer = null;
+        this.capsulesDirty = false;
+        this.capsulePreviewChars = Number(options.capsulePreviewChars ?? process.env.OPENCLAW_CAPSULE_PREVIEW_CHARS ?? 200);
+        // 置信度随时间衰减（半衰期，0 = 关闭）；只影响过滤时的有效置信度，不修改存储值
+        this.confidenceHalfLifeMs = Number(options.confidenceHalfLifeMs ?? process.env.OPENCLAW_CONFIDENCE_HALF_LIFE_MS ?? 0);
+        // 自动清理：低于置信度阈值或超过闲置窗口未被访问的胶囊（0 = 关闭该条件），promoted 胶囊保留
+        this.pruneMinConfidence = Number(options.pruneMinConfidence ?? process.env.OPENCLAW_PRUNE_MIN_CONFIDENCE ?? 0);
+        this.pruneIdleMs = Number(options.pruneIdleMs ?? process.env.OPENCLAW_PRUNE_IDLE_MS ?? 0);
+        this.pruneIntervalMs = Number(options.pruneIntervalMs ?? process.env.OPENCLAW_PRUNE_INTERVAL_MS ?? 60 * 60 * 1000);
+        this.pruneTimer = null;
+        this.pruneStats = { runs: 0, totalPruned: 0, lastRun: null };
+        // 胶囊数量上限（0 = 不限）：超出时按最近访问时间淘汰最久未用的胶囊，promoted 胶囊保留
+        this.maxCapsules = Number(options.maxCapsules ?? process.env.OPENCLAW_MAX_CAPSULES ?? 0);
+        this.evictedCapsules = 0;
+        this.lastAccess = new Map(); // asset_id -> 最近访问时间（仅内存，重启后从加载时刻算起）
+        this.accessBaseline = Date.now();
+        // 严格模式下拒绝缺少或校验失败的来源链
+        this.strictProvenance = Boolean(options.strictProvenance ?? process.env.OPENCLAW_STRICT_PROVENANCE === '1');
+        // 胶囊聚合统计缓存：写入时失效，超过缓存时长后重新计算
+        this.capsuleStatsTtlMs = Number(options.capsuleStatsTtlMs ?? process.env.OPENCLAW_CAPSULE_STATS_TTL_MS ?? 30000);
+        this.capsuleStatsCache = null;
+        // 胶囊评分：由上层注入（返回 asset_id -> 聚合评分的 Map），按权重计入查询排序
+        this.capsuleRatings = typeof options.capsuleRatings === 'function' ? options.capsuleRatings : null;
+        this.ratingWeight = Number(options.ratingWeight ?? process.env.OPENCLAW_CAPSULE_RATING_WEIGHT ?? 0.1);
         this.initialized = false;
     }
     
@@ -44,12 +135,19 @@ class MemoryStore {
         // 加载已有数据
         await this.loadFromDisk();
         await this.ensureDataIntegrity();
+        // 账户索引与账户记录不一致时（如两次写入之间崩溃）先重建索引，避免重复创建账户
+        if (!(await this.reconcileAccountIndex({ dryRun: true })).consistent) {
+            this.lastAccountIndexReconcile = await this.reconcileAccountIndex();
+            console.warn(`⚠️  Account index was inconsistent, rebuilt: ${JSON.stringify(this.lastAccountIndexReconcile.changes)}`);
+        }
         if (this.isGenesisNode) {
             this.ensureGenesisAccount();
         } else if (this.masterUrl) {
             await this.syncFromMaster(this.masterUrl);
         }
         
+        this.accessBaseline = Date.now();
+        this.startPruning();
         this.initialized = true;
         console.log(`💾 Memory store initialized: ${this.dataDir}`);
         console.log(`   Loaded ${this.capsules.size} capsules`);
@@ -88,10 +186,27 @@ class MemoryStore {
 
     async withLanceLock(fn) {
         if (!this.lanceAvailable || !this.lanceDb) return null;
+        return this.withStoreLock(fn);
+    }
+
+    // 存储锁：所有 LanceDB 读写串行执行
+    withStoreLock(fn) {
         this.lanceQueue = this.lanceQueue.then(fn, fn);
         return this.lanceQueue;
     }
 
+    // 在存储锁队列末尾排一个空操作，超时仍未轮到则认为存储无响应
+    probeStoreLock(timeoutMs = 5000) {
+        return new Promise(resolve => {
+            const timer = setTimeout(() => resolve(false), timeoutMs);
+            timer.unref?.();
+            this.withStoreLock(() => {}).then(() => {
+                clearTimeout(timer);
+                resolve(true);
+            });
+        });
+    }
+
     async ensureLanceTables() {
         const tableNames = ['capsules', 'accounts', 'account_index', 'ledger', 'escrows'];
         for (const name of tableNames) {
@@ -123,6 +238,7 @@ class MemoryStore {
                     if (row && row.capsule_json) {
                         const capsule = JSON.parse(row.capsule_json);
                         this.capsules.set(capsule.asset_id, capsule);
+                        this.indexCapsule(capsule);
                     }
                 }
             }
@@ -225,6 +341,7 @@ class MemoryStore {
 
     applySnapshot(snapshot) {
         this.capsules.clear();
+        this.clearCapsuleIndex();
         this.accounts.clear();
         this.accountIndex.clear();
         this.ledger = [];
@@ -232,6 +349,7 @@ class MemoryStore {
         for (const capsule of snapshot.capsules || []) {
             if (capsule && capsule.asset_id) {
                 this.capsules.set(capsule.asset_id, capsule);
+                this.indexCapsule(capsule);
             }
         }
         for (const account of snapshot.accounts || []) {
@@ -252,16 +370,62 @@ class MemoryStore {
         }
     }
 
-    getSnapshot() {
+    // include 为空时返回全部分区；否则只序列化请求的分区
+    getSnapshot(include = null) {
+        const sections = {
+            capsules: () => Array.from(this.capsules.values()).map(capsule => this.redactCapsule(capsule)),
+            accounts: () => Array.from(this.accounts.values()),
+            accountIndex: () => Array.from(this.accountIndex.entries()).map(([nodeId, accountId]) => ({ nodeId, accountId })),
+            ledger: () => this.ledger,
+            escrows: () => Array.from(this.escrows.values())
+        };
+        const wanted = Array.isArray(include) && include.length > 0
+            ? include.filter(name => Object.prototype.hasOwnProperty.call(sections, name))
+            : Object.keys(sections);
+        const snapshot = {};
+        for (const name of wanted) {
+            snapshot[name] = sections[name]();
+        }
+        return snapshot;
+    }
+
+    // 脱敏：去掉完整内容，只保留预览与作者声明可见的字段（content.preview_fields）。
+    // 胶囊自带的 redacted 标记不可信，总是从 content 重新生成脱敏视图。
+    // 置顶、保留时长和本地发布标记是本节点的本地设置，与广播时一样不出现在脱敏视图中
+    redactCapsule(capsule) {
+        if (!capsule) return null;
+        const { pinned, retentionMs, localOrigin, ...shared } = capsule;
+        const content = capsule.content;
+        if (!content || typeof content !== 'object') {
+            return { ...shared, content: null, preview: null, redacted: true };
+        }
+        const visible = {};
+        for (const fieldPath of Array.isArray(content.preview_fields) ? content.preview_fields : []) {
+            const parts = String(fieldPath).split('.');
+            let value = content;
+            for (const part of parts) {
+                value = value == null ? undefined : value[part];
+            }
+            if (value === undefined) continue;
+            let target = visible;
+            for (const part of parts.slice(0, -1)) {
+                target[part] = target[part] || {};
+                target = target[part];
+            }
+            target[parts[parts.length - 1]] = value;
+        }
+        const source = typeof content.preview === 'string'
+            ? content.preview
+            : (content.gene?.solution || content.capsule?.code || JSON.stringify(content));
+        const text = String(source);
+        const preview = text.length > this.capsulePreviewChars
+            ? text.slice(0, this.capsulePreviewChars) + '…'
+            : text;
         return {
-            capsules: Array.from(this.capsules.values()).map(capsule => ({
-                ...capsule,
-                content: null
-            })),
-            accounts: Array.from(this.accounts.values()),
-            accountIndex: Array.from(this.accountIndex.entries()).map(([nodeId, accountId]) => ({ nodeId, accountId })),
-            ledger: this.ledger,
-            escrows: Array.from(this.escrows.values())
+            ...shared,
+            content: Object.keys(visible).length > 0 ? visible : null,
+            preview,
+            redacted: true
         };
     }
 
@@ -282,6 +446,7 @@ class MemoryStore {
                 const data = JSON.parse(fs.readFileSync(filePath, 'utf8'));
                 for (const [key, value] of Object.entries(data)) {
                     this.capsules.set(key, value);
+                    this.indexCapsule(value, key);
                 }
             } catch (e) {
                 console.error('Failed to load capsules:', e.message);
@@ -308,6 +473,11 @@ class MemoryStore {
             try {
                 const data = JSON.parse(fs.readFileSync(ledgerPath, 'utf8'));
                 if (Array.isArray(data)) {
+                    // 加载时会重新计算链hash，先校验磁盘上的原始链，避免损坏被悄悄掩盖
+                    const check = this.verifyLedger(data);
+                    if (!check.valid && data.every(entry => entry?.hash)) {
+                        console.error(`⚠️  ledger.json chain broken at index ${check.brokenIndex} (${check.reason})`);
+                    }
                     this.ledger = this.normalizeLedger(data);
                 }
             } catch (e) {
@@ -329,6 +499,44 @@ class MemoryStore {
 
     }
     
+    // 写入临时文件并fsync后再原子替换，保证崩溃后文件要么是旧版本要么是完整的新版本
+    writeFileDurable(filePath, content) {
+        const tmpPath = `${filePath}.tmp`;
+        const fd = fs.openSync(tmpPath, 'w');
+        try {
+            fs.writeSync(fd, content);
+            fs.fsyncSync(fd);
+        } finally {
+            fs.closeSync(fd);
+        }
+        fs.renameSync(tmpPath, filePath);
+    }
+
+    scheduleCapsuleFlush() {
+        this.capsulesDirty = true;
+        if (this.capsuleFlushIntervalMs <= 0) {
+            return this.flushCapsules();
+        }
+        if (this.capsuleFlushTimer) return null;
+        this.capsuleFlushTimer = setTimeout(() => {
+            this.flushCapsules().catch(e => console.error('Failed to flush capsules:', e.message));
+        }, this.capsuleFlushIntervalMs);
+        if (this.capsuleFlushTimer.unref) {
+            this.capsuleFlushTimer.unref();
+        }
+        return null;
+    }
+
+    async flushCapsules() {
+        if (this.capsuleFlushTimer) {
+            clearTimeout(this.capsuleFlushTimer);
+            this.capsuleFlushTimer = null;
+        }
+        if (!this.capsulesDirty) return;
+        this.capsulesDirty = false;
+        await this.saveToDisk();
+    }
+    
     async saveToDisk() {
         const filePath = this.getCapsulesPath();
         const data = Object.fromEntries(this.capsules);
@@ -345,7 +553,7 @@ class MemoryStore {
             accounts: Object.fromEntries(this.accounts),
             index: Object.fromEntries(this.accountIndex)
         };
-        fs.writeFileSync(filePath, JSON.stringify(data, null, 2));
+        this.writeFileDurable(filePath, JSON.stringify(data, null, 2));
         await this.saveTable('accounts', Object.entries(data.accounts).map(([accountId, account]) => ({
             accountId,
             nodeId: account.nodeId || '',
@@ -363,7 +571,7 @@ class MemoryStore {
 
     async saveLedgerToDisk() {
         const filePath = this.getLedgerPath();
-        fs.writeFileSync(filePath, JSON.stringify(this.ledger, null, 2));
+        this.writeFileDurable(filePath, JSON.stringify(this.ledger, null, 2));
         await this.saveTable('ledger', this.ledger.map(entry => ({
             ...entry,
             prevHash: entry.prevHash || '',
@@ -373,19 +581,105 @@ class MemoryStore {
 
     async saveEscrowsToDisk() {
         const filePath = this.getEscrowPath();
-        fs.writeFileSync(filePath, JSON.stringify(Object.fromEntries(this.escrows), null, 2));
+        this.writeFileDurable(filePath, JSON.stringify(Object.fromEntries(this.escrows), null, 2));
         await this.saveTable('escrows', Array.from(this.escrows.values()).map(escrow => ({
             ...escrow,
             escrow_json: JSON.stringify(escrow)
         })));
     }
     
+    // 来源链：每一步由该步创建者签名，并引用上一步的签名与来源胶囊
+    verifyProvenance(capsule) {
+        const chain = capsule?.provenance;
+        if (!Array.isArray(chain) || chain.length === 0) {
+            return { valid: false, reason: 'Missing provenance' };
+        }
+        for (let i = 0; i < chain.length; i++) {
+            const step = chain[i] || {};
+            const prev = chain[i - 1] || null;
+            if (!step.publicKeyPem || !step.signature) {
+                return { valid: false, reason: `Step ${i} is not signed` };
+            }
+            if (accountIdFromPublicKey(step.publicKeyPem) !== step.accountId) {
+                return { valid: false, reason: `Step ${i} account does not match its key` };
+            }
+            if ((step.prevSignature || null) !== (prev ? prev.signature : null)) {
+                return { valid: false, reason: `Step ${i} does not link to the previous step` };
+            }
+            if (prev && !(step.derivedFrom || []).includes(prev.assetId)) {
+                return { valid: false, reason: `Step ${i} does not derive from ${prev.assetId}` };
+            }
+            const statement = {
+                assetId: step.assetId,
+                creator: step.creator,
+                accountId: step.accountId,
+                derivedFrom: step.derivedFrom || [],
+                prevSignature: step.prevSignature || null,
+                signedAt: step.signedAt
+            };
+            let ok = false;
+            try {
+                ok = verifyPayload(step.publicKeyPem, statement, step.signature);
+            } catch (e) {
+                ok = false;
+            }
+            if (!ok) {
+                return { valid: false, reason: `Step ${i} signature is invalid` };
+            }
+        }
+        if (chain[chain.length - 1].assetId !== capsule.asset_id) {
+            return { valid: false, reason: 'Provenance does not end at this capsule' };
+        }
+        return { valid: true };
+    }
+
     // 存储胶囊
-    async storeCapsule(capsule) {
+    async storeCapsule(capsule, { ttlMs, localOrigin = false } = {}) {
         // 确保有asset_id
         if (!capsule.asset_id) {
             capsule.asset_id = this.computeAssetId(capsule);
         }
+
+        // 保留时长只由本地调用方决定，不沿用收到的胶囊自带的值；未指定时沿用已有记录的保留时长
+        const existingRetention = this.capsules.get(capsule.asset_id)?.retentionMs;
+        if (this.isValidTtl(ttlMs)) {
+            capsule.retentionMs = Number(ttlMs);
+        } else if (this.isValidTtl(existingRetention)) {
+            capsule.retentionMs = existingRetention;
+        } else {
+            delete capsule.retentionMs;
+        }
+
+        // 置顶同样是本地决定：重新存储时沿用已有记录的置顶状态
+        if (this.capsules.get(capsule.asset_id)?.pinned) {
+            capsule.pinned = true;
+        } else {
+            delete capsule.pinned;
+        }
+
+        // 是否由本节点发布也只由本地调用方标记，收到的胶囊自带的标记不算数
+        if (localOrigin || this.capsules.get(capsule.asset_id)?.localOrigin) {
+            capsule.localOrigin = true;
+        } else {
+            delete capsule.localOrigin;
+        }
+
+        // 访问控制列表必须由创建者签名，签名无效时只保留创建者本人的访问权限
+        if (capsule.acl !== undefined && !this.verifyAcl(capsule, capsule)) {
+            console.warn(`⚠️  Capsule ${capsule.asset_id} has an invalid ACL signature, restricting to creator`);
+            capsule.acl = [];
+            delete capsule.aclSignature;
+        }
+
+        if (capsule.provenance || this.strictProvenance) {
+            const check = this.verifyProvenance(capsule);
+            if (!check.valid) {
+                if (this.strictProvenance) {
+                    throw new Error(`Invalid provenance for ${capsule.asset_id}: ${check.reason}`);
+                }
+                console.warn(`⚠️  Capsule ${capsule.asset_id} has invalid provenance: ${check.reason}`);
+            }
+        }
         
         // 添加元数据
         if (!capsule.attribution) {
@@ -402,13 +696,64 @@ class MemoryStore {
         
         // 存储
         this.capsules.set(capsule.asset_id, capsule);
+        this.indexCapsule(capsule);
+        await this.evictOverCapacity(capsule.asset_id);
         
-        // 持久化
-        await this.saveToDisk();
+        // 持久化（批量）
+        await this.scheduleCapsuleFlush();
         
         return capsule.asset_id;
     }
 
+    // 没有 acl 的胶囊沿用全局规则；有 acl 时只有创建者和列出的节点能看到完整内容
+    canRead(capsule, nodeId) {
+        if (!capsule || !Array.isArray(capsule.acl)) return true;
+        if (nodeId && nodeId === capsule.attribution?.creator) return true;
+        return Boolean(nodeId) && capsule.acl.includes(nodeId);
+    }
+
+    // 签名者必须是来源链最后一步（即本胶囊创建者）的账户
+    verifyAcl(capsule, update) {
+        const creatorStep = Array.isArray(capsule?.provenance) ? capsule.provenance[capsule.provenance.length - 1] : null;
+        if (!creatorStep?.publicKeyPem || !Array.isArray(update?.acl) || !update.aclSignature) return false;
+        if (accountIdFromPublicKey(creatorStep.publicKeyPem) !== creatorStep.accountId) return false;
+        try {
+            return verifyPayload(creatorStep.publicKeyPem, {
+                assetId: capsule.asset_id,
+                acl: update.acl,
+                aclUpdatedAt: update.aclUpdatedAt
+            }, update.aclSignature);
+        } catch (e) {
+            return false;
+        }
+    }
+
+    // 应用创建者签名的 ACL 变更（授权/撤销），只接受比当前更新的版本
+    async applyAclUpdate(update) {
+        const capsule = this.capsules.get(update?.assetId);
+        if (!capsule) return { applied: false, reason: 'Capsule not found' };
+        if (Number(update.aclUpdatedAt) <= Number(capsule.aclUpdatedAt || 0)) {
+            return { applied: false, reason: 'Stale ACL update' };
+        }
+        if (!this.verifyAcl(capsule, update)) {
+            return { applied: false, reason: 'Invalid ACL signature' };
+        }
+        capsule.acl = update.acl;
+        capsule.aclUpdatedAt = update.aclUpdatedAt;
+        capsule.aclSignature = update.aclSignature;
+        await this.scheduleCapsuleFlush();
+        return { applied: true };
+    }
+
+    async removeCapsule(assetId) {
+        if (!this.capsules.delete(assetId)) return false;
+        this.unindexCapsule(assetId);
+        delete this.indexIds[this.indexSeq.get(assetId)];
+        this.indexSeq.delete(assetId);
+        await this.scheduleCapsuleFlush();
+        return true;
+    }
+
     ensureAccount(nodeId, options = {}) {
         if (this.accountIndex.has(nodeId)) {
             return this.getAccountByNodeId(nodeId);
@@ -511,6 +856,52 @@ class MemoryStore {
         return this.getAccountByNodeId(nodeId);
     }
 
+    // 按 accounts 中的 nodeId 重建 accountIndex；同一 nodeId 有多个账户时保留索引当前指向的，否则取最新的
+    async reconcileAccountIndex({ dryRun = false } = {}) {
+        const expected = new Map();
+        const timeOf = (account) => Date.parse(account.importedAt || account.createdAt || '') || 0;
+        // 没有记录 nodeId 的旧账户只能信任现有索引
+        for (const [nodeId, accountId] of this.accountIndex) {
+            const account = this.accounts.get(accountId);
+            if (account && !account.nodeId) expected.set(nodeId, account);
+        }
+        for (const account of this.accounts.values()) {
+            if (!account?.nodeId || !account.accountId) continue;
+            const current = expected.get(account.nodeId);
+            if (!current) {
+                expected.set(account.nodeId, account);
+                continue;
+            }
+            const indexed = this.accountIndex.get(account.nodeId);
+            if (indexed === current.accountId) continue;
+            if (indexed === account.accountId || timeOf(account) > timeOf(current)) {
+                expected.set(account.nodeId, account);
+            }
+        }
+        const changes = [];
+        for (const [nodeId, accountId] of this.accountIndex) {
+            const account = expected.get(nodeId);
+            if (!account) {
+                changes.push({ nodeId, action: 'removed', from: accountId });
+            } else if (account.accountId !== accountId) {
+                changes.push({ nodeId, action: 'updated', from: accountId, to: account.accountId });
+            }
+        }
+        for (const [nodeId, account] of expected) {
+            if (!this.accountIndex.has(nodeId)) {
+                changes.push({ nodeId, action: 'added', to: account.accountId });
+            }
+        }
+        const result = { consistent: changes.length === 0, changes };
+        if (dryRun || changes.length === 0) return result;
+        this.accountIndex.clear();
+        for (const [nodeId, account] of expected) {
+            this.accountIndex.set(nodeId, account.accountId);
+        }
+        await this.saveAccountsToDisk();
+        return result;
+    }
+
     getAccountByNodeId(nodeId) {
         const accountId = this.accountIndex.get(nodeId);
         if (!accountId) return null;
@@ -527,9 +918,83 @@ class MemoryStore {
         return account.balance || 0;
     }
 
+    isAccountActive(account) {
+        return Boolean(account) && account.status !== 'inactive';
+    }
+
+    assertAccountActive(account) {
+        if (!this.isAccountActive(account)) {
+            throw new Error('Account is inactive');
+        }
+    }
+
+    // 账户列表：停用的账户仍然列出（带 status/deactivatedAt），余额按账本计算
+    listAccounts() {
+        return Array.from(this.accounts.values()).map(account => ({
+            ...account,
+            status: this.isAccountActive(account) ? 'active' : 'inactive',
+            balance: this.computeBalance(account.accountId)
+        }));
+    }
+
+    // 软删除：账户标记为停用，不再允许转出、扣款或接收转账；账本历史保留。
+    // 指定 sweepTo 时先把余额以 transfer 条目转到该账户
+    deactivateAccount(accountId, options = {}) {
+        const account = this.accounts.get(accountId);
+        if (!account) {
+            throw new Error('Account not found');
+        }
+        if (!this.isAccountActive(account)) {
+            throw new Error('Account is already inactive');
+        }
+        if (account.nodeId && account.nodeId === this.genesisNodeId) {
+            throw new Error('Genesis account cannot be deactivated');
+        }
+        const sweepTo = options.sweepTo || null;
+        const target = sweepTo ? this.accounts.get(sweepTo) : null;
+        if (sweepTo && (!target || sweepTo === accountId)) {
+            throw new Error('Sweep account not found');
+        }
+        if (target && !this.isAccountActive(target)) {
+            throw new Error('Sweep account is inactive');
+        }
+        const balance = this.computeBalance(accountId);
+        let sweep = null;
+        if (target && balance > 0) {
+            sweep = this.appendLedgerEntry({
+                type: 'transfer',
+                from: accountId,
+                to: sweepTo,
+                fromNodeId: account.nodeId,
+                toNodeId: target.nodeId,
+                amount: balance,
+                meta: { reason: 'account_deactivated' }
+            });
+        }
+        const deactivatedAt = Date.now();
+        this.appendLedgerEntry({
+            type: 'account_deactivated',
+            accountId,
+            nodeId: account.nodeId,
+            sweptTo: sweep ? sweepTo : null,
+            amount: sweep ? balance : 0
+        });
+        this.accounts.set(accountId, { ...account, status: 'inactive', deactivatedAt });
+        this.saveAccountsToDisk();
+        this.saveLedgerToDisk();
+        return {
+            accountId,
+            status: 'inactive',
+            deactivatedAt,
+            swept: sweep ? { to: sweepTo, amount: balance } : null,
+            balance: this.computeBalance(accountId)
+        };
+    }
+
     debit(nodeId, amount, meta = {}) {
         if (amount <= 0) return 0;
         const account = this.ensureAccount(nodeId);
+        this.assertAccountActive(account);
         const balance = this.computeBalance(account.accountId);
         if (balance < amount) {
             throw new Error('Insufficient balance');
@@ -608,6 +1073,26 @@ class MemoryStore {
         return { released: escrow.amount };
     }
 
+    // 任务取消时把托管金额退回锁定它的节点
+    refundEscrow(taskId, meta = {}) {
+        const escrow = this.escrows.get(taskId);
+        if (!escrow) {
+            return { refunded: 0 };
+        }
+        this.escrows.delete(taskId);
+        this.credit(escrow.from, escrow.amount, { reason: 'task_cancelled', taskId, token: escrow.token, ...meta });
+        this.appendLedgerEntry({
+            type: 'escrow_refunded',
+            taskId,
+            to: escrow.from,
+            amount: escrow.amount,
+            token: escrow.token
+        });
+        this.saveEscrowsToDisk();
+        this.saveLedgerToDisk();
+        return { refunded: escrow.amount };
+    }
+
     transfer(fromAccountId, toAccountId, amount, meta = {}) {
         if (amount <= 0) return { success: false, reason: 'Invalid amount' };
         const genesisAccount = this.ensureAccount(this.genesisNodeId);
@@ -627,6 +1112,8 @@ class MemoryStore {
         if (!toAccount) {
             throw new Error('To account not found');
         }
+        this.assertAccountActive(fromAccount);
+        this.assertAccountActive(toAccount);
         const balance = this.computeBalance(fromAccountId);
         if (balance < amount) {
             throw new Error('Insufficient balance');
@@ -696,7 +1183,7 @@ class MemoryStore {
         this.saveLedgerToDisk();
     }
 
-    appendLedgerEntry(entry, options = {}) {
+    appendLedgerEntry(entry) {
         const prevHash = this.getLedgerHeadHash() || '';
         const index = this.ledger.length;
         const payload = {
@@ -708,13 +1195,6 @@ class MemoryStore {
         const hash = crypto.createHash('sha256').update(JSON.stringify(payload)).digest('hex');
         const fullEntry = { ...payload, hash };
         this.ledger.push(fullEntry);
-        if (this.onLedgerEntry && options.broadcast !== false) {
-            try {
-                this.onLedgerEntry(fullEntry);
-            } catch (e) {
-                // Ignore broadcast errors
-            }
-        }
         return fullEntry;
     }
 
@@ -730,21 +1210,13 @@ class MemoryStore {
         return computed === hash;
     }
 
-    applyLedgerEntry(entry) {
-        if (!entry) {
-            return { applied: false, reason: 'Invalid entry' };
-        }
-        if (entry.hash && this.ledger.some(e => e?.meta?.receivedHash === entry.hash)) {
-            return { applied: false, reason: 'Duplicate entry' };
-        }
-        const { hash, index, prevHash, ...payload } = entry;
-        const meta = { ...(payload.meta || {}) };
-        if (hash) {
-            meta.receivedHash = hash;
-        }
-        const localEntry = this.appendLedgerEntry({ ...payload, meta }, { broadcast: false });
-        this.saveLedgerToDisk();
-        return { applied: true, entry: localEntry };
+    // SQLite 账本同步到主节点签名的停用交易时调用：只改本地账户状态，不写 JSON 账本
+    markAccountInactive(accountId, deactivatedAt = Date.now()) {
+        const account = this.accounts.get(accountId);
+        if (!account || !this.isAccountActive(account)) return false;
+        this.accounts.set(accountId, { ...account, status: 'inactive', deactivatedAt });
+        this.saveAccountsToDisk();
+        return true;
     }
 
     getLedgerHeadHash() {
@@ -773,32 +1245,233 @@ class MemoryStore {
     }
 
     verifyLedgerIntegrity() {
+        if (!this.verifyLedger().valid) {
+            throw new Error('Ledger integrity check failed');
+        }
+    }
+
+    // 按 index 顺序校验账本链：index 连续、prevHash 指向上一条、hash 与内容一致。
+    // 返回第一处断裂的位置（brokenIndex）和其之前已校验通过的链头
+    verifyLedger(entries = this.ledger) {
         let prevHash = '';
-        for (let i = 0; i < this.ledger.length; i += 1) {
-            const entry = this.ledger[i];
-            const { hash, ...payload } = entry;
+        for (let i = 0; i < entries.length; i += 1) {
+            const { hash, ...payload } = entries[i] || {};
+            let reason = null;
             if (payload.index !== i) {
-                throw new Error('Ledger integrity check failed');
+                reason = 'index mismatch';
+            } else if ((payload.prevHash || '') !== prevHash) {
+                reason = 'prevHash mismatch';
+            } else if (crypto.createHash('sha256').update(JSON.stringify(payload)).digest('hex') !== hash) {
+                reason = 'hash mismatch';
             }
-            if ((payload.prevHash || '') !== prevHash) {
-                throw new Error('Ledger integrity check failed');
-            }
-            const expected = crypto.createHash('sha256').update(JSON.stringify(payload)).digest('hex');
-            if (expected !== hash) {
-                throw new Error('Ledger integrity check failed');
+            if (reason) {
+                return { valid: false, length: entries.length, verified: i, brokenIndex: i, reason, headHash: prevHash };
             }
             prevHash = hash;
         }
+        return { valid: true, length: entries.length, verified: entries.length, brokenIndex: null, reason: null, headHash: prevHash };
+    }
+
+    // 校验磁盘上的 ledger.json（不经过加载时的重算），文件不存在时校验内存中的账本。
+    // 传入 SQLite 账本（LedgerStore）时一并校验 tx_log 的链hash，结果放在 txLog 中，两条链都完好才算通过
+    verifyPersistedLedger(txLedger = null) {
+        const ledgerPath = this.getLedgerPath();
+        let result;
+        if (!fs.existsSync(ledgerPath)) {
+            result = { source: 'memory', ...this.verifyLedger() };
+        } else {
+            const data = JSON.parse(fs.readFileSync(ledgerPath, 'utf8'));
+            result = Array.isArray(data)
+                ? { source: 'disk', ...this.verifyLedger(data) }
+                : { source: 'disk', valid: false, length: 0, verified: 0, brokenIndex: 0, reason: 'not an array', headHash: '' };
+        }
+        if (!txLedger) return result;
+        const txLog = txLedger.verifyChain();
+        return { ...result, valid: result.valid && txLog.valid, txLog };
+    }
+
+    // 修复磁盘上断裂的账本链：原文件备份为 ledger.json.bak-<时间戳>，
+    // 从第一条出错的记录起的尾部移入 ledger.quarantine-<时间戳>.json，只保留校验通过的前缀并重建链头。
+    // 余额由账本实时计算，截断后即重新计算；托管记录不做改动
+    async repairLedger() {
+        const ledgerPath = this.getLedgerPath();
+        if (!fs.existsSync(ledgerPath)) {
+            return { repaired: false, reason: 'ledger.json not found' };
+        }
+        const entries = JSON.parse(fs.readFileSync(ledgerPath, 'utf8'));
+        if (!Array.isArray(entries)) {
+            throw new Error('ledger.json is not an array');
+        }
+        const check = this.verifyLedger(entries);
+        if (check.valid) {
+            return { repaired: false, reason: 'ledger chain is intact', length: check.length, headHash: check.headHash };
+        }
+        const stamp = Date.now();
+        const backupPath = `${ledgerPath}.bak-${stamp}`;
+        fs.copyFileSync(ledgerPath, backupPath);
+        const quarantinePath = path.join(this.dataDir, `ledger.quarantine-${stamp}.json`);
+        const tail = entries.slice(check.brokenIndex);
+        this.writeFileDurable(quarantinePath, JSON.stringify(tail, null, 2));
+
+        this.ledger = entries.slice(0, check.brokenIndex);
+        await this.saveLedgerToDisk();
+        const affected = new Set();
+        for (const entry of tail) {
+            const accountIds = entry?.type === 'transfer' ? [entry.from, entry.to] : [entry?.accountId];
+            for (const accountId of accountIds) {
+                if (accountId) affected.add(accountId);
+            }
+        }
+        const balances = {};
+        for (const accountId of affected) {
+            balances[accountId] = this.computeBalance(accountId);
+        }
+        console.warn(`⚠️  Ledger repaired: kept ${check.brokenIndex} entries, quarantined ${tail.length} (${check.reason})`);
+        return {
+            repaired: true,
+            reason: check.reason,
+            kept: check.brokenIndex,
+            quarantined: tail.length,
+            headHash: this.getLedgerHeadHash(),
+            backupPath,
+            quarantinePath,
+            balances
+        };
     }
     
     // 获取胶囊
+    // 是否已存有该胶囊（没有 asset_id 时按内容计算），不更新访问时间
+    hasCapsule(capsule) {
+        const assetId = capsule?.asset_id || (capsule?.content ? this.computeAssetId(capsule) : null);
+        return !!assetId && this.capsules.has(assetId);
+    }
+
     getCapsule(assetId) {
-        return this.capsules.get(assetId) || null;
+        const capsule = this.capsules.get(assetId) || null;
+        if (capsule) this.touchCapsules([capsule]);
+        return capsule;
+    }
+
+    touchCapsules(capsules, now = Date.now()) {
+        for (const capsule of capsules) {
+            if (capsule?.asset_id) this.lastAccess.set(capsule.asset_id, now);
+        }
+        return capsules;
+    }
+
+    getLastAccess(capsule) {
+        if (this.lastAccess.has(capsule.asset_id)) return this.lastAccess.get(capsule.asset_id);
+        const createdAt = Date.parse(capsule.attribution?.created_at);
+        return Number.isFinite(createdAt) ? Math.max(createdAt, this.accessBaseline) : this.accessBaseline;
+    }
+
+    isValidTtl(ttlMs) {
+        return ttlMs !== undefined && ttlMs !== null && Number.isFinite(Number(ttlMs)) && Number(ttlMs) >= 0;
+    }
+
+    // 胶囊的空闲保留时长：存储时指定的覆盖值优先，否则使用全局 pruneIdleMs；0 表示不因空闲被清理
+    getCapsuleTtl(capsule) {
+        return this.isValidTtl(capsule?.retentionMs) ? capsule.retentionMs : this.pruneIdleMs;
+    }
+
+    // 重新发布时只会延长保留时长，不会缩短
+    async extendCapsuleTtl(assetId, ttlMs) {
+        const capsule = this.capsules.get(assetId);
+        if (!capsule || !this.isValidTtl(ttlMs)) return false;
+        const current = this.getCapsuleTtl(capsule);
+        const next = Number(ttlMs);
+        if (current === 0 || (next !== 0 && next <= current)) return false;
+        capsule.retentionMs = next;
+        await this.scheduleCapsuleFlush();
+        return true;
+    }
+
+    isPinned(capsule) {
+        return capsule?.pinned === true;
+    }
+
+    // 置顶的胶囊不会被清理、容量淘汰或空闲过期删除
+    async setCapsulePinned(assetId, pinned) {
+        const capsule = this.capsules.get(assetId);
+        if (!capsule) return null;
+        if (pinned) {
+            capsule.pinned = true;
+        } else {
+            delete capsule.pinned;
+        }
+        await this.scheduleCapsuleFlush();
+        return capsule;
+    }
+
+    startPruning() {
+        if (this.pruneTimer || this.pruneIntervalMs <= 0) return;
+        if (!(this.pruneMinConfidence > 0) && !(this.pruneIdleMs > 0)) return;
+        this.pruneTimer = setInterval(() => {
+            this.pruneCapsules().catch(e => console.error('Capsule pruning failed:', e.message));
+        }, this.pruneIntervalMs);
+        this.pruneTimer.unref?.();
+    }
+
+    async pruneCapsules(now = Date.now()) {
+        const pruned = [];
+        let lowConfidence = 0;
+        let idle = 0;
+        for (const capsule of Array.from(this.capsules.values())) {
+            if (capsule.status === 'promoted' || this.isPinned(capsule)) continue;
+            const isLow = this.pruneMinConfidence > 0 && this.getEffectiveConfidence(capsule, now) < this.pruneMinConfidence;
+            const ttl = this.getCapsuleTtl(capsule);
+            const isIdle = ttl > 0 && now - this.getLastAccess(capsule) > ttl;
+            if (!isLow && !isIdle) continue;
+            if (isLow) lowConfidence++;
+            else idle++;
+            pruned.push(capsule.asset_id);
+        }
+        for (const assetId of pruned) {
+            await this.removeCapsule(assetId);
+            this.lastAccess.delete(assetId);
+        }
+        const run = { at: new Date(now).toISOString(), pruned: pruned.length, lowConfidence, idle, remaining: this.capsules.size };
+        this.pruneStats = { runs: this.pruneStats.runs + 1, totalPruned: this.pruneStats.totalPruned + pruned.length, lastRun: run };
+        if (pruned.length > 0) {
+            console.log(`🧹 Pruned ${pruned.length} capsules (${lowConfidence} low confidence, ${idle} idle)`);
+        }
+        return { ...run, assetIds: pruned };
     }
     
+    // 超出数量上限时淘汰最久未访问的胶囊（刚写入的、置顶的和本节点发布的除外），访问时间相同时先淘汰先写入的。
+    // 收到的胶囊写入时也会触发淘汰，本节点发布的胶囊不参与淘汰，远端大量写入不会挤掉它们
+    async evictOverCapacity(keepAssetId = null) {
+        if (!(this.maxCapsules > 0) || this.capsules.size <= this.maxCapsules) return [];
+        const candidates = Array.from(this.capsules.values())
+            .filter(capsule => capsule.status !== 'promoted' && !this.isPinned(capsule) && !capsule.localOrigin
+                && capsule.asset_id !== keepAssetId)
+            .sort((a, b) => this.getLastAccess(a) - this.getLastAccess(b)
+                || (this.indexSeq.get(a.asset_id) ?? 0) - (this.indexSeq.get(b.asset_id) ?? 0));
+        const evicted = candidates.slice(0, this.capsules.size - this.maxCapsules).map(capsule => capsule.asset_id);
+        for (const assetId of evicted) {
+            await this.removeCapsule(assetId);
+            this.lastAccess.delete(assetId);
+        }
+        this.evictedCapsules += evicted.length;
+        if (evicted.length > 0) {
+            console.log(`🧹 Evicted ${evicted.length} capsules over the ${this.maxCapsules} capsule cap`);
+        }
+        return evicted;
+    }
+
     // 查询胶囊
     queryCapsules(filter = {}) {
-        let results = Array.from(this.capsules.values());
+        if (filter.mode === 'similarity') {
+            return this.querySimilarCapsules(filter);
+        }
+        let results;
+        if (filter.tags && filter.tags.length > 0) {
+            results = this.getIndexedIds(filter.tags)
+                .map(id => this.capsules.get(id))
+                .filter(Boolean);
+        } else {
+            results = Array.from(this.capsules.values());
+        }
         
         if (filter.type) {
             results = results.filter(c => c.type === filter.type);
@@ -812,27 +1485,249 @@ class MemoryStore {
             results = results.filter(c => c.status === filter.status);
         }
         
-        if (filter.tags && filter.tags.length > 0) {
-            results = results.filter(c => {
-                const tags = c.content?.capsule?.blast_radius || [];
-                return filter.tags.some(tag => tags.includes(tag));
-            });
-        }
-        
         if (filter.minConfidence) {
-            results = results.filter(c => c.confidence >= filter.minConfidence);
+            const now = Date.now();
+            results = results.filter(c => this.getEffectiveConfidence(c, now) >= filter.minConfidence);
         }
         
-        // 排序
-        results.sort((a, b) => b.confidence - a.confidence);
+        // 排序：置信度加上评分修正
+        const ratings = this.capsuleRatings ? this.capsuleRatings() : new Map();
+        const rank = capsule => Number(capsule.confidence || 0) + this.ratingWeight * (ratings.get(capsule.asset_id)?.value || 0);
+        results.sort((a, b) => rank(b) - rank(a));
         
         if (filter.limit) {
             results = results.slice(0, filter.limit);
         }
         
-        return results;
+        return this.withRatings(this.touchCapsules(results), ratings);
+    }
+
+    // 已被评分的胶囊返回带 rating 的副本，不修改存储的胶囊
+    withRatings(capsules, ratings) {
+        if (ratings.size === 0) return capsules;
+        return capsules.map(capsule => ratings.has(capsule.asset_id) ? { ...capsule, rating: ratings.get(capsule.asset_id) } : capsule);
+    }
+
+    // 相似度查询：按查询文本与胶囊词频向量的余弦相似度排序，部分命中的胶囊也会返回
+    querySimilarCapsules(filter = {}) {
+        const queryTf = this.countTokens(this.tokenize(filter.query || ''));
+        let queryNorm = 0;
+        for (const count of queryTf.values()) queryNorm += count * count;
+        if (queryNorm === 0) return [];
+        queryNorm = Math.sqrt(queryNorm);
+
+        const dot = new Map();
+        for (const [token, queryCount] of queryTf) {
+            const posting = this.tokenIndex.get(token);
+            if (!posting) continue;
+            for (const [assetId, count] of posting) {
+                dot.set(assetId, (dot.get(assetId) || 0) + queryCount * count);
+            }
+        }
+
+        const tags = Array.isArray(filter.tags) && filter.tags.length > 0 ? new Set(filter.tags) : null;
+        const now = Date.now();
+        let scored = [];
+        for (const [assetId, value] of dot) {
+            const capsule = this.capsules.get(assetId);
+            if (!capsule) continue;
+            if (tags && !this.getCapsuleTags(capsule).some(tag => tags.has(tag))) continue;
+            if (filter.type && capsule.type !== filter.type) continue;
+            if (filter.creator && capsule.attribution?.creator !== filter.creator) continue;
+            if (filter.status && capsule.status !== filter.status) continue;
+            if (filter.minConfidence && this.getEffectiveConfidence(capsule, now) < filter.minConfidence) continue;
+            scored.push({ capsule, score: value / (queryNorm * this.indexedTokens.get(assetId).norm) });
+        }
+        scored.sort((a, b) => b.score - a.score || b.capsule.confidence - a.capsule.confidence);
+        scored = scored.slice(0, Number(filter.limit) > 0 ? Number(filter.limit) : 10);
+
+        this.touchCapsules(scored.map(item => item.capsule));
+        const ratings = this.capsuleRatings ? this.capsuleRatings() : new Map();
+        return this.withRatings(scored.map(item => ({ ...item.capsule, similarity: Number(item.score.toFixed(4)) })), ratings);
+    }
+
+    tokenize(text) {
+        return String(text).toLowerCase().split(/[^\p{L}\p{N}]+/u).filter(token => token.length > 1);
+    }
+
+    countTokens(tokens) {
+        const counts = new Map();
+        for (const token of tokens) counts.set(token, (counts.get(token) || 0) + 1);
+        return counts;
+    }
+
+    // 胶囊的词项：标签加上按内容类型选出的文本
+    getCapsuleTokens(capsule) {
+        const texts = [...this.getCapsuleTags(capsule), ...this.getIndexableTexts(capsule?.content)];
+        return this.tokenize(texts.join(' '));
+    }
+
+    // text/plain、text/markdown 索引 content.text；application/json 只索引指定字段的字符串值（不含键名）；
+    // 未声明类型或 naive 模式下索引内容中的所有字符串
+    getIndexableTexts(content) {
+        const texts = [];
+        const walk = (value) => {
+            if (typeof value === 'string') texts.push(value);
+            else if (Array.isArray(value)) value.forEach(walk);
+            else if (value && typeof value === 'object') Object.values(value).forEach(walk);
+        };
+        const contentType = typeof content?.content_type === 'string' ? content.content_type.split(';')[0].trim().toLowerCase() : null;
+        if (this.indexMode === 'naive' || !contentType) {
+            walk(content);
+        } else if (contentType === 'text/plain' || contentType === 'text/markdown') {
+            const text = typeof content.text === 'string' ? content.text : '';
+            texts.push(contentType === 'text/markdown' ? this.stripMarkdown(text) : text);
+        } else if (contentType === 'application/json') {
+            const fields = Array.isArray(content.index_fields) ? content.index_fields : this.indexFields;
+            for (const fieldPath of fields) {
+                let value = content;
+                for (const part of String(fieldPath).split('.')) {
+                    value = value == null ? undefined : value[part];
+                }
+                walk(value);
+            }
+        } else {
+            walk(content);
+        }
+        return texts;
+    }
+
+    // 去掉 Markdown 中链接地址、图片、代码块标记等不参与检索的部分
+    stripMarkdown(text) {
+        return text
+            .replace(/```[^\n]*\n/g, '\n')
+            .replace(/!\[([^\]]*)\]\([^)]*\)/g, '$1')
+            .replace(/\[([^\]]*)\]\([^)]*\)/g, '$1')
+            .replace(/<[^>]+>/g, ' ');
     }
     
+    getCapsuleTags(capsule) {
+        const tags = capsule?.content?.capsule?.blast_radius;
+        return Array.isArray(tags) ? tags : [];
+    }
+
+    indexCapsule(capsule, assetId = capsule?.asset_id) {
+        if (!assetId) return;
+        this.unindexTokens(assetId);
+        if (!this.indexSeq.has(assetId)) {
+            this.indexSeq.set(assetId, this.nextIndexSeq);
+            this.indexIds[this.nextIndexSeq++] = assetId;
+        }
+        this.capsuleStatsCache = null;
+        const seq = this.indexSeq.get(assetId);
+        const previous = this.indexedTags.get(assetId) || [];
+        const tags = Array.from(new Set(this.getCapsuleTags(capsule)));
+        // 先更新胶囊的标签，压缩倒排表时据此判断条目是否仍然有效；标签不变的重写不触碰倒排表
+        this.indexedTags.set(assetId, tags);
+        for (const tag of previous) {
+            if (!tags.includes(tag)) this.removePosting(tag);
+        }
+        for (const tag of tags) {
+            if (previous.includes(tag)) continue;
+            if (!this.tagIndex.has(tag)) {
+                this.tagIndex.set(tag, new PostingList());
+            }
+            this.tagIndex.get(tag).append(seq);
+        }
+        const counts = this.countTokens(this.getCapsuleTokens(capsule));
+        let norm = 0;
+        for (const [token, count] of counts) {
+            if (!this.tokenIndex.has(token)) {
+                this.tokenIndex.set(token, new Map());
+            }
+            this.tokenIndex.get(token).set(assetId, count);
+            norm += count * count;
+        }
+        if (norm > 0) {
+            this.indexedTokens.set(assetId, { tokens: Array.from(counts.keys()), norm: Math.sqrt(norm) });
+        }
+    }
+
+    unindexCapsule(assetId) {
+        const tags = this.indexedTags.get(assetId);
+        if (!tags) return;
+        this.capsuleStatsCache = null;
+        this.indexedTags.delete(assetId);
+        for (const tag of tags) {
+            this.removePosting(tag);
+        }
+        this.unindexTokens(assetId);
+    }
+
+    removePosting(tag) {
+        const posting = this.tagIndex.get(tag);
+        if (!posting) return;
+        posting.remove();
+        if (posting.size === 0) {
+            this.tagIndex.delete(tag);
+        } else if (posting.needsCompaction()) {
+            posting.compact(seq => this.isPostingLive(tag, seq));
+        }
+    }
+
+    // 倒排表条目有效：该序号的胶囊仍在，且当前标签包含该标签
+    isPostingLive(tag, seq) {
+        const assetId = this.indexIds[seq];
+        return assetId !== undefined && (this.indexedTags.get(assetId) || []).includes(tag);
+    }
+
+    unindexTokens(assetId) {
+        const entry = this.indexedTokens.get(assetId);
+        if (!entry) return;
+        for (const token of entry.tokens) {
+            const posting = this.tokenIndex.get(token);
+            if (!posting) continue;
+            posting.delete(assetId);
+            if (posting.size === 0) {
+                this.tokenIndex.delete(token);
+            }
+        }
+        this.indexedTokens.delete(assetId);
+    }
+
+    clearCapsuleIndex() {
+        this.capsuleStatsCache = null;
+        this.tagIndex.clear();
+        this.indexedTags.clear();
+        this.tokenIndex.clear();
+        this.indexedTokens.clear();
+        this.indexSeq.clear();
+        this.indexIds = [];
+        this.nextIndexSeq = 0;
+    }
+
+    // 任一标签命中的 asset_id，按插入顺序返回（与全量扫描的顺序一致）；单个标签直接按倒排表顺序读出，无需排序
+    getIndexedIds(tags = []) {
+        const postings = Array.from(new Set(tags)).filter(tag => this.tagIndex.has(tag));
+        let matched = [];
+        for (const tag of postings) {
+            for (const seq of this.tagIndex.get(tag).entries()) {
+                if (this.isPostingLive(tag, seq)) matched.push(seq);
+            }
+        }
+        if (postings.length > 1) {
+            matched = Uint32Array.from(matched).sort();
+        }
+        const ids = [];
+        let last = -1;
+        for (const seq of matched) {
+            if (seq === last) continue;
+            ids.push(this.indexIds[seq]);
+            last = seq;
+        }
+        return ids;
+    }
+
+    // 有效置信度：按创建时间与半衰期衰减
+    getEffectiveConfidence(capsule, now = Date.now()) {
+        const confidence = Number(capsule?.confidence ?? capsule?.content?.capsule?.confidence ?? 0);
+        if (!(this.confidenceHalfLifeMs > 0)) return confidence;
+        const createdAt = Date.parse(capsule?.attribution?.created_at || '');
+        if (!Number.isFinite(createdAt)) return confidence;
+        const age = Math.max(0, now - createdAt);
+        return confidence * Math.pow(0.5, age / this.confidenceHalfLifeMs);
+    }
+
     // 搜索记忆（简单文本搜索）
     searchMemories(query) {
         const lowerQuery = query.toLowerCase();
@@ -845,7 +1740,7 @@ class MemoryStore {
             }
         }
         
-        return results.sort((a, b) => b.confidence - a.confidence);
+        return this.touchCapsules(results.sort((a, b) => b.confidence - a.confidence));
     }
     
     // 获取统计
@@ -863,16 +1758,57 @@ class MemoryStore {
         return {
             total: capsules.length,
             promoted,
-            avgConfidence
+            avgConfidence,
+            pruning: this.pruneStats,
+            eviction: { maxCapsules: this.maxCapsules, evicted: this.evictedCapsules }
         };
     }
     
+    // 胶囊聚合统计：按类型计数、热门标签、平均置信度、存储字节数
+    getCapsuleStats({ topTags = 10, now = Date.now() } = {}) {
+        let cache = this.capsuleStatsCache;
+        if (!cache || (this.capsuleStatsTtlMs > 0 && now - cache.computedAt > this.capsuleStatsTtlMs)) {
+            const byType = {};
+            let confidenceSum = 0;
+            let storageBytes = 0;
+            for (const capsule of this.capsules.values()) {
+                const type = capsule?.type || capsule?.content?.capsule?.type || 'unknown';
+                byType[type] = (byType[type] || 0) + 1;
+                confidenceSum += Number(capsule?.confidence ?? capsule?.content?.capsule?.confidence ?? 0) || 0;
+                storageBytes += Buffer.byteLength(JSON.stringify(capsule));
+            }
+            // 标签计数直接取倒排索引，无需扫描胶囊
+            const byTag = Array.from(this.tagIndex.entries())
+                .map(([tag, ids]) => ({ tag, count: ids.size }))
+                .sort((a, b) => b.count - a.count || a.tag.localeCompare(b.tag));
+            cache = {
+                total: this.capsules.size,
+                byType,
+                byTag,
+                avgConfidence: this.capsules.size > 0 ? confidenceSum / this.capsules.size : 0,
+                storageBytes,
+                computedAt: now
+            };
+            this.capsuleStatsCache = cache;
+        }
+        const limit = Math.max(0, Number(topTags) || 0);
+        return {
+            total: cache.total,
+            byType: { ...cache.byType },
+            topTags: cache.byTag.slice(0, limit),
+            tagCount: cache.byTag.length,
+            avgConfidence: cache.avgConfidence,
+            storageBytes: cache.storageBytes,
+            computedAt: new Date(cache.computedAt).toISOString()
+        };
+    }
+
     // 更新胶囊状态
     updateStatus(assetId, status) {
         const capsule = this.capsules.get(assetId);
         if (capsule) {
             capsule.status = status;
-            this.saveToDisk();
+            this.scheduleCapsuleFlush();
         }
     }
     
@@ -881,9 +1817,127 @@ class MemoryStore {
         const content = JSON.stringify(capsule.content);
         return 'sha256:' + crypto.createHash('sha256').update(content).digest('hex');
     }
+
+    hashJson(value) {
+        return crypto.createHash('sha256').update(JSON.stringify(value)).digest('hex');
+    }
+
+    // 导出胶囊包和完整性清单：清单列出每个胶囊的内容hash与整条记录的hash，可由导出节点签名
+    exportCapsules({ assetIds = null, signer = null, now = Date.now() } = {}) {
+        const selected = assetIds
+            ? assetIds.map(id => this.capsules.get(id)).filter(Boolean)
+            : Array.from(this.capsules.values());
+        // 本地保留时长和本地发布标记不随胶囊导出
+        const capsules = selected.map(capsule => {
+            const { retentionMs, localOrigin, ...rest } = JSON.parse(JSON.stringify(capsule));
+            return rest;
+        });
+        const exportedAt = new Date(now).toISOString();
+        const bundle = { version: 1, exportedAt, capsules };
+        const manifest = {
+            version: 1,
+            exportedAt,
+            count: capsules.length,
+            bundleHash: this.hashJson(capsules),
+            entries: capsules.map(capsule => ({
+                asset_id: capsule.asset_id,
+                contentHash: this.computeAssetId(capsule),
+                recordHash: this.hashJson(capsule)
+            }))
+        };
+        if (signer?.privateKeyPem && signer?.publicKeyPem) {
+            manifest.exporter = accountIdFromPublicKey(signer.publicKeyPem);
+            manifest.publicKeyPem = signer.publicKeyPem;
+            manifest.signature = signPayload(signer.privateKeyPem, manifest);
+        }
+        return { bundle, manifest };
+    }
+
+    // 按清单校验胶囊包：签名、数量（截断）、每个胶囊的内容与记录hash
+    verifyCapsuleBundle(bundle, manifest, { requireSignature = false, trustedExporters = null } = {}) {
+        const fail = (reason, errors = []) => ({ valid: false, reason, errors, signed: Boolean(manifest?.signature), exporter: manifest?.exporter || null });
+        if (!manifest || !Array.isArray(manifest.entries)) return fail('Missing manifest');
+        if (!bundle || !Array.isArray(bundle.capsules)) return fail('Missing bundle');
+        // 只要求签名而不指定可信导出方时，任何人自签的清单都能通过，等于没有校验
+        if (requireSignature && !(Array.isArray(trustedExporters) && trustedExporters.length > 0)) {
+            return fail('Signature required but no trusted exporters configured');
+        }
+
+        if (manifest.signature) {
+            const { signature, ...unsigned } = manifest;
+            let ok = false;
+            try {
+                ok = accountIdFromPublicKey(manifest.publicKeyPem) === manifest.exporter
+                    && verifyPayload(manifest.publicKeyPem, unsigned, signature);
+            } catch (e) {
+                ok = false;
+            }
+            if (!ok) return fail('Manifest signature is invalid');
+            if (Array.isArray(trustedExporters) && !trustedExporters.includes(manifest.exporter)) {
+                return fail(`Exporter ${manifest.exporter} is not trusted`);
+            }
+        } else if (requireSignature || Array.isArray(trustedExporters)) {
+            return fail('Manifest is not signed');
+        }
+
+        if (manifest.count !== manifest.entries.length) return fail('Manifest count does not match its entries');
+        if (bundle.capsules.length !== manifest.count) {
+            return fail(`Bundle has ${bundle.capsules.length} capsules, manifest lists ${manifest.count}`);
+        }
+
+        const errors = [];
+        const byId = new Map(bundle.capsules.map(capsule => [capsule?.asset_id, capsule]));
+        for (const entry of manifest.entries) {
+            const capsule = byId.get(entry.asset_id);
+            if (!capsule) {
+                errors.push({ asset_id: entry.asset_id, reason: 'Missing from bundle' });
+            } else if (this.computeAssetId(capsule) !== entry.contentHash) {
+                errors.push({ asset_id: entry.asset_id, reason: 'Content hash mismatch' });
+            } else if (this.hashJson(capsule) !== entry.recordHash) {
+                errors.push({ asset_id: entry.asset_id, reason: 'Record hash mismatch' });
+            }
+            byId.delete(entry.asset_id);
+        }
+        for (const assetId of byId.keys()) {
+            errors.push({ asset_id: assetId, reason: 'Not listed in manifest' });
+        }
+        if (errors.length === 0 && this.hashJson(bundle.capsules) !== manifest.bundleHash) {
+            errors.push({ asset_id: null, reason: 'Bundle hash mismatch' });
+        }
+        if (errors.length > 0) return fail(`${errors.length} capsule(s) failed verification`, errors);
+        return { valid: true, count: manifest.count, errors: [], signed: Boolean(manifest.signature), exporter: manifest.exporter || null };
+    }
+
+    // 先校验再导入，任一胶囊校验失败时整包都不写入；已存在的胶囊跳过
+    async importCapsules(bundle, manifest, options = {}) {
+        const check = this.verifyCapsuleBundle(bundle, manifest, options);
+        if (!check.valid) {
+            return { ...check, imported: 0, skipped: 0 };
+        }
+        let imported = 0;
+        let skipped = 0;
+        for (const capsule of bundle.capsules) {
+            if (this.capsules.has(capsule.asset_id)) {
+                skipped++;
+                continue;
+            }
+            await this.storeCapsule(JSON.parse(JSON.stringify(capsule)));
+            imported++;
+        }
+        return { ...check, imported, skipped };
+    }
     
     // 关闭
     async close() {
+        if (this.pruneTimer) {
+            clearInterval(this.pruneTimer);
+            this.pruneTimer = null;
+        }
+        if (this.capsuleFlushTimer) {
+            clearTimeout(this.capsuleFlushTimer);
+            this.capsuleFlushTimer = null;
+        }
+        this.capsulesDirty = false;
         await this.saveToDisk();
         await this.saveAccountsToDisk();
         await this.saveLedgerToDisk();
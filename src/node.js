@@ -6,6 +6,98 @@
 const EventEmitter = require('events');
 const net = require('net');
 const crypto = require('crypto');
+const { signPayload, verifyPayload, nodeIdFromPublicKey } = require('./wallet');
+const { validateSchema } = require('./schema');
+
+function queryAbortedError() {
+    const err = new Error('Query cancelled');
+    err.code = 'ABORTED';
+    return err;
+}
+
+// 线协议版本：握手消息格式或语义不兼容时递增
+const PROTOCOL_VERSION = 1;
+
+// 发送队列满时可以丢弃的 gossip 消息，丢了只会稍晚通过其他 peer 或重新通告收到
+const DROPPABLE_MESSAGE_TYPES = ['capsule', 'capsule_rating', 'task_like', 'peer_exchange', 'peer_list'];
+
+// 转发策略：flood 把完整消息推给 fanout 个peer；gossip 只向少数peer推送完整消息，
+// 其余peer只收到 ihave（消息ID），没从别处收到的再用 iwant 索取
+const RELAY_STRATEGIES = ['flood', 'gossip'];
+
+// key 与节点的距离：sha256(key) 与 sha256(nodeId) 的异或
+function keyDistance(keyHash, nodeId) {
+    return keyHash ^ BigInt('0x' + crypto.createHash('sha256').update(nodeId).digest('hex'));
+}
+
+function hashKey(key) {
+    return BigInt('0x' + crypto.createHash('sha256').update(String(key)).digest('hex'));
+}
+
+// 一致性哈希环上的位置：sha256 的前 52 位（可精确表示为 Number）
+function ringPosition(value) {
+    return parseInt(crypto.createHash('sha256').update(String(value)).digest('hex').slice(0, 13), 16);
+}
+
+// 在 JSON.parse 之前扫描嵌套深度（跳过字符串内容），避免病态嵌套消耗栈/CPU
+function exceedsJsonDepth(text, maxDepth) {
+    let depth = 0;
+    let inString = false;
+    for (let i = 0; i < text.length; i++) {
+        const ch = text.charCodeAt(i);
+        if (inString) {
+            if (ch === 92) i++; // 反斜杠转义
+            else if (ch === 34) inString = false;
+        } else if (ch === 34) {
+            inString = true;
+        } else if (ch === 123 || ch === 91) {
+            if (++depth > maxDepth) return true;
+        } else if (ch === 125 || ch === 93) {
+            depth--;
+        }
+    }
+    return false;
+}
+
+// 滚动布隆过滤器：两代位图，当前代写满 capacity 个后整体轮换，查询时两代都查。
+// 只记录因数量上限被挤出精确表的消息ID，可能误判为已见（丢掉一条新消息），不会漏判
+class RollingBloom {
+    constructor(bits, capacity, hashes = 4) {
+        this.bits = Math.max(64, Math.floor(bits));
+        this.capacity = Math.max(1, Math.floor(capacity));
+        this.hashes = hashes;
+        this.current = new Uint8Array(Math.ceil(this.bits / 8));
+        this.previous = new Uint8Array(this.current.length);
+        this.added = 0;
+    }
+
+    positions(id) {
+        const digest = crypto.createHash('sha256').update(String(id)).digest();
+        const positions = [];
+        for (let i = 0; i < this.hashes; i++) {
+            positions.push(digest.readUInt32BE(i * 4) % this.bits);
+        }
+        return positions;
+    }
+
+    add(id) {
+        if (this.added >= this.capacity) {
+            this.previous = this.current;
+            this.current = new Uint8Array(this.previous.length);
+            this.added = 0;
+        }
+        for (const bit of this.positions(id)) {
+            this.current[bit >> 3] |= 1 << (bit & 7);
+        }
+        this.added++;
+    }
+
+    has(id) {
+        const positions = this.positions(id);
+        const test = (bitmap) => positions.every(bit => bitmap[bit >> 3] & (1 << (bit & 7)));
+        return test(this.current) || test(this.previous);
+    }
+}
 
 class MeshNode extends EventEmitter {
     constructor(options = {}) {
@@ -13,19 +105,193 @@ class MeshNode extends EventEmitter {
         this.nodeId = options.nodeId;
         this.port = options.port || 0;
         this.bootstrapNodes = options.bootstrapNodes || [];
+        // DNS种子：TXT记录列出 host:port，A记录使用 dnsSeedPort；失败时回退到静态 bootstrapNodes
+        this.dnsSeed = options.dnsSeed || null;
+        this.dnsSeedPort = options.dnsSeedPort || 4000;
+        this.dnsSeedRefreshMs = options.dnsSeedRefreshMs || 600000;
+        this.resolver = options.resolver || require('dns').promises;
+        this.dnsSeedCache = { addresses: [], resolvedAt: 0 };
+        this.dnsSeedTimer = null;
+        // 拓扑图：可选地广播本节点的peer列表，汇总为近似的邻接图（最终一致）
+        this.sharePeerList = Boolean(options.sharePeerList);
+        this.peerListIntervalMs = options.peerListIntervalMs || 60000;
+        this.graphStaleMs = options.graphStaleMs || 300000;
+        this.maxGraphNodes = options.maxGraphNodes || 500;
+        this.peerLists = new Map(); // nodeId -> { peers, receivedAt }
+        this.peerListTimer = null;
+        // 地址簿：peer 列表中附带的可拨号地址（去重、有上限），记录最近出现时间；
+        // 过期条目会被清理，拨号失败的地址在冷却期内不再拨号
+        this.maxPexAddresses = options.maxPexAddresses || 50;
+        this.maxAddressBook = options.maxAddressBook || 1000;
+        this.addressBookTtlMs = options.addressBookTtlMs || 3600000;
+        this.addressCooldownMs = options.addressCooldownMs || 300000;
+        this.pexTargetPeers = options.pexTargetPeers || 8;
+        this.addressBookPath = options.addressBookPath || null;
+        this.addressBook = new Map(); // address -> { address, nodeId, lastSeen, failures, lastFailedAt, dialing }
         
         this.peers = new Map(); // peerId -> socket
         this.server = null;
         this.messageHandlers = new Map();
+        // 已处理消息ID -> 首次见到的时间；按插入顺序即时间顺序，过期和超量都从最早的一端淘汰
         this.seenMessages = new Map();
+        this.seenLatestAt = 0;
         this.seenTtlMs = options.seenTtlMs || 300000;
         this.maxSeenMessages = options.maxSeenMessages || 10000;
+        // 可选的滚动布隆过滤器（位数，0 = 关闭）：超量被挤出的ID仍能去重，消息量很大时不必调高 maxSeenMessages
+        this.seenBloom = options.seenBloomBits > 0 ? new RollingBloom(options.seenBloomBits, this.maxSeenMessages) : null;
         this.peerStats = new Map();
         this.pendingPings = new Map();
+        // RTT直方图（ms，上界），由心跳pong增量累计
+        this.rttBuckets = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, Infinity];
+        this.rttHistogram = { counts: this.rttBuckets.map(() => 0), count: 0, min: null, max: null };
+        // 直方图持久化文件（null = 不持久化），启动时读回，每次心跳和停止时写入
+        this.rttHistogramPath = options.rttHistogramPath || null;
+        // 全局转发速率上限（条/秒，令牌桶，突发上限为1秒的量；0 = 不限），超出的转发直接丢弃并计数
+        this.maxRelayPerSec = options.maxRelayPerSec || 0;
+        this.relayTokens = this.maxRelayPerSec;
+        this.relayRefilledAt = Date.now();
+        this.relayStats = { relayed: 0, suppressed: 0 };
+        // 本节点发布胶囊的广播限速（条/秒，0 = 不限）：超出的进入队列按固定间隔发出，不丢弃
+        this.maxPublishPerSec = options.maxPublishPerSec || 0;
+        // 限速队列的长度上限（0 = 不限）：队列满时新的发布以 BUSY 拒绝，与 queryBusyMode=reject 一致
+        this.maxPublishQueue = options.maxPublishQueue ?? 1000;
+        // key 放置方式：'xor'（按 sha256 异或距离，同路由追踪）或 'ring'（一致性哈希环，每个节点 ringVirtualNodes 个虚拟节点）
+        this.keyPlacement = options.keyPlacement === 'ring' ? 'ring' : 'xor';
+        this.ringVirtualNodes = options.ringVirtualNodes || 64;
+        this.hashRing = null; // { members, points: [{ position, nodeId }] }
+        this.publishQueue = [];
+        this.publishTimer = null;
+        this.nextPublishAt = 0;
+        this.publishStats = { sent: 0, queued: 0, rejected: 0 };
+        // 丢弃统计：重复消息与跳数耗尽；logDrops 开启时打印（每秒最多 maxDropLogsPerSec 条）
+        this.dropStats = { duplicate: 0, hopsExhausted: 0, storedCapsule: 0 };
+        this.logDrops = Boolean(options.logDrops);
+        this.maxDropLogsPerSec = options.maxDropLogsPerSec || 10;
+        this.dropLogWindow = { startedAt: 0, logged: 0, suppressed: 0 };
         this.defaultFanout = options.fanout || 6;
         this.taskFanout = options.taskFanout || 8;
         this.defaultHops = options.defaultHops || 3;
         this.taskHops = options.taskHops || 4;
+        this.relayStrategy = RELAY_STRATEGIES.includes(options.relayStrategy) ? options.relayStrategy : 'flood';
+        // gossip 策略：完整推送的peer数、收到 ihave 后等待多久仍未收到才发 iwant、完整消息缓存多久以响应 iwant
+        this.gossipEagerFanout = options.gossipEagerFanout ?? 2;
+        this.gossipIwantDelayMs = options.gossipIwantDelayMs ?? 200;
+        this.gossipCacheMs = options.gossipCacheMs || 60000;
+        // 缓存的总字节上限（按序列化后的长度计），大消息较多时也不会撑爆内存
+        this.gossipCacheMaxBytes = options.gossipCacheMaxBytes || 16 * 1024 * 1024;
+        this.gossipCache = new Map(); // messageId -> { message, cachedAt, bytes }，按插入顺序即时间顺序
+        this.gossipCacheBytes = 0;
+        // 向一个通告者索取后等待多久仍未收到，就改向下一个通告者索取
+        this.gossipIwantTimeoutMs = options.gossipIwantTimeoutMs ?? 1000;
+        this.gossipWants = new Map(); // messageId -> { announcers, next, timer }
+        this.gossipStats = { eager: 0, ihave: 0, iwant: 0, served: 0 };
+        // 话题订阅：握手时声明感兴趣的标签/类型，空 = 全部
+        this.topics = Array.isArray(options.topics) ? options.topics.filter(Boolean) : [];
+        this.peerTopics = new Map(); // peerId -> [topic]
+        // 容量权重：握手时声明，评分相近（同一档）的peer优先选容量大的，让大节点承担更多存储
+        this.capacityWeight = options.capacityWeight > 0 ? Number(options.capacityWeight) : 1;
+        // 对方声明的容量权重不可信，超过上限的按上限计，避免虚报容量独占转发名额
+        this.maxPeerCapacity = options.maxPeerCapacity > 0 ? Number(options.maxPeerCapacity) : 16;
+        this.peerCapacity = new Map(); // peerId -> weight
+        // 节点角色：握手和 peer 列表中声明，方便对方按角色选择查询/任务的目标
+        this.roles = Array.isArray(options.roles) ? options.roles.map(String) : [];
+        this.peerRoles = new Map(); // peerId -> [role]
+        // 综合评分：RTT、有效流量（非重复消息）、协议合规（近期违规）与在线时长的加权平均，取值 0..1
+        // 用于转发目标选择（同一档内再比较容量）和重连名额的优先级
+        this.peerScoreWeights = { rtt: 1, usefulness: 1, compliance: 1, uptime: 1, ...(options.peerScoreWeights || {}) };
+        this.peerScoreRttRefMs = options.peerScoreRttRefMs || 100;
+        this.peerScoreUptimeMs = options.peerScoreUptimeMs || 600000;
+        this.peerScoreBand = options.peerScoreBand || 0.05;
+        this.peerDelivery = new Map(); // peerId -> { useful, duplicate }
+        this.peerConnectedAt = new Map(); // peerId -> 本次连接建立时间
+        // 按消息类型关闭处理与转发，支持前缀通配（如 task*）；握手与心跳不可关闭
+        this.disabledMessageTypes = Array.isArray(options.disabledMessageTypes)
+            ? options.disabledMessageTypes.map(String).filter(Boolean)
+            : [];
+        // 入站胶囊的最低置信度：低于该值的既不交给上层存储也不转发（0 = 全部接受）
+        this.minAcceptConfidence = Number(options.minAcceptConfidence) || 0;
+        this.lowConfidenceDropped = 0;
+        // 入站载荷的 JSON Schema（按消息类型，如 { capsule, task }）：不符合的既不交给上层存储也不转发，未配置的类型全部接受
+        this.messageSchemas = options.messageSchemas && typeof options.messageSchemas === 'object' ? options.messageSchemas : {};
+        // 已存有的胶囊既不再交给上层存储也不再转发；hasCapsule 由上层注入
+        this.suppressStoredCapsules = options.suppressStoredCapsules !== false;
+        this.hasCapsule = typeof options.hasCapsule === 'function' ? options.hasCapsule : null;
+        // 并发网络查询上限：超出时排队（wait）或直接返回busy（reject）
+        this.queryTimeoutMs = options.queryTimeoutMs || 5000;
+        this.maxConcurrentQueries = options.maxConcurrentQueries || 8;
+        this.queryBusyMode = options.queryBusyMode === 'reject' ? 'reject' : 'wait';
+        this.activeQueries = 0;
+        this.queryWaiters = [];
+        // 查询分页：结果缓存在本地，游标只编码缓存ID与偏移，翻页不再重新查询网络
+        this.queryCursors = new Map(); // cursorId -> { ids, items, truncated, expiresAt }
+        this.queryCursorTtlMs = options.queryCursorTtlMs || 300000;
+        this.maxQueryCursors = options.maxQueryCursors || 100;
+        // 进行中的查询：requestId -> { startedAt, cancel }，超时、响应或取消时立即移除
+        this.pendingQueries = new Map();
+        // 每次查询最多返回的胶囊数：应答方取到上限即停止并标记 truncated，发起方同样按上限截断
+        this.maxQueryResults = Math.max(Math.floor(Number(options.maxQueryResults) || 200), 1);
+        // 压测模式：只有开启时才把带 bench 标记的胶囊当作压测流量处理（回执、不入库）
+        this.benchMode = options.benchMode === true;
+        // 本地查询处理：由上层注入（返回胶囊数组），未注入时返回空结果
+        this.queryHandler = typeof options.queryHandler === 'function' ? options.queryHandler : null;
+        // 跨节点余额查询：由上层注入（返回签名的余额声明，本地不认识该账户时返回 null）
+        this.balanceHandler = typeof options.balanceHandler === 'function' ? options.balanceHandler : null;
+        this.balanceTimeoutMs = options.balanceTimeoutMs || this.queryTimeoutMs;
+        // 握手挑战：{ publicKeyPem, privateKeyPem }，用于证明持有节点密钥
+        this.identity = options.identity || null;
+        this.requireSignedHandshake = Boolean(options.requireSignedHandshake);
+        this.peerAuth = new Map(); // nodeId -> { verified, publicKeyPem, verifiedAt }
+        // 握手公钥必须与 nodeId 绑定：nodeId 由该公钥派生，或与这里固定的公钥一致
+        this.peerKeys = new Map(Object.entries(options.peerKeys || {})); // nodeId -> publicKeyPem
+        // 消息签名：每条消息带 origin/originKey/signature，origin 必须由 originKey 派生
+        this.signMessages = Boolean(options.signMessages) && !!this.identity;
+        if (this.signMessages && this.nodeId !== nodeIdFromPublicKey(this.identity.publicKeyPem)) {
+            throw new Error('signMessages requires a nodeId derived from the identity public key');
+        }
+        // 违规累计达到阈值后临时封禁（按 nodeId 或 IP）
+        this.bans = new Map(); // peerId/ip -> { until, reason, bannedAt }
+        this.violations = new Map(); // peerId/ip -> [timestamp]
+        this.banThreshold = options.banThreshold || 5;
+        this.banWindowMs = options.banWindowMs || 60000;
+        // 连接数上限（0 = 不限）：满员时新入站连接先顶替最早的未握手匿名连接，
+        // 否则在新连接所在IP近期无违规、且RTT最差的已知peer慢于 peerScoreRttRefMs 时顶替它，都不满足则直接关闭
+        this.maxPeers = options.maxPeers || 0;
+        // 套接字选项：默认关闭 Nagle 让小消息立即发出；keepalive 探测无声断开的连接（0 = 关闭）
+        this.tcpNoDelay = options.tcpNoDelay !== false;
+        this.tcpKeepAliveMs = options.tcpKeepAliveMs ?? 60000;
+        this.connectionStats = { rejected: 0, evicted: 0 };
+        // 每个peer的发送队列：套接字写满（write 返回 false）后消息排队等待 drain。
+        // 队列达到 maxSendQueue（0 = 不限）时丢弃最早的 gossip，控制和任务消息从不丢弃；
+        // 队列持续满员超过 sendQueueStallMs 说明对方跟不上，断开连接
+        this.maxSendQueue = options.maxSendQueue || 0;
+        this.sendQueueStallMs = options.sendQueueStallMs ?? 30000;
+        this.sendQueueStats = { dropped: 0, stalled: 0, byPeer: new Map() };
+        this.banDurationMs = options.banDurationMs || 600000;
+        // 每个连接的入站限速（条/秒，令牌桶，突发上限为1秒的量；0 = 不限）：超出的消息直接丢弃并按peer计数，
+        // 持续超速时每秒记一次违规，累计达到 banThreshold 后封禁并断开
+        this.maxInboundPerSec = options.maxInboundPerSec || 0;
+        this.inboundDrops = new Map(); // peerId/ip -> { dropped, lastDropAt }
+        // 入站消息限制：单行字节数与JSON嵌套深度，超出即丢弃并记违规
+        this.maxMessageBytes = options.maxMessageBytes || 4 * 1024 * 1024;
+        // 出站分帧：'line'（换行分隔，旧协议）或 'length'（4字节大端长度前缀）；
+        // 入站按首字节自动识别，并按对方使用的分帧回复。下个版本默认改为 'length'
+        this.wireFraming = options.wireFraming === 'length' ? 'length' : 'line';
+        this.maxJsonDepth = options.maxJsonDepth || 64;
+        // 非bootstrap peer断开后的重连：只重拨近期低延迟或高流量的peer，指数退避并限制数量
+        this.reconnectPeers = options.reconnectPeers !== false;
+        this.reconnectMaxRttMs = options.reconnectMaxRttMs || 250;
+        this.reconnectMinMessages = options.reconnectMinMessages || 50;
+        this.reconnectBaseDelayMs = options.reconnectBaseDelayMs || 1000;
+        this.reconnectMaxDelayMs = options.reconnectMaxDelayMs || 60000;
+        this.maxReconnectAttempts = options.maxReconnectAttempts || 5;
+        this.maxReconnectPeers = options.maxReconnectPeers || 8;
+        this.peerAddresses = new Map(); // nodeId -> 可拨号地址 host:port
+        this.peerTraffic = new Map(); // nodeId -> 收到的消息数
+        this.reconnectTimers = new Map(); // nodeId -> timer
+        // bootstrap 地址连接失败或断开后按同样的退避一直重试，不受次数和名额限制
+        this.bootstrapTimers = new Map(); // address -> { timer, attempt }
+        this.stopping = false;
+        this.heartbeatTimer = null;
         
         this.setupMessageHandlers();
     }
@@ -33,8 +299,26 @@ class MeshNode extends EventEmitter {
     setupMessageHandlers() {
         // 处理新记忆胶囊
         this.messageHandlers.set('capsule', async (message, peerId) => {
+            if (this.isBenchCapsule(message)) {
+                this.ackBenchCapsule(message.payload.bench);
+                return;
+            }
             this.emit('memory:received', message.payload);
         });
+
+        this.messageHandlers.set('capsule_rating', (message) => {
+            this.emit('capsule:rating', message.payload);
+        });
+
+        this.messageHandlers.set('capsule_acl', (message) => {
+            this.emit('capsule:acl', message.payload);
+        });
+
+        this.messageHandlers.set('bench_ack', (message) => {
+            if (message.payload?.origin === this.nodeId) {
+                this.emit('bench:ack', message.payload);
+            }
+        });
         
         // 处理新任务
         this.messageHandlers.set('task', async (message, peerId) => {
@@ -46,31 +330,90 @@ class MeshNode extends EventEmitter {
             this.emit('task:bid', message.payload);
         });
         
-        // 处理任务完成通知
+        // 处理任务完成通知（状态消息附带签名来源，上层据此判断是否由发布者或被分配节点发出）
         this.messageHandlers.set('task_completed', async (message, peerId) => {
-            this.emit('task:completed', message.payload);
+            this.emit('task:completed', message.payload, this.signedOrigin(message));
         });
 
         // 处理任务失败通知
         this.messageHandlers.set('task_failed', async (message, peerId) => {
-            this.emit('task:failed', message.payload);
+            this.emit('task:failed', message.payload, this.signedOrigin(message));
         });
 
         this.messageHandlers.set('task_assigned', async (message, peerId) => {
-            this.emit('task:assigned', message.payload);
+            this.emit('task:assigned', message.payload, this.signedOrigin(message));
+        });
+
+        this.messageHandlers.set('task_cancelled', async (message, peerId) => {
+            this.emit('task:cancelled', message.payload);
+        });
+
+        this.messageHandlers.set('task_expired', async (message, peerId) => {
+            this.emit('task:expired', message.payload);
+        });
+
+        // 评选模式任务的解与发布者的评选结果
+        this.messageHandlers.set('task_submission', async (message, peerId) => {
+            this.emit('task:submission', message.payload);
+        });
+
+        this.messageHandlers.set('task_awarded', async (message, peerId) => {
+            this.emit('task:awarded', message.payload);
         });
         
         this.messageHandlers.set('task_like', async (message, peerId) => {
             this.emit('task:like', message.payload);
         });
         
+        // 握手后对方直接发来的地址样本：记入地址簿并在连接数不足时拨号
+        this.messageHandlers.set('peer_exchange', (message) => {
+            const { nodeId, addresses } = message.payload || {};
+            if (!this.sharePeerList || nodeId === this.nodeId || !Array.isArray(addresses)) return;
+            this.learnAddresses(addresses);
+            this.dialKnownAddresses();
+        });
+
+        // peer 列表只接受它所描述的节点本人发出的：签名消息看签名者，未签名的只认直连的发送方
+        this.messageHandlers.set('peer_list', (message, peerId) => {
+            const { nodeId, peers, roles, addresses } = message.payload || {};
+            if (!nodeId || nodeId === this.nodeId || !Array.isArray(peers)) return;
+            const reporter = message.signature ? this.signedOrigin(message) : peerId;
+            if (reporter !== nodeId) return;
+            if (Array.isArray(addresses)) {
+                this.learnAddresses(addresses);
+                this.dialKnownAddresses();
+            }
+            this.peerLists.set(nodeId, {
+                peers: peers.filter(id => typeof id === 'string').slice(0, this.maxGraphNodes),
+                roles: Array.isArray(roles) ? roles.map(String) : [],
+                receivedAt: Date.now()
+            });
+            this.prunePeerLists();
+        });
+        
+        // gossip 转发：对方通告了消息ID，本节点稍后仍未收到时索取
+        this.messageHandlers.set('ihave', async (message, peerId) => {
+            this.handleIHave(message.payload?.messageIds, peerId);
+        });
+
+        this.messageHandlers.set('iwant', async (message, peerId) => {
+            const messageIds = Array.isArray(message.payload?.messageIds) ? message.payload.messageIds.slice(0, 100) : [];
+            for (const messageId of messageIds) {
+                const cached = this.gossipCache.get(messageId);
+                if (cached && this.sendToPeer(peerId, cached.message)) {
+                    this.gossipStats.served++;
+                }
+            }
+        });
+
         // 处理查询请求
         this.messageHandlers.set('query', async (message, peerId) => {
             const response = await this.handleQuery(message.payload);
             this.sendToPeer(peerId, {
                 type: 'query_response',
                 payload: response,
-                requestId: message.requestId
+                requestId: message.requestId,
+                ...(message.traceId ? { traceId: message.traceId } : {})
             });
         });
         
@@ -79,6 +422,25 @@ class MeshNode extends EventEmitter {
             this.emit(`query_response:${message.requestId}`, message.payload);
         });
         
+        // 处理余额查询：回复签名的余额声明（不认识该账户时为 null）
+        this.messageHandlers.set('balance_request', async (message, peerId) => {
+            let statement = null;
+            try {
+                statement = this.balanceHandler ? await this.balanceHandler(message.payload || {}) : null;
+            } catch (e) {
+                console.error('Balance handler failed:', e.message);
+            }
+            this.sendToPeer(peerId, {
+                type: 'balance_response',
+                payload: { statement },
+                requestId: message.requestId
+            });
+        });
+
+        this.messageHandlers.set('balance_response', (message, peerId) => {
+            this.emit(`balance_response:${message.requestId}`, message.payload, peerId);
+        });
+        
         // 处理ping
         this.messageHandlers.set('ping', (message, peerId) => {
             const pong = {
@@ -99,6 +461,7 @@ class MeshNode extends EventEmitter {
                     const rtt = Date.now() - pending.sentAt;
                     this.pendingPings.delete(message.pingId);
                     this.peerStats.set(peerId, { rtt, lastSeen: Date.now() });
+                    this.recordRtt(rtt);
                 }
             }
             this.emit('peer:alive', peerId);
@@ -152,6 +515,9 @@ class MeshNode extends EventEmitter {
                 
                 // 启动心跳
                 this.startHeartbeat();
+                this.loadAddressBook();
+                this.loadRttHistogram();
+                this.startPeerListSharing();
                 
                 resolve();
             });
@@ -161,24 +527,40 @@ class MeshNode extends EventEmitter {
     }
     
     handleConnection(socket) {
-        let buffer = '';
         let peerId = null;
         
+        const remoteIp = this.getRemoteIp(socket);
+        if (this.isBanned(remoteIp)) {
+            socket.destroy();
+            return;
+        }
+        
+        if (this.maxPeers > 0 && this.peers.size >= this.maxPeers && !this.makeRoomForInbound(remoteIp)) {
+            this.connectionStats.rejected++;
+            socket.destroy();
+            return;
+        }
+        
+        this.applySocketOptions(socket);
+        
         // Store socket immediately by remote address (temporary key)
         const remoteKey = socket.remoteAddress + ':' + socket.remotePort;
+        socket.meshInboundKey = remoteKey;
+        socket.meshAcceptedAt = Date.now();
         this.peers.set(remoteKey, socket);
         
         socket.on('data', (data) => {
-            buffer += data.toString();
-            
-            // 处理消息（按行分割）
-            let lines = buffer.split('\n');
-            buffer = lines.pop(); // 保留不完整行
+            const lines = this.readFrames(socket, data);
+            if (!lines) {
+                this.recordViolation(peerId || remoteIp, 'message too large');
+                socket.destroy();
+                return;
+            }
             
             for (const line of lines) {
                 if (line.trim()) {
                     try {
-                        const message = JSON.parse(line);
+                        const message = this.parseWireMessage(line);
                         if (message.type === 'handshake' && message.nodeId) {
                             peerId = message.nodeId;
                             const socket = this.peers.get(remoteKey);
@@ -188,18 +570,26 @@ class MeshNode extends EventEmitter {
                                 console.log(`✅ handshake mapped socket for ${peerId} (inbound)`);
                             }
                         }
-                        this.handleMessage(message, peerId || remoteKey);
+                        if (!this.allowInbound(socket, peerId || remoteIp, message)) continue;
+                        this.handleMessage(message, peerId || remoteKey, socket);
                     } catch (e) {
                         console.error('Invalid message:', e.message);
+                        this.recordViolation(peerId || remoteIp, 'invalid message');
                     }
                 }
             }
         });
         
         socket.on('close', () => {
+            this.forgetSendQueueStats(socket, [peerId, remoteKey]);
+            this.forgetInboundDrops(socket, [peerId, remoteIp]);
             if (peerId) {
+                const wasMapped = this.peers.get(peerId) === socket;
                 this.peers.delete(peerId);
                 this.emit('peer:disconnected', peerId);
+                if (wasMapped) {
+                    this.handlePeerLoss(peerId);
+                }
             }
             // Also remove by remote key
             this.peers.delete(remoteKey);
@@ -210,10 +600,123 @@ class MeshNode extends EventEmitter {
         });
     }
     
-    handleMessage(message, peerId) {
+    applySocketOptions(socket) {
+        socket.setNoDelay?.(this.tcpNoDelay);
+        if (this.tcpKeepAliveMs > 0) {
+            socket.setKeepAlive?.(true, this.tcpKeepAliveMs);
+        } else {
+            socket.setKeepAlive?.(false);
+        }
+        socket.meshSocketOptions = { noDelay: this.tcpNoDelay, keepAliveMs: this.tcpKeepAliveMs };
+    }
+
+    // 为新入站连接腾出一个位置，返回是否成功
+    // 已知 peer 中只有慢于 peerScoreRttRefMs 或从未测得RTT（不回 pong）的才可被顶替，按 peer 评分取最低者；
+    // bootstrap 地址上的 peer 不被顶替
+    makeRoomForInbound(remoteIp, now = Date.now()) {
+        const bootstrap = new Set(this.getBootstrapAddresses());
+        let anonymous = null;
+        let weakest = null;
+        for (const [key, socket] of this.peers) {
+            if (socket?.meshInboundKey === key) {
+                if (!anonymous || socket.meshAcceptedAt < anonymous.socket.meshAcceptedAt) anonymous = { key, socket };
+                continue;
+            }
+            if (bootstrap.has(this.peerAddresses.get(key))) continue;
+            const rtt = this.peerStats.get(key)?.rtt;
+            if (typeof rtt === 'number' && rtt <= this.peerScoreRttRefMs) continue;
+            const score = this.getPeerScore(key, now, 0).score;
+            if (!weakest || score < weakest.score) weakest = { key, socket, score };
+        }
+        let victim = anonymous;
+        if (!victim) {
+            const violations = (this.violations.get(remoteIp) || []).filter(ts => now - ts < this.banWindowMs).length;
+            if (violations === 0 && weakest) victim = weakest;
+        }
+        if (!victim) return false;
+        // 先移出 peers，close 时不会当作意外断线去重连
+        this.peers.delete(victim.key);
+        victim.socket?.destroy?.();
+        this.connectionStats.evicted++;
+        console.log(`🔌 Peer limit reached, evicted ${victim.key}`);
+        return true;
+    }
+
+    getConnectionStats() {
+        return {
+            current: this.peers.size,
+            limit: this.maxPeers,
+            ...this.connectionStats,
+            sendQueues: this.getSendQueueStats()
+        };
+    }
+
+    getSendQueueStats() {
+        const queued = {};
+        for (const [key, socket] of this.peers) {
+            if (socket?.meshSendQueue?.length) queued[key] = socket.meshSendQueue.length;
+        }
+        return {
+            limit: this.maxSendQueue,
+            dropped: this.sendQueueStats.dropped,
+            stalled: this.sendQueueStats.stalled,
+            droppedByPeer: Object.fromEntries(this.sendQueueStats.byPeer),
+            queued
+        };
+    }
+
+    parseWireMessage(line) {
+        if (Buffer.byteLength(line) > this.maxMessageBytes) {
+            throw new Error(`message exceeds ${this.maxMessageBytes} bytes`);
+        }
+        if (exceedsJsonDepth(line, this.maxJsonDepth)) {
+            throw new Error(`message nesting exceeds depth ${this.maxJsonDepth}`);
+        }
+        return JSON.parse(line);
+    }
+    
+    handleMessage(message, peerId, socket = null) {
+        if (!this.verifyMessageSignature(message)) {
+            this.recordDrop('badSignature', message);
+            if (message.type === 'handshake') {
+                this.rejectPeer(socket || this.peers.get(peerId), message.nodeId, 'invalid message signature');
+            } else {
+                this.recordViolation(peerId, 'invalid message signature');
+            }
+            return;
+        }
+        if (message.type === 'handshake_ack') {
+            this.handleHandshakeAck(message, socket || this.peers.get(peerId));
+            return;
+        }
+
         // 更新peerId（如果是handshake消息）
         if (message.type === 'handshake') {
+            if (this.isBanned(message.nodeId)) {
+                const banned = socket || this.peers.get(peerId);
+                if (banned) {
+                    try { banned.destroy(); } catch (e) {}
+                }
+                this.peers.delete(peerId);
+                return;
+            }
             const oldKey = peerId; // Could be remoteKey or address like "localhost:4001"
+            if (Array.isArray(message.topics) && message.topics.length > 0) {
+                this.peerTopics.set(message.nodeId, message.topics.map(String));
+            } else {
+                this.peerTopics.delete(message.nodeId);
+            }
+            const capacity = Number(message.capacity);
+            if (capacity > 0 && Number.isFinite(capacity)) {
+                this.peerCapacity.set(message.nodeId, Math.min(capacity, this.maxPeerCapacity));
+            } else {
+                this.peerCapacity.delete(message.nodeId);
+            }
+            if (Array.isArray(message.roles) && message.roles.length > 0) {
+                this.peerRoles.set(message.nodeId, message.roles.map(String));
+            } else {
+                this.peerRoles.delete(message.nodeId);
+            }
             
             // If peerId already looks like a nodeId (starts with node_), skip
             if (!oldKey.startsWith('node_')) {
@@ -234,11 +737,16 @@ class MeshNode extends EventEmitter {
                     this.peers.set(peerId, socket);
                     
                     // Send handshake back for bidirectional connection (only if not already sent)
-                    if (!oldKey.includes(this.nodeId)) {
+                    // Challenge-carrying handshakes are answered in handleHandshakeChallenge
+                    if (!oldKey.includes(this.nodeId) && !(this.identity && message.challenge)) {
                         this.send(socket, {
                             type: 'handshake',
                             nodeId: this.nodeId,
-                            port: this.port
+                            port: this.port,
+                            protocolVersion: PROTOCOL_VERSION,
+                            capacity: this.capacityWeight,
+                            ...(this.roles.length > 0 ? { roles: this.roles } : {}),
+                            ...(this.topics.length > 0 ? { topics: this.topics } : {})
                         });
                     }
                 }
@@ -251,23 +759,93 @@ class MeshNode extends EventEmitter {
             } else {
                 console.log(`✅ handshake mapped socket for ${peerId}`);
             }
+            if (!this.handleHandshakeChallenge(message, socket || mapped)) {
+                return;
+            }
+            this.rememberPeerAddress(peerId, socket || mapped, message.port);
+            if (!this.peerConnectedAt.has(peerId)) {
+                this.peerConnectedAt.set(peerId, Date.now());
+                this.sendPeerExchange(peerId);
+            }
             this.emit('peer:connected', peerId);
+        } else if (this.requireSignedHandshake && !socket?.meshAuth?.verified) {
+            // 未通过挑战的连接不处理业务消息
+            return;
         }
 
+        if (this.isMessageTypeDisabled(message.type)) {
+            return;
+        }
         if (!this.shouldProcessMessage(message)) {
+            this.recordDelivery(peerId, false);
+            return;
+        }
+        this.recordDelivery(peerId, true);
+        // 只有压测模式下的压测胶囊跳过置信度/schema/已存储检查，其他消息类型的 bench 字段不起作用
+        const bench = this.isBenchCapsule(message);
+        if (message.type === 'capsule' && !bench && this.isBelowAcceptConfidence(message.payload)) {
+            this.lowConfidenceDropped++;
+            return;
+        }
+        if (!bench && !this.matchesMessageSchema(message)) {
+            this.recordDrop('schema', message);
+            return;
+        }
+        if (message.type === 'capsule' && !bench && this.isStoredCapsule(message.payload)) {
+            this.recordDrop('storedCapsule', message);
             return;
         }
         if (message && (message.type === 'tx_log_request' || message.type === 'tx_log_batch')) {
             console.log(`⬅️  recv ${message.type} from ${peerId}`);
         }
+        this.logTrace(message, 'recv', `from ${peerId}`);
+        // 处理器可能改动消息内容，签名消息按收到时的原样转发
+        const relayed = message.signature ? structuredClone(message) : message;
         
         const handler = this.messageHandlers.get(message.type);
         if (handler) {
             handler(message, peerId);
         }
+        if (this.peerAddresses.has(peerId)) {
+            this.peerTraffic.set(peerId, (this.peerTraffic.get(peerId) || 0) + 1);
+        }
 
-        if (this.shouldRelayMessage(message)) {
-            this.relayMessage(message, peerId);
+        if (this.shouldRelayMessage(relayed)) {
+            this.relayMessage(relayed, peerId);
+        }
+    }
+
+    // 本节点发出的消息签名一次，转发时保留原始签名；hopsLeft 每跳变化，不参与签名
+    signMessage(message) {
+        if (!this.signMessages || message.signature) return message;
+        message.origin = this.nodeId;
+        message.originKey = this.identity.publicKeyPem;
+        const { hopsLeft, ...signed } = message;
+        message.signature = signPayload(this.identity.privateKeyPem, signed);
+        return message;
+    }
+
+    // 签名已在 handleMessage 入口校验过，签名消息的 origin 可信；未签名的消息没有可信来源
+    signedOrigin(message) {
+        return message?.signature ? message.origin || null : null;
+    }
+
+    // 带签名的消息总是校验；开启签名后拒绝未签名消息
+    verifyMessageSignature(message) {
+        if (!message || !message.signature) {
+            return !this.signMessages;
+        }
+        const { signature, hopsLeft, ...signed } = message;
+        if (!message.origin || !message.originKey) return false;
+        if (nodeIdFromPublicKey(message.originKey) !== message.origin) return false;
+        // 握手等消息自带的 nodeId 必须与签名者一致
+        if (message.nodeId && message.nodeId !== message.origin) return false;
+        // 竞价只能由竞价者本人签名发出，否则任何节点都能替别人出价
+        if (message.type === 'task_bid' && message.payload?.bid?.nodeId !== message.origin) return false;
+        try {
+            return verifyPayload(message.originKey, signed, signature);
+        } catch (e) {
+            return false;
         }
     }
     
@@ -286,13 +864,63 @@ class MeshNode extends EventEmitter {
     }
     
     async connectToBootstrapNodes() {
-        for (const addr of this.bootstrapNodes) {
+        if (this.dnsSeed) {
+            await this.resolveDnsSeed();
+            this.startDnsSeedRefresh();
+        }
+        for (const addr of this.getBootstrapAddresses()) {
             try {
                 await this.connectToPeer(addr);
             } catch (e) {
                 console.error(`Failed to connect to bootstrap ${addr}:`, e.message);
+                this.scheduleBootstrapReconnect(addr);
+            }
+        }
+    }
+
+    getBootstrapAddresses() {
+        const self = new Set([`localhost:${this.port}`, `127.0.0.1:${this.port}`]);
+        const addresses = [...this.bootstrapNodes, ...this.dnsSeedCache.addresses];
+        return Array.from(new Set(addresses)).filter(addr => !self.has(addr));
+    }
+
+    async resolveDnsSeed() {
+        if (!this.dnsSeed) return [];
+        let addresses = [];
+        try {
+            const records = await this.resolver.resolveTxt(this.dnsSeed);
+            addresses = records
+                .map(chunks => chunks.join(''))
+                .flatMap(record => record.split(/[\s,]+/))
+                .filter(entry => /^[^\s:]+:\d+$/.test(entry));
+        } catch (e) {
+            // 没有TXT记录时尝试A记录
+        }
+        if (addresses.length === 0) {
+            try {
+                const ips = await this.resolver.resolve4(this.dnsSeed);
+                addresses = ips.map(ip => `${ip}:${this.dnsSeedPort}`);
+            } catch (e) {
+                console.error(`DNS seed ${this.dnsSeed} lookup failed:`, e.message);
             }
         }
+        if (addresses.length > 0) {
+            this.dnsSeedCache = { addresses: Array.from(new Set(addresses)), resolvedAt: Date.now() };
+            console.log(`🌱 DNS seed ${this.dnsSeed}: ${this.dnsSeedCache.addresses.length} addresses`);
+        }
+        return this.dnsSeedCache.addresses;
+    }
+
+    startDnsSeedRefresh() {
+        if (this.dnsSeedTimer) return;
+        this.dnsSeedTimer = setInterval(async () => {
+            const addresses = await this.resolveDnsSeed();
+            for (const addr of addresses) {
+                if (this.peers.has(addr)) continue;
+                this.connectToPeer(addr).catch(() => {});
+            }
+        }, this.dnsSeedRefreshMs);
+        this.dnsSeedTimer.unref?.();
     }
     
     async connectToPeer(address) {
@@ -300,41 +928,47 @@ class MeshNode extends EventEmitter {
             if (this.peers.has(address)) {
                 return resolve();
             }
+            if (this.isBanned(address) || this.isBanned(address.split(':')[0])) {
+                return reject(new Error(`Peer ${address} is banned`));
+            }
             const [host, port] = address.split(':');
+            let remoteNodeId = null;
             const socket = net.createConnection({ host, port: parseInt(port) }, () => {
+                this.applySocketOptions(socket);
                 // Store temporarily by address
+                socket.meshConnected = true;
                 this.peers.set(address, socket);
                 
                 // 发送handshake
-                this.send(socket, {
-                    type: 'handshake',
-                    nodeId: this.nodeId,
-                    port: this.port
-                });
+                this.send(socket, this.buildHandshake(socket));
                 
                 console.log(`🔗 Connected to peer: ${address}`);
                 resolve();
             });
             
             // Handle incoming messages on this outgoing connection
-            let buffer = '';
             socket.on('data', (data) => {
-                buffer += data.toString();
-                let lines = buffer.split('\n');
-                buffer = lines.pop();
+                const lines = this.readFrames(socket, data);
+                if (!lines) {
+                    this.recordViolation(this.getRemoteIp(socket), 'message too large');
+                    socket.destroy();
+                    return;
+                }
                 
                 for (const line of lines) {
                     if (line.trim()) {
                         try {
-                            const message = JSON.parse(line);
+                            const message = this.parseWireMessage(line);
                             // Handle peer handshake response - update peer mapping
                             if (message.type === 'handshake' && message.nodeId) {
                                 // Remove old address key, add nodeId
                                 this.peers.delete(address);
                                 this.peers.set(message.nodeId, socket);
+                                remoteNodeId = message.nodeId;
                                 console.log(`🔄 Mapped peer: ${message.nodeId}`);
                             }
-                            this.handleMessage(message, message.nodeId || address);
+                            if (!this.allowInbound(socket, remoteNodeId || this.getRemoteIp(socket), message)) continue;
+                            this.handleMessage(message, message.nodeId || address, socket);
                         } catch (e) {
                             // Ignore parse errors
                         }
@@ -344,140 +978,953 @@ class MeshNode extends EventEmitter {
             
             socket.on('error', reject);
             
+            socket.meshDialAddress = address;
             socket.on('close', () => {
+                this.forgetSendQueueStats(socket, [address, remoteNodeId]);
+                this.forgetInboundDrops(socket, [remoteNodeId, this.getRemoteIp(socket)]);
                 this.peers.delete(address);
+                if (remoteNodeId && this.peers.get(remoteNodeId) === socket) {
+                    this.peers.delete(remoteNodeId);
+                    this.emit('peer:disconnected', remoteNodeId);
+                    this.handlePeerLoss(remoteNodeId);
+                } else if (!remoteNodeId && socket.meshConnected && this.getBootstrapAddresses().includes(address)) {
+                    // 握手前就断开的 bootstrap 连接
+                    this.scheduleBootstrapReconnect(address);
+                }
             });
         });
     }
-    
-    send(socket, message) {
-        if (socket && !socket.destroyed && socket.writable) {
-            if (message && (message.type === 'tx_log_request' || message.type === 'tx_log_batch')) {
-                console.log(`➡️  send ${message.type} to ${socket.remoteAddress || 'peer'}:${socket.remotePort || ''}`);
-            }
-            socket.write(JSON.stringify(message) + '\n');
+
+    // 出站连接使用拨号地址；入站连接使用对端IP + 握手中声明的端口
+    rememberPeerAddress(nodeId, socket, advertisedPort) {
+        if (!nodeId || !socket) return;
+        let address = socket.meshDialAddress || null;
+        const ip = this.getRemoteIp(socket);
+        if (!address && ip && Number(advertisedPort) > 0) {
+            address = `${ip}:${Number(advertisedPort)}`;
+        }
+        if (address) {
+            this.peerAddresses.set(nodeId, address);
         }
     }
-    
-    sendToPeer(peerId, message) {
-        const socket = this.peers.get(peerId) || this.getSocketForPeer(peerId);
-        if (socket && !socket.destroyed) {
-            if (!socket.writable) {
-                console.log(`⚠️  peer socket not writable: ${peerId}`);
-                return false;
+
+    isReconnectCandidate(nodeId) {
+        const rtt = this.peerStats.get(nodeId)?.rtt;
+        const traffic = this.peerTraffic.get(nodeId) || 0;
+        return (typeof rtt === 'number' && rtt <= this.reconnectMaxRttMs) || traffic >= this.reconnectMinMessages;
+    }
+
+    handlePeerLoss(nodeId) {
+        this.peerConnectedAt.delete(nodeId);
+        const scheduled = this.reconnectLostPeer(nodeId);
+        this.forgetPeerDelivery(nodeId);
+        return scheduled;
+    }
+
+    reconnectLostPeer(nodeId) {
+        if (!this.reconnectPeers || this.stopping || this.reconnectTimers.has(nodeId)) return false;
+        const address = this.peerAddresses.get(nodeId);
+        if (!address) return false;
+        if (this.getBootstrapAddresses().includes(address)) {
+            return this.scheduleBootstrapReconnect(address);
+        }
+        if (!this.isReconnectCandidate(nodeId) || this.isBanned(nodeId)) return false;
+        if (this.reconnectTimers.size >= this.maxReconnectPeers) {
+            // 名额已满：评分更高的peer顶替评分最低的待重连peer
+            let lowest = null;
+            for (const pendingId of this.reconnectTimers.keys()) {
+                const score = this.getPeerScore(pendingId).score;
+                if (!lowest || score < lowest.score) lowest = { nodeId: pendingId, score };
             }
-            this.send(socket, message);
-            return true;
+            if (!lowest || lowest.score >= this.getPeerScore(nodeId).score) return false;
+            clearTimeout(this.reconnectTimers.get(lowest.nodeId));
+            this.reconnectTimers.delete(lowest.nodeId);
+            this.forgetPeerDelivery(lowest.nodeId);
         }
-        console.log(`⚠️  missing peer socket: ${peerId}`);
-        // Clean up stale peer
-        this.peers.delete(peerId);
-        return false;
+        this.scheduleReconnect(nodeId, address, 0);
+        return true;
     }
-    
-    // 广播胶囊到所有peer
-    async broadcastCapsule(capsule) {
-        const message = {
-            type: 'capsule',
-            payload: capsule,
-            timestamp: Date.now()
-        };
-        this.broadcast(message, { fanout: this.defaultFanout, hopsLeft: this.defaultHops });
+
+    recordDelivery(peerId, useful) {
+        if (!peerId) return;
+        const delivery = this.peerDelivery.get(peerId) || { useful: 0, duplicate: 0 };
+        if (useful) delivery.useful++;
+        else delivery.duplicate++;
+        this.peerDelivery.set(peerId, delivery);
     }
-    
-    // 广播任务
-    async broadcastTask(task) {
-        const message = {
-            type: 'task',
-            payload: task,
-            timestamp: Date.now()
+
+    // 投递计数只为在线和待重连的 peer 保留（待重连的评分决定重连名额），其余断开即删除
+    forgetPeerDelivery(nodeId) {
+        if (!nodeId || this.peers.has(nodeId) || this.reconnectTimers.has(nodeId)) return;
+        this.peerDelivery.delete(nodeId);
+    }
+
+    // missingRtt：未测得RTT时该项的取值，默认中性；顶替连接时按最差处理
+    getPeerScore(peerId, now = Date.now(), missingRtt = 0.5) {
+        const rtt = this.peerStats.get(peerId)?.rtt;
+        const delivery = this.peerDelivery.get(peerId) || { useful: 0, duplicate: 0 };
+        const violations = (this.violations.get(peerId) || []).filter(ts => now - ts < this.banWindowMs).length;
+        const connectedAt = this.peerConnectedAt.get(peerId);
+        const components = {
+            rtt: typeof rtt === 'number' ? this.peerScoreRttRefMs / (this.peerScoreRttRefMs + rtt) : missingRtt,
+            usefulness: Math.min(1, delivery.useful / this.reconnectMinMessages),
+            compliance: 1 / (1 + violations),
+            uptime: connectedAt ? Math.min(1, (now - connectedAt) / this.peerScoreUptimeMs) : 0
         };
-        this.broadcast(message, { fanout: this.taskFanout, hopsLeft: this.taskHops });
+        let weighted = 0;
+        let total = 0;
+        for (const [name, value] of Object.entries(components)) {
+            const weight = Math.max(0, Number(this.peerScoreWeights[name]) || 0);
+            weighted += weight * value;
+            total += weight;
+        }
+        return { score: total > 0 ? weighted / total : 0, ...components };
     }
-    
-    broadcast(message, options = {}) {
-        const { fanout, excludePeerId, hopsLeft } = options;
-        const peers = this.selectPeers(fanout || this.defaultFanout, excludePeerId);
-        const messageId = this.ensureMessageId(message);
-        this.markMessageSeen(messageId);
-        for (const { peerId, socket } of peers) {
+
+    scheduleReconnect(nodeId, address, attempt) {
+        if (attempt >= this.maxReconnectAttempts || this.stopping) {
+            this.reconnectTimers.delete(nodeId);
+            this.forgetPeerDelivery(nodeId);
+            console.log(`🔌 Giving up reconnecting to ${nodeId} (${address})`);
+            return;
+        }
+        const delay = Math.min(this.reconnectBaseDelayMs * 2 ** attempt, this.reconnectMaxDelayMs);
+        const timer = setTimeout(async () => {
+            if (this.peers.has(nodeId)) {
+                this.reconnectTimers.delete(nodeId);
+                return;
+            }
             try {
-                if (socket && !socket.destroyed) {
-                    const outbound = {
-                        ...message,
-                        messageId,
-                        hopsLeft: typeof hopsLeft === 'number' ? hopsLeft : this.defaultHops
-                    };
-                    this.send(socket, outbound);
-                } else {
-                    this.peers.delete(peerId);
-                }
+                await this.connectToPeer(address);
+                this.reconnectTimers.delete(nodeId);
+                this.forgetPeerDelivery(nodeId);
+                console.log(`🔁 Reconnected to ${nodeId} (${address})`);
             } catch (e) {
-                console.error(`Failed to send to ${peerId}:`, e.message);
-                this.peers.delete(peerId);
+                this.scheduleReconnect(nodeId, address, attempt + 1);
             }
-        }
+        }, delay);
+        timer.unref?.();
+        this.reconnectTimers.set(nodeId, timer);
     }
 
-    broadcastAll(message, options = {}) {
-        const { excludePeerId, hopsLeft } = options;
-        const messageId = this.ensureMessageId(message);
-        this.markMessageSeen(messageId);
-        for (const [peerId, socket] of this.peers) {
-            if (excludePeerId && peerId === excludePeerId) continue;
+    scheduleBootstrapReconnect(address, attempt = 0) {
+        if (!this.reconnectPeers || this.stopping || this.bootstrapTimers.has(address)) return false;
+        const delay = Math.min(this.reconnectBaseDelayMs * 2 ** attempt, this.reconnectMaxDelayMs);
+        const timer = setTimeout(async () => {
+            this.bootstrapTimers.delete(address);
+            if (this.stopping || this.isConnectedToAddress(address)) return;
             try {
-                if (socket && !socket.destroyed) {
-                    const outbound = {
-                        ...message,
-                        messageId,
-                        hopsLeft: typeof hopsLeft === 'number' ? hopsLeft : this.defaultHops
-                    };
-                    this.send(socket, outbound);
-                } else {
-                    this.peers.delete(peerId);
-                }
+                await this.connectToPeer(address);
+                console.log(`🔁 Reconnected to bootstrap ${address}`);
             } catch (e) {
-                console.error(`Failed to send to ${peerId}:`, e.message);
-                this.peers.delete(peerId);
+                this.scheduleBootstrapReconnect(address, attempt + 1);
             }
+        }, delay);
+        timer.unref?.();
+        this.bootstrapTimers.set(address, { timer, attempt });
+        return true;
+    }
+
+    isConnectedToAddress(address) {
+        if (this.peers.has(address)) return true;
+        for (const socket of this.peers.values()) {
+            if (socket && !socket.destroyed && socket.meshDialAddress === address) return true;
         }
+        return false;
     }
-    
-    // 查询网络中的记忆
-    async queryMemories(filter = {}) {
-        const requestId = crypto.randomUUID();
-        const query = {
-            type: 'query',
-            payload: { type: 'memories', filter },
-            requestId
+
+    buildHandshake(socket, peerChallenge = null) {
+        const message = {
+            type: 'handshake',
+            nodeId: this.nodeId,
+            port: this.port,
+            protocolVersion: PROTOCOL_VERSION,
+            capacity: this.capacityWeight
         };
+        if (this.roles.length > 0) {
+            message.roles = this.roles;
+        }
+        if (this.topics.length > 0) {
+            message.topics = this.topics;
+        }
+        if (!this.identity) {
+            return message;
+        }
+        const challenge = crypto.randomBytes(16).toString('hex');
+        if (socket) {
+            socket.meshChallenge = challenge;
+        }
+        message.challenge = challenge;
+        message.publicKeyPem = this.identity.publicKeyPem;
+        if (peerChallenge) {
+            message.challengeResponse = this.signChallenge(peerChallenge);
+        }
+        return message;
+    }
+
+    signChallenge(challenge) {
+        return signPayload(this.identity.privateKeyPem, { challenge, nodeId: this.nodeId });
+    }
+
+    isKeyBoundToNode(nodeId, publicKeyPem) {
+        if (!nodeId || !publicKeyPem) return false;
+        const pinned = this.peerKeys.get(nodeId);
+        if (pinned) return pinned.trim() === String(publicKeyPem).trim();
+        return nodeIdFromPublicKey(publicKeyPem) === nodeId;
+    }
+
+    // 签名有效还不够：出示的公钥必须属于所声明的 nodeId，否则任何密钥都能冒充任意节点
+    verifyChallengeResponse(nodeId, publicKeyPem, challenge, signature) {
+        if (!publicKeyPem || !challenge || !signature) return false;
+        if (!this.isKeyBoundToNode(nodeId, publicKeyPem)) return false;
+        try {
+            return verifyPayload(publicKeyPem, { challenge, nodeId }, signature);
+        } catch (e) {
+            return false;
+        }
+    }
+
+    recordPeerAuth(socket, nodeId, verified, publicKeyPem) {
+        const auth = { verified, publicKeyPem: publicKeyPem || null, verifiedAt: verified ? Date.now() : null };
+        if (socket) {
+            socket.meshAuth = auth;
+        }
+        if (nodeId) {
+            this.peerAuth.set(nodeId, auth);
+        }
+        return auth;
+    }
+
+    rejectPeer(socket, nodeId, reason) {
+        console.log(`⛔ Handshake rejected for ${nodeId || 'unknown'}: ${reason}`);
+        if (nodeId && this.peers.get(nodeId) === socket) {
+            this.peers.delete(nodeId);
+        }
+        if (socket) {
+            try { socket.destroy(); } catch (e) {}
+        }
+        this.emit('peer:rejected', nodeId, reason);
+        this.recordViolation(nodeId || this.getRemoteIp(socket), reason);
+    }
+
+    recordRtt(rtt) {
+        const value = Number(rtt);
+        if (!Number.isFinite(value) || value < 0) return;
+        const h = this.rttHistogram;
+        const idx = this.rttBuckets.findIndex(bound => value <= bound);
+        h.counts[idx] += 1;
+        h.count += 1;
+        h.min = h.min === null ? value : Math.min(h.min, value);
+        h.max = h.max === null ? value : Math.max(h.max, value);
+    }
+
+    // 按桶估算分位数：取包含该排名的桶上界，并限制在 [min, max] 内
+    getRttPercentile(p) {
+        const h = this.rttHistogram;
+        if (h.count === 0) return null;
+        const rank = Math.max(1, Math.ceil(p * h.count));
+        let seen = 0;
+        for (let i = 0; i < h.counts.length; i++) {
+            seen += h.counts[i];
+            if (seen >= rank) {
+                return Math.max(h.min, Math.min(h.max, this.rttBuckets[i]));
+            }
+        }
+        return h.max;
+    }
+
+    getRttSummary() {
+        const h = this.rttHistogram;
+        return {
+            count: h.count,
+            min: h.min,
+            median: this.getRttPercentile(0.5),
+            p95: this.getRttPercentile(0.95),
+            max: h.max,
+            buckets: this.rttBuckets.map((bound, i) => ({
+                le: Number.isFinite(bound) ? bound : '+Inf',
+                count: h.counts[i]
+            }))
+        };
+    }
+
+    // 桶边界与当前配置不一致的文件直接忽略，避免把计数放进错误的桶
+    loadRttHistogram() {
+        if (!this.rttHistogramPath) return;
+        try {
+            const saved = JSON.parse(require('fs').readFileSync(this.rttHistogramPath, 'utf8'));
+            const bounds = this.rttBuckets.map(bound => Number.isFinite(bound) ? bound : null);
+            if (JSON.stringify(saved?.buckets) !== JSON.stringify(bounds)) return;
+            const counts = saved.counts;
+            if (!Array.isArray(counts) || counts.length !== bounds.length) return;
+            if (!counts.every(c => Number.isInteger(c) && c >= 0)) return;
+            const count = counts.reduce((sum, c) => sum + c, 0);
+            this.rttHistogram = {
+                counts: counts.slice(),
+                count,
+                min: count > 0 && Number.isFinite(saved.min) ? saved.min : null,
+                max: count > 0 && Number.isFinite(saved.max) ? saved.max : null
+            };
+        } catch (e) {
+            // 文件不存在或损坏时从空直方图开始
+        }
+    }
+
+    saveRttHistogram() {
+        if (!this.rttHistogramPath) return;
+        try {
+            const h = this.rttHistogram;
+            require('fs').writeFileSync(this.rttHistogramPath, JSON.stringify({
+                buckets: this.rttBuckets.map(bound => Number.isFinite(bound) ? bound : null),
+                counts: h.counts,
+                min: h.min,
+                max: h.max
+            }));
+        } catch (e) {
+            console.error('Failed to save RTT histogram:', e.message);
+        }
+    }
+
+    getRemoteIp(socket) {
+        return socket?.remoteAddress ? socket.remoteAddress.replace('::ffff:', '') : null;
+    }
+
+    recordViolation(peerKey, reason) {
+        if (!peerKey) return false;
+        const now = Date.now();
+        const recent = (this.violations.get(peerKey) || []).filter(ts => now - ts < this.banWindowMs);
+        recent.push(now);
+        this.violations.set(peerKey, recent);
+        if (recent.length >= this.banThreshold) {
+            this.violations.delete(peerKey);
+            this.banPeer(peerKey, this.banDurationMs, reason);
+            return true;
+        }
+        return false;
+    }
+
+    banPeer(peerKey, durationMs = this.banDurationMs, reason = 'manual') {
+        if (!peerKey) return null;
+        const now = Date.now();
+        const ban = { peerId: peerKey, reason, bannedAt: now, until: now + Number(durationMs) };
+        this.bans.set(peerKey, ban);
+        console.log(`🚫 Peer banned: ${peerKey} (${reason}) until ${new Date(ban.until).toISOString()}`);
+        for (const [id, socket] of this.peers) {
+            if (id === peerKey || this.getRemoteIp(socket) === peerKey) {
+                try { socket.destroy(); } catch (e) {}
+                this.peers.delete(id);
+            }
+        }
+        this.emit('peer:banned', ban);
+        return ban;
+    }
+
+    unbanPeer(peerKey) {
+        this.violations.delete(peerKey);
+        return this.bans.delete(peerKey);
+    }
+
+    isBanned(peerKey) {
+        if (!peerKey) return false;
+        const ban = this.bans.get(peerKey);
+        if (!ban) return false;
+        if (Date.now() >= ban.until) {
+            this.bans.delete(peerKey);
+            return false;
+        }
+        return true;
+    }
+
+    getBans() {
+        const now = Date.now();
+        for (const [peerKey, ban] of this.bans) {
+            if (now >= ban.until) {
+                this.bans.delete(peerKey);
+            }
+        }
+        return Array.from(this.bans.values());
+    }
+
+    // 处理握手中的挑战：回应对方的nonce，并校验对方对我方nonce的签名
+    // 返回false表示连接已被拒绝
+    handleHandshakeChallenge(message, socket) {
+        if (!socket) return true;
+        if (!message.challenge) {
+            this.recordPeerAuth(socket, message.nodeId, false, null);
+            if (this.requireSignedHandshake) {
+                this.rejectPeer(socket, message.nodeId, 'missing challenge');
+                return false;
+            }
+            return true;
+        }
+        socket.meshPeerPublicKey = message.publicKeyPem || null;
+        if (!message.challengeResponse) {
+            // 对方发起握手：回复我方挑战并签名对方的nonce，等待 handshake_ack
+            this.recordPeerAuth(socket, message.nodeId, false, message.publicKeyPem);
+            if (this.identity) {
+                this.send(socket, this.buildHandshake(socket, message.challenge));
+            }
+            return true;
+        }
+        const verified = this.verifyChallengeResponse(message.nodeId, message.publicKeyPem, socket.meshChallenge, message.challengeResponse);
+        this.recordPeerAuth(socket, message.nodeId, verified, message.publicKeyPem);
+        if (!verified && this.requireSignedHandshake) {
+            this.rejectPeer(socket, message.nodeId, 'invalid challenge response');
+            return false;
+        }
+        if (this.identity) {
+            this.send(socket, {
+                type: 'handshake_ack',
+                nodeId: this.nodeId,
+                challengeResponse: this.signChallenge(message.challenge)
+            });
+        }
+        return true;
+    }
+
+    handleHandshakeAck(message, socket) {
+        if (!socket) return;
+        const verified = this.verifyChallengeResponse(message.nodeId, socket.meshPeerPublicKey, socket.meshChallenge, message.challengeResponse);
+        // 每个nonce只能使用一次
+        socket.meshChallenge = null;
+        this.recordPeerAuth(socket, message.nodeId, verified, socket.meshPeerPublicKey);
+        if (!verified && this.requireSignedHandshake) {
+            this.rejectPeer(socket, message.nodeId, 'invalid handshake ack');
+        }
+    }
+    
+    send(socket, message) {
+        if (socket && !socket.destroyed && socket.writable) {
+            if (message && (message.type === 'tx_log_request' || message.type === 'tx_log_batch')) {
+                console.log(`➡️  send ${message.type} to ${socket.remoteAddress || 'peer'}:${socket.remotePort || ''}`);
+            }
+            this.signMessage(message);
+            const frame = this.encodeFrame(message, socket.meshFraming || this.wireFraming);
+            if (socket.meshAwaitingDrain) {
+                this.enqueueFrame(socket, frame, message);
+            } else if (socket.write(frame) === false) {
+                this.waitForDrain(socket);
+            }
+        }
+    }
+
+    waitForDrain(socket) {
+        socket.meshSendQueue = socket.meshSendQueue || [];
+        socket.meshAwaitingDrain = true;
+        if (socket.meshDrainListener) return;
+        socket.meshDrainListener = () => this.flushSendQueue(socket);
+        socket.on('drain', socket.meshDrainListener);
+    }
+
+    isDroppableMessage(message) {
+        return DROPPABLE_MESSAGE_TYPES.includes(message?.type);
+    }
+
+    enqueueFrame(socket, frame, message, now = Date.now()) {
+        const queue = socket.meshSendQueue;
+        const droppable = this.isDroppableMessage(message);
+        if (this.maxSendQueue > 0 && queue.length >= this.maxSendQueue) {
+            socket.meshSendQueueFullSince = socket.meshSendQueueFullSince || now;
+            if (this.sendQueueStallMs > 0 && now - socket.meshSendQueueFullSince >= this.sendQueueStallMs) {
+                this.disconnectStalledPeer(socket);
+                return;
+            }
+            const oldest = queue.findIndex(item => item.droppable);
+            if (oldest >= 0) {
+                this.recordSendQueueDrop(socket, queue.splice(oldest, 1)[0].message);
+            } else if (droppable) {
+                this.recordSendQueueDrop(socket, message);
+                return;
+            }
+            // 队列里只剩控制/任务消息时允许超出上限
+        }
+        queue.push({ frame, message, droppable });
+    }
+
+    flushSendQueue(socket) {
+        const queue = socket.meshSendQueue || [];
+        socket.meshAwaitingDrain = false;
+        while (queue.length > 0 && !socket.destroyed) {
+            if (socket.write(queue.shift().frame) === false) {
+                socket.meshAwaitingDrain = true;
+                break;
+            }
+        }
+        if (this.maxSendQueue === 0 || queue.length < this.maxSendQueue) {
+            socket.meshSendQueueFullSince = null;
+        }
+    }
+
+    peerKeysForSocket(socket) {
+        return Array.from(this.peers).filter(([, s]) => s === socket).map(([key]) => key);
+    }
+
+    recordSendQueueDrop(socket, message) {
+        const keys = this.peerKeysForSocket(socket);
+        // 优先按 nodeId 记录，握手前只有地址
+        const peer = keys.find(key => !key.includes(':')) || keys[0] || 'unknown';
+        this.sendQueueStats.dropped++;
+        this.sendQueueStats.byPeer.set(peer, (this.sendQueueStats.byPeer.get(peer) || 0) + 1);
+        this.recordDrop('sendQueueFull', message);
+    }
+
+    // 对端不再有新流量时 enqueueFrame 不会被调用，由心跳定期检查队列满员是否超时
+    checkStalledSendQueues(now = Date.now()) {
+        if (this.sendQueueStallMs <= 0) return;
+        for (const socket of new Set(this.peers.values())) {
+            const fullSince = socket?.meshSendQueueFullSince;
+            if (fullSince && !socket.destroyed && now - fullSince >= this.sendQueueStallMs) {
+                this.disconnectStalledPeer(socket);
+            }
+        }
+    }
+
+    // 断开后删除该 peer 的丢弃计数；同一 key 已被新连接占用时保留
+    forgetSendQueueStats(socket, keys) {
+        for (const key of keys) {
+            if (!key) continue;
+            const current = this.peers.get(key);
+            if (current && current !== socket) continue;
+            this.sendQueueStats.byPeer.delete(key);
+        }
+    }
+
+    // 断开后删除入站丢弃计数（握手前按IP计）；同一 nodeId 或 IP 仍有其他连接时保留
+    forgetInboundDrops(socket, keys) {
+        for (const key of keys) {
+            if (!key || !this.inboundDrops.has(key)) continue;
+            const current = this.peers.get(key);
+            if (current && current !== socket) continue;
+            let shared = false;
+            for (const other of this.peers.values()) {
+                if (other !== socket && this.getRemoteIp(other) === key) {
+                    shared = true;
+                    break;
+                }
+            }
+            if (!shared) this.inboundDrops.delete(key);
+        }
+    }
+
+    disconnectStalledPeer(socket) {
+        const keys = this.peerKeysForSocket(socket);
+        // 先移出 peers，close 时不会当作意外断线去重连
+        keys.forEach(key => this.peers.delete(key));
+        this.forgetSendQueueStats(socket, keys);
+        this.forgetInboundDrops(socket, [...keys, this.getRemoteIp(socket)]);
+        socket.meshSendQueue = [];
+        socket.destroy?.();
+        this.sendQueueStats.stalled++;
+        console.log(`🐢 Send queue full for ${this.sendQueueStallMs}ms, disconnected ${keys[0] || 'peer'}`);
+    }
+
+    encodeFrame(message, framing = this.wireFraming) {
+        if (framing !== 'length') {
+            return JSON.stringify(message) + '\n';
+        }
+        const body = Buffer.from(JSON.stringify(message));
+        const header = Buffer.alloc(4);
+        header.writeUInt32BE(body.length, 0);
+        return Buffer.concat([header, body]);
+    }
+
+    // 拆出完整的帧；首字节为 '{' 或空白时按行分帧，否则为长度前缀。
+    // 帧超过 maxMessageBytes 时返回 null，由调用方断开连接
+    readFrames(socket, data) {
+        const state = socket.meshFrameState || (socket.meshFrameState = { buffer: Buffer.alloc(0) });
+        state.buffer = state.buffer.length > 0 ? Buffer.concat([state.buffer, data]) : Buffer.from(data);
+        if (!socket.meshFraming && state.buffer.length > 0) {
+            const first = state.buffer[0];
+            socket.meshFraming = first === 0x7b || first === 0x20 || first === 0x0a || first === 0x0d || first === 0x09 ? 'line' : 'length';
+        }
+        const frames = [];
+        if (socket.meshFraming === 'length') {
+            while (state.buffer.length >= 4) {
+                const size = state.buffer.readUInt32BE(0);
+                if (size > this.maxMessageBytes) {
+                    state.buffer = Buffer.alloc(0);
+                    return null;
+                }
+                if (state.buffer.length < 4 + size) break;
+                frames.push(state.buffer.subarray(4, 4 + size).toString('utf8'));
+                state.buffer = state.buffer.subarray(4 + size);
+            }
+            return frames;
+        }
+        let newline;
+        while ((newline = state.buffer.indexOf(0x0a)) !== -1) {
+            frames.push(state.buffer.subarray(0, newline).toString('utf8'));
+            state.buffer = state.buffer.subarray(newline + 1);
+        }
+        if (state.buffer.length > this.maxMessageBytes) {
+            state.buffer = Buffer.alloc(0);
+            return null;
+        }
+        return frames;
+    }
+    
+    sendToPeer(peerId, message) {
+        const socket = this.peers.get(peerId) || this.getSocketForPeer(peerId);
+        if (socket && !socket.destroyed) {
+            if (!socket.writable) {
+                console.log(`⚠️  peer socket not writable: ${peerId}`);
+                return false;
+            }
+            this.send(socket, message);
+            return true;
+        }
+        console.log(`⚠️  missing peer socket: ${peerId}`);
+        // Clean up stale peer
+        this.peers.delete(peerId);
+        return false;
+    }
+    
+    // 广播胶囊到所有peer
+    async broadcastCapsule(capsule) {
+        const message = {
+            type: 'capsule',
+            payload: capsule,
+            timestamp: Date.now()
+        };
+        if (!(this.maxPublishPerSec > 0)) {
+            this.broadcast(message, { fanout: this.defaultFanout, hopsLeft: this.defaultHops });
+            return;
+        }
+        this.assertPublishCapacity();
+        this.publishQueue.push(message);
+        this.publishStats.queued++;
+        this.drainPublishQueue();
+    }
+
+    drainPublishQueue() {
+        if (this.publishTimer || this.stopping) return;
+        const now = Date.now();
+        if (now < this.nextPublishAt) {
+            this.publishTimer = setTimeout(() => {
+                this.publishTimer = null;
+                this.drainPublishQueue();
+            }, this.nextPublishAt - now);
+            return;
+        }
+        const message = this.publishQueue.shift();
+        if (!message) return;
+        this.broadcast(message, { fanout: this.defaultFanout, hopsLeft: this.defaultHops });
+        this.publishStats.sent++;
+        this.nextPublishAt = now + 1000 / this.maxPublishPerSec;
+        if (this.publishQueue.length > 0) {
+            this.drainPublishQueue();
+        }
+    }
+
+    assertPublishCapacity() {
+        if (!(this.maxPublishPerSec > 0) || !(this.maxPublishQueue > 0) || this.publishQueue.length < this.maxPublishQueue) return;
+        this.publishStats.rejected++;
+        const err = new Error('Node busy: publish queue is full');
+        err.code = 'BUSY';
+        throw err;
+    }
+
+    getPublishQueueStats() {
+        return {
+            depth: this.publishQueue.length,
+            maxPublishPerSec: this.maxPublishPerSec,
+            maxPublishQueue: this.maxPublishQueue,
+            ...this.publishStats
+        };
+    }
+    
+    // 未开启压测模式时 bench 标记没有任何特殊待遇，胶囊按普通胶囊处理
+    isBenchCapsule(message) {
+        return this.benchMode && message?.type === 'capsule' && !!message.payload?.bench;
+    }
+
+    // 压测胶囊不入库，只向发起节点回执（经gossip转发）
+    ackBenchCapsule(bench) {
+        if (!bench.benchId || !bench.origin || bench.origin === this.nodeId) return;
+        this.broadcastAll({
+            type: 'bench_ack',
+            payload: { benchId: bench.benchId, seq: bench.seq, origin: bench.origin, nodeId: this.nodeId },
+            timestamp: Date.now()
+        }, { hopsLeft: this.defaultHops });
+    }
+    
+    // 广播任务
+    async broadcastTask(task) {
+        const message = {
+            type: 'task',
+            payload: task,
+            timestamp: Date.now(),
+            traceId: task.traceId || crypto.randomUUID()
+        };
+        this.logTrace(message, 'origin');
+        return this.broadcast(message, { fanout: this.taskFanout, hopsLeft: this.taskHops });
+    }
+    
+    // 返回实际发出的peer数
+    broadcast(message, options = {}) {
+        const { fanout, excludePeerId, hopsLeft } = options;
+        const peers = this.selectPeers(fanout || this.defaultFanout, excludePeerId, message);
+        const messageId = this.ensureMessageId(message);
+        this.markMessageSeen(messageId);
+        this.signMessage(message);
+        let sent = 0;
+        for (const { peerId, socket } of peers) {
+            try {
+                if (socket && !socket.destroyed) {
+                    const outbound = {
+                        ...message,
+                        messageId,
+                        hopsLeft: typeof hopsLeft === 'number' ? hopsLeft : this.defaultHops
+                    };
+                    this.send(socket, outbound);
+                    if (socket.writable) sent++;
+                } else {
+                    this.peers.delete(peerId);
+                }
+            } catch (e) {
+                console.error(`Failed to send to ${peerId}:`, e.message);
+                this.peers.delete(peerId);
+            }
+        }
+        return sent;
+    }
+
+    getLivePeerCount() {
+        let count = 0;
+        for (const socket of this.peers.values()) {
+            if (socket && !socket.destroyed) count++;
+        }
+        return count;
+    }
+
+    broadcastAll(message, options = {}) {
+        const { excludePeerId, hopsLeft } = options;
+        const messageId = this.ensureMessageId(message);
+        this.markMessageSeen(messageId);
+        this.signMessage(message);
+        const topics = this.getMessageTopics(message);
+        for (const [peerId, socket] of this.peers) {
+            if (excludePeerId && peerId === excludePeerId) continue;
+            if (!this.isPeerSubscribed(peerId, topics)) continue;
+            try {
+                if (socket && !socket.destroyed) {
+                    const outbound = {
+                        ...message,
+                        messageId,
+                        hopsLeft: typeof hopsLeft === 'number' ? hopsLeft : this.defaultHops
+                    };
+                    this.send(socket, outbound);
+                } else {
+                    this.peers.delete(peerId);
+                }
+            } catch (e) {
+                console.error(`Failed to send to ${peerId}:`, e.message);
+                this.peers.delete(peerId);
+            }
+        }
+    }
+    
+    // 查询网络中的记忆
+    // options.signal (AbortSignal) 可取消查询：排队中的直接出队，进行中的立即清理等待状态
+    async queryMemories(filter = {}, options = {}) {
+        return (await this.queryMemoriesResult(filter, options)).memories;
+    }
+
+    // 同 queryMemories，但返回 { memories, truncated }，truncated 表示结果被 maxQueryResults 截断
+    async queryMemoriesResult(filter = {}, options = {}) {
+        const { signal } = options;
+        if (signal?.aborted) throw queryAbortedError();
+        await this.acquireQuerySlot(signal);
+        try {
+            return await this.runQuery(filter, signal);
+        } finally {
+            this.releaseQuerySlot();
+        }
+    }
+
+    cancelQuery(requestId) {
+        const pending = this.pendingQueries.get(requestId);
+        if (!pending) return false;
+        pending.cancel();
+        return true;
+    }
+
+    runQuery(filter, signal = null) {
+        const requestId = crypto.randomUUID();
+        const query = {
+            type: 'query',
+            payload: { type: 'memories', filter },
+            requestId,
+            traceId: requestId
+        };
+        this.logTrace(query, 'origin');
         
         // 发送查询到所有peer
         this.broadcastAll(query, { hopsLeft: 0 });
         
-        // 等待响应（简化版，实际应该设置超时）
+        // 等待响应（超时返回空结果）
+        return new Promise((resolve, reject) => {
+            const event = `query_response:${requestId}`;
+            const finish = (settle, value) => {
+                clearTimeout(timeout);
+                this.removeListener(event, onResponse);
+                signal?.removeEventListener('abort', onAbort);
+                this.pendingQueries.delete(requestId);
+                settle(value);
+            };
+            const onResponse = (response) => {
+                const memories = Array.isArray(response?.memories) ? response.memories : [];
+                finish(resolve, {
+                    memories: memories.slice(0, this.maxQueryResults),
+                    truncated: Boolean(response?.truncated) || memories.length > this.maxQueryResults
+                });
+            };
+            const onAbort = () => finish(reject, queryAbortedError());
+            const timeout = setTimeout(() => finish(resolve, { memories: [], truncated: false }), this.queryTimeoutMs);
+
+            this.once(event, onResponse);
+            signal?.addEventListener('abort', onAbort, { once: true });
+            this.pendingQueries.set(requestId, { startedAt: Date.now(), cancel: onAbort });
+        });
+    }
+
+    // 向直连peer询问账户余额，返回第一个被 accept 接受的声明；都不认识或超时返回 null
+    requestBalance(accountId, options = {}) {
+        const { token = null, accept = () => true, timeoutMs = this.balanceTimeoutMs } = options;
+        let waiting = this.getLivePeerCount();
+        if (!accountId || waiting === 0) return Promise.resolve(null);
+        const requestId = crypto.randomUUID();
+        this.broadcastAll({
+            type: 'balance_request',
+            payload: { accountId, ...(token ? { token } : {}) },
+            requestId
+        }, { hopsLeft: 0 });
+
         return new Promise((resolve) => {
-            const results = [];
-            const timeout = setTimeout(() => resolve(results), 5000);
-            
-            this.once(`query_response:${requestId}`, (response) => {
+            const event = `balance_response:${requestId}`;
+            const finish = (value) => {
                 clearTimeout(timeout);
-                resolve(response.memories || []);
-            });
+                this.removeListener(event, onResponse);
+                resolve(value);
+            };
+            const onResponse = (payload, peerId) => {
+                const statement = payload?.statement;
+                if (statement && statement.accountId === accountId && accept(statement, peerId)) {
+                    finish({ ...statement, respondedBy: peerId });
+                    return;
+                }
+                if (--waiting <= 0) finish(null);
+            };
+            const timeout = setTimeout(() => finish(null), timeoutMs);
+            this.on(event, onResponse);
+        });
+    }
+
+    async queryMemoriesPage(filter = {}, options = {}) {
+        const pageSize = Math.min(Math.max(Math.floor(Number(options.limit) || 20), 1), 500);
+        const now = Date.now();
+        for (const [id, entry] of this.queryCursors) {
+            if (entry.expiresAt <= now) this.queryCursors.delete(id);
+        }
+        let cursorId;
+        let offset = 0;
+        let entry;
+        if (options.cursor) {
+            let decoded = null;
+            try {
+                decoded = JSON.parse(Buffer.from(String(options.cursor), 'base64url').toString('utf8'));
+            } catch (e) {
+                decoded = null;
+            }
+            entry = decoded ? this.queryCursors.get(decoded.id) : null;
+            if (!entry) {
+                const err = new Error('Invalid or expired cursor');
+                err.code = 'BAD_CURSOR';
+                throw err;
+            }
+            cursorId = decoded.id;
+            offset = Math.max(0, Math.floor(Number(decoded.offset) || 0));
+        } else {
+            const items = new Map();
+            const result = await this.queryMemoriesResult(filter, { signal: options.signal });
+            for (const capsule of result.memories) {
+                if (capsule?.asset_id && !items.has(capsule.asset_id)) {
+                    items.set(capsule.asset_id, capsule);
+                }
+            }
+            cursorId = crypto.randomUUID();
+            entry = { ids: Array.from(items.keys()), items, truncated: result.truncated, expiresAt: now + this.queryCursorTtlMs };
+            this.queryCursors.set(cursorId, entry);
+            while (this.queryCursors.size > this.maxQueryCursors) {
+                this.queryCursors.delete(this.queryCursors.keys().next().value);
+            }
+        }
+        const nextOffset = offset + pageSize;
+        return {
+            items: entry.ids.slice(offset, nextOffset).map(id => entry.items.get(id)),
+            cursor: nextOffset < entry.ids.length
+                ? Buffer.from(JSON.stringify({ id: cursorId, offset: nextOffset })).toString('base64url')
+                : null,
+            total: entry.ids.length,
+            truncated: entry.truncated
+        };
+    }
+
+    acquireQuerySlot(signal = null) {
+        if (this.activeQueries < this.maxConcurrentQueries) {
+            this.activeQueries++;
+            return Promise.resolve();
+        }
+        if (this.queryBusyMode === 'reject') {
+            const err = new Error('Node busy: too many concurrent queries');
+            err.code = 'BUSY';
+            return Promise.reject(err);
+        }
+        return new Promise((resolve, reject) => {
+            const waiter = () => {
+                signal?.removeEventListener('abort', onAbort);
+                resolve();
+            };
+            const onAbort = () => {
+                const index = this.queryWaiters.indexOf(waiter);
+                if (index !== -1) this.queryWaiters.splice(index, 1);
+                reject(queryAbortedError());
+            };
+            signal?.addEventListener('abort', onAbort, { once: true });
+            this.queryWaiters.push(waiter);
         });
     }
+
+    releaseQuerySlot() {
+        const next = this.queryWaiters.shift();
+        if (next) {
+            // 名额直接交给排队者，activeQueries 不变
+            next();
+        } else {
+            this.activeQueries = Math.max(0, this.activeQueries - 1);
+        }
+    }
     
     async handleQuery(query) {
-        // 本地查询（实际应该查询memory store）
         if (query.type === 'memories') {
-            return { memories: [] };
+            // 多取一条用于判断是否截断；queryHandler 按 limit 截取后再脱敏，不会为全部候选生成副本
+            // 远端给的 limit 不可信：缺省时取上限，其余一律夹到 [1, maxQueryResults]
+            const requested = Math.floor(Number(query.filter?.limit));
+            const limit = Number.isFinite(requested)
+                ? Math.min(Math.max(requested, 1), this.maxQueryResults)
+                : this.maxQueryResults;
+            const memories = this.queryHandler ? await this.queryHandler({ ...(query.filter || {}), limit: limit + 1 }) : [];
+            const list = Array.isArray(memories) ? memories : [];
+            return { memories: list.slice(0, limit), truncated: list.length > limit };
         }
         return {};
     }
     
     startHeartbeat() {
-        setInterval(() => {
+        if (this.heartbeatTimer) return;
+        this.heartbeatTimer = setInterval(() => {
             const now = Date.now();
+            this.checkStalledSendQueues(now);
             for (const [pingId, pending] of this.pendingPings) {
                 if (now - pending.sentAt > 15000) {
                     this.pendingPings.delete(pingId);
@@ -493,6 +1940,7 @@ class MeshNode extends EventEmitter {
                     this.peers.delete(peerId);
                 }
             }
+            this.saveRttHistogram();
         }, 30000); // 每30秒发送一次心跳
     }
     
@@ -503,12 +1951,21 @@ class MeshNode extends EventEmitter {
             peers.push({
                 nodeId: id,
                 ip: socket.remoteAddress ? socket.remoteAddress.replace('::ffff:', '') : 'unknown',
-                connectedAt: Date.now()
+                connectedAt: this.peerConnectedAt.get(id) || Date.now(),
+                score: this.getPeerScore(id),
+                roles: this.peerRoles.get(id) || [],
+                address: this.peerAddresses.get(id) || null,
+                inboundDropped: this.inboundDrops.get(id)?.dropped || 0
             });
         }
         return peers;
     }
 
+    // 未声明角色的旧节点视为具备所有角色
+    getPeersWithRole(role) {
+        return this.getPeers().filter(peer => peer.roles.length === 0 || peer.roles.includes(role));
+    }
+
     ensureMessageId(message) {
         if (!message.messageId) {
             message.messageId = crypto.randomUUID();
@@ -516,96 +1973,662 @@ class MeshNode extends EventEmitter {
         return message.messageId;
     }
 
-    markMessageSeen(messageId) {
+    markMessageSeen(messageId, seenAt = Date.now()) {
         if (!messageId) return;
-        this.seenMessages.set(messageId, Date.now());
-        this.cleanupSeenMessages();
+        // 先删除再插入，保证 Map 的迭代顺序始终是时间顺序；时钟回拨时沿用最新的时间，不让新条目排到旧条目前面
+        // 调用方传入的时间不能超过当前时间，否则之后所有条目的时间都会被推到未来
+        this.seenLatestAt = Math.max(this.seenLatestAt, Math.min(seenAt, Date.now()));
+        this.seenMessages.delete(messageId);
+        this.seenMessages.set(messageId, this.seenLatestAt);
+        this.cleanupSeenMessages(this.seenLatestAt);
     }
 
-    cleanupSeenMessages() {
-        const now = Date.now();
+    hasSeenMessage(messageId) {
+        return this.seenMessages.has(messageId) || Boolean(this.seenBloom?.has(messageId));
+    }
+
+    // 从最早的一端淘汰：遇到第一个未过期的条目即停止，不扫描整张表
+    cleanupSeenMessages(now = Date.now()) {
         for (const [messageId, seenAt] of this.seenMessages) {
-            if (now - seenAt > this.seenTtlMs) {
-                this.seenMessages.delete(messageId);
+            if (now - seenAt <= this.seenTtlMs && this.seenMessages.size <= this.maxSeenMessages) break;
+            this.seenMessages.delete(messageId);
+            if (now - seenAt <= this.seenTtlMs) {
+                this.seenBloom?.add(messageId);
             }
         }
-        while (this.seenMessages.size > this.maxSeenMessages) {
-            const oldest = this.seenMessages.keys().next().value;
-            if (!oldest) break;
-            this.seenMessages.delete(oldest);
-        }
     }
 
     shouldProcessMessage(message) {
         if (!message || !message.messageId) {
             return true;
         }
-        if (this.seenMessages.has(message.messageId)) {
+        if (this.hasSeenMessage(message.messageId)) {
+            this.recordDrop('duplicate', message);
             return false;
         }
         this.markMessageSeen(message.messageId);
         return true;
     }
 
+    recordDrop(reason, message, now = Date.now()) {
+        this.dropStats[reason] = (this.dropStats[reason] || 0) + 1;
+        if (!this.logDrops) return;
+        const window = this.dropLogWindow;
+        if (now - window.startedAt >= 1000) {
+            if (window.suppressed > 0) {
+                console.log(`🗑️  ${window.suppressed} more drops not logged`);
+            }
+            this.dropLogWindow = { startedAt: now, logged: 0, suppressed: 0 };
+        }
+        if (this.dropLogWindow.logged >= this.maxDropLogsPerSec) {
+            this.dropLogWindow.suppressed++;
+            return;
+        }
+        this.dropLogWindow.logged++;
+        console.log(`🗑️  drop ${reason} ${message?.type || 'unknown'} ${message?.messageId || '-'}`);
+    }
+
+    isMessageTypeDisabled(type) {
+        if (!type || this.disabledMessageTypes.length === 0) return false;
+        if (['handshake', 'handshake_ack', 'ping', 'pong'].includes(type)) return false;
+        return this.disabledMessageTypes.some(pattern => pattern.endsWith('*')
+            ? type.startsWith(pattern.slice(0, -1))
+            : type === pattern);
+    }
+
+    isBelowAcceptConfidence(capsule) {
+        if (!(this.minAcceptConfidence > 0)) return false;
+        const confidence = Number(capsule?.confidence ?? capsule?.content?.capsule?.confidence ?? 0);
+        return !(confidence >= this.minAcceptConfidence);
+    }
+
+    matchesMessageSchema(message) {
+        const schema = this.messageSchemas[message.type];
+        if (!schema) return true;
+        const errors = validateSchema(schema, message.payload);
+        if (errors.length > 0 && this.logDrops) {
+            console.log(`🧾 ${message.type} rejected by schema: ${errors.slice(0, 3).join('; ')}`);
+        }
+        return errors.length === 0;
+    }
+
+    isStoredCapsule(capsule) {
+        return this.suppressStoredCapsules && !!this.hasCapsule && !!capsule && this.hasCapsule(capsule);
+    }
+
     shouldRelayMessage(message) {
         if (!message || !message.messageId) return false;
-        if (message.type === 'handshake') return false;
+        if (this.isMessageTypeDisabled(message.type)) return false;
+        if (message.type === 'handshake' || message.type === 'peer_exchange') return false;
         if (message.type === 'ping' || message.type === 'pong') return false;
         if (message.type === 'query' || message.type === 'query_response') return false;
+        if (message.type === 'balance_request' || message.type === 'balance_response') return false;
         if (typeof message.hopsLeft !== 'number') return true;
-        return message.hopsLeft > 0;
+        if (message.hopsLeft > 0) return true;
+        this.recordDrop('hopsExhausted', message);
+        return false;
+    }
+
+    takeRelayToken(now = Date.now()) {
+        if (!this.maxRelayPerSec) return true;
+        const elapsed = Math.max(0, now - this.relayRefilledAt);
+        this.relayTokens = Math.min(this.maxRelayPerSec, this.relayTokens + elapsed * this.maxRelayPerSec / 1000);
+        this.relayRefilledAt = now;
+        if (this.relayTokens < 1) return false;
+        this.relayTokens -= 1;
+        return true;
+    }
+
+    // 握手消息不限速，否则超速的新连接无法完成握手
+    allowInbound(socket, peerKey, message, now = Date.now()) {
+        if (!this.maxInboundPerSec || !socket) return true;
+        // 每个连接只有第一次握手不计入额度，重复发送的握手和普通消息一样限速
+        if (message.type === 'handshake' || message.type === 'handshake_ack') {
+            const seen = socket.meshHandshakesSeen || (socket.meshHandshakesSeen = new Set());
+            if (!seen.has(message.type)) {
+                seen.add(message.type);
+                return true;
+            }
+        }
+        const bucket = socket.meshInboundBucket || (socket.meshInboundBucket = { tokens: this.maxInboundPerSec, refilledAt: now, violationAt: 0 });
+        const elapsed = Math.max(0, now - bucket.refilledAt);
+        bucket.tokens = Math.min(this.maxInboundPerSec, bucket.tokens + elapsed * this.maxInboundPerSec / 1000);
+        bucket.refilledAt = now;
+        if (bucket.tokens >= 1) {
+            bucket.tokens -= 1;
+            return true;
+        }
+        const stats = this.inboundDrops.get(peerKey) || { dropped: 0, lastDropAt: null };
+        stats.dropped++;
+        stats.lastDropAt = now;
+        this.inboundDrops.set(peerKey, stats);
+        this.recordDrop('rateLimited', message, now);
+        if (now - bucket.violationAt >= 1000) {
+            bucket.violationAt = now;
+            this.recordViolation(peerKey, 'inbound message flood');
+        }
+        return false;
+    }
+
+    getRelayStats() {
+        return {
+            ...this.relayStats,
+            maxRelayPerSec: this.maxRelayPerSec,
+            strategy: this.relayStrategy,
+            gossip: { ...this.gossipStats },
+            droppedLowConfidence: this.lowConfidenceDropped,
+            publishQueue: this.getPublishQueueStats(),
+            dropped: { ...this.dropStats }
+        };
     }
 
     relayMessage(message, fromPeerId) {
         const nextHops = typeof message.hopsLeft === 'number' ? message.hopsLeft - 1 : this.defaultHops - 1;
         if (nextHops < 0) return;
+        if (!this.takeRelayToken()) {
+            this.relayStats.suppressed++;
+            this.logTrace(message, 'relay-suppressed');
+            return;
+        }
+        this.relayStats.relayed++;
         const fanout = message.type === 'task' ? this.taskFanout : this.defaultFanout;
-        this.broadcast(message, {
-            fanout,
-            excludePeerId: fromPeerId,
-            hopsLeft: nextHops
+        this.logTrace(message, 'relay', `hopsLeft=${nextHops}`);
+        const options = { fanout, excludePeerId: fromPeerId, hopsLeft: nextHops };
+        switch (this.relayStrategy) {
+            case 'gossip':
+                this.gossipRelay(message, options);
+                break;
+            default:
+                this.broadcast(message, options);
+        }
+    }
+
+    // 选中的前 gossipEagerFanout 个peer收到完整消息，其余的只收到 ihave；完整消息缓存 gossipCacheMs 以响应 iwant
+    gossipRelay(message, { fanout, excludePeerId, hopsLeft }) {
+        const peers = this.selectPeers(fanout, excludePeerId, message);
+        const messageId = this.ensureMessageId(message);
+        const outbound = { ...message, messageId, hopsLeft };
+        this.cacheGossipMessage(outbound);
+        let sent = 0;
+        peers.forEach(({ socket }, i) => {
+            if (!socket || socket.destroyed) return;
+            if (i < this.gossipEagerFanout) {
+                this.send(socket, outbound);
+                this.gossipStats.eager++;
+            } else {
+                this.send(socket, { type: 'ihave', payload: { messageIds: [messageId] } });
+                this.gossipStats.ihave++;
+            }
+            sent++;
         });
+        return sent;
     }
 
-    selectPeers(fanout, excludePeerId) {
+    // 从最早的一端淘汰过期、超量或超出字节预算的条目；超过预算的单条消息不缓存
+    cacheGossipMessage(message, now = Date.now()) {
+        const bytes = Buffer.byteLength(JSON.stringify(message));
+        const previous = this.gossipCache.get(message.messageId);
+        if (previous) {
+            this.gossipCache.delete(message.messageId);
+            this.gossipCacheBytes -= previous.bytes;
+        }
+        if (bytes > this.gossipCacheMaxBytes) return;
+        this.gossipCache.set(message.messageId, { message, cachedAt: now, bytes });
+        this.gossipCacheBytes += bytes;
+        for (const [messageId, entry] of this.gossipCache) {
+            if (now - entry.cachedAt <= this.gossipCacheMs
+                && this.gossipCache.size <= this.maxSeenMessages
+                && this.gossipCacheBytes <= this.gossipCacheMaxBytes) break;
+            this.gossipCache.delete(messageId);
+            this.gossipCacheBytes -= entry.bytes;
+        }
+    }
+
+    // 同一时间只向一个通告者索取；等待期间记下其他通告者，索取超时后依次改向它们索取，从别处收到就停止
+    handleIHave(messageIds, peerId) {
+        if (!Array.isArray(messageIds)) return;
+        for (const messageId of messageIds.slice(0, 100)) {
+            if (typeof messageId !== 'string' || this.hasSeenMessage(messageId)) continue;
+            const want = this.gossipWants.get(messageId);
+            if (want) {
+                if (!want.announcers.includes(peerId) && want.announcers.length < this.defaultFanout) {
+                    want.announcers.push(peerId);
+                }
+                continue;
+            }
+            const entry = { announcers: [peerId], next: 0, timer: null };
+            this.gossipWants.set(messageId, entry);
+            this.scheduleIWant(messageId, entry, this.gossipIwantDelayMs);
+        }
+    }
+
+    scheduleIWant(messageId, want, delayMs) {
+        want.timer = setTimeout(() => {
+            if (this.stopping || this.hasSeenMessage(messageId)) {
+                this.gossipWants.delete(messageId);
+                return;
+            }
+            while (want.next < want.announcers.length) {
+                const announcer = want.announcers[want.next++];
+                if (this.sendToPeer(announcer, { type: 'iwant', payload: { messageIds: [messageId] } })) {
+                    this.gossipStats.iwant++;
+                    this.scheduleIWant(messageId, want, this.gossipIwantTimeoutMs);
+                    return;
+                }
+            }
+            this.gossipWants.delete(messageId);
+        }, delayMs);
+        want.timer.unref?.();
+    }
+
+    // 带 traceId 的消息在每一跳打印一行，便于跨节点聚合日志追踪
+    logTrace(message, stage, detail = '') {
+        if (!message?.traceId) return;
+        console.log(`🧵 [trace ${message.traceId}] ${this.nodeId} ${stage} ${message.type}${detail ? ' ' + detail : ''}`);
+    }
+
+    // 胶囊/任务的话题：类型 + 标签；其他消息不过滤
+    getMessageTopics(message) {
+        if (!message || (message.type !== 'capsule' && message.type !== 'task')) return null;
+        const payload = message.payload || {};
+        const topics = [];
+        if (payload.type) topics.push(payload.type);
+        if (Array.isArray(payload.tags)) topics.push(...payload.tags);
+        const blastRadius = payload.content?.capsule?.blast_radius;
+        if (Array.isArray(blastRadius)) topics.push(...blastRadius);
+        return topics.map(String);
+    }
+
+    isPeerSubscribed(peerId, topics) {
+        if (!topics) return true;
+        const subscribed = this.peerTopics.get(peerId);
+        if (!subscribed || subscribed.length === 0) return true;
+        return topics.some(topic => subscribed.includes(topic));
+    }
+
+    selectPeers(fanout, excludePeerId, message = null) {
         const peers = [];
+        const topics = this.getMessageTopics(message);
         for (const [peerId, socket] of this.peers) {
             if (excludePeerId && peerId === excludePeerId) continue;
+            if (!this.isPeerSubscribed(peerId, topics)) continue;
             if (!socket || socket.destroyed) {
                 this.peers.delete(peerId);
                 continue;
             }
             const stats = this.peerStats.get(peerId);
-            peers.push({ peerId, socket, rtt: stats?.rtt });
+            peers.push({ peerId, socket, rtt: stats?.rtt, capacity: this.peerCapacity.get(peerId) || 1, score: this.getPeerScore(peerId).score });
         }
-        const withStats = peers.filter(p => typeof p.rtt === 'number');
-        const withoutStats = peers.filter(p => typeof p.rtt !== 'number');
-        withStats.sort((a, b) => a.rtt - b.rtt);
-        for (let i = withoutStats.length - 1; i > 0; i--) {
+        // 评分是主排序；评分落在同一档的peer按容量从大到小，评分与容量都相同时随机
+        for (let i = peers.length - 1; i > 0; i--) {
             const j = Math.floor(Math.random() * (i + 1));
-            [withoutStats[i], withoutStats[j]] = [withoutStats[j], withoutStats[i]];
+            [peers[i], peers[j]] = [peers[j], peers[i]];
         }
-        const ordered = [...withStats, ...withoutStats];
+        const band = (p) => Math.floor(p.score / this.peerScoreBand);
+        const ordered = peers.sort((a, b) => band(b) - band(a) || b.capacity - a.capacity || b.score - a.score);
         if (!fanout || fanout >= ordered.length) {
             return ordered;
         }
         return ordered.slice(0, fanout);
     }
     
+    startPeerListSharing() {
+        if (!this.sharePeerList || this.peerListTimer) return;
+        this.peerListTimer = setInterval(() => this.broadcastPeerList(), this.peerListIntervalMs);
+        this.peerListTimer.unref?.();
+    }
+
+    // 已握手的peer（key为nodeId，排除临时的 ip:port）
+    getConnectedNodeIds() {
+        return Array.from(this.peers.keys()).filter(id => !id.includes(':'));
+    }
+
+    broadcastPeerList() {
+        this.broadcastAll({
+            type: 'peer_list',
+            payload: {
+                nodeId: this.nodeId,
+                peers: this.getConnectedNodeIds(),
+                roles: this.roles,
+                addresses: this.getPexAddresses(),
+                timestamp: Date.now()
+            }
+        });
+        this.saveAddressBook();
+    }
+
+    // 已连接peer的拨号地址，按地址去重并限制数量
+    getPexAddresses() {
+        const seen = new Set();
+        const addresses = [];
+        for (const nodeId of this.getConnectedNodeIds()) {
+            const address = this.peerAddresses.get(nodeId);
+            if (!address || seen.has(address)) continue;
+            seen.add(address);
+            addresses.push({ nodeId, address });
+            if (addresses.length >= this.maxPexAddresses) break;
+        }
+        return addresses;
+    }
+
+    // 发给新peer的地址样本：已连接peer与地址簿中最近拨通过的地址，随机抽取，不含对方自己
+    samplePexAddresses(excludeNodeId = null) {
+        const exclude = excludeNodeId ? this.peerAddresses.get(excludeNodeId) : null;
+        const candidates = new Map();
+        for (const entry of this.getPexAddresses()) {
+            candidates.set(entry.address, entry);
+        }
+        for (const entry of this.addressBook.values()) {
+            if (entry.failures > 0 || candidates.has(entry.address)) continue;
+            candidates.set(entry.address, { nodeId: entry.nodeId, address: entry.address });
+        }
+        const sample = Array.from(candidates.values())
+            .filter(entry => entry.address !== exclude && entry.nodeId !== excludeNodeId);
+        for (let i = sample.length - 1; i > 0; i--) {
+            const j = Math.floor(Math.random() * (i + 1));
+            [sample[i], sample[j]] = [sample[j], sample[i]];
+        }
+        return sample.slice(0, this.maxPexAddresses);
+    }
+
+    sendPeerExchange(peerId) {
+        if (!this.sharePeerList) return false;
+        const addresses = this.samplePexAddresses(peerId);
+        if (addresses.length === 0) return false;
+        return this.sendToPeer(peerId, {
+            type: 'peer_exchange',
+            payload: { nodeId: this.nodeId, addresses, timestamp: Date.now() }
+        });
+    }
+
+    learnAddresses(entries, now = Date.now()) {
+        const self = new Set([`localhost:${this.port}`, `127.0.0.1:${this.port}`]);
+        for (const entry of entries.slice(0, this.maxPexAddresses)) {
+            const address = typeof entry === 'string' ? entry : entry?.address;
+            const nodeId = typeof entry?.nodeId === 'string' ? entry.nodeId : null;
+            if (typeof address !== 'string' || !/^[^\s:]+:\d+$/.test(address)) continue;
+            if (self.has(address) || nodeId === this.nodeId) continue;
+            const known = this.addressBook.get(address);
+            if (known) {
+                known.lastSeen = now;
+                if (nodeId) known.nodeId = nodeId;
+            } else {
+                this.addressBook.set(address, { address, nodeId, lastSeen: now, failures: 0, lastFailedAt: null, dialing: false });
+            }
+        }
+        this.pruneAddressBook(now);
+    }
+
+    pruneAddressBook(now = Date.now()) {
+        for (const [address, entry] of this.addressBook) {
+            if (now - entry.lastSeen > this.addressBookTtlMs) {
+                this.addressBook.delete(address);
+            }
+        }
+        if (this.addressBook.size > this.maxAddressBook) {
+            const oldest = Array.from(this.addressBook.values())
+                .sort((a, b) => a.lastSeen - b.lastSeen)
+                .slice(0, this.addressBook.size - this.maxAddressBook);
+            for (const entry of oldest) {
+                this.addressBook.delete(entry.address);
+            }
+        }
+    }
+
+    // 连接数不足时拨号地址簿中的地址：跳过已连接、被封禁、正在拨号以及冷却期内失败过的地址
+    dialKnownAddresses(now = Date.now()) {
+        const dialed = [];
+        let slots = this.pexTargetPeers - this.getConnectedNodeIds().length;
+        for (const entry of this.addressBook.values()) {
+            if (slots <= 0) break;
+            if (entry.dialing || this.peers.has(entry.address)) continue;
+            if (entry.nodeId && this.peers.has(entry.nodeId)) continue;
+            if (entry.lastFailedAt && now - entry.lastFailedAt < this.addressCooldownMs) continue;
+            if (this.isBanned(entry.address) || this.isBanned(entry.address.split(':')[0]) || (entry.nodeId && this.isBanned(entry.nodeId))) continue;
+            entry.dialing = true;
+            slots--;
+            dialed.push(entry.address);
+            this.connectToPeer(entry.address).then(() => {
+                entry.failures = 0;
+                entry.lastFailedAt = null;
+            }).catch(() => {
+                entry.failures++;
+                entry.lastFailedAt = Date.now();
+            }).finally(() => {
+                entry.dialing = false;
+            });
+        }
+        return dialed;
+    }
+
+    loadAddressBook() {
+        if (!this.addressBookPath) return;
+        try {
+            const entries = JSON.parse(require('fs').readFileSync(this.addressBookPath, 'utf8'));
+            for (const entry of Array.isArray(entries) ? entries : []) {
+                if (typeof entry?.address !== 'string') continue;
+                this.addressBook.set(entry.address, { ...entry, dialing: false });
+            }
+            this.pruneAddressBook();
+        } catch (e) {
+            // 文件不存在或损坏时从空地址簿开始
+        }
+    }
+
+    saveAddressBook() {
+        if (!this.addressBookPath) return;
+        try {
+            const entries = Array.from(this.addressBook.values()).map(({ dialing, ...entry }) => entry);
+            require('fs').writeFileSync(this.addressBookPath, JSON.stringify(entries, null, 2));
+        } catch (e) {
+            console.error('Failed to save address book:', e.message);
+        }
+    }
+
+    getAddressBook() {
+        return Array.from(this.addressBook.values()).map(({ dialing, ...entry }) => entry);
+    }
+
+    prunePeerLists(now = Date.now()) {
+        for (const [nodeId, entry] of this.peerLists) {
+            if (now - entry.receivedAt > this.graphStaleMs) {
+                this.peerLists.delete(nodeId);
+            }
+        }
+        if (this.peerLists.size > this.maxGraphNodes) {
+            const oldest = Array.from(this.peerLists.entries())
+                .sort((a, b) => a[1].receivedAt - b[1].receivedAt)
+                .slice(0, this.peerLists.size - this.maxGraphNodes);
+            for (const [nodeId] of oldest) {
+                this.peerLists.delete(nodeId);
+            }
+        }
+    }
+
+    getNetworkGraph() {
+        this.prunePeerLists();
+        const nodes = new Set([this.nodeId]);
+        const edges = new Map();
+        const addEdge = (a, b) => {
+            if (!a || !b || a === b) return;
+            if (nodes.size >= this.maxGraphNodes && (!nodes.has(a) || !nodes.has(b))) return;
+            nodes.add(a);
+            nodes.add(b);
+            const [source, target] = a < b ? [a, b] : [b, a];
+            edges.set(`${source}|${target}`, { source, target });
+        };
+        for (const peerId of this.getConnectedNodeIds()) {
+            addEdge(this.nodeId, peerId);
+        }
+        const reports = Array.from(this.peerLists.entries()).sort((a, b) => b[1].receivedAt - a[1].receivedAt);
+        for (const [nodeId, entry] of reports) {
+            for (const peerId of entry.peers) {
+                addEdge(nodeId, peerId);
+            }
+        }
+        return {
+            nodes: Array.from(nodes).map(id => ({
+                id,
+                self: id === this.nodeId,
+                roles: id === this.nodeId ? this.roles : (this.peerRoles.get(id) || this.peerLists.get(id)?.roles || [])
+            })),
+            edges: Array.from(edges.values()),
+            generatedAt: Date.now()
+        };
+    }
+
+    sortByKeyDistance(keyHash, ids) {
+        const distances = new Map(Array.from(new Set(ids)).filter(id => id !== this.nodeId).map(id => [id, keyDistance(keyHash, id)]));
+        return Array.from(distances.keys()).sort((a, b) => {
+            const da = distances.get(a);
+            const db = distances.get(b);
+            return da < db ? -1 : (da > db ? 1 : 0);
+        });
+    }
+
+    // key 的副本应放置的 k 个直连peer
+    getClosestPeers(key, k = 3) {
+        const ids = this.getConnectedNodeIds();
+        if (this.keyPlacement === 'ring') {
+            return this.getRingOwners(key, ids, k);
+        }
+        return this.sortByKeyDistance(hashKey(key), ids).slice(0, k);
+    }
+
+    getHashRing(nodeIds) {
+        const members = Array.from(new Set(nodeIds)).sort().join(',');
+        if (this.hashRing?.members === members) return this.hashRing;
+        const points = [];
+        for (const nodeId of members ? members.split(',') : []) {
+            for (let i = 0; i < this.ringVirtualNodes; i++) {
+                points.push({ position: ringPosition(`${nodeId}#${i}`), nodeId });
+            }
+        }
+        points.sort((a, b) => a.position - b.position || (a.nodeId < b.nodeId ? -1 : 1));
+        this.hashRing = { members, points };
+        return this.hashRing;
+    }
+
+    // 从 key 在环上的位置顺时针取前 k 个不同节点：第一个为主副本，其余为后继副本
+    getRingOwners(key, nodeIds, k = 3) {
+        const { points } = this.getHashRing(nodeIds.filter(id => id !== this.nodeId));
+        if (points.length === 0) return [];
+        const position = ringPosition(key);
+        let low = 0;
+        let high = points.length;
+        while (low < high) {
+            const mid = (low + high) >> 1;
+            if (points[mid].position < position) low = mid + 1;
+            else high = mid;
+        }
+        const owners = [];
+        for (let i = 0; i < points.length && owners.length < k; i++) {
+            const { nodeId } = points[(low + i) % points.length];
+            if (!owners.includes(nodeId)) owners.push(nodeId);
+        }
+        return owners;
+    }
+
+    // 只发给离 key 最近的 k 个直连peer，不再转发；返回发出的peer数
+    announceToClosestPeers(key, message, k = 3) {
+        let sent = 0;
+        for (const peerId of this.getClosestPeers(key, k)) {
+            if (this.sendToPeer(peerId, { ...message, messageId: crypto.randomUUID(), hopsLeft: 0 })) sent++;
+        }
+        return sent;
+    }
+
+    // 按 key 做一次迭代式最近节点查找的演练：不取值，只返回查找路径。
+    // 每轮向 alpha 个最近且未询问的节点“询问”，其返回的最近集合取自该节点 gossip 的 peer 列表
+    traceKeyRoute(key, options = {}) {
+        const alpha = Math.max(1, Number(options.alpha) || 3);
+        const k = Math.max(1, Number(options.k) || 8);
+        const keyHash = hashKey(key);
+        const distance = (nodeId) => keyDistance(keyHash, nodeId);
+        this.prunePeerLists();
+        const closestOf = (ids) => this.sortByKeyDistance(keyHash, ids).slice(0, k);
+
+        let shortlist = closestOf(this.getConnectedNodeIds());
+        const queried = new Set();
+        const path = [];
+        let round = 0;
+        for (;;) {
+            const batch = shortlist.filter(id => !queried.has(id)).slice(0, alpha);
+            if (batch.length === 0) break;
+            round++;
+            const learned = [];
+            for (const nodeId of batch) {
+                queried.add(nodeId);
+                const report = this.peerLists.get(nodeId);
+                const closest = report ? closestOf(report.peers) : null;
+                path.push({ round, nodeId, distance: distance(nodeId).toString(16).padStart(64, '0'), closest });
+                if (closest) learned.push(...closest);
+            }
+            shortlist = closestOf([...shortlist, ...learned]);
+        }
+        return {
+            key: String(key),
+            keyHash: keyHash.toString(16).padStart(64, '0'),
+            rounds: round,
+            path,
+            closest: shortlist
+        };
+    }
+
     async stop() {
-        // 关闭所有peer连接
-        for (const [peerId, socket] of this.peers) {
+        this.stopping = true;
+        for (const timer of this.reconnectTimers.values()) {
+            clearTimeout(timer);
+        }
+        this.reconnectTimers.clear();
+        for (const want of this.gossipWants.values()) {
+            clearTimeout(want.timer);
+        }
+        this.gossipWants.clear();
+        if (this.publishTimer) {
+            clearTimeout(this.publishTimer);
+            this.publishTimer = null;
+        }
+        if (this.publishQueue.length > 0) {
+            console.log(`📤 Dropping ${this.publishQueue.length} queued capsule broadcasts on stop`);
+            this.publishQueue = [];
+        }
+        for (const { timer } of this.bootstrapTimers.values()) {
+            clearTimeout(timer);
+        }
+        this.bootstrapTimers.clear();
+        if (this.peerListTimer) {
+            clearInterval(this.peerListTimer);
+            this.peerListTimer = null;
+        }
+        this.saveAddressBook();
+        this.saveRttHistogram();
+        if (this.dnsSeedTimer) {
+            clearInterval(this.dnsSeedTimer);
+            this.dnsSeedTimer = null;
+        }
+        if (this.heartbeatTimer) {
+            clearInterval(this.heartbeatTimer);
+            this.heartbeatTimer = null;
+        }
+        // 关闭所有peer连接；同一连接可能同时以地址和 nodeId 为键，只计一次
+        const sockets = new Set(this.peers.values());
+        for (const socket of sockets) {
             socket.destroy();
         }
+        const peersClosed = sockets.size;
         this.peers.clear();
         
-        // 关闭服务器
+        // 关闭服务器，不再接受新连接
         if (this.server) {
             this.server.close();
         }
         
         console.log('📡 P2P node stopped');
+        return { peersClosed };
     }
 }
 
+MeshNode.PROTOCOL_VERSION = PROTOCOL_VERSION;
+
 module.exports = MeshNode;
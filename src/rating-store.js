@@ -30,6 +30,23 @@ class RatingStore {
                 vote_value INTEGER,
                 voted_at INTEGER
             );
+            CREATE TABLE IF NOT EXISTS capsule_ratings (
+                asset_id TEXT NOT NULL,
+                rater_node TEXT NOT NULL,
+                thumb TEXT NOT NULL,
+                score INTEGER NOT NULL,
+                rated_at INTEGER,
+                public_key TEXT,
+                signature TEXT,
+                PRIMARY KEY (asset_id, rater_node)
+            );
+            CREATE TABLE IF NOT EXISTS node_reputation (
+                node_id TEXT PRIMARY KEY,
+                score INTEGER DEFAULT 0,
+                successes INTEGER DEFAULT 0,
+                failures INTEGER DEFAULT 0,
+                updated_at INTEGER DEFAULT 0
+            );
         `);
     }
 
@@ -96,6 +113,31 @@ class RatingStore {
         return this.getNode(nodeId);
     }
 
+    // 信誉：完成任务 +1，失败 -1；用于竞标金额接近时优先选择可靠的节点
+    getReputation(nodeId) {
+        const row = this.db.prepare('SELECT score FROM node_reputation WHERE node_id = ?').get(nodeId);
+        return row ? Number(row.score) : 0;
+    }
+
+    recordOutcome(nodeId, success) {
+        const column = success ? 'successes' : 'failures';
+        this.db.prepare(`
+            INSERT INTO node_reputation (node_id, score, ${column}, updated_at) VALUES (?, ?, 1, ?)
+            ON CONFLICT(node_id) DO UPDATE SET score = score + excluded.score, ${column} = ${column} + 1, updated_at = excluded.updated_at
+        `).run(nodeId, success ? 1 : -1, Date.now());
+        return this.getReputation(nodeId);
+    }
+
+    getReputations() {
+        return this.db.prepare('SELECT * FROM node_reputation ORDER BY score DESC, node_id ASC').all().map(row => ({
+            nodeId: row.node_id,
+            score: Number(row.score),
+            successes: Number(row.successes),
+            failures: Number(row.failures),
+            updatedAt: Number(row.updated_at)
+        }));
+    }
+
     addVote(taskId, nodeId, votedByNode = null, delta = 0) {
         const existing = this.db.prepare('SELECT 1 FROM task_votes WHERE task_id = ?').get(taskId);
         if (existing) return { ok: false, reason: 'Task already voted' };
@@ -122,6 +164,49 @@ class RatingStore {
         return Boolean(this.db.prepare('SELECT 1 FROM task_votes WHERE task_id = ?').get(taskId));
     }
 
+    // 每个节点（及其公钥）对同一胶囊只能评分一次
+    addCapsuleRating(rating) {
+        const existing = this.db.prepare('SELECT 1 FROM capsule_ratings WHERE asset_id = ? AND (rater_node = ? OR public_key = ?)')
+            .get(rating.assetId, rating.raterNodeId, rating.publicKeyPem);
+        if (existing) return { ok: false, reason: 'Capsule already rated by this node' };
+        this.db.prepare(`
+            INSERT INTO capsule_ratings (asset_id, rater_node, thumb, score, rated_at, public_key, signature)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+        `).run(rating.assetId, rating.raterNodeId, rating.thumb, rating.score, rating.ratedAt, rating.publicKeyPem, rating.signature);
+        return { ok: true, rating: this.getCapsuleRating(rating.assetId) };
+    }
+
+    getCapsuleRating(assetId) {
+        return this.getCapsuleRatings([assetId]).get(assetId) || null;
+    }
+
+    // 聚合评分：value 为带符号的平均分归一到 [-1, 1]（赞为正、踩为负，满分 5）
+    getCapsuleRatings(assetIds = null) {
+        const rows = this.db.prepare(`
+            SELECT asset_id,
+                COUNT(*) AS count,
+                SUM(CASE WHEN thumb = 'up' THEN 1 ELSE 0 END) AS up,
+                AVG(score) AS average_score,
+                SUM(CASE WHEN thumb = 'up' THEN score ELSE -score END) AS net
+            FROM capsule_ratings
+            GROUP BY asset_id
+        `).all();
+        const wanted = Array.isArray(assetIds) ? new Set(assetIds) : null;
+        const ratings = new Map();
+        for (const row of rows) {
+            if (wanted && !wanted.has(row.asset_id)) continue;
+            const count = Number(row.count || 0);
+            ratings.set(row.asset_id, {
+                count,
+                up: Number(row.up || 0),
+                down: count - Number(row.up || 0),
+                averageScore: Number(Number(row.average_score || 0).toFixed(2)),
+                value: Number((Number(row.net || 0) / (count * 5)).toFixed(4))
+            });
+        }
+        return ratings;
+    }
+
     getRules() {
         return {
             alpha: this.alpha,
@@ -0,0 +1,90 @@
+/**
+ * Schema - 入站载荷的轻量 JSON Schema 校验
+ * 只支持常用关键字：type、enum、const、required、properties、additionalProperties、
+ * items、minItems/maxItems、minLength/maxLength、pattern、minimum/maximum
+ */
+
+function typeOf(value) {
+    if (value === null) return 'null';
+    if (Array.isArray(value)) return 'array';
+    return typeof value;
+}
+
+function matchesType(value, type) {
+    if (type === 'integer') return Number.isInteger(value);
+    if (type === 'number') return typeof value === 'number' && Number.isFinite(value);
+    return typeOf(value) === type;
+}
+
+// 返回错误列表（为空表示通过），每条形如 "$.capsule.summary: is required"
+function validateSchema(schema, value, at = '$') {
+    if (!schema || typeof schema !== 'object') return [];
+    const errors = [];
+
+    if (schema.type !== undefined) {
+        const types = Array.isArray(schema.type) ? schema.type : [schema.type];
+        if (!types.some(type => matchesType(value, type))) {
+            return [`${at}: expected ${types.join(' or ')}, got ${typeOf(value)}`];
+        }
+    }
+    if (Array.isArray(schema.enum) && !schema.enum.some(item => JSON.stringify(item) === JSON.stringify(value))) {
+        errors.push(`${at}: must be one of ${JSON.stringify(schema.enum)}`);
+    }
+    if (schema.const !== undefined && JSON.stringify(schema.const) !== JSON.stringify(value)) {
+        errors.push(`${at}: must equal ${JSON.stringify(schema.const)}`);
+    }
+
+    if (typeof value === 'string') {
+        if (schema.minLength !== undefined && value.length < schema.minLength) {
+            errors.push(`${at}: shorter than ${schema.minLength}`);
+        }
+        if (schema.maxLength !== undefined && value.length > schema.maxLength) {
+            errors.push(`${at}: longer than ${schema.maxLength}`);
+        }
+        if (schema.pattern !== undefined && !new RegExp(schema.pattern).test(value)) {
+            errors.push(`${at}: does not match ${schema.pattern}`);
+        }
+    }
+    if (typeof value === 'number') {
+        if (schema.minimum !== undefined && value < schema.minimum) {
+            errors.push(`${at}: less than ${schema.minimum}`);
+        }
+        if (schema.maximum !== undefined && value > schema.maximum) {
+            errors.push(`${at}: greater than ${schema.maximum}`);
+        }
+    }
+
+    if (Array.isArray(value)) {
+        if (schema.minItems !== undefined && value.length < schema.minItems) {
+            errors.push(`${at}: fewer than ${schema.minItems} items`);
+        }
+        if (schema.maxItems !== undefined && value.length > schema.maxItems) {
+            errors.push(`${at}: more than ${schema.maxItems} items`);
+        }
+        if (schema.items) {
+            value.forEach((item, i) => errors.push(...validateSchema(schema.items, item, `${at}[${i}]`)));
+        }
+    }
+
+    if (typeOf(value) === 'object') {
+        for (const key of schema.required || []) {
+            if (value[key] === undefined) {
+                errors.push(`${at}.${key}: is required`);
+            }
+        }
+        const properties = schema.properties || {};
+        for (const [key, item] of Object.entries(value)) {
+            if (properties[key]) {
+                errors.push(...validateSchema(properties[key], item, `${at}.${key}`));
+            } else if (schema.additionalProperties === false) {
+                errors.push(`${at}.${key}: is not allowed`);
+            } else if (schema.additionalProperties && typeof schema.additionalProperties === 'object') {
+                errors.push(...validateSchema(schema.additionalProperties, item, `${at}.${key}`));
+            }
+        }
+    }
+
+    return errors;
+}
+
+module.exports = { validateSchema };
@@ -5,6 +5,50 @@
 
 const EventEmitter = require('events');
 const crypto = require('crypto');
+const { verifyPayload, accountIdFromPublicKey, nodeIdFromPublicKey } = require('./wallet');
+
+// 任务生命周期时间戳统一为毫秒时间戳；旧数据中的 ISO 字符串在读入时转换
+const TASK_TIMESTAMP_FIELDS = ['published_at', 'votingStartedAt', 'lastBidAt', 'assignedAt', 'completedAt', 'failedAt', 'cancelledAt', 'expiredAt', 'deadline', 'awardedAt'];
+
+// 解的选取方式：first-valid 第一个有效解直接获胜；review 收集多个解，由发布者评选
+const SELECTION_MODES = ['first-valid', 'review'];
+
+// 状态只能前进：排名更低的状态更新视为过期消息；终态之间不能互相转换
+const STATUS_RANK = {
+    pending_escrow: 0,
+    open: 1,
+    voting: 2,
+    assigned: 3,
+    under_review: 3,
+    completed: 4,
+    failed: 4,
+    cancelled: 4,
+    expired: 4
+};
+const TERMINAL_RANK = 4;
+
+function toMillis(value) {
+    if (value === undefined || value === null || value === '') return null;
+    if (typeof value === 'number') return Number.isFinite(value) ? value : null;
+    const numeric = Number(value);
+    if (Number.isFinite(numeric)) return numeric;
+    const parsed = Date.parse(value);
+    return Number.isFinite(parsed) ? parsed : null;
+}
+
+function normalizeTaskTimestamps(task) {
+    if (!task || typeof task !== 'object') return task;
+    for (const field of TASK_TIMESTAMP_FIELDS) {
+        if (task[field] === undefined) continue;
+        const ms = toMillis(task[field]);
+        if (ms === null) {
+            delete task[field];
+        } else {
+            task[field] = ms;
+        }
+    }
+    return task;
+}
 
 class TaskBazaar extends EventEmitter {
     constructor(options = {}) {
@@ -16,11 +60,24 @@ class TaskBazaar extends EventEmitter {
         this.ratingStore = options.ratingStore || null;
         this.dataDir = options.dataDir || process.cwd();
         this.tasksPath = require('path').join(this.dataDir, 'tasks.json');
+        // 已结束任务超过保留期后移入归档（JSONL，追加写），0 = 不归档
+        this.archivePath = require('path').join(this.dataDir, 'tasks-archive.jsonl');
+        this.archiveAfterMs = Number(options.archiveAfterMs ?? 0);
+        // 每个任务最多保留的竞标数（0 = 不限）；超出时按获胜顺序保留最有竞争力的
+        this.maxBidsPerTask = Number(options.maxBidsPerTask ?? 0);
+        // 竞标排序与 TaskWorker.determineWinner 一致，由上层注入；默认按金额从低到高
+        this.compareBids = (a, b) => a.amount - b.amount || String(a.nodeId).localeCompare(String(b.nodeId));
         
         this.tasks = new Map(); // taskId -> task
         this.submissions = new Map(); // taskId -> [solutions]
         this.completedTasks = new Set();
+        this.archivedTaskIds = new Set(); // 仅保存ID，用于去重网络重放
+        // 能力倒排索引：标签或类型（小写）-> Set(taskId)，worker 只取与自身能力相关的任务
+        this.capabilityIndex = new Map();
+        this.indexedCapabilities = new Map(); // taskId -> [capability]
+        this.untaggedTaskIds = new Set(); // 没有标签的通用任务
         this.loadFromDisk();
+        this.loadArchivedIds();
     }
 
     loadFromDisk() {
@@ -31,7 +88,8 @@ class TaskBazaar extends EventEmitter {
             if (Array.isArray(raw)) {
                 raw.forEach(t => {
                     if (t && t.taskId) {
-                        this.tasks.set(t.taskId, t);
+                        this.tasks.set(t.taskId, normalizeTaskTimestamps(t));
+                        this.indexTask(t);
                         if (t.status === 'completed') {
                             this.completedTasks.add(t.taskId);
                         }
@@ -65,13 +123,36 @@ class TaskBazaar extends EventEmitter {
 
         task.publisher = task.publisher || this.nodeId;
         task.bounty.token = task.bounty.token || 'CLAW';
+        task.published_at = task.published_at ?? Date.now();
+        // deadlineMs：相对发布时间的期限，未直接给出 deadline 时换算成截止时间
+        if (task.deadlineMs !== undefined && task.deadlineMs !== null) {
+            const deadlineMs = Number(task.deadlineMs);
+            if (!Number.isFinite(deadlineMs) || deadlineMs <= 0) {
+                throw new Error('Invalid task: deadlineMs must be a positive number');
+            }
+            task.deadlineMs = deadlineMs;
+            if (task.deadline === undefined || task.deadline === null || task.deadline === '') {
+                task.deadline = toMillis(task.published_at) + deadlineMs;
+            }
+        }
+        normalizeTaskTimestamps(task);
+        if (task.selectionMode !== undefined && task.selectionMode !== null && !SELECTION_MODES.includes(task.selectionMode)) {
+            throw new Error(`Invalid task: selectionMode must be one of ${SELECTION_MODES.join(', ')}`);
+        }
+        task.selectionMode = this.getSelectionMode(task);
+        // 发布者一直不评选时，托管靠截止时间过期退回，因此评选模式必须有截止时间
+        if (task.selectionMode === 'review' && !this.getTaskDeadline(task)) {
+            throw new Error('Invalid task: review mode requires a deadline or deadlineMs');
+        }
 
         task.escrowAccountId = task.escrowAccountId || this.getEscrowAccountId(task.taskId);
         
         // 存储任务
         task.status = this.isEscrowFunded(task) ? 'open' : 'pending_escrow';
+        task.version = 1;
         task.submissions = [];
         this.tasks.set(task.taskId, task);
+        this.indexTask(task);
         this.saveToDisk();
         
         console.log(`🎯 Task published: ${task.taskId}`);
@@ -83,25 +164,52 @@ class TaskBazaar extends EventEmitter {
     
     // 处理网络中的新任务
     async handleNewTask(task) {
-        if (this.tasks.has(task.taskId)) {
+        if (this.tasks.has(task.taskId) || this.archivedTaskIds.has(task.taskId)) {
             return; // 已存在
         }
+        normalizeTaskTimestamps(task);
+        // 转发节点可能改写未签名的 publisherAccountId，与签名托管证明不一致的任务直接丢弃
+        const signedPublisher = task.escrowProof?.statement?.publisher;
+        if (task.publisherAccountId && signedPublisher && task.publisherAccountId !== signedPublisher) {
+            console.warn(`⚠️  Dropped task ${task.taskId}: publisherAccountId does not match the escrow proof`);
+            return;
+        }
+        // 托管去向只由本节点在取消/评选/过期时计算，不接受网络带来的值
+        delete task.escrowDisposition;
+        if (Array.isArray(task.bids)) {
+            task.bids = this.trimBids(task.bids, task.taskId).kept;
+        }
         task.escrowAccountId = task.escrowAccountId || this.getEscrowAccountId(task.taskId);
         task.status = this.isEscrowFunded(task) ? 'open' : 'pending_escrow';
+        task.version = Number(task.version) > 0 ? Number(task.version) : 1;
         task.submissions = [];
         this.tasks.set(task.taskId, task);
+        this.indexTask(task);
         this.saveToDisk();
         
         console.log(`📬 New task from network: ${task.taskId}`);
         this.emit('task:received', task);
     }
     
+    // 未指定时 type 为 review 的任务默认评选模式
+    getSelectionMode(task) {
+        if (SELECTION_MODES.includes(task?.selectionMode)) return task.selectionMode;
+        return task?.type === 'review' ? 'review' : 'first-valid';
+    }
+
     // 提交解决方案
-    async submitSolution(taskId, solution, solverId) {
+    async submitSolution(taskId, solution, solverId, options = {}) {
         const task = this.tasks.get(taskId);
         if (!task) {
             throw new Error('Task not found');
         }
+
+        if (this.getSelectionMode(task) === 'review') {
+            if (!(await this.validateSolution(task, solution))) {
+                return { success: false, reason: 'Invalid solution' };
+            }
+            return this.addSubmission(taskId, { solverId, accountId: options.accountId, solution, submittedAt: options.submittedAt });
+        }
         
         if (task.status !== 'open') {
             throw new Error('Task is not open');
@@ -118,6 +226,8 @@ class TaskBazaar extends EventEmitter {
             // 第一个有效解获胜
             this.completedTasks.add(taskId);
             task.status = 'completed';
+            task.version = (task.version || 0) + 1;
+            task.completedAt = Date.now();
             task.winner = solverId;
             
             const reward = task.bounty?.amount || 0;
@@ -146,6 +256,71 @@ class TaskBazaar extends EventEmitter {
         };
     }
     
+    // 评选模式：收集解并进入 under_review，同一提交者重复提交时以最后一次为准（网络上的解先经 verifyTaskSubmission 验签）
+    addSubmission(taskId, { solverId, accountId, solution, submittedAt } = {}) {
+        const task = this.tasks.get(taskId);
+        if (!task) {
+            return { success: false, reason: 'Task not found' };
+        }
+        if (this.getSelectionMode(task) !== 'review') {
+            return { success: false, reason: 'Task does not accept multiple submissions' };
+        }
+        if (!['open', 'under_review'].includes(task.status) || task.winner) {
+            return { success: false, reason: `Task is ${task.status}` };
+        }
+        if (!solverId || !accountId || !solution) {
+            return { success: false, reason: 'Missing solver, account or solution' };
+        }
+        const submission = {
+            solverId,
+            accountId,
+            solution,
+            submittedAt: toMillis(submittedAt) || Date.now()
+        };
+        const submissions = (task.submissions || []).filter(item => item.solverId !== solverId);
+        submissions.push(submission);
+        this.updateTask(taskId, { status: 'under_review', submissions });
+        this.emit('task:submission', { taskId, submission });
+        return { success: true, winner: false, underReview: true, submissionCount: submissions.length, submission };
+    }
+
+    // 发布者从已收集的解中选出获胜者，托管去向记为获胜者账户；
+    // 其他节点应用评选结果时传入签名评选结果中的 accountId，托管只付给该账户
+    awardTask(taskId, requesterNodeId, solverId, options = {}) {
+        const task = this.tasks.get(taskId);
+        if (!task) {
+            return { success: false, reason: 'Task not found' };
+        }
+        if (!requesterNodeId || task.publisher !== requesterNodeId) {
+            return { success: false, reason: 'Only the publisher can award the task' };
+        }
+        if (task.status !== 'under_review' || task.winner) {
+            return { success: false, reason: `Task cannot be awarded while ${task.status}` };
+        }
+        const submission = (task.submissions || []).find(item => item.solverId === solverId);
+        if (!submission) {
+            return { success: false, reason: 'No submission from this solver' };
+        }
+        const accountId = options.accountId ?? submission.accountId;
+        if (!accountId) {
+            return { success: false, reason: 'Missing winner account' };
+        }
+        const awardedAt = toMillis(options.awardedAt) || Date.now();
+        const awarded = this.updateTask(taskId, {
+            status: 'completed',
+            winner: solverId,
+            awardedAt,
+            completedAt: awardedAt,
+            escrowDisposition: { type: 'award', to: accountId }
+        });
+        this.completedTasks.add(taskId);
+        const reward = task.bounty?.amount || 0;
+        console.log(`🏆 Task awarded: ${taskId}`);
+        console.log(`   Winner: ${solverId}`);
+        this.emit('task:completed', { taskId, winner: solverId, reward });
+        return { success: true, task: awarded, reward, winnerId: solverId };
+    }
+
     // 验证解决方案
     async validateSolution(task, solution) {
         // 简化验证：检查是否有代码和说明
@@ -166,9 +341,12 @@ class TaskBazaar extends EventEmitter {
         return true;
     }
     
-    // 获取任务列表
+    // 获取任务列表（includeArchived 时合并归档）
     getTasks(filter = {}) {
         let tasks = Array.from(this.tasks.values());
+        if (filter.includeArchived) {
+            tasks = tasks.concat(this.loadArchivedTasks());
+        }
         
         if (filter.status) {
             tasks = tasks.filter(t => t.status === filter.status);
@@ -181,33 +359,359 @@ class TaskBazaar extends EventEmitter {
         return tasks.sort((a, b) => new Date(b.published_at) - new Date(a.published_at));
     }
     
+    taskCapabilities(task) {
+        const tags = Array.isArray(task?.tags) ? task.tags : [];
+        const values = task?.type ? [...tags, task.type] : tags;
+        return Array.from(new Set(values.map(value => String(value).toLowerCase())));
+    }
+
+    indexTask(task) {
+        if (!task?.taskId) return;
+        this.unindexTask(task.taskId);
+        const capabilities = this.taskCapabilities(task);
+        for (const capability of capabilities) {
+            if (!this.capabilityIndex.has(capability)) {
+                this.capabilityIndex.set(capability, new Set());
+            }
+            this.capabilityIndex.get(capability).add(task.taskId);
+        }
+        this.indexedCapabilities.set(task.taskId, capabilities);
+        if (!Array.isArray(task.tags) || task.tags.length === 0) {
+            this.untaggedTaskIds.add(task.taskId);
+        }
+    }
+
+    unindexTask(taskId) {
+        for (const capability of this.indexedCapabilities.get(taskId) || []) {
+            const ids = this.capabilityIndex.get(capability);
+            ids?.delete(taskId);
+            if (ids && ids.size === 0) this.capabilityIndex.delete(capability);
+        }
+        this.indexedCapabilities.delete(taskId);
+        this.untaggedTaskIds.delete(taskId);
+    }
+
+    // 标签或类型与能力列表相交的任务（默认包含没有标签的通用任务），只遍历索引命中的任务
+    getTasksForCapabilities(capabilities = [], { status = null, includeUntagged = true } = {}) {
+        const ids = new Set(includeUntagged ? this.untaggedTaskIds : []);
+        for (const capability of capabilities) {
+            for (const taskId of this.capabilityIndex.get(String(capability).toLowerCase()) || []) {
+                ids.add(taskId);
+            }
+        }
+        const tasks = [];
+        for (const taskId of ids) {
+            const task = this.tasks.get(taskId);
+            if (task && (!status || task.status === status)) tasks.push(task);
+        }
+        return tasks.sort((a, b) => new Date(b.published_at) - new Date(a.published_at));
+    }
+
     // 获取单个任务
     getTask(taskId) {
         return this.tasks.get(taskId);
     }
+
+    getArchivedTask(taskId) {
+        if (!this.archivedTaskIds.has(taskId)) return null;
+        return this.loadArchivedTasks().find(t => t.taskId === taskId) || null;
+    }
+
+    getTaskEndedAt(task) {
+        return toMillis(task.completedAt || task.cancelledAt || task.expiredAt || task.published_at) || 0;
+    }
+
+    getTaskDeadline(task) {
+        const at = toMillis(task?.deadline);
+        return at > 0 ? at : null;
+    }
+
+    // 其他节点宣布的过期：截止时间确已过去才接受，已过期的任务不重复处理
+    applyRemoteExpiry(taskId, expiredAt, expiredBy, now = Date.now()) {
+        const task = this.tasks.get(taskId);
+        if (!task) return { applied: false, reason: 'Task not found' };
+        if (task.status === 'expired') return { applied: false, reason: 'Already expired' };
+        if (!this.isExpirable(task, now)) return { applied: false, reason: `Task is ${task.status} or before its deadline` };
+        this.markExpired(task, Number(expiredAt) > 0 ? Number(expiredAt) : now, expiredBy);
+        this.saveToDisk();
+        this.emit('task:expired', task);
+        return { applied: true, task };
+    }
+
+    // 发布者在任务仍为 open/voting 且没有获胜者时取消任务，托管去向记为退回发布者
+    cancelTask(taskId, requesterNodeId, options = {}) {
+        const task = this.tasks.get(taskId);
+        if (!task) {
+            return { success: false, reason: 'Task not found' };
+        }
+        if (!requesterNodeId || task.publisher !== requesterNodeId) {
+            return { success: false, reason: 'Only the publisher can cancel the task' };
+        }
+        if (!['open', 'voting'].includes(task.status) || task.winner) {
+            return { success: false, reason: `Task cannot be cancelled while ${task.status}` };
+        }
+        const cancelled = this.updateTask(taskId, {
+            status: 'cancelled',
+            cancelledAt: options.cancelledAt || Date.now(),
+            cancelledBy: requesterNodeId,
+            cancelReason: options.reason || 'cancelled by publisher',
+            escrowDisposition: { type: 'refund', to: this.getPublisherAccountId(task) }
+        });
+        this.emit('task:cancelled', cancelled);
+        return { success: true, task: cancelled };
+    }
+
+    // 取消通知必须由发布任务的钱包签名（与托管证明同一账户）
+    verifyTaskCancellation(notice) {
+        return this.verifyPublisherNotice(notice, 'cancellation');
+    }
+
+    // 评选结果同样必须由发布者签名
+    verifyTaskAward(notice) {
+        return this.verifyPublisherNotice(notice, 'award');
+    }
+
+    // 评选模式的解必须由解题节点的钱包签名：solverId 与 accountId 都由签名公钥派生
+    verifyTaskSubmission(notice) {
+        const { statement, publicKeyPem, signature } = notice || {};
+        if (!statement || !publicKeyPem || !signature) {
+            return { valid: false, reason: 'Missing submission signature' };
+        }
+        if (!this.tasks.has(statement.taskId)) {
+            return { valid: false, reason: 'Task not found' };
+        }
+        let signed = false;
+        try {
+            signed = nodeIdFromPublicKey(publicKeyPem) === statement.solverId
+                && accountIdFromPublicKey(publicKeyPem) === statement.accountId
+                && verifyPayload(publicKeyPem, statement, signature);
+        } catch (e) {
+            signed = false;
+        }
+        return signed ? { valid: true } : { valid: false, reason: 'Invalid submission signature' };
+    }
+
+    verifyPublisherNotice(notice, kind) {
+        const label = kind.charAt(0).toUpperCase() + kind.slice(1);
+        const { statement, publicKeyPem, signature } = notice || {};
+        if (!statement || !publicKeyPem || !signature) {
+            return { valid: false, reason: `Missing ${kind} signature` };
+        }
+        const task = this.tasks.get(statement.taskId);
+        if (!task) {
+            return { valid: false, reason: 'Task not found' };
+        }
+        if (statement.publisher !== task.publisher) {
+            return { valid: false, reason: `${label} does not match task publisher` };
+        }
+        // 有托管证明时签名账户必须是证明中的发布者账户；没有托管的任务要求发布者 nodeId 由签名公钥派生
+        const publisherAccountId = this.getPublisherAccountId(task);
+        if (task.escrowProof && !publisherAccountId) {
+            return { valid: false, reason: 'Invalid escrow proof' };
+        }
+        let signed = false;
+        try {
+            const signer = publisherAccountId
+                ? accountIdFromPublicKey(publicKeyPem) === publisherAccountId
+                : nodeIdFromPublicKey(publicKeyPem) === task.publisher;
+            signed = signer && verifyPayload(publicKeyPem, statement, signature);
+        } catch (e) {
+            signed = false;
+        }
+        return signed ? { valid: true } : { valid: false, reason: `Invalid ${kind} signature` };
+    }
+
+    // 评选中（under_review）的任务到截止时间仍未评选同样过期，托管不会一直锁着
+    isExpirable(task, now = Date.now()) {
+        if (!task || !['open', 'pending_escrow', 'voting', 'under_review'].includes(task.status) || task.winner) return false;
+        const deadline = this.getTaskDeadline(task);
+        return !!deadline && now >= deadline;
+    }
+
+    markExpired(task, expiredAt, expiredBy) {
+        if (task.status !== 'expired') task.version = (task.version || 0) + 1;
+        task.status = 'expired';
+        task.expiredAt = expiredAt;
+        task.expiredBy = expiredBy || null;
+        // 备用账户与发布者账户都取自校验通过的签名托管证明，转发节点无法改写托管去向
+        const publisherAccountId = this.getPublisherAccountId(task);
+        const fallbackAccountId = publisherAccountId ? task.escrowProof.statement.fallbackAccountId : null;
+        task.escrowDisposition = fallbackAccountId
+            ? { type: 'fallback', to: fallbackAccountId }
+            : { type: 'refund', to: publisherAccountId };
+        return task;
+    }
+
+    // 截止时间已过且没有获胜者的任务标记为过期，并记录托管去向（备用账户或退回发布者）
+    // canExpire 由上层注入，决定本节点此刻是否有权宣布过期
+    expireTasks(now = Date.now(), canExpire = null) {
+        const expired = [];
+        for (const task of this.tasks.values()) {
+            if (!this.isExpirable(task, now)) continue;
+            if (canExpire && !canExpire(task)) continue;
+            expired.push(this.markExpired(task, now, this.nodeId));
+        }
+        if (expired.length > 0) {
+            this.saveToDisk();
+            for (const task of expired) {
+                this.emit('task:expired', task);
+            }
+        }
+        return expired;
+    }
+
+    // 归档超过保留期的已完成/已取消/已过期任务
+    archiveTasks(now = Date.now()) {
+        if (!(this.archiveAfterMs > 0)) return 0;
+        const fs = require('fs');
+        const archived = [];
+        for (const task of this.tasks.values()) {
+            if (!['completed', 'cancelled', 'expired'].includes(task.status)) continue;
+            const endedAt = this.getTaskEndedAt(task);
+            if (endedAt && now - endedAt >= this.archiveAfterMs) {
+                archived.push(task);
+            }
+        }
+        if (archived.length === 0) return 0;
+        try {
+            fs.appendFileSync(this.archivePath, archived.map(t => JSON.stringify({ ...t, archivedAt: now })).join('\n') + '\n');
+        } catch (e) {
+            console.error('Failed to archive tasks:', e.message);
+            return 0;
+        }
+        for (const task of archived) {
+            this.tasks.delete(task.taskId);
+            this.unindexTask(task.taskId);
+            this.completedTasks.delete(task.taskId);
+            this.submissions.delete(task.taskId);
+            this.archivedTaskIds.add(task.taskId);
+        }
+        this.saveToDisk();
+        console.log(`🗄️  Archived ${archived.length} tasks`);
+        return archived.length;
+    }
+
+    loadArchivedTasks() {
+        const fs = require('fs');
+        if (!fs.existsSync(this.archivePath)) return [];
+        const tasks = [];
+        for (const line of fs.readFileSync(this.archivePath, 'utf8').split('\n')) {
+            if (!line.trim()) continue;
+            try {
+                tasks.push(normalizeTaskTimestamps(JSON.parse(line)));
+            } catch (e) {
+            }
+        }
+        return tasks;
+    }
+
+    loadArchivedIds() {
+        for (const task of this.loadArchivedTasks()) {
+            if (task && task.taskId) {
+                this.archivedTaskIds.add(task.taskId);
+            }
+        }
+    }
     
+    // 添加竞标：同一节点只保留一次；超过上限时淘汰排序最差的，新竞标本身最差则拒绝
+    addBid(taskId, bid) {
+        const task = this.tasks.get(taskId);
+        if (!task) return { accepted: false, reason: 'Task not found' };
+        if (!bid?.nodeId) return { accepted: false, reason: 'Invalid bid' };
+        const bids = task.bids || [];
+        if (bids.some(b => b.nodeId === bid.nodeId)) {
+            return { accepted: false, reason: 'Duplicate bid' };
+        }
+        const { kept, dropped } = this.trimBids([...bids, bid], taskId);
+        if (dropped.includes(bid)) {
+            return { accepted: false, reason: 'Bid not competitive' };
+        }
+        this.updateTask(taskId, {
+            bids: kept,
+            status: task.status === 'open' ? 'voting' : task.status,
+            votingStartedAt: task.votingStartedAt || bid.timestamp || Date.now(),
+            lastBidAt: Date.now()
+        });
+        return { accepted: true, dropped };
+    }
+
+    trimBids(bids, taskId) {
+        if (!(this.maxBidsPerTask > 0) || bids.length <= this.maxBidsPerTask) {
+            return { kept: bids, dropped: [] };
+        }
+        const sorted = [...bids].sort((a, b) => this.compareBids(a, b, taskId));
+        return { kept: sorted.slice(0, this.maxBidsPerTask), dropped: sorted.slice(this.maxBidsPerTask) };
+    }
+
     // 获取任务数量
     getTaskCount() {
         return this.tasks.size;
     }
     
     // 更新任务状态
+    // 状态变化时 version 加一；带入的 version 更大时（来自网络）取较大值
     updateTask(taskId, updates) {
         const task = this.tasks.get(taskId);
         if (task) {
-            this.tasks.set(taskId, { ...task, ...updates });
+            const statusChanged = updates.status !== undefined && updates.status !== task.status;
+            const version = Math.max((task.version || 0) + (statusChanged ? 1 : 0), Number(updates.version) || 0);
+            this.tasks.set(taskId, normalizeTaskTimestamps({ ...task, ...updates, version }));
+            if ('tags' in updates || 'type' in updates) {
+                this.indexTask(this.tasks.get(taskId));
+            }
             this.saveToDisk();
             return this.tasks.get(taskId);
         }
         return null;
     }
     
+    // 网络上的状态更新可能乱序到达：状态倒退、离开终态，或同一状态下版本更低的更新都被忽略
+    isStaleUpdate(task, status, version) {
+        const current = STATUS_RANK[task.status] ?? 0;
+        const next = STATUS_RANK[status] ?? current;
+        if (next < current) {
+            return `${task.status} -> ${status} is a backward transition`;
+        }
+        if (current === TERMINAL_RANK && status !== task.status) {
+            return `task is already ${task.status}`;
+        }
+        if (status === task.status && Number(version) <= (task.version || 0)) {
+            return `version ${version} is not newer than ${task.version}`;
+        }
+        return null;
+    }
+
+    // 分配只能由发布者宣布；完成/失败由被分配节点或发布者宣布。sender 必须是签名校验过的消息来源
+    isAuthorizedUpdater(task, status, sender) {
+        if (!sender) return false;
+        if (status === 'assigned') return sender === task.publisher;
+        return sender === task.publisher || (!!task.assignedTo && sender === task.assignedTo);
+    }
+
+    // 版本号最多比本地前进 1，夸大的版本号不会让之后的正常更新被当成过期消息
+    applyRemoteUpdate(taskId, updates, version, sender = null) {
+        const task = this.tasks.get(taskId);
+        if (!task) {
+            return { applied: false, reason: 'Task not found' };
+        }
+        if (!this.isAuthorizedUpdater(task, updates.status, sender)) {
+            return { applied: false, reason: sender ? `Unauthorized update from ${sender}` : 'Unsigned status update' };
+        }
+        const stale = this.isStaleUpdate(task, updates.status, version);
+        if (stale) {
+            return { applied: false, reason: `Stale update: ${stale}` };
+        }
+        const capped = Math.min(Number(version) || 0, (task.version || 0) + 1);
+        return { applied: true, task: this.updateTask(taskId, { ...updates, version: capped }) };
+    }
+
     // 完成任务
     completeTask(taskId, result) {
         const task = this.tasks.get(taskId);
         if (task) {
+            if (task.status !== 'completed') task.version = (task.version || 0) + 1;
             task.status = 'completed';
-            task.completedAt = new Date().toISOString();
+            task.completedAt = Date.now();
             task.result = result;
             this.tasks.set(taskId, task);
             this.saveToDisk();
@@ -252,11 +756,69 @@ class TaskBazaar extends EventEmitter {
         for (const task of this.tasks.values()) {
             if (task.status === 'pending_escrow' && this.isEscrowFunded(task)) {
                 task.status = 'open';
+                task.version = (task.version || 0) + 1;
                 this.tasks.set(task.taskId, task);
             }
         }
     }
 
+    // 校验发布者签名的托管证明：签名、托管账户、金额，以及账本中的锁定交易
+    verifyEscrowProof(task) {
+        const bounty = Number(task?.bounty?.amount || 0);
+        if (bounty <= 0) return { valid: true };
+        const proof = task.escrowProof;
+        if (!proof || !proof.statement || !proof.signature || !proof.publicKeyPem) {
+            return { valid: false, reason: 'Missing escrow proof' };
+        }
+        const { statement } = proof;
+        const escrowAccountId = this.getEscrowAccountId(task.taskId);
+        if (statement.taskId !== task.taskId || statement.escrowAccountId !== escrowAccountId) {
+            return { valid: false, reason: 'Escrow proof does not match task' };
+        }
+        if (task.publisherAccountId && task.publisherAccountId !== statement.publisher) {
+            return { valid: false, reason: 'Publisher account does not match escrow proof' };
+        }
+        if (Number(statement.amount) < bounty) {
+            return { valid: false, reason: 'Escrowed amount below bounty' };
+        }
+        let signed = false;
+        try {
+            signed = accountIdFromPublicKey(proof.publicKeyPem) === statement.publisher
+                && verifyPayload(proof.publicKeyPem, statement, proof.signature);
+        } catch (e) {
+            signed = false;
+        }
+        if (!signed) {
+            return { valid: false, reason: 'Invalid escrow proof signature' };
+        }
+        if (this.ledger) {
+            const tx = this.ledger.getTxById(statement.txId);
+            if (!tx) {
+                return { valid: false, reason: 'Escrow tx not found in ledger' };
+            }
+            if (tx.from !== statement.publisher || tx.to !== escrowAccountId || Number(tx.amount) < bounty) {
+                return { valid: false, reason: 'Escrow tx does not match proof' };
+            }
+        }
+        return { valid: true };
+    }
+
+    // 退款目标与发布者签名校验只认通过校验的托管证明中的发布者账户，不用任务里未签名的 publisherAccountId
+    getPublisherAccountId(task) {
+        const proof = task?.escrowProof;
+        if (!proof?.statement || !proof.signature || !proof.publicKeyPem) return null;
+        let signed = false;
+        try {
+            signed = proof.statement.taskId === task.taskId
+                && accountIdFromPublicKey(proof.publicKeyPem) === proof.statement.publisher
+                && verifyPayload(proof.publicKeyPem, proof.statement, proof.signature);
+        } catch (e) {
+            signed = false;
+        }
+        if (!signed || !this.verifyEscrowProof(task).valid) return null;
+        return proof.statement.publisher;
+    }
+
     getEscrowAccountId(taskId) {
         const hash = crypto.createHash('sha256').update(String(taskId)).digest('hex').slice(0, 24);
         return `escrow_${hash}`;
@@ -282,7 +844,7 @@ class TaskBazaar extends EventEmitter {
                 amount: totalBounty,
                 token: 'CLAW'
             },
-            published_at: new Date().toISOString()
+            published_at: Date.now()
         };
         
         return await this.publishTask(swarmTask);
@@ -307,3 +869,5 @@ class TaskBazaar extends EventEmitter {
 }
 
 module.exports = TaskBazaar;
+module.exports.normalizeTaskTimestamps = normalizeTaskTimestamps;
+module.exports.SELECTION_MODES = SELECTION_MODES;
@@ -0,0 +1,162 @@
+/**
+ * TaskExecutor - 中标任务的可插拔执行器
+ * 执行器实现 execute(task, workDir, { signal })，成功返回 { success: true, outputFiles, ... }，
+ * 失败返回 { success: false, error }。默认仍由 TaskWorker 调用 OpenClaw 子代理
+ */
+
+const fs = require('fs').promises;
+const { createWriteStream } = require('fs');
+const path = require('path');
+const { spawn } = require('child_process');
+
+// 写入结果的标准输出上限，超出部分只保留在 solution 文件里；标准错误同样只在内存中保留这么多（末尾）
+const MAX_SOLUTION_CHARS = 64 * 1024;
+// 任务描述是远端发布者写的任意文本，只通过环境变量传入，不能放进命令参数
+const ENV_ONLY_FIELDS = ['description'];
+
+// 按空白拆分命令模板（支持单/双引号），不经过 shell，任务内容无法注入额外命令
+function splitCommand(template) {
+    const args = [];
+    const pattern = /"([^"]*)"|'([^']*)'|(\S+)/g;
+    let match;
+    while ((match = pattern.exec(template)) !== null) {
+        args.push(match[1] ?? match[2] ?? match[3]);
+    }
+    return args;
+}
+
+// 参数中的 {taskId} {tags} {workDir} 替换为任务字段。taskId 和 tags 同样来自远端任务，
+// 替换后以 - 开头（模板原本不是选项）时拒绝执行，避免被目标程序当成选项
+function buildCommandArgs(template, task, workDir) {
+    const values = {
+        taskId: task.taskId,
+        tags: (Array.isArray(task.tags) ? task.tags : []).join(','),
+        workDir
+    };
+    return splitCommand(template).map(arg => {
+        const value = arg.replace(/\{(taskId|tags|workDir)\}/g, (_, key) => values[key]);
+        if (value.startsWith('-') && !arg.startsWith('-')) {
+            throw new Error(`Task field in argument ${arg} would be passed as an option`);
+        }
+        return value;
+    });
+}
+
+async function listOutputFiles(workDir) {
+    const outputFiles = [];
+    for (const name of await fs.readdir(workDir)) {
+        if (name.endsWith('.zip')) continue;
+        const stat = await fs.stat(path.join(workDir, name));
+        if (stat.isFile()) outputFiles.push({ name, size: stat.size });
+    }
+    return outputFiles;
+}
+
+class ShellExecutor {
+    constructor(options = {}) {
+        if (!options.command) {
+            throw new Error('ShellExecutor requires a command');
+        }
+        const envOnly = ENV_ONLY_FIELDS.find(field => options.command.includes(`{${field}}`));
+        if (envOnly) {
+            throw new Error(`{${envOnly}} cannot be used in the command, read OPENCLAW_TASK_${envOnly.toUpperCase()} instead`);
+        }
+        this.command = options.command;
+        this.timeoutMs = options.timeoutMs || 600000;
+    }
+
+    // 在任务工作目录中运行命令；标准输出作为解，命令写入工作目录的文件一并作为产物
+    async execute(task, workDir, { signal } = {}) {
+        const env = {
+            ...process.env,
+            OPENCLAW_TASK_ID: task.taskId,
+            OPENCLAW_TASK_DESCRIPTION: task.description || '',
+            OPENCLAW_TASK_TAGS: (Array.isArray(task.tags) ? task.tags : []).join(','),
+            OPENCLAW_TASK_WORKDIR: workDir,
+            OPENCLAW_TASK_JSON: JSON.stringify({ taskId: task.taskId, description: task.description, tags: task.tags || [], bounty: task.bounty })
+        };
+        const startedAt = Date.now();
+        const outputPath = path.join(workDir, 'solution.txt');
+        let run;
+        try {
+            const [bin, ...args] = buildCommandArgs(this.command, task, workDir);
+            run = await this.run(bin, args, workDir, env, signal, outputPath);
+        } catch (error) {
+            await fs.rm(outputPath, { force: true });
+            return { success: false, error: error.message };
+        }
+        if (run.code !== 0) {
+            await fs.rm(outputPath, { force: true });
+            const detail = run.stderr.trim().slice(-2000) || `exited with code ${run.code}`;
+            return { success: false, error: run.timedOut ? `Command timed out after ${this.timeoutMs}ms` : detail };
+        }
+
+        // 完整输出已写入 solution.txt；未超出上限且能解析为 JSON 时改名为 solution.json
+        let solutionFile = 'solution.txt';
+        if (!run.truncated) {
+            try {
+                JSON.parse(run.stdout);
+                solutionFile = 'solution.json';
+                await fs.rename(outputPath, path.join(workDir, solutionFile));
+            } catch (e) {
+            }
+        }
+        return {
+            success: true,
+            outputFiles: await listOutputFiles(workDir),
+            solution: run.stdout,
+            solutionFile,
+            processingTime: Date.now() - startedAt,
+            completedAt: Date.now(),
+            source: 'shell'
+        };
+    }
+
+    // 标准输出边读边写入 outputPath，内存中只保留前 MAX_SOLUTION_CHARS 个字符，标准错误只保留末尾
+    run(bin, args, cwd, env, signal, outputPath) {
+        return new Promise((resolve, reject) => {
+            const output = createWriteStream(outputPath);
+            const child = spawn(bin, args, { stdio: ['ignore', 'pipe', 'pipe'], cwd, env });
+            let stdout = '';
+            let stderr = '';
+            let truncated = false;
+            let timedOut = false;
+            const timer = setTimeout(() => {
+                timedOut = true;
+                child.kill('SIGTERM');
+            }, this.timeoutMs);
+            // 排空超时后取消子进程
+            const onAbort = () => child.kill('SIGTERM');
+            if (signal) {
+                if (signal.aborted) onAbort();
+                else signal.addEventListener('abort', onAbort, { once: true });
+            }
+            child.stdout.setEncoding('utf8');
+            child.stderr.setEncoding('utf8');
+            child.stdout.on('data', (data) => {
+                output.write(data);
+                if (stdout.length + data.length > MAX_SOLUTION_CHARS) {
+                    truncated = true;
+                    stdout = (stdout + data).slice(0, MAX_SOLUTION_CHARS);
+                } else {
+                    stdout += data;
+                }
+            });
+            child.stderr.on('data', (data) => {
+                stderr = (stderr + data).slice(-MAX_SOLUTION_CHARS);
+            });
+            child.on('error', (error) => {
+                clearTimeout(timer);
+                signal?.removeEventListener('abort', onAbort);
+                output.end(() => reject(error));
+            });
+            child.on('close', (code) => {
+                clearTimeout(timer);
+                signal?.removeEventListener('abort', onAbort);
+                output.end(() => resolve({ code, stdout, stderr, truncated, timedOut }));
+            });
+        });
+    }
+}
+
+module.exports = { ShellExecutor, buildCommandArgs, splitCommand };
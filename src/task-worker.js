@@ -4,7 +4,10 @@
 
 const fs = require('fs').promises;
 const path = require('path');
+const crypto = require('crypto');
 const { spawn } = require('child_process');
+const { ShellExecutor } = require('./task-executor');
+const { verifyPayload, accountIdFromPublicKey } = require('./wallet');
 
 class TaskWorker {
     constructor(meshNode) {
@@ -14,6 +17,25 @@ class TaskWorker {
         this.completedTasks = new Map();
         this.workDir = path.join(process.cwd(), 'task-workspace');
         this.biddingTasks = new Map(); // Tasks currently being voted on
+        this.unfundedTasks = new Map(); // taskId -> last escrow proof rejection reason
+        this.tieBreakSeeds = new Map(); // taskId -> { signature, seed }
+        this.votingPeriodMs = meshNode.options?.votingPeriodMs ?? 5000;
+        // Quiet period with no new bids before finalizing, so every node sees the same bid set
+        this.votingQuietMs = meshNode.options?.votingQuietMs ?? 3000;
+        // 出价策略：fixed 固定比例、random 区间内随机、match-tags 按标签重合度定价
+        const strategy = meshNode.options?.bidStrategy;
+        this.bidStrategy = ['random', 'match-tags'].includes(strategy) ? strategy : 'fixed';
+        this.bidPercent = meshNode.options?.bidPercent ?? 0.9;
+        this.bidMinPercent = meshNode.options?.bidMinPercent ?? 0.7;
+        this.bidMaxPercent = meshNode.options?.bidMaxPercent ?? 0.95;
+        // 本节点擅长的标签，非空时跳过标签不相交的任务
+        this.workerTags = (meshNode.options?.workerTags || []).map(tag => String(tag).toLowerCase());
+        // 严格匹配：任务的每个标签都必须在本节点标签中
+        this.requireAllTags = meshNode.options?.requireAllTags === true;
+        // 任务执行器：自定义 taskExecutor 对象，或按 taskCommand 模板运行命令；都未配置时调用 OpenClaw 子代理
+        this.executor = meshNode.options?.taskExecutor || (meshNode.options?.taskCommand
+            ? new ShellExecutor({ command: meshNode.options.taskCommand, timeoutMs: meshNode.options.taskCommandTimeoutMs })
+            : null);
         this.init();
     }
 
@@ -33,17 +55,28 @@ class TaskWorker {
         console.log('   Node ID:', this.nodeId);
         
         // Check for new tasks and voting results
-        setInterval(() => this.checkTasks(), 10000);
+        this.checkTimer = setInterval(() => this.checkTasks(), 10000);
         
         // Process voting results after voting period
-        setInterval(() => this.processVotingResults(), 5000);
+        this.votingTimer = setInterval(() => this.processVotingResults(), 5000);
+    }
+
+    stopAutoBidding() {
+        clearInterval(this.checkTimer);
+        clearInterval(this.votingTimer);
+        this.checkTimer = null;
+        this.votingTimer = null;
     }
 
     async checkTasks() {
         if (!this.mesh || !this.mesh.taskBazaar) return;
+        if (this.mesh.isDraining?.()) return;
 
-        const tasks = this.mesh.taskBazaar.getTasks();
-        const openTasks = tasks.filter(t => t.status === 'open');
+        // 声明了标签时只从能力索引中取相关任务，不再扫描全部任务
+        const bazaar = this.mesh.taskBazaar;
+        const openTasks = this.workerTags.length > 0 && bazaar.getTasksForCapabilities
+            ? bazaar.getTasksForCapabilities(this.workerTags, { status: 'open' })
+            : bazaar.getTasks().filter(t => t.status === 'open');
         
         if (openTasks.length > 0) {
             console.log('🔍 Found', openTasks.length, 'open tasks');
@@ -53,49 +86,98 @@ class TaskWorker {
             if (this.activeTasks.has(task.taskId)) continue;
             if (this.completedTasks.has(task.taskId)) continue;
             if (this.biddingTasks.has(task.taskId)) continue;
+            if (!this.hasValidEscrow(task)) continue;
+            if (!this.matchesWorkerTags(task)) continue;
+            // 评选模式的任务通过提交解竞争，不走竞标分配
+            if (this.mesh.taskBazaar.getSelectionMode?.(task) === 'review') continue;
 
             // Start voting for this task
             await this.submitBid(task);
         }
     }
 
+    // Only bid on tasks whose publisher proved the bounty is locked in escrow
+    hasValidEscrow(task) {
+        const check = this.mesh.taskBazaar.verifyEscrowProof?.(task);
+        if (!check || check.valid) {
+            this.unfundedTasks.delete(task.taskId);
+            return true;
+        }
+        if (this.unfundedTasks.get(task.taskId) !== check.reason) {
+            console.log('⛔ Skipping task', task.taskId.slice(0, 16), '...', check.reason);
+            this.unfundedTasks.set(task.taskId, check.reason);
+        }
+        return false;
+    }
+
+    taskTags(task) {
+        return (Array.isArray(task.tags) ? task.tags : []).map(tag => String(tag).toLowerCase());
+    }
+
+    // 任务标签与本节点标签的重合比例；未打标签的任务视为通用任务
+    tagOverlap(task) {
+        const taskTags = this.taskTags(task);
+        if (taskTags.length === 0 || this.workerTags.length === 0) return null;
+        const matched = taskTags.filter(tag => this.workerTags.includes(tag)).length;
+        return matched / taskTags.length;
+    }
+
+    matchesWorkerTags(task) {
+        const taskTags = this.taskTags(task);
+        if (taskTags.length === 0) return true;
+        if (this.requireAllTags) {
+            return taskTags.every(tag => this.workerTags.includes(tag));
+        }
+        const overlap = this.tagOverlap(task);
+        return overlap === null || overlap > 0;
+    }
+
+    // 按策略计算出价比例，结果限制在 (0, 1] 之间
+    bidRatio(task) {
+        const min = Math.min(this.bidMinPercent, this.bidMaxPercent);
+        const max = Math.max(this.bidMinPercent, this.bidMaxPercent);
+        let ratio = this.bidPercent;
+        if (this.bidStrategy === 'random') {
+            ratio = min + Math.random() * (max - min);
+        } else if (this.bidStrategy === 'match-tags') {
+            // 重合度越高出价越低（越有竞争力），无法判断时取区间上限
+            const overlap = this.tagOverlap(task) ?? 0;
+            ratio = max - overlap * (max - min);
+        }
+        return Math.min(1, Math.max(0.01, ratio));
+    }
+
+    computeBidAmount(task) {
+        return Math.floor(task.bounty.amount * this.bidRatio(task));
+    }
+
     async submitBid(task) {
         if (this.mesh?.ratingStore?.isDisqualified(this.nodeId)) {
             return;
         }
+        if (this.mesh?.isDraining?.()) {
+            return;
+        }
+        const bidAmount = this.computeBidAmount(task);
         // Mark as bidding to avoid duplicate bids
         this.biddingTasks.set(task.taskId, {
             bidTime: Date.now(),
-            amount: Math.floor(task.bounty.amount * 0.9)
+            amount: bidAmount
         });
         
-        const bidAmount = Math.floor(task.bounty.amount * 0.9);
         console.log('💰 Submitting bid for task:', task.taskId.slice(0, 16), '...', 'Amount:', bidAmount);
 
-        // Add bid to task
-        const taskData = this.mesh.taskBazaar.getTask(task.taskId) || task;
-        taskData.bids = taskData.bids || [];
-        
-        // Check if already bid
-        const existingBid = taskData.bids.find(b => b.nodeId === this.nodeId);
-        if (existingBid) {
-            console.log('   Already bid on this task');
-            return;
-        }
-        
         const bid = {
             nodeId: this.nodeId,
             amount: bidAmount,
             timestamp: Date.now()
         };
-        taskData.bids.push(bid);
-        
-        // Update task
-        this.mesh.taskBazaar.updateTask(task.taskId, { 
-            bids: taskData.bids, 
-            status: 'voting',
-            votingStartedAt: taskData.votingStartedAt || Date.now()
-        });
+        // Add bid to task (duplicates and uncompetitive bids beyond the cap are rejected)
+        const added = this.mesh.taskBazaar.addBid(task.taskId, bid);
+        if (!added.accepted) {
+            console.log('   Bid not added:', added.reason);
+            return;
+        }
         
         // Broadcast bid to P2P network
         if (this.mesh.node && this.mesh.node.broadcast) {
@@ -104,7 +186,8 @@ class TaskWorker {
                 payload: {
                     taskId: task.taskId,
                     bid: bid
-                }
+                },
+                ...this.traceFields(task.taskId)
             });
         }
     }
@@ -118,9 +201,11 @@ class TaskWorker {
         for (const task of votingTasks) {
             const coordinatorId = task.publisher || task.coordinator;
             if (coordinatorId && coordinatorId !== this.nodeId) continue;
-            // Check if voting period is over (5 seconds)
+            // Check if voting period is over and the bid set has settled
             const votingAge = Date.now() - (task.votingStartedAt || 0);
-            if (votingAge < 5000) continue; // Still voting
+            if (votingAge < this.votingPeriodMs) continue; // Still voting
+            const quietFor = Date.now() - (task.lastBidAt || task.votingStartedAt || 0);
+            if (quietFor < this.votingQuietMs) continue; // Bids still arriving
             
             // Determine winner deterministically
             const winner = this.determineWinner(task);
@@ -128,7 +213,7 @@ class TaskWorker {
             if (!winner) continue;
 
             const assignedAt = Date.now();
-            this.mesh.taskBazaar.updateTask(task.taskId, { 
+            const assigned = this.mesh.taskBazaar.updateTask(task.taskId, { 
                 status: 'assigned',
                 assignedTo: winner.nodeId,
                 assignedAt
@@ -139,8 +224,10 @@ class TaskWorker {
                     payload: {
                         taskId: task.taskId,
                         assignedTo: winner.nodeId,
-                        assignedAt
-                    }
+                        assignedAt,
+                        version: assigned?.version
+                    },
+                    ...this.traceFields(task.taskId)
                 });
             }
 
@@ -154,33 +241,86 @@ class TaskWorker {
         }
     }
 
+    // 沿用任务发布时生成的 traceId
+    traceFields(taskId) {
+        const traceId = this.mesh?.taskBazaar?.getTask(taskId)?.traceId;
+        return traceId ? { traceId } : {};
+    }
+
     determineWinner(task) {
         if (!task.bids || task.bids.length === 0) return null;
         const allowedBids = task.bids.filter(b => this.mesh?.ratingStore ? !this.mesh.ratingStore.isDisqualified(b.nodeId) : true);
         if (allowedBids.length === 0) return null;
         
-        // Sort by amount (lowest wins), then by timestamp (earliest wins)
-        const sortedBids = [...allowedBids].sort((a, b) => {
-            if (a.amount !== b.amount) return a.amount - b.amount;
-            return a.timestamp - b.timestamp;
-        });
+        const sortedBids = [...allowedBids].sort((a, b) => this.compareBids(a, b, task.taskId));
         
         return sortedBids[0];
     }
 
+    // Sort by amount (lowest wins), then by a hash seeded with the taskId.
+    // Timestamps depend on the bidder's clock and gossip order, so they are not used.
+    compareBids(a, b, taskId = '') {
+        if (a.amount !== b.amount) return a.amount - b.amount;
+        const ha = this.bidTieBreakKey(a, taskId);
+        const hb = this.bidTieBreakKey(b, taskId);
+        if (ha !== hb) return ha < hb ? -1 : 1;
+        return String(a.nodeId).localeCompare(String(b.nodeId));
+    }
+
+    // 平局按 hash(全网种子, 任务种子, nodeId) 排序：所有节点结果一致，但任务出现之前无法预知谁会赢。
+    // 全网种子（tieBreakSeed）必须在所有节点上相同，否则各节点会选出不同的获胜者
+    bidTieBreakKey(bid, taskId = '') {
+        const seed = this.mesh?.options?.tieBreakSeed || '';
+        return crypto.createHash('sha256').update(`${seed}:${this.taskTieBreakSeed(taskId)}:${bid.nodeId}:${bid.amount}`).digest('hex');
+    }
+
+    // 任务种子只取发布者签名的托管证明中的字段（taskId、锁定交易、发布者账户、签发时间），
+    // 签名无效或没有证明时只用 taskId；不读任务中其他可被转发节点改写的字段
+    taskTieBreakSeed(taskId) {
+        const proof = this.mesh?.taskBazaar?.getTask?.(taskId)?.escrowProof;
+        const statement = proof?.statement;
+        if (!statement || !proof.signature || !proof.publicKeyPem) return String(taskId);
+        const cached = this.tieBreakSeeds.get(taskId);
+        if (cached && cached.signature === proof.signature) return cached.seed;
+        let signed = false;
+        try {
+            signed = statement.taskId === taskId
+                && accountIdFromPublicKey(proof.publicKeyPem) === statement.publisher
+                && verifyPayload(proof.publicKeyPem, statement, proof.signature);
+        } catch (e) {
+            signed = false;
+        }
+        const seed = signed
+            ? `${statement.taskId}:${statement.txId}:${statement.publisher}:${statement.issuedAt}`
+            : String(taskId);
+        this.tieBreakSeeds.delete(taskId);
+        this.tieBreakSeeds.set(taskId, { signature: proof.signature, seed });
+        if (this.tieBreakSeeds.size > 1000) {
+            this.tieBreakSeeds.delete(this.tieBreakSeeds.keys().next().value);
+        }
+        return seed;
+    }
+
     async startWorkingOnTask(task) {
         if (this.activeTasks.has(task.taskId)) return;
+        if (this.mesh?.isDraining?.()) {
+            console.log('⏸️ Draining, not starting task:', task.taskId.slice(0, 16), '...');
+            return;
+        }
         
         this.activeTasks.set(task.taskId, task);
         
-        const taskWorkDir = path.join(this.workDir, task.taskId);
-        await fs.mkdir(taskWorkDir, { recursive: true });
+        try {
+            const taskWorkDir = path.join(this.workDir, task.taskId);
+            await fs.mkdir(taskWorkDir, { recursive: true });
 
-        console.log('🔨 Starting work on:', task.description, '...');
-        console.log('   Work directory:', taskWorkDir);
-        
-        await this.processTaskWithOpenClaw(task, taskWorkDir);
-        this.activeTasks.delete(task.taskId);
+            console.log('🔨 Starting work on:', task.description, '...');
+            console.log('   Work directory:', taskWorkDir);
+            
+            await this.processTaskWithOpenClaw(task, taskWorkDir);
+        } finally {
+            this.activeTasks.delete(task.taskId);
+        }
     }
 
     // Main method: Process task using OpenClaw sub-agent
@@ -196,8 +336,10 @@ class TaskWorker {
         await fs.writeFile(path.join(workDir, 'STATUS.txt'), 'PROCESSING: Task submitted to OpenClaw sub-agent\nStarted: ' + new Date().toISOString());
 
         try {
-            // Spawn OpenClaw sub-agent using CLI
-            const result = await this.spawnOpenClawAgent(task, workDir);
+            // Spawn OpenClaw sub-agent using CLI, unless a task executor is configured
+            const result = this.executor
+                ? await this.runExecutor(task, workDir)
+                : await this.spawnOpenClawAgent(task, workDir);
             
             if (result.success) {
                 console.log('✅ Sub-agent completed task successfully');
@@ -222,6 +364,15 @@ class TaskWorker {
         }
     }
 
+    async runExecutor(task, workDir) {
+        console.log('⚙️  Running task executor:', this.executor.constructor?.name || 'custom');
+        const result = await this.executor.execute(task, workDir, { signal: this.mesh?.drainController?.signal });
+        if (result?.success) {
+            await this.finalizeOutput(task, workDir, result.outputFiles || [], result.source || 'task-executor');
+        }
+        return result || { success: false, error: 'Executor returned no result' };
+    }
+
     buildTaskInstruction(task, workDir) {
         return `# OpenClaw Task Assignment
 
@@ -528,6 +679,13 @@ All required outputs have been generated and validated.
     async runOpenClawCommand(bin, args, cwd, onStdout) {
         return new Promise((resolve, reject) => {
             const child = spawn(bin, args, { stdio: ['ignore', 'pipe', 'pipe'], cwd });
+            // 排空超时后取消子进程
+            const signal = this.mesh?.drainController?.signal;
+            const onAbort = () => child.kill('SIGTERM');
+            if (signal) {
+                if (signal.aborted) onAbort();
+                else signal.addEventListener('abort', onAbort, { once: true });
+            }
             let stderr = '';
             let stdout = '';
             child.stdout.on('data', (data) => {
@@ -544,6 +702,7 @@ All required outputs have been generated and validated.
             });
             child.on('error', reject);
             child.on('close', (code) => {
+                signal?.removeEventListener('abort', onAbort);
                 if (code !== 0) {
                     reject(new Error(stderr || `openclaw exited with code ${code}`));
                     return;
@@ -716,13 +875,13 @@ All required outputs have been generated and validated.
         return outputFiles;
     }
 
-    async finalizeOutput(task, workDir, outputFiles) {
+    async finalizeOutput(task, workDir, outputFiles, processedBy = 'OpenClaw Sub-Agent') {
         const manifest = {
             taskId: task.taskId,
             description: task.description,
             completedAt: new Date().toISOString(),
             outputFiles,
-            processedBy: 'OpenClaw Sub-Agent',
+            processedBy,
             nodeId: this.mesh?.nodeId
         };
         
@@ -733,7 +892,7 @@ All required outputs have been generated and validated.
         
         await fs.writeFile(
             path.join(workDir, 'STATUS.txt'),
-            'COMPLETED: Task finished by OpenClaw sub-agent\nCompleted: ' + new Date().toISOString()
+            'COMPLETED: Task finished by ' + processedBy + '\nCompleted: ' + new Date().toISOString()
         );
         
         await this.createDownloadPackage(task.taskId, workDir);
@@ -782,11 +941,12 @@ All required outputs have been generated and validated.
         }
 
         // Update task in bazaar with completion info
+        let completed = null;
         if (this.mesh.taskBazaar) {
-            this.mesh.taskBazaar.completeTask(taskId, {
+            completed = this.mesh.taskBazaar.completeTask(taskId, {
                 result,
                 nodeId: this.nodeId,
-                completedAt: new Date().toISOString()
+                completedAt: Date.now()
             });
         }
 
@@ -814,8 +974,10 @@ All required outputs have been generated and validated.
                         outputFiles: result.outputFiles,
                         completedAt: result.completedAt
                     },
-                    package: packageData
-                }
+                    package: packageData,
+                    version: completed?.version
+                },
+                ...this.traceFields(taskId)
             });
             console.log('📡 Broadcasted task completion to P2P network');
         }
@@ -826,11 +988,49 @@ All required outputs have been generated and validated.
         console.log('   Package location:', completedDir);
     }
 
+    // 把结果包上传到发布者的 API（POST /api/tasks/:id/upload），从服务端已接收的偏移处续传
+    async uploadPackage(apiUrl, taskId, zipPath, options = {}) {
+        const { signPayload } = require('./wallet');
+        const chunkSize = options.chunkSize || 1024 * 1024;
+        const endpoint = `${apiUrl.replace(/\/$/, '')}/api/tasks/${taskId}/upload`;
+        const headers = { 'x-node-id': this.nodeId };
+        const data = await fs.readFile(zipPath);
+        const status = await fetch(endpoint, { headers }).then(r => r.json());
+        if (status.error) throw new Error(status.error);
+        let offset = status.offset;
+        while (offset < data.length) {
+            const chunk = data.subarray(offset, offset + chunkSize);
+            const statement = {
+                taskId,
+                nodeId: this.nodeId,
+                offset,
+                total: data.length,
+                sha256: crypto.createHash('sha256').update(chunk).digest('hex')
+            };
+            const result = await fetch(endpoint, {
+                method: 'POST',
+                headers: {
+                    ...headers,
+                    'Content-Type': 'application/octet-stream',
+                    'x-upload-offset': String(offset),
+                    'x-upload-total': String(data.length),
+                    'x-signature': signPayload(this.mesh.wallet.privateKeyPem, statement)
+                },
+                body: chunk
+            }).then(r => r.json());
+            if (result.error && (result.offset === undefined || result.offset === offset)) throw new Error(result.error);
+            // 偏移不一致时按服务端返回的偏移继续
+            offset = result.offset;
+        }
+        return { taskId, size: data.length };
+    }
+
     async failTask(taskId, error) {
         console.error('❌ Task failed:', taskId.slice(0, 16), '... -', error);
         
+        let failed = null;
         if (this.mesh.taskBazaar) {
-            this.mesh.taskBazaar.updateTask(taskId, { 
+            failed = this.mesh.taskBazaar.updateTask(taskId, { 
                 status: 'failed', 
                 error 
             });
@@ -843,8 +1043,10 @@ All required outputs have been generated and validated.
                     taskId,
                     nodeId: this.nodeId,
                     error,
-                    failedAt: Date.now()
-                }
+                    failedAt: Date.now(),
+                    version: failed?.version
+                },
+                ...this.traceFields(taskId)
             });
         }
     }
@@ -2,6 +2,10 @@ const fs = require('fs');
 const path = require('path');
 const crypto = require('crypto');
 
+// 节点能够用来签名的密钥算法；导入时允许的集合可通过配置收窄
+const SUPPORTED_KEY_ALGORITHMS = ['ed25519', 'ed448'];
+const DEFAULT_KEY_ALGORITHM = 'ed25519';
+
 function sha256Hex(input) {
     return crypto.createHash('sha256').update(input).digest('hex');
 }
@@ -11,6 +15,11 @@ function accountIdFromPublicKey(publicKeyPem) {
     return `acct_${hash.slice(0, 16)}`;
 }
 
+// 启用消息签名时节点ID由公钥派生，无法冒用他人的ID
+function nodeIdFromPublicKey(publicKeyPem) {
+    return `node_${sha256Hex(publicKeyPem).slice(0, 16)}`;
+}
+
 function loadOrCreateWallet(dataDir) {
     const walletPath = path.join(dataDir, 'wallet.json');
     if (fs.existsSync(walletPath)) {
@@ -19,19 +28,20 @@ function loadOrCreateWallet(dataDir) {
             walletPath,
             publicKeyPem: raw.publicKeyPem,
             privateKeyPem: raw.privateKeyPem,
-            accountId: raw.accountId
+            accountId: raw.accountId,
+            algorithm: raw.algorithm || DEFAULT_KEY_ALGORITHM
         };
     }
     const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
     const publicKeyPem = publicKey.export({ type: 'spki', format: 'pem' });
     const privateKeyPem = privateKey.export({ type: 'pkcs8', format: 'pem' });
     const accountId = accountIdFromPublicKey(publicKeyPem);
-    const payload = { publicKeyPem, privateKeyPem, accountId, createdAt: new Date().toISOString() };
+    const payload = { publicKeyPem, privateKeyPem, accountId, algorithm: DEFAULT_KEY_ALGORITHM, createdAt: new Date().toISOString() };
     fs.writeFileSync(walletPath, JSON.stringify(payload, null, 2));
-    return { walletPath, publicKeyPem, privateKeyPem, accountId };
+    return { walletPath, publicKeyPem, privateKeyPem, accountId, algorithm: DEFAULT_KEY_ALGORITHM };
 }
 
-function importWallet(dataDir, payload = {}) {
+function importWallet(dataDir, payload = {}, options = {}) {
     const walletPath = path.join(dataDir, 'wallet.json');
     const source = payload.account || payload || {};
     const privateKeyPem = source.privateKeyPem || source.privateKey || payload.privateKeyPem;
@@ -39,6 +49,16 @@ function importWallet(dataDir, payload = {}) {
         throw new Error('Missing privateKeyPem in import payload');
     }
     const privateKeyObj = crypto.createPrivateKey(privateKeyPem);
+    const allowed = (options.supportedAlgorithms || [DEFAULT_KEY_ALGORITHM])
+        .filter(alg => SUPPORTED_KEY_ALGORITHMS.includes(alg));
+    const keyAlgorithm = privateKeyObj.asymmetricKeyType;
+    const algorithm = source.algorithm || payload.algorithm || keyAlgorithm;
+    if (!allowed.includes(algorithm)) {
+        throw new Error(`Unsupported account algorithm: ${algorithm}`);
+    }
+    if (keyAlgorithm !== algorithm) {
+        throw new Error(`Private key type ${keyAlgorithm} does not match algorithm ${algorithm}`);
+    }
     const derivedPublicKeyPem = crypto.createPublicKey(privateKeyObj).export({ type: 'spki', format: 'pem' });
     const publicKeyPem = source.publicKeyPem || payload.publicKeyPem || derivedPublicKeyPem;
     if (publicKeyPem.trim() !== derivedPublicKeyPem.trim()) {
@@ -53,10 +73,11 @@ function importWallet(dataDir, payload = {}) {
         publicKeyPem,
         privateKeyPem,
         accountId,
+        algorithm,
         importedAt: new Date().toISOString()
     };
     fs.writeFileSync(walletPath, JSON.stringify(payloadToWrite, null, 2));
-    return { walletPath, publicKeyPem, privateKeyPem, accountId };
+    return { walletPath, publicKeyPem, privateKeyPem, accountId, algorithm };
 }
 
 function signPayload(privateKeyPem, payload) {
@@ -75,5 +96,7 @@ module.exports = {
     signPayload,
     verifyPayload,
     accountIdFromPublicKey,
-    importWallet
+    nodeIdFromPublicKey,
+    importWallet,
+    SUPPORTED_KEY_ALGORITHMS
 };
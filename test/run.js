@@ -6,6 +6,8 @@ const OpenClawMesh = require('../src/index');
 const MemoryStore = require('../src/memory-store');
 const MeshNode = require('../src/node');
 const TaskBazaar = require('../src/task-bazaar');
+const TaskWorker = require('../src/task-worker');
+const WebUIServer = require('../web/server');
 
 // 测试配置
 const TEST_CONFIG = {
@@ -15,6 +17,59 @@ const TEST_CONFIG = {
     dataDir: './test/data'
 };
 
+// 发布者钱包签名的托管证明；零赏金任务也可附带，用来绑定发布者账户
+function signEscrowProof(wallet, taskId, amount = 0) {
+    const { signPayload } = require('../src/wallet');
+    const statement = {
+        taskId,
+        escrowAccountId: TaskBazaar.prototype.getEscrowAccountId(taskId),
+        amount,
+        token: 'CLAW',
+        txId: 'tx_escrow_' + taskId,
+        publisher: wallet.accountId,
+        issuedAt: Date.now()
+    };
+    return { statement, publicKeyPem: wallet.publicKeyPem, signature: signPayload(wallet.privateKeyPem, statement) };
+}
+
+// 假的 peer 连接：记录写出的消息，destroy 后标记为已断开
+function makeSocket() {
+    return {
+        writable: true,
+        destroyed: false,
+        sent: [],
+        write(line) { this.sent.push(JSON.parse(line)); },
+        destroy() { this.destroyed = true; }
+    };
+}
+
+// 假的 HTTP 响应：记录状态码，结束时把解析后的 JSON 响应体和状态码交给 onBody
+function makeResponse(onBody) {
+    return {
+        statusCode: 200,
+        setHeader() {},
+        writeHead(code) { this.statusCode = code; },
+        end(chunk) { onBody(JSON.parse(chunk), this.statusCode); }
+    };
+}
+
+// 直接调用 WebUIServer.handleAPI，返回解析后的 JSON 响应体
+function callApi(server, req) {
+    return new Promise((resolve) => server.handleAPI(req, makeResponse(resolve)));
+}
+
+// 使用独立节点ID和数据目录的完整节点实例
+function createTestMesh(name, webPort, options = {}) {
+    const stamp = Date.now();
+    return new OpenClawMesh({
+        ...TEST_CONFIG,
+        nodeId: `node_${name}_${stamp}`,
+        dataDir: `${TEST_CONFIG.dataDir}/${name}_${stamp}`,
+        webPort,
+        ...options
+    });
+}
+
 // 简单的测试框架
 class TestRunner {
     constructor() {
@@ -195,6 +250,5353 @@ runner.test('OpenClawMesh.init() - should initialize all components', async () =
     await mesh.stop();
 });
 
+// 测试8: 两个节点对相同竞价集合选出相同的获胜者
+runner.test('TaskWorker.determineWinner() - nodes with the same bids agree', async () => {
+    const workerA = new TaskWorker({ options: { nodeId: 'node_a' } });
+    const workerB = new TaskWorker({ options: { nodeId: 'node_b' } });
+    const bids = [
+        { nodeId: 'node_x', amount: 90, timestamp: 3000 },
+        { nodeId: 'node_y', amount: 90, timestamp: 1000 },
+        { nodeId: 'node_z', amount: 95, timestamp: 500 }
+    ];
+    // Same final bid set, but different arrival order and local clocks
+    const seenByB = [...bids].reverse().map(b => ({ ...b, timestamp: b.timestamp + 7000 }));
+    const winnerA = workerA.determineWinner({ taskId: 'task_1', bids });
+    const winnerB = workerB.determineWinner({ taskId: 'task_1', bids: seenByB });
+    if (!winnerA || !winnerB || winnerA.nodeId !== winnerB.nodeId) {
+        throw new Error('Nodes picked different winners');
+    }
+    if (winnerA.amount !== 90) {
+        throw new Error('Lowest bid should win');
+    }
+});
+
+// 测试9: 查询接口的字段投影
+runner.test('WebUIServer /api/memories?fields= - should return only requested fields', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir, { useLance: false });
+    await store.init();
+    await store.storeCapsule({
+        asset_id: 'sha256:projection',
+        content: { capsule: { type: 'skill', confidence: 0.7, blast_radius: ['api'] } }
+    });
+    const web = new WebUIServer({ port: 9997, mesh: { options: { isGenesisNode: true }, memoryStore: store } });
+    await web.start();
+    try {
+        const res = await fetch('http://localhost:9997/api/memories?fields=asset_id,confidence');
+        const items = await res.json();
+        const item = items.find(c => c.asset_id === 'sha256:projection');
+        if (!item) {
+            throw new Error('Capsule missing from results');
+        }
+        const keys = Object.keys(item).sort().join(',');
+        if (keys !== 'asset_id,confidence') {
+            throw new Error(`Unexpected fields: ${keys}`);
+        }
+    } finally {
+        await web.stop();
+        await store.close();
+    }
+});
+
+// 测试10: 重放的握手应被拒绝
+runner.test('MeshNode handshake challenge - replayed handshake is rejected', async () => {
+    const crypto = require('crypto');
+    const makeIdentity = () => {
+        const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
+        return {
+            publicKeyPem: publicKey.export({ type: 'spki', format: 'pem' }),
+            privateKeyPem: privateKey.export({ type: 'pkcs8', format: 'pem' })
+        };
+    };
+    const { nodeIdFromPublicKey } = require('../src/wallet');
+    const idA = makeIdentity();
+    const nodeAId = nodeIdFromPublicKey(idA.publicKeyPem);
+    const nodeA = new MeshNode({ nodeId: nodeAId, identity: idA });
+    const nodeB = new MeshNode({ nodeId: 'node_b', identity: makeIdentity(), requireSignedHandshake: true });
+
+    // 正常握手
+    const live = makeSocket();
+    nodeB.peers.set('10.0.0.1:5000', live);
+    const hello = nodeA.buildHandshake(makeSocket());
+    nodeB.handleMessage(hello, '10.0.0.1:5000', live);
+    const reply = live.sent.find(m => m.type === 'handshake');
+    const ack = { type: 'handshake_ack', nodeId: nodeAId, challengeResponse: nodeA.signChallenge(reply.challenge) };
+    nodeB.handleMessage(ack, nodeAId, live);
+    if (!nodeB.peerAuth.get(nodeAId)?.verified || live.destroyed) {
+        throw new Error('Live handshake should verify');
+    }
+
+    // 攻击者重放截获的握手与ack
+    nodeB.peers.delete(nodeAId);
+    const replay = makeSocket();
+    nodeB.peers.set('10.0.0.9:6000', replay);
+    nodeB.handleMessage(hello, '10.0.0.9:6000', replay);
+    nodeB.handleMessage(ack, nodeAId, replay);
+    if (!replay.destroyed || nodeB.peerAuth.get(nodeAId)?.verified) {
+        throw new Error('Replayed handshake should be rejected');
+    }
+
+    // 攻击者用自己的密钥正确完成挑战，但声明的是别人的 nodeId
+    const mallory = new MeshNode({ nodeId: nodeAId, identity: makeIdentity() });
+    const forged = makeSocket();
+    nodeB.peers.set('10.0.0.8:7000', forged);
+    nodeB.handleMessage(mallory.buildHandshake(makeSocket()), '10.0.0.8:7000', forged);
+    const challenge = forged.sent.find(m => m.type === 'handshake').challenge;
+    nodeB.handleMessage({ type: 'handshake_ack', nodeId: nodeAId, challengeResponse: mallory.signChallenge(challenge) }, nodeAId, forged);
+    if (!forged.destroyed || nodeB.peerAuth.get(nodeAId)?.verified) {
+        throw new Error('A key that does not belong to the claimed nodeId should be rejected');
+    }
+
+    // 非派生的 nodeId 只有在 peerKeys 中固定了公钥时才能通过
+    const idC = makeIdentity();
+    const nodeC = new MeshNode({ nodeId: 'node_named', identity: idC });
+    const pinnedB = new MeshNode({ nodeId: 'node_pinned', identity: makeIdentity(), requireSignedHandshake: true, peerKeys: { node_named: idC.publicKeyPem } });
+    const pinned = makeSocket();
+    pinnedB.peers.set('10.0.0.2:5000', pinned);
+    pinnedB.handleMessage(nodeC.buildHandshake(makeSocket()), '10.0.0.2:5000', pinned);
+    const pinnedChallenge = pinned.sent.find(m => m.type === 'handshake').challenge;
+    pinnedB.handleMessage({ type: 'handshake_ack', nodeId: 'node_named', challengeResponse: nodeC.signChallenge(pinnedChallenge) }, 'node_named', pinned);
+    if (!pinnedB.peerAuth.get('node_named')?.verified || pinned.destroyed) {
+        throw new Error('A pinned key should verify a non-derived nodeId');
+    }
+});
+
+// 测试11: 关键写入在模拟崩溃后仍然存在
+runner.test('MemoryStore durability - critical writes survive a simulated crash', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/durability_' + Date.now();
+    const store = new MemoryStore(dataDir, { useLance: false, capsuleFlushIntervalMs: 60000 });
+    await store.init();
+    store.credit('node_crash', 50, { reason: 'test' });
+    await store.storeCapsule({ asset_id: 'sha256:batched', content: { capsule: { type: 'skill' } } });
+    // 模拟崩溃：不调用 close()，直接丢弃实例
+    clearTimeout(store.capsuleFlushTimer);
+
+    const reopened = new MemoryStore(dataDir, { useLance: false });
+    await reopened.init();
+    if (reopened.getAccountByNodeId('node_crash')?.balance !== 50) {
+        throw new Error('Ledger credit should survive a crash');
+    }
+    if (reopened.getCapsule('sha256:batched')) {
+        throw new Error('Batched capsule should not be flushed yet');
+    }
+    await reopened.close();
+});
+
+// 测试12: 违规累计触发封禁并自动过期
+runner.test('MeshNode.recordViolation() - repeated violations ban the peer until expiry', async () => {
+    const node = new MeshNode({ nodeId: 'node_ban_test', banThreshold: 3, banDurationMs: 50 });
+    node.recordViolation('node_bad', 'invalid signature');
+    node.recordViolation('node_bad', 'invalid signature');
+    if (node.isBanned('node_bad')) {
+        throw new Error('Peer should not be banned below the threshold');
+    }
+    node.recordViolation('node_bad', 'invalid signature');
+    if (!node.isBanned('node_bad') || node.getBans().length !== 1) {
+        throw new Error('Peer should be banned after reaching the threshold');
+    }
+    await new Promise(resolve => setTimeout(resolve, 60));
+    if (node.isBanned('node_bad') || node.getBans().length !== 0) {
+        throw new Error('Ban should expire');
+    }
+
+    // 手动封禁/解封需要管理员权限
+    const web = new WebUIServer({ port: 0, mesh: { options: {}, node } });
+    const post = (url, ip) => new Promise((resolve) => {
+        const handlers = {};
+        web.handleAPI({
+            method: 'POST', url, headers: {}, socket: { remoteAddress: ip },
+            on(event, cb) { handlers[event] = cb; if (event === 'end') setImmediate(() => { handlers.data?.(JSON.stringify({ peerId: 'node_victim' })); cb(); }); }
+        }, makeResponse(resolve));
+    });
+    if ((await post('/api/peers/ban', '10.0.0.9')).error !== 'Not authorized' || node.isBanned('node_victim')) {
+        throw new Error('Remote ban requests without an admin token should be rejected');
+    }
+    if (!(await post('/api/peers/ban', '127.0.0.1')).success || !node.isBanned('node_victim')) {
+        throw new Error('Local admin should be able to ban a peer');
+    }
+    if ((await post('/api/peers/unban', '10.0.0.9')).error !== 'Not authorized' || !node.isBanned('node_victim')) {
+        throw new Error('Remote unban requests without an admin token should be rejected');
+    }
+});
+
+// 测试13: 置信度衰减使旧胶囊低于阈值
+runner.test('MemoryStore confidence decay - old capsule falls below minConfidence', async () => {
+    const dayMs = 24 * 60 * 60 * 1000;
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/decay_' + Date.now(), {
+        useLance: false,
+        capsuleFlushIntervalMs: 0,
+        confidenceHalfLifeMs: 7 * dayMs
+    });
+    await store.init();
+    await store.storeCapsule({
+        asset_id: 'sha256:fresh',
+        content: { capsule: { type: 'skill', confidence: 0.8 } },
+        attribution: { creator: 'node_test', created_at: new Date().toISOString() }
+    });
+    await store.storeCapsule({
+        asset_id: 'sha256:old',
+        content: { capsule: { type: 'skill', confidence: 0.8 } },
+        attribution: { creator: 'node_test', created_at: new Date(Date.now() - 14 * dayMs).toISOString() }
+    });
+    const ids = store.queryCapsules({ minConfidence: 0.5 }).map(c => c.asset_id);
+    if (!ids.includes('sha256:fresh') || ids.includes('sha256:old')) {
+        throw new Error(`Unexpected decay filter result: ${ids.join(',')}`);
+    }
+    if (store.getCapsule('sha256:old').confidence !== 0.8) {
+        throw new Error('Stored confidence should not be mutated');
+    }
+    await store.close();
+});
+
+// 测试14: RTT直方图分位数
+runner.test('MeshNode.getRttSummary() - computes percentiles from RTT histogram', async () => {
+    const node = new MeshNode({ nodeId: 'node_rtt_test' });
+    if (node.getRttSummary().median !== null) {
+        throw new Error('Empty histogram should have no median');
+    }
+    // 18个 ~20ms 样本，1个 400ms，1个 3000ms
+    for (let i = 0; i < 18; i++) node.recordRtt(18 + (i % 3));
+    node.recordRtt(400);
+    node.recordRtt(3000);
+    const summary = node.getRttSummary();
+    if (summary.count !== 20 || summary.min !== 18 || summary.max !== 3000) {
+        throw new Error(`Unexpected count/min/max: ${JSON.stringify(summary)}`);
+    }
+    if (summary.median !== 25) {
+        throw new Error(`Expected median bucket 25, got ${summary.median}`);
+    }
+    if (summary.p95 !== 500) {
+        throw new Error(`Expected p95 bucket 500, got ${summary.p95}`);
+    }
+
+    // 直方图写入数据目录，重启后的节点读回并继续累计
+    const fs = require('fs');
+    const dir = TEST_CONFIG.dataDir + '/rtt_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const histogramPath = dir + '/rtt-histogram.json';
+    const saver = new MeshNode({ nodeId: 'node_rtt_save', rttHistogramPath: histogramPath });
+    for (let i = 0; i < 18; i++) saver.recordRtt(18 + (i % 3));
+    saver.recordRtt(400);
+    saver.recordRtt(3000);
+    saver.saveRttHistogram();
+    const restarted = new MeshNode({ nodeId: 'node_rtt_save', rttHistogramPath: histogramPath });
+    restarted.loadRttHistogram();
+    const reloaded = restarted.getRttSummary();
+    if (JSON.stringify(reloaded) !== JSON.stringify(summary)) {
+        throw new Error(`Reloaded histogram should match saved one: ${JSON.stringify(reloaded)}`);
+    }
+    restarted.recordRtt(7);
+    if (restarted.getRttSummary().count !== 21) {
+        throw new Error('Reloaded histogram should keep accumulating');
+    }
+    fs.writeFileSync(histogramPath, JSON.stringify({ buckets: [1, 2, null], counts: [1, 1, 1], min: 1, max: 3 }));
+    const mismatched = new MeshNode({ nodeId: 'node_rtt_save', rttHistogramPath: histogramPath });
+    mismatched.loadRttHistogram();
+    if (mismatched.getRttSummary().count !== 0) {
+        throw new Error('Histogram with different bucket bounds should be ignored');
+    }
+});
+
+// 测试15: 标签索引查询与全量扫描结果一致
+runner.test('MemoryStore tag index - indexed tag query matches full scan', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/tagindex_' + Date.now(), { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    for (let i = 0; i < 30; i++) {
+        await store.storeCapsule({
+            asset_id: 'sha256:idx' + i,
+            content: { capsule: { type: 'skill', confidence: (i % 4) / 4, blast_radius: ['shared', 'tag' + (i % 3)] } }
+        });
+    }
+    // 重新写入后旧标签不应再命中
+    await store.storeCapsule({
+        asset_id: 'sha256:idx0',
+        content: { capsule: { type: 'skill', confidence: 0.5, blast_radius: ['moved'] } }
+    });
+    const scan = (tags) => Array.from(store.capsules.values())
+        .filter(c => tags.some(tag => (c.content?.capsule?.blast_radius || []).includes(tag)))
+        .sort((a, b) => b.confidence - a.confidence)
+        .map(c => c.asset_id);
+    for (const tags of [['shared'], ['tag0'], ['tag1', 'tag2'], ['moved'], ['missing']]) {
+        const indexed = store.queryCapsules({ tags }).map(c => c.asset_id);
+        if (JSON.stringify(indexed) !== JSON.stringify(scan(tags))) {
+            throw new Error(`Index mismatch for ${tags.join(',')}`);
+        }
+    }
+    if (store.queryCapsules({ tags: ['tag0'] }).some(c => c.asset_id === 'sha256:idx0')) {
+        throw new Error('Re-stored capsule should be removed from old tag postings');
+    }
+    await store.close();
+});
+
+// 测试16: 话题订阅过滤gossip
+runner.test('MeshNode topic subscription - subscribed peer skips non-matching capsules', async () => {
+    const node = new MeshNode({ nodeId: 'node_topic_src' });
+    const subscribed = makeSocket();
+    const everyone = makeSocket();
+    node.peers.set('10.0.0.2:5000', subscribed);
+    node.handleMessage({ type: 'handshake', nodeId: 'node_x_only', port: 5000, topics: ['X'] }, '10.0.0.2:5000', subscribed);
+    node.peers.set('node_all', everyone);
+
+    await node.broadcastCapsule({ asset_id: 'sha256:y', type: 'skill', tags: ['Y'], content: null });
+    await node.broadcastCapsule({ asset_id: 'sha256:x', type: 'skill', tags: ['X'], content: null });
+
+    const received = (socket) => socket.sent.filter(m => m.type === 'capsule').map(m => m.payload.asset_id);
+    if (JSON.stringify(received(subscribed)) !== JSON.stringify(['sha256:x'])) {
+        throw new Error(`Subscribed peer got: ${received(subscribed).join(',')}`);
+    }
+    if (received(everyone).length !== 2) {
+        throw new Error('Unsubscribed peer should receive all capsules');
+    }
+});
+
+// 测试17: traceId 在每一跳的日志中出现
+runner.test('MeshNode trace IDs - same trace ID logged at every relaying node', async () => {
+    const nodeA = new MeshNode({ nodeId: 'node_trace_a' });
+    const nodeB = new MeshNode({ nodeId: 'node_trace_b' });
+    const nodeC = new MeshNode({ nodeId: 'node_trace_c' });
+    const aToB = makeSocket();
+    const bToC = makeSocket();
+    nodeA.peers.set('node_trace_b', aToB);
+    nodeB.peers.set('node_trace_a', makeSocket());
+    nodeB.peers.set('node_trace_c', bToC);
+    nodeC.peers.set('node_trace_b', makeSocket());
+
+    const logs = [];
+    const originalLog = console.log;
+    console.log = (...args) => logs.push(args.join(' '));
+    try {
+        await nodeA.broadcastTask({ taskId: 'task_trace', description: 'trace me', bounty: { amount: 1 } });
+        nodeB.handleMessage(aToB.sent[0], 'node_trace_a', null);
+        nodeC.handleMessage(bToC.sent[0], 'node_trace_b', null);
+    } finally {
+        console.log = originalLog;
+    }
+
+    const traceId = aToB.sent[0].traceId;
+    if (!traceId || bToC.sent[0].traceId !== traceId) {
+        throw new Error('Trace ID should be propagated unchanged');
+    }
+    for (const nodeId of ['node_trace_a', 'node_trace_b', 'node_trace_c']) {
+        if (!logs.some(line => line.includes(`[trace ${traceId}] ${nodeId}`))) {
+            throw new Error(`Missing trace log for ${nodeId}`);
+        }
+    }
+    if (!logs.some(line => line.includes(`[trace ${traceId}] node_trace_b relay`))) {
+        throw new Error('Relaying node should log the relay hop');
+    }
+});
+
+// 测试18: 并发查询上限，第 N+1 个查询排队
+runner.test('MeshNode.queryMemories() - (N+1)th concurrent query waits behind N', async () => {
+    const node = new MeshNode({ nodeId: 'node_query_limit', maxConcurrentQueries: 2, queryTimeoutMs: 5000 });
+    const sent = [];
+    node.broadcastAll = (message) => sent.push(message);
+    const queries = [node.queryMemories(), node.queryMemories(), node.queryMemories()];
+    await new Promise(resolve => setImmediate(resolve));
+    if (sent.length !== 2 || node.queryWaiters.length !== 1) {
+        throw new Error(`Expected 2 in flight and 1 queued, got ${sent.length}/${node.queryWaiters.length}`);
+    }
+    node.emit(`query_response:${sent[0].requestId}`, { memories: [] });
+    await new Promise(resolve => setImmediate(resolve));
+    if (sent.length !== 3 || node.activeQueries !== 2) {
+        throw new Error('Queued query should start after a slot is released');
+    }
+    node.emit(`query_response:${sent[1].requestId}`, { memories: [] });
+    node.emit(`query_response:${sent[2].requestId}`, { memories: [] });
+    await Promise.all(queries);
+    if (node.activeQueries !== 0) {
+        throw new Error('All slots should be released');
+    }
+
+    const rejecting = new MeshNode({ nodeId: 'node_query_busy', maxConcurrentQueries: 1, queryBusyMode: 'reject', queryTimeoutMs: 20 });
+    rejecting.broadcastAll = () => {};
+    const first = rejecting.queryMemories();
+    let busy = null;
+    try {
+        await rejecting.queryMemories();
+    } catch (e) {
+        busy = e;
+    }
+    await first;
+    if (!busy || busy.code !== 'BUSY') {
+        throw new Error('Saturated node in reject mode should return busy');
+    }
+});
+
+// 测试19: 批量导入任务并锁定托管
+runner.test('OpenClawMesh.importTasks() - publishes a batch, skips duplicates and escrows bounties', async () => {
+    const mesh = createTestMesh('import', 9996, {
+        isGenesisNode: true,
+        taskPublishFee: 0,
+        txTimeoutMs: { taskPublish: 50, taskEscrow: 50 }
+    });
+    await mesh.init();
+    try {
+        const report = await mesh.importTasks([
+            { externalId: 'ext-1', description: 'Seed task one', bounty: 10 },
+            { externalId: 'ext-2', description: 'Seed task two', bounty: { amount: 15 } },
+            { externalId: 'ext-1', description: 'Duplicate of one', bounty: 10 },
+            { externalId: 'ext-3', description: '', bounty: 5 }
+        ]);
+        const statuses = report.results.map(r => r.status).join(',');
+        if (statuses !== 'published,published,skipped,failed') {
+            throw new Error(`Unexpected statuses: ${statuses}`);
+        }
+        if (report.totalEscrowed !== 25) {
+            throw new Error(`Expected 25 escrowed, got ${report.totalEscrowed}`);
+        }
+        for (const r of report.results.filter(r => r.status === 'published')) {
+            const task = mesh.taskBazaar.getTask(r.taskId);
+            const locked = mesh.ledger.getBalance(task.escrowAccountId);
+            if (locked !== task.bounty.amount) {
+                throw new Error(`Escrow for ${r.externalId} holds ${locked}, expected ${task.bounty.amount}`);
+            }
+        }
+        const again = await mesh.importTasks([{ externalId: 'ext-2', description: 'Seed task two', bounty: 15 }]);
+        if (again.results[0].status !== 'skipped') {
+            throw new Error('Re-import should skip existing externalId');
+        }
+    } finally {
+        await mesh.stop();
+    }
+});
+
+// 测试20: 旧的已完成任务被归档且仍可查询
+runner.test('TaskBazaar.archiveTasks() - archives old completed tasks and keeps them queryable', async () => {
+    const fs = require('fs');
+    const dataDir = TEST_CONFIG.dataDir + '/archive_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const dayMs = 24 * 60 * 60 * 1000;
+    const bazaar = new TaskBazaar({ nodeId: 'node_test', memoryStore: null, dataDir, archiveAfterMs: 7 * dayMs });
+    await bazaar.publishTask({ taskId: 'task_old', description: 'old', bounty: { amount: 1 } });
+    await bazaar.publishTask({ taskId: 'task_recent', description: 'recent', bounty: { amount: 1 } });
+    await bazaar.publishTask({ taskId: 'task_open', description: 'open', bounty: { amount: 1 } });
+    bazaar.updateTask('task_old', { status: 'completed', completedAt: Date.now() - 30 * dayMs });
+    bazaar.updateTask('task_recent', { status: 'completed', completedAt: new Date().toISOString() });
+
+    if (bazaar.archiveTasks() !== 1) {
+        throw new Error('Exactly one task should be archived');
+    }
+    const active = bazaar.getTasks().map(t => t.taskId).sort();
+    if (JSON.stringify(active) !== JSON.stringify(['task_open', 'task_recent'])) {
+        throw new Error(`Unexpected active tasks: ${active.join(',')}`);
+    }
+    if (!bazaar.getTasks({ includeArchived: true }).some(t => t.taskId === 'task_old')) {
+        throw new Error('Archived task should be returned with includeArchived');
+    }
+
+    // 重启后归档仍可读取，网络重放不会重新加入
+    const reloaded = new TaskBazaar({ nodeId: 'node_test', memoryStore: null, dataDir, archiveAfterMs: 7 * dayMs });
+    if (reloaded.getTask('task_old') || reloaded.getArchivedTask('task_old')?.status !== 'completed') {
+        throw new Error('Archived task should persist outside the active map');
+    }
+    await reloaded.handleNewTask({ taskId: 'task_old', description: 'old', bounty: { amount: 1 } });
+    if (reloaded.getTask('task_old')) {
+        throw new Error('Archived task should not be re-added from gossip');
+    }
+});
+
+// 测试21: 非主节点脱敏后保留预览
+runner.test('Capsule redaction - preview is present and full content is absent', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/redact_' + Date.now(), { useLance: false, capsulePreviewChars: 16 });
+    await store.init();
+    const secret = 'const clean = stdout.slice(1, -1); return JSON.parse(JSON.parse(clean));';
+    await store.storeCapsule({
+        asset_id: 'sha256:redact',
+        content: {
+            gene: { trigger: 'api_error', solution: secret },
+            capsule: { type: 'skill', code: secret, confidence: 0.9, blast_radius: ['api'] },
+            preview_fields: ['gene.trigger', 'capsule.blast_radius']
+        }
+    });
+    const check = (capsule, where) => {
+        if (!capsule?.redacted || capsule.preview !== secret.slice(0, 16) + '…') {
+            throw new Error(`${where}: missing preview`);
+        }
+        if (JSON.stringify(capsule).includes(secret)) {
+            throw new Error(`${where}: full content leaked`);
+        }
+        if (capsule.content?.gene?.trigger !== 'api_error' || capsule.content?.capsule?.blast_radius?.[0] !== 'api') {
+            throw new Error(`${where}: opted-in fields should stay visible`);
+        }
+    };
+    const web = new WebUIServer({ port: 9995, mesh: { options: { isGenesisNode: false }, memoryStore: store } });
+    await web.start();
+    try {
+        const list = await fetch('http://localhost:9995/api/memories').then(r => r.json());
+        check(list.find(c => c.asset_id === 'sha256:redact'), 'memories');
+        check(await fetch('http://localhost:9995/api/memory/sha256:redact').then(r => r.json()), 'memory_by_id');
+        check(store.getSnapshot().capsules.find(c => c.asset_id === 'sha256:redact'), 'snapshot');
+        // 自称已脱敏的胶囊同样重新脱敏
+        await store.storeCapsule({
+            asset_id: 'sha256:redact_claimed',
+            redacted: true,
+            content: {
+                gene: { trigger: 'api_error', solution: secret },
+                capsule: { type: 'skill', code: secret, confidence: 0.9, blast_radius: ['api'] },
+                preview_fields: ['gene.trigger', 'capsule.blast_radius']
+            }
+        });
+        check(store.getSnapshot().capsules.find(c => c.asset_id === 'sha256:redact_claimed'), 'claimed_redacted');
+        // 置顶与保留时长是本地设置，不随脱敏视图发给 peer
+        await store.setCapsulePinned('sha256:redact', true);
+        await store.extendCapsuleTtl('sha256:redact', 0);
+        const shared = store.redactCapsule(store.getCapsule('sha256:redact'));
+        if ('pinned' in shared || 'retentionMs' in shared) {
+            throw new Error(`Redacted view should not carry local settings: ${Object.keys(shared).join(',')}`);
+        }
+    } finally {
+        await web.stop();
+        await store.close();
+    }
+});
+
+// 测试22: DNS种子解析与静态回退
+runner.test('MeshNode.resolveDnsSeed() - uses seed records and falls back to static bootstrap', async () => {
+    let fail = false;
+    const resolver = {
+        resolveTxt: async () => {
+            if (fail) throw new Error('ENOTFOUND');
+            return [['10.0.0.1:4000, 10.0.0.2:4001'], ['10.0.0.3', ':4002']];
+        },
+        resolve4: async () => {
+            if (fail) throw new Error('ENOTFOUND');
+            return ['10.0.0.9'];
+        }
+    };
+    const node = new MeshNode({ nodeId: 'node_dns', dnsSeed: 'seed.example.org', resolver, bootstrapNodes: ['static.example.org:4000'] });
+    await node.resolveDnsSeed();
+    const expected = ['static.example.org:4000', '10.0.0.1:4000', '10.0.0.2:4001', '10.0.0.3:4002'];
+    if (JSON.stringify(node.getBootstrapAddresses()) !== JSON.stringify(expected)) {
+        throw new Error(`Unexpected addresses: ${node.getBootstrapAddresses().join(',')}`);
+    }
+
+    // A记录回退
+    const aOnly = new MeshNode({ nodeId: 'node_dns_a', dnsSeed: 'seed.example.org', dnsSeedPort: 4100, resolver: { resolveTxt: async () => [], resolve4: async () => ['10.0.0.9'] } });
+    if (JSON.stringify(await aOnly.resolveDnsSeed()) !== JSON.stringify(['10.0.0.9:4100'])) {
+        throw new Error('A records should use dnsSeedPort');
+    }
+
+    // DNS失败时保留缓存；无缓存时只剩静态节点
+    fail = true;
+    await node.resolveDnsSeed();
+    if (node.getBootstrapAddresses().length !== 4) {
+        throw new Error('Cached seed addresses should survive a failed refresh');
+    }
+    const cold = new MeshNode({ nodeId: 'node_dns_cold', dnsSeed: 'seed.example.org', resolver, bootstrapNodes: ['static.example.org:4000'] });
+    await cold.resolveDnsSeed();
+    if (JSON.stringify(cold.getBootstrapAddresses()) !== JSON.stringify(['static.example.org:4000'])) {
+        throw new Error('Should fall back to static bootstrap nodes');
+    }
+});
+
+// 测试23: 重复发布相同胶囊不再广播
+runner.test('OpenClawMesh.publishCapsule() - identical re-publish is a no-op', async () => {
+    const mesh = createTestMesh('republish', 9994, {
+        isGenesisNode: true,
+        capsulePublishFee: 0
+    });
+    await mesh.init();
+    try {
+        let broadcasts = 0;
+        mesh.node.broadcastCapsule = async () => { broadcasts++; };
+        const content = { capsule: { type: 'skill', code: 'retry()', confidence: 0.6 } };
+        const first = await mesh.publishCapsule({ content: JSON.parse(JSON.stringify(content)) });
+        const createdAt = mesh.memoryStore.getCapsule(first.assetId).attribution.created_at;
+        await new Promise(resolve => setTimeout(resolve, 5));
+        const second = await mesh.publishCapsule({ content: JSON.parse(JSON.stringify(content)) });
+        if (first.alreadyExists || !second.alreadyExists || second.assetId !== first.assetId) {
+            throw new Error('Second identical publish should report alreadyExists');
+        }
+        if (broadcasts !== 1) {
+            throw new Error(`Expected 1 broadcast, got ${broadcasts}`);
+        }
+        if (mesh.memoryStore.getCapsule(first.assetId).attribution.created_at !== createdAt) {
+            throw new Error('Re-publish should not reset created_at');
+        }
+    } finally {
+        await mesh.stop();
+    }
+});
+
+// 测试24: 两个互联节点在拓扑图中形成一条边
+runner.test('MeshNode.getNetworkGraph() - interconnected nodes produce an edge', async () => {
+    const nodeA = new MeshNode({ nodeId: 'node_graph_a', sharePeerList: true });
+    const nodeB = new MeshNode({ nodeId: 'node_graph_b', sharePeerList: true });
+    const observer = new MeshNode({ nodeId: 'node_graph_obs', graphStaleMs: 1000 });
+    const aSocket = makeSocket();
+    const bSocket = makeSocket();
+    nodeA.peers.set('node_graph_b', aSocket);
+    nodeB.peers.set('node_graph_a', bSocket);
+
+    nodeA.broadcastPeerList();
+    nodeB.broadcastPeerList();
+    observer.handleMessage(aSocket.sent.find(m => m.type === 'peer_list'), 'node_graph_a', null);
+    observer.handleMessage(bSocket.sent.find(m => m.type === 'peer_list'), 'node_graph_b', null);
+    // 未签名的 peer 列表经第三方转发时不接受，不能替别的节点上报邻居
+    const forged = { ...aSocket.sent.find(m => m.type === 'peer_list'), messageId: 'forged_peer_list' };
+    forged.payload = { ...forged.payload, peers: ['node_graph_fake'] };
+    observer.handleMessage(forged, 'node_graph_b', null);
+
+    const graph = observer.getNetworkGraph();
+    const edges = graph.edges.filter(e => e.source === 'node_graph_a' && e.target === 'node_graph_b');
+    if (edges.length !== 1) {
+        throw new Error(`Expected one a-b edge, got ${JSON.stringify(graph.edges)}`);
+    }
+    if (graph.nodes.some(n => n.id === 'node_graph_fake')) {
+        throw new Error('A peer list relayed on behalf of another node should be ignored');
+    }
+    if (!graph.nodes.some(n => n.id === 'node_graph_a') || !graph.nodes.some(n => n.id === 'node_graph_b')) {
+        throw new Error('Both nodes should appear in the graph');
+    }
+
+    // 过期的报告被丢弃
+    observer.peerLists.get('node_graph_a').receivedAt -= 5000;
+    observer.peerLists.get('node_graph_b').receivedAt -= 5000;
+    if (observer.getNetworkGraph().edges.length !== 0) {
+        throw new Error('Stale peer lists should be dropped');
+    }
+});
+
+// 测试25: 转账备注写入账本条目
+runner.test('LedgerStore transfer memo - memo is stored in the ledger entry meta', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/memo_' + Date.now();
+    fs.mkdirSync(dataDir + '/replica', { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: wallet.accountId,
+        genesisSupply: 1000,
+        genesisPublicKeyPem: wallet.publicKeyPem,
+        genesisPrivateKeyPem: wallet.privateKeyPem
+    });
+    const replica = new LedgerStore(dataDir + '/replica');
+    replica.init();
+    try {
+        const signTransfer = (memo, nonce) => {
+            const payload = {
+                type: 'transfer',
+                from: wallet.accountId,
+                to: 'acct_memo_target',
+                amount: 5,
+                nonce,
+                timestamp: Date.now(),
+                meta: { memo }
+            };
+            const signature = signPayload(wallet.privateKeyPem, payload);
+            return { ...payload, pubkeyPem: wallet.publicKeyPem, signature, txId: 'tx_memo_' + nonce };
+        };
+        const memo = LedgerStore.sanitizeMemo('  invoice-42\n');
+        const result = ledger.appendAsMaster(signTransfer(memo, ledger.getNonce(wallet.accountId) + 1));
+        if (!result.accepted) {
+            throw new Error(`Transfer rejected: ${result.reason}`);
+        }
+        if (ledger.getTxById('tx_memo_' + (ledger.getNonce(wallet.accountId)))?.meta?.memo !== 'invoice-42') {
+            throw new Error('Memo should be stored in ledger entry meta');
+        }
+
+        // 同步到其他节点时备注随签名一起校验
+        const entries = ledger.getTxLogSince(0);
+        const tampered = entries.map(e => e.meta ? { ...e, meta: { memo: 'forged' } } : e);
+        if (replica.applyLogEntry(tampered[0]).applied !== true || replica.applyLogEntry(tampered[1]).applied !== false) {
+            throw new Error('Tampered memo should fail signature verification');
+        }
+        if (replica.applyLogEntry(entries[1]).applied !== true || replica.getTxById(entries[1].txId).meta.memo !== 'invoice-42') {
+            throw new Error('Replica should keep the memo');
+        }
+
+        let tooLong = null;
+        try {
+            LedgerStore.sanitizeMemo('x'.repeat(LedgerStore.MAX_MEMO_LENGTH + 1));
+        } catch (e) {
+            tooLong = e;
+        }
+        if (!tooLong) {
+            throw new Error('Over-long memo should be rejected');
+        }
+    } finally {
+        ledger.close();
+        replica.close();
+    }
+});
+
+// 测试26: 托管证明校验
+runner.test('TaskBazaar.verifyEscrowProof() - accepts valid proofs and rejects missing ones', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/escrow_proof_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const publisher = loadOrCreateWallet(dataDir);
+    const ledgerTxs = new Map();
+    const ledger = { getTxById: (txId) => ledgerTxs.get(txId) || null, getBalance: () => 0 };
+    const bazaar = new TaskBazaar({ nodeId: 'node_worker', memoryStore: null, ledger, dataDir });
+
+    const task = { taskId: 'task_escrow_proof', description: 'funded', bounty: { amount: 40, token: 'CLAW' } };
+    task.escrowAccountId = bazaar.getEscrowAccountId(task.taskId);
+    ledgerTxs.set('tx_lock', { txId: 'tx_lock', from: publisher.accountId, to: task.escrowAccountId, amount: 40 });
+    task.escrowProof = OpenClawMesh.prototype.createEscrowProof.call({ wallet: publisher }, task, 'tx_lock', 40);
+
+    if (!bazaar.verifyEscrowProof(task).valid) {
+        throw new Error(`Valid proof rejected: ${bazaar.verifyEscrowProof(task).reason}`);
+    }
+    const cases = {
+        missing: { ...task, escrowProof: undefined },
+        underfunded: { ...task, bounty: { amount: 100, token: 'CLAW' } },
+        forged: { ...task, escrowProof: { ...task.escrowProof, statement: { ...task.escrowProof.statement, amount: 400 } } },
+        unknownTx: { ...task, escrowProof: OpenClawMesh.prototype.createEscrowProof.call({ wallet: publisher }, task, 'tx_missing', 40) }
+    };
+    for (const [name, candidate] of Object.entries(cases)) {
+        if (bazaar.verifyEscrowProof(candidate).valid) {
+            throw new Error(`${name} proof should be rejected`);
+        }
+    }
+
+    // 竞标方跳过没有有效托管证明的任务
+    const worker = new TaskWorker({ options: { nodeId: 'node_worker' }, taskBazaar: bazaar });
+    if (!worker.hasValidEscrow(task) || worker.hasValidEscrow(cases.missing)) {
+        throw new Error('Worker should only bid on tasks with a valid escrow proof');
+    }
+});
+
+// 测试27: WebSocket状态推送间隔可配置
+runner.test('WebUIServer WebSocket status interval - tick rate follows configuration', async () => {
+    const EventEmitter = require('events');
+    const makeWs = () => {
+        const ws = new EventEmitter();
+        ws.readyState = 1;
+        ws.sent = [];
+        ws.pings = 0;
+        ws.send = (line) => ws.sent.push(JSON.parse(line));
+        ws.ping = () => { ws.pings++; };
+        return ws;
+    };
+    const statusCount = (ws) => ws.sent.filter(m => m.type === 'status').length;
+    const mesh = { getStats: () => ({ nodeId: 'node_ws' }) };
+    const web = new WebUIServer({ mesh, wsMinStatusIntervalMs: 10, wsMinPingIntervalMs: 10, wsStatusIntervalMs: 40, wsPingIntervalMs: 50 });
+
+    const ws = makeWs();
+    web.handleWebSocket(ws, { url: '/' });
+    await new Promise(resolve => setTimeout(resolve, 230));
+    // 初始推送 + 约 5 次定时推送
+    const ticks = statusCount(ws) - 1;
+    if (ticks < 4 || ticks > 6) {
+        throw new Error(`Expected ~5 status ticks at 40ms, got ${ticks}`);
+    }
+    if (ws.pings < 3 || ws.pings > 5) {
+        throw new Error(`Expected ~4 pings at 50ms, got ${ws.pings}`);
+    }
+
+    // 客户端订阅更慢的频率；低于下限的请求被提升到下限
+    web.handleWebSocketMessage(ws, { type: 'subscribe', statusIntervalMs: 100 });
+    const before = statusCount(ws);
+    await new Promise(resolve => setTimeout(resolve, 230));
+    const slowTicks = statusCount(ws) - before;
+    ws.emit('close');
+    if (slowTicks < 1 || slowTicks > 3) {
+        throw new Error(`Expected ~2 status ticks at 100ms, got ${slowTicks}`);
+    }
+    const fast = makeWs();
+    web.handleWebSocket(fast, { url: '/?interval=1' });
+    fast.emit('close');
+    if (fast.statusIntervalMs !== 10) {
+        throw new Error('Requested interval should be clamped to the minimum');
+    }
+});
+
+// 测试28: 拒绝深度嵌套与超大消息
+runner.test('MeshNode.handleConnection() - rejects deeply nested and oversized payloads', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_json_limit', maxJsonDepth: 32, maxMessageBytes: 4096 });
+    const handled = [];
+    node.handleMessage = (message) => handled.push(message.type);
+    const socket = new EventEmitter();
+    socket.remoteAddress = '10.0.0.9';
+    socket.remotePort = 5000;
+    socket.destroyed = false;
+    socket.destroy = () => { socket.destroyed = true; };
+    node.handleConnection(socket);
+
+    const nested = '['.repeat(100) + ']'.repeat(100);
+    socket.emit('data', Buffer.from(`{"type":"capsule","payload":${nested}}\n`));
+    socket.emit('data', Buffer.from(JSON.stringify({ type: 'capsule', payload: { note: '[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[[' } }) + '\n'));
+    if (handled.length !== 1) {
+        throw new Error(`Only the well-formed message should be handled, got ${handled.length}`);
+    }
+    if (node.violations.get('10.0.0.9')?.length !== 1) {
+        throw new Error('Nested payload should be recorded as a violation');
+    }
+
+    // 未换行的超大数据直接断开
+    socket.emit('data', Buffer.from('x'.repeat(5000)));
+    if (!socket.destroyed || node.violations.get('10.0.0.9').length !== 2) {
+        throw new Error('Oversized payload should close the connection');
+    }
+});
+
+// 测试29: 导入账户时校验密钥算法
+runner.test('importWallet() - validates account algorithm against the key', async () => {
+    const fs = require('fs');
+    const crypto = require('crypto');
+    const { importWallet, accountIdFromPublicKey } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/wallet_alg_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const makeAccount = (type, algorithm) => {
+        const { publicKey, privateKey } = crypto.generateKeyPairSync(type);
+        const publicKeyPem = publicKey.export({ type: 'spki', format: 'pem' });
+        return {
+            account: {
+                accountId: accountIdFromPublicKey(publicKeyPem),
+                algorithm,
+                publicKeyPem,
+                privateKeyPem: privateKey.export({ type: 'pkcs8', format: 'pem' })
+            }
+        };
+    };
+
+    const wallet = importWallet(dataDir, makeAccount('ed25519', 'ed25519'));
+    if (wallet.algorithm !== 'ed25519') {
+        throw new Error('Supported, consistent account should import');
+    }
+    const expectFailure = (payload, pattern, options) => {
+        try {
+            importWallet(dataDir, payload, options);
+        } catch (e) {
+            if (!pattern.test(e.message)) throw new Error(`Unexpected error: ${e.message}`);
+            return;
+        }
+        throw new Error(`Import should fail with ${pattern}`);
+    };
+    expectFailure(makeAccount('ed448', 'ed25519'), /does not match algorithm/, { supportedAlgorithms: ['ed25519', 'ed448'] });
+    expectFailure(makeAccount('ed25519', 'secp256k1'), /Unsupported account algorithm/);
+    expectFailure(makeAccount('ed448', 'ed448'), /Unsupported account algorithm/);
+    if (JSON.parse(fs.readFileSync(dataDir + '/wallet.json', 'utf8')).accountId !== wallet.accountId) {
+        throw new Error('Rejected imports must not overwrite the wallet');
+    }
+});
+
+// 测试30: 修改类API调用写入审计日志
+runner.test('WebUIServer audit log - publish and transfer are recorded', async () => {
+    const EventEmitter = require('events');
+    const mesh = {
+        options: { isGenesisNode: true },
+        publishCapsule: async () => ({ assetId: 'sha256:audited', txReceipts: [] }),
+        memoryStore: { getCapsule: (assetId) => ({ asset_id: assetId }) },
+        createSignedTransfer: (to, amount) => ({ txId: 'tx_audited', to, amount }),
+        submitTx: () => ({ accepted: true })
+    };
+    const server = new WebUIServer({ port: 0, mesh, adminToken: 'secret', maxAuditEntries: 10 });
+    const call = (method, url, body, headers = {}) => new Promise((resolve) => {
+        const req = new EventEmitter();
+        Object.assign(req, { method, url, headers, socket: { remoteAddress: '::ffff:10.0.0.5' } });
+        server.handleAPI(req, makeResponse(resolve));
+        if (body !== undefined) {
+            req.emit('data', JSON.stringify(body));
+            req.emit('end');
+        }
+    });
+
+    await call('POST', '/api/memory/publish', { content: { capsule: { type: 'skill' } } }, { authorization: 'Bearer secret' });
+    await call('POST', '/api/account/transfer', { toAccountId: 'acct_target', amount: 5 });
+    await call('POST', '/api/account/transfer', { toAccountId: 'acct_target', amount: -1 });
+
+    if (!(await call('GET', '/api/audit')).error) {
+        throw new Error('Audit log should require the admin token');
+    }
+    for (const token of ['secre', 'secret!', 'Secret']) {
+        if (!(await call('GET', '/api/audit', undefined, { 'x-admin-token': token })).error) {
+            throw new Error(`A wrong admin token should be rejected: ${token}`);
+        }
+    }
+    const { items } = await call('GET', '/api/audit', undefined, { 'x-admin-token': 'secret' });
+    const [rejected, transfer, publish] = items;
+    if (items.length !== 3 || publish.action !== '/api/memory/publish' || publish.target !== 'sha256:audited') {
+        throw new Error(`Publish missing from audit log: ${JSON.stringify(items)}`);
+    }
+    if (publish.actor.identity !== 'admin' || transfer.actor.identity !== null || transfer.actor.ip !== '10.0.0.5') {
+        throw new Error('Audit entries should record the caller identity');
+    }
+    if (transfer.target !== 'tx_audited' || transfer.result !== 'ok' || rejected.result !== 'error') {
+        throw new Error('Transfer results should be recorded');
+    }
+});
+
+// 测试31: 断开的高价值peer会被重新拨号
+runner.test('MeshNode.handlePeerLoss() - re-dials dropped high-value peers with backoff', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_reconnect', reconnectBaseDelayMs: 5, maxReconnectAttempts: 3 });
+    const dialed = [];
+    let failures = 1;
+    node.connectToPeer = async (address) => {
+        dialed.push(address);
+        if (failures-- > 0) throw new Error('connection refused');
+    };
+    const connectInbound = (nodeId, ip, port) => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: '::ffff:' + ip, remotePort: 50000, writable: true, destroyed: false });
+        socket.write = () => {};
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        socket.emit('data', Buffer.from(JSON.stringify({ type: 'handshake', nodeId, port }) + '\n'));
+        return socket;
+    };
+
+    const valuable = connectInbound('node_fast', '10.0.0.7', 4555);
+    const idle = connectInbound('node_idle', '10.0.0.8', 4556);
+    node.peerStats.set('node_fast', { rtt: 20, lastSeen: Date.now() });
+    node.recordDelivery('node_fast', true);
+    node.recordDelivery('node_idle', true);
+    valuable.emit('close');
+    idle.emit('close');
+    // 不重连的 peer 断开即删除投递计数；待重连的保留到重连结束
+    if (node.peerDelivery.has('node_idle') || !node.peerDelivery.has('node_fast')) {
+        throw new Error('Delivery stats should be pruned on disconnect unless a reconnect is pending');
+    }
+    await new Promise(resolve => setTimeout(resolve, 60));
+    if (node.peerDelivery.size !== 0) {
+        throw new Error('Delivery stats should be pruned once the reconnect finishes');
+    }
+
+    if (dialed.length !== 2 || dialed.some(addr => addr !== '10.0.0.7:4555')) {
+        throw new Error(`Only the high-value peer should be re-dialed (with one retry), got ${dialed.join(',')}`);
+    }
+    if (node.reconnectTimers.size !== 0) {
+        throw new Error('Reconnect timers should be cleared after success');
+    }
+    await node.stop();
+});
+
+// 测试32: 胶囊来源链校验
+runner.test('MemoryStore.verifyProvenance() - accepts a two-step chain and rejects tampering', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const base = TEST_CONFIG.dataDir + '/provenance_' + Date.now();
+    fs.mkdirSync(base + '/alice', { recursive: true });
+    fs.mkdirSync(base + '/bob', { recursive: true });
+    const store = new MemoryStore(base + '/store', { useLance: false, capsuleFlushIntervalMs: 0, strictProvenance: true });
+    await store.init();
+    const createProvenance = (nodeId, wallet, assetId, derivedFrom) => OpenClawMesh.prototype.createProvenance.call(
+        { options: { nodeId }, wallet, memoryStore: store }, assetId, derivedFrom
+    );
+    try {
+        const alice = loadOrCreateWallet(base + '/alice');
+        const bob = loadOrCreateWallet(base + '/bob');
+        await store.storeCapsule({
+            asset_id: 'sha256:origin',
+            content: { capsule: { type: 'skill' } },
+            provenance: createProvenance('node_alice', alice, 'sha256:origin', [])
+        });
+        const derived = {
+            asset_id: 'sha256:derived',
+            content: { capsule: { type: 'skill' } },
+            provenance: createProvenance('node_bob', bob, 'sha256:derived', ['sha256:origin'])
+        };
+        if (derived.provenance.length !== 2 || !store.verifyProvenance(derived).valid) {
+            throw new Error(`Two-step chain should verify: ${store.verifyProvenance(derived).reason}`);
+        }
+        await store.storeCapsule(derived);
+
+        const [origin, step] = derived.provenance;
+        const tampered = {
+            creator: { ...derived, asset_id: 'sha256:t1', provenance: [{ ...origin, creator: 'node_mallory' }, step] },
+            relinked: { ...derived, asset_id: 'sha256:t2', provenance: [step] },
+            unsigned: { asset_id: 'sha256:t3', content: { capsule: { type: 'skill' } } }
+        };
+        for (const [name, capsule] of Object.entries(tampered)) {
+            let rejected = false;
+            try {
+                await store.storeCapsule(capsule);
+            } catch (e) {
+                rejected = /Invalid provenance/.test(e.message);
+            }
+            if (!rejected || store.getCapsule(capsule.asset_id)) {
+                throw new Error(`${name} provenance should be rejected in strict mode`);
+            }
+        }
+
+        // 网络同步时逐个校验：被拒的胶囊只计数，不影响同批其余胶囊
+        const synced = {
+            asset_id: 'sha256:synced',
+            content: { capsule: { type: 'skill' } },
+            provenance: createProvenance('node_alice', alice, 'sha256:synced', [])
+        };
+        const mesh = { node: { queryMemories: async () => [tampered.unsigned, synced, tampered.relinked] }, memoryStore: store };
+        const stored = await OpenClawMesh.prototype.syncMemories.call(mesh);
+        if (stored !== 1 || mesh.lastMemorySync.rejected !== 2 || !store.getCapsule('sha256:synced')) {
+            throw new Error(`Sync should store valid capsules and count rejections: ${JSON.stringify(mesh.lastMemorySync)}`);
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试33: 压测接口返回吞吐与回执延迟
+runner.test('POST /api/bench/gossip - publishes synthetic capsules and reports timing', async () => {
+    const http = require('http');
+    const mesh = createTestMesh('bench', 9993, {
+        isGenesisNode: true,
+        benchMode: true
+    });
+    await mesh.init();
+    const peer = new MeshNode({ nodeId: 'node_bench_peer', bootstrapNodes: [`127.0.0.1:${mesh.node.port}`], benchMode: true });
+    try {
+        await peer.init();
+        await new Promise(resolve => setTimeout(resolve, 100));
+        const before = mesh.memoryStore.getStats().total;
+        const result = await new Promise((resolve, reject) => {
+            const req = http.request({ host: '127.0.0.1', port: 9993, path: '/api/bench/gossip', method: 'POST' }, (res) => {
+                let body = '';
+                res.on('data', chunk => body += chunk);
+                res.on('end', () => resolve(JSON.parse(body)));
+            });
+            req.on('error', reject);
+            req.end(JSON.stringify({ count: 5, size: 256, waitMs: 200 }));
+        });
+        if (result.error || result.count !== 5 || typeof result.publishPerSec !== 'number') {
+            throw new Error(`Unexpected bench result: ${JSON.stringify(result)}`);
+        }
+        if (result.acks !== 5 || result.responders !== 1 || typeof result.latencyMs.p50 !== 'number') {
+            throw new Error(`Peer acks should be measured: ${JSON.stringify(result)}`);
+        }
+        if (mesh.memoryStore.getStats().total !== before) {
+            throw new Error('Synthetic capsules should be removed after the run');
+        }
+
+        // 未开启压测模式的节点不回执，bench 标记的胶囊按普通胶囊交给上层校验
+        const plain = new MeshNode({ nodeId: 'node_bench_plain' });
+        const received = [];
+        let acked = 0;
+        plain.ackBenchCapsule = () => acked++;
+        plain.on('memory:received', (capsule) => received.push(capsule));
+        await plain.messageHandlers.get('capsule')({ type: 'capsule', payload: { asset_id: 'sha256:fake', bench: { benchId: 'b', seq: 1, origin: 'node_x' } } });
+        if (acked !== 0 || received.length !== 1) {
+            throw new Error('Bench flag should be ignored when bench mode is off');
+        }
+    } finally {
+        await peer.stop();
+        await mesh.stop();
+    }
+});
+
+// 测试34: 快照按 include 选择分区
+runner.test('GET /api/snapshot?include= - returns only requested sections', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/snapshot_include_' + Date.now(), { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    try {
+        await store.storeCapsule({ asset_id: 'sha256:snap', content: { capsule: { type: 'skill' } } });
+        store.ensureAccount('node_snapshot');
+        const web = new WebUIServer({ port: 0, mesh: { options: { isGenesisNode: true }, memoryStore: store } });
+        const get = (url) => callApi(web, { method: 'GET', url, headers: {} });
+        const partial = await get('/api/snapshot?include=accounts');
+        if (Object.keys(partial).join(',') !== 'accounts' || partial.accounts.length !== 1) {
+            throw new Error(`include=accounts should omit other sections: ${Object.keys(partial).join(',')}`);
+        }
+        const full = await get('/api/snapshot');
+        if (!full.capsules?.length || !full.accounts?.length || !Array.isArray(full.ledger) || !Array.isArray(full.accountIndex)) {
+            throw new Error('Default snapshot should include every section');
+        }
+        const both = await get('/api/snapshot?include=account_index,ledger');
+        if (Object.keys(both).sort().join(',') !== 'accountIndex,ledger') {
+            throw new Error('include should accept several sections');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试35: 关闭任务消息的节点既不处理也不转发任务gossip
+runner.test('MeshNode disabledMessageTypes - task gossip is ignored entirely', async () => {
+    const origin = new MeshNode({ nodeId: 'node_roles_origin' });
+    const storage = new MeshNode({ nodeId: 'node_roles_storage', disabledMessageTypes: ['task*'] });
+    const originToStorage = makeSocket();
+    const storageToNext = makeSocket();
+    origin.peers.set('node_roles_storage', originToStorage);
+    storage.peers.set('node_roles_origin', makeSocket());
+    storage.peers.set('node_roles_next', storageToNext);
+
+    const received = [];
+    storage.on('task:received', task => received.push(task.taskId));
+    storage.on('task:bid', payload => received.push(payload.taskId));
+    storage.on('memory:received', capsule => received.push(capsule.asset_id));
+
+    await origin.broadcastTask({ taskId: 'task_roles', description: 'ignored', bounty: { amount: 1 } });
+    origin.broadcast({ type: 'task_bid', payload: { taskId: 'task_roles', bid: {} } });
+    await origin.broadcastCapsule({ asset_id: 'sha256:roles' });
+    for (const message of originToStorage.sent) {
+        storage.handleMessage(message, 'node_roles_origin', null);
+    }
+
+    if (received.join(',') !== 'sha256:roles') {
+        throw new Error(`Only the capsule should be handled, got ${received.join(',')}`);
+    }
+    if (storageToNext.sent.some(message => message.type.startsWith('task'))) {
+        throw new Error('Disabled task messages should not be relayed');
+    }
+    if (!storageToNext.sent.some(message => message.type === 'capsule')) {
+        throw new Error('Enabled message types should still be relayed');
+    }
+});
+
+// 测试36: 最少peer数不足时不锁定托管，广播无人接收时主节点退款、非主节点不提交锁定
+runner.test('OpenClawMesh.publishTask() - minTaskPeers rejects or refunds when no peer is reached', async () => {
+    const mesh = createTestMesh('min_peers', 9992, {
+        isGenesisNode: true,
+        taskPublishFee: 0,
+        minTaskPeers: 1
+    });
+    await mesh.init();
+    try {
+        const accountId = mesh.wallet.accountId;
+        const before = mesh.ledger.getBalance(accountId);
+        const publish = async () => {
+            try {
+                await mesh.publishTask({ description: 'needs peers', bounty: { amount: 10, token: 'CLAW' } });
+            } catch (e) {
+                return e;
+            }
+            throw new Error('Publish should fail without reachable peers');
+        };
+
+        const isolated = await publish();
+        if (isolated.code !== 'INSUFFICIENT_PEERS' || mesh.ledger.getBalance(accountId) !== before || mesh.taskBazaar.getTasks().length !== 0) {
+            throw new Error(`Isolated publish should be rejected before escrow: ${isolated.message}`);
+        }
+
+        // peer在检查时存在，但广播时连接已失效
+        mesh.node.peers.set('node_flaky', { destroyed: false, writable: true, write() { throw new Error('EPIPE'); }, destroy() {} });
+        const unreachable = await publish();
+        const task = mesh.taskBazaar.getTask(unreachable.taskId);
+        if (unreachable.code !== 'UNREACHABLE' || task?.status !== 'cancelled') {
+            throw new Error(`Unreachable task should be cancelled: ${unreachable.message}`);
+        }
+        if (mesh.ledger.getBalance(accountId) !== before || mesh.ledger.getBalance(task.escrowAccountId) !== 0) {
+            throw new Error('Escrow should be refunded to the publisher');
+        }
+
+        // 非主节点退不回托管，广播失败时锁定交易根本不提交
+        const submitted = [];
+        mesh.options.isGenesisNode = false;
+        mesh.submitTx = (tx) => { submitted.push(tx); return { accepted: false, pending: true }; };
+        mesh.node.peers.set('node_flaky', { destroyed: false, writable: true, write() { throw new Error('EPIPE'); }, destroy() {} });
+        const remote = await publish();
+        if (remote.code !== 'UNREACHABLE' || submitted.length !== 0 || mesh.taskBazaar.getTask(remote.taskId)?.status !== 'cancelled') {
+            throw new Error(`A non-genesis publisher should not submit the escrow lock for an unreachable task: ${remote.message}`);
+        }
+    } finally {
+        await mesh.stop();
+    }
+});
+
+// 测试37: 收到匹配的胶囊时调用webhook（失败后重试）
+runner.test('CapsuleWebhook - posts matching received capsules with retry', async () => {
+    const http = require('http');
+    const requests = [];
+    const hook = http.createServer((req, res) => {
+        let body = '';
+        req.on('data', chunk => body += chunk);
+        req.on('end', () => {
+            requests.push(JSON.parse(body));
+            res.writeHead(requests.length === 1 ? 500 : 200);
+            res.end();
+        });
+    });
+    await new Promise(resolve => hook.listen(0, '127.0.0.1', resolve));
+    const mesh = createTestMesh('webhook', 9991, {
+        isGenesisNode: true,
+        capsuleWebhook: { url: `http://127.0.0.1:${hook.address().port}/capsules`, tags: ['trading'], baseDelayMs: 10 }
+    });
+    await mesh.init();
+    try {
+        const listeners = mesh.node.listeners('memory:received');
+        const receive = (capsule) => Promise.all(listeners.map(listener => listener(capsule)));
+        await receive({ asset_id: 'sha256:hook_other', type: 'skill', tags: ['infra'], content: null });
+        await receive({ asset_id: 'sha256:hook_match', type: 'skill', tags: ['trading'], content: null });
+        const deadline = Date.now() + 2000;
+        while (mesh.capsuleWebhook.stats.delivered < 1 && Date.now() < deadline) {
+            await new Promise(resolve => setTimeout(resolve, 10));
+        }
+        if (requests.length !== 2 || requests.some(r => r.capsule.asset_id !== 'sha256:hook_match')) {
+            throw new Error(`Webhook should receive only the matching capsule (retried once), got ${requests.length}`);
+        }
+        if (requests[1].event !== 'capsule.received' || requests[1].nodeId !== mesh.options.nodeId) {
+            throw new Error('Webhook body should include event metadata');
+        }
+    } finally {
+        await mesh.stop();
+        hook.close();
+    }
+});
+
+// 测试38: 平局由taskId决定，独立实例结果一致且随任务变化
+runner.test('TaskWorker.determineWinner() - ties are broken by a taskId-seeded hash', async () => {
+    const bids = ['node_p', 'node_q', 'node_r'].map(nodeId => ({ nodeId, amount: 50, timestamp: Date.now() }));
+    const workerA = new TaskWorker({ options: { nodeId: 'node_a', tieBreakSeed: 'mesh-1' } });
+    const workerB = new TaskWorker({ options: { nodeId: 'node_b', tieBreakSeed: 'mesh-1' } });
+    const winners = new Set();
+    for (let i = 0; i < 20; i++) {
+        const taskId = 'task_tie_' + i;
+        const a = workerA.determineWinner({ taskId, bids });
+        const b = workerB.determineWinner({ taskId, bids: [...bids].reverse() });
+        if (a.nodeId !== b.nodeId) {
+            throw new Error(`Instances disagree on ${taskId}`);
+        }
+        winners.add(a.nodeId);
+    }
+    if (winners.size < 2) {
+        throw new Error('Tie winner should depend on the taskId');
+    }
+
+    // 任务种子只取发布者签名的托管证明；篡改过的证明退回只用 taskId
+    const fs = require('fs');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/tiebreak_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const tasks = new Map();
+    const withTasks = (nodeId) => new TaskWorker({
+        options: { nodeId, tieBreakSeed: 'mesh-1' },
+        taskBazaar: { getTask: (taskId) => tasks.get(taskId) }
+    });
+    const signedA = withTasks('node_a');
+    const signedB = withTasks('node_b');
+    tasks.set('task_tie_signed', { taskId: 'task_tie_signed', escrowProof: signEscrowProof(wallet, 'task_tie_signed') });
+    const proofSeed = signedA.taskTieBreakSeed('task_tie_signed');
+    if (proofSeed === 'task_tie_signed' || proofSeed !== signedB.taskTieBreakSeed('task_tie_signed')) {
+        throw new Error(`Signed escrow proof should seed the tie-break on every node: ${proofSeed}`);
+    }
+    const proof = signEscrowProof(wallet, 'task_tie_forged');
+    tasks.set('task_tie_forged', { taskId: 'task_tie_forged', escrowProof: { ...proof, statement: { ...proof.statement, txId: 'tx_forged' } } });
+    if (signedA.taskTieBreakSeed('task_tie_forged') !== 'task_tie_forged') {
+        throw new Error('A tampered escrow proof should not feed the tie-break seed');
+    }
+    if (signedA.determineWinner({ taskId: 'task_tie_signed', bids }).nodeId !== signedB.determineWinner({ taskId: 'task_tie_signed', bids: [...bids].reverse() }).nodeId) {
+        throw new Error('Instances should agree when seeding from the escrow proof');
+    }
+});
+
+// 测试39: 超出全局转发速率的消息不再转发
+runner.test('MeshNode.relayMessage() - relays beyond maxRelayPerSec are suppressed', async () => {
+    const node = new MeshNode({ nodeId: 'node_relay_limit', maxRelayPerSec: 5 });
+    const forwarded = [];
+    node.broadcast = (message) => forwarded.push(message.messageId);
+    for (let i = 0; i < 12; i++) {
+        node.handleMessage({ type: 'capsule', payload: { asset_id: 'sha256:storm' + i }, messageId: 'storm_' + i, hopsLeft: 3 }, 'node_upstream', null);
+    }
+    const stats = node.getRelayStats();
+    if (forwarded.length !== 5 || stats.relayed !== 5 || stats.suppressed !== 7) {
+        throw new Error(`Expected 5 relayed / 7 suppressed, got ${forwarded.length} / ${stats.suppressed}`);
+    }
+    // 令牌随时间恢复
+    await new Promise(resolve => setTimeout(resolve, 250));
+    node.handleMessage({ type: 'capsule', payload: {}, messageId: 'storm_late', hopsLeft: 3 }, 'node_upstream', null);
+    if (forwarded.length !== 6) {
+        throw new Error('Relay budget should refill over time');
+    }
+});
+
+// 测试40: 只清理低置信度或长期未访问的胶囊
+runner.test('MemoryStore.pruneCapsules() - removes only low-value capsules', async () => {
+    const dayMs = 24 * 60 * 60 * 1000;
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/prune_' + Date.now(), {
+        useLance: false,
+        capsuleFlushIntervalMs: 0,
+        pruneMinConfidence: 0.3,
+        pruneIdleMs: 30 * dayMs
+    });
+    await store.init();
+    try {
+        const seed = (id, confidence, extra = {}) => store.storeCapsule({
+            asset_id: id,
+            content: { capsule: { type: 'skill', confidence, blast_radius: ['prune'] } },
+            ...extra
+        });
+        await seed('sha256:high_used', 0.9);
+        await seed('sha256:high_idle', 0.9);
+        await seed('sha256:low', 0.1);
+        await seed('sha256:low_promoted', 0.1, { status: 'promoted' });
+
+        const later = Date.now() + 31 * dayMs;
+        store.touchCapsules([store.capsules.get('sha256:high_used')], later - dayMs);
+        const result = await store.pruneCapsules(later);
+
+        const remaining = store.queryCapsules({ tags: ['prune'] }).map(c => c.asset_id).sort();
+        if (remaining.join(',') !== 'sha256:high_used,sha256:low_promoted') {
+            throw new Error(`Unexpected survivors: ${remaining.join(',')}`);
+        }
+        if (result.pruned !== 2 || result.lowConfidence !== 1 || result.idle !== 1) {
+            throw new Error(`Unexpected prune stats: ${JSON.stringify(result)}`);
+        }
+        if (store.getStats().pruning.totalPruned !== 2) {
+            throw new Error('Prune stats should be exposed in getStats()');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试41: HTTPS握手与修改类接口的mTLS校验
+runner.test('WebUIServer TLS - HTTPS handshake and client certificates for writes', async () => {
+    const fs = require('fs');
+    const https = require('https');
+    const { execSync } = require('child_process');
+    try {
+        execSync('openssl version', { stdio: 'ignore' });
+    } catch (e) {
+        console.log('   (openssl not available, skipping TLS handshake)');
+        return;
+    }
+    const dir = TEST_CONFIG.dataDir + '/tls_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const run = (cmd) => execSync(cmd, { cwd: dir, stdio: 'ignore' });
+    run('openssl req -x509 -newkey rsa:2048 -nodes -keyout ca.key -out ca.crt -days 1 -subj "/CN=mesh-test-ca"');
+    for (const name of ['server', 'client']) {
+        const cn = name === 'server' ? 'localhost' : 'ops-client';
+        run(`openssl req -newkey rsa:2048 -nodes -keyout ${name}.key -out ${name}.csr -subj "/CN=${cn}"`);
+        fs.writeFileSync(`${dir}/${name}.ext`, name === 'server' ? 'subjectAltName=DNS:localhost,IP:127.0.0.1' : 'extendedKeyUsage=clientAuth');
+        run(`openssl x509 -req -in ${name}.csr -CA ca.crt -CAkey ca.key -CAcreateserial -out ${name}.crt -days 1 -extfile ${name}.ext`);
+    }
+    const mesh = {
+        options: {},
+        getStats: () => ({ nodeId: 'node_tls' }),
+        createSignedTransfer: (to, amount) => ({ txId: 'tx_tls', to, amount }),
+        submitTx: () => ({ accepted: true })
+    };
+    const web = new WebUIServer({
+        port: 9990,
+        mesh,
+        tls: { certPath: `${dir}/server.crt`, keyPath: `${dir}/server.key`, caPath: `${dir}/ca.crt` }
+    });
+    await web.start();
+    const request = (method, path, body, withCert) => new Promise((resolve, reject) => {
+        const req = https.request({
+            host: '127.0.0.1',
+            port: 9990,
+            path,
+            method,
+            servername: 'localhost',
+            ca: fs.readFileSync(`${dir}/ca.crt`),
+            ...(withCert ? { cert: fs.readFileSync(`${dir}/client.crt`), key: fs.readFileSync(`${dir}/client.key`) } : {})
+        }, (res) => {
+            let data = '';
+            res.on('data', chunk => data += chunk);
+            res.on('end', () => resolve({ status: res.statusCode, body: JSON.parse(data) }));
+        });
+        req.on('error', reject);
+        req.end(body ? JSON.stringify(body) : undefined);
+    });
+    try {
+        const status = await request('GET', '/api/status', null, false);
+        if (status.status !== 200 || status.body.nodeId !== 'node_tls') {
+            throw new Error('Read-only endpoint should work over TLS without a client cert');
+        }
+        const transfer = { toAccountId: 'acct_tls', amount: 1 };
+        const anonymous = await request('POST', '/api/account/transfer', transfer, false);
+        if (anonymous.status !== 403) {
+            throw new Error('Writes without a client certificate should be rejected');
+        }
+        const authorized = await request('POST', '/api/account/transfer', transfer, true);
+        if (!authorized.body.success || web.getAuditLog(1)[0].actor.identity !== 'cert:ops-client') {
+            throw new Error('Writes with a CA-signed client certificate should succeed and be attributed');
+        }
+        // WebSocket 升级同样要求客户端证书
+        const upgrade = (authorized) => new Promise(resolve => web.verifyWebSocketClient(
+            { req: { socket: { authorized } } },
+            (accepted, code) => resolve({ accepted, code })
+        ));
+        const rejected = await upgrade(false);
+        if (rejected.accepted || rejected.code !== 403 || !(await upgrade(true)).accepted) {
+            throw new Error('WebSocket upgrades without an authorized client certificate should be rejected');
+        }
+    } finally {
+        await web.stop();
+    }
+});
+
+// 测试42: 网络查询分页，翻完所有页不重复不遗漏
+runner.test('MeshNode.queryMemoriesPage() - cursor pagination yields every result exactly once', async () => {
+    const node = new MeshNode({ nodeId: 'node_query_page', queryTimeoutMs: 1000 });
+    const sent = [];
+    node.broadcastAll = (message) => {
+        sent.push(message);
+        const memories = Array.from({ length: 7 }, (_, i) => ({ asset_id: `sha256:page_${i}` }));
+        memories.push({ asset_id: 'sha256:page_3' });
+        setImmediate(() => node.emit(`query_response:${message.requestId}`, { memories }));
+    };
+    const seen = [];
+    let page = await node.queryMemoriesPage({ tags: ['x'] }, { limit: 3 });
+    if (page.total !== 7) {
+        throw new Error(`Expected 7 unique results, got ${page.total}`);
+    }
+    seen.push(...page.items.map(c => c.asset_id));
+    let pages = 1;
+    while (page.cursor) {
+        page = await node.queryMemoriesPage({}, { limit: 3, cursor: page.cursor });
+        seen.push(...page.items.map(c => c.asset_id));
+        pages++;
+    }
+    if (pages !== 3 || seen.length !== 7 || new Set(seen).size !== 7) {
+        throw new Error(`Pagination returned duplicates or gaps: ${seen.join(',')}`);
+    }
+    if (sent.length !== 1) {
+        throw new Error('Following a cursor should not re-query the network');
+    }
+    let bad = null;
+    try {
+        await node.queryMemoriesPage({}, { cursor: 'bogus' });
+    } catch (e) {
+        bad = e;
+    }
+    if (!bad || bad.code !== 'BAD_CURSOR') {
+        throw new Error('Unknown cursor should be rejected');
+    }
+});
+
+// 测试43: 创世定义多代币，只有授权的 minter 能增发
+runner.test('LedgerStore mint authority - configured token minted only by its minters', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/mint_' + Date.now();
+    for (const dir of ['replica', 'minter', 'outsider']) {
+        fs.mkdirSync(`${dataDir}/${dir}`, { recursive: true });
+    }
+    const master = loadOrCreateWallet(dataDir);
+    const minter = loadOrCreateWallet(dataDir + '/minter');
+    const outsider = loadOrCreateWallet(dataDir + '/outsider');
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: master.accountId,
+        genesisSupply: 1000,
+        genesisTokens: [{ symbol: 'GOLD', supply: 50, minters: [minter.accountId] }],
+        genesisPublicKeyPem: master.publicKeyPem,
+        genesisPrivateKeyPem: master.privateKeyPem
+    });
+    const replica = new LedgerStore(dataDir + '/replica');
+    replica.init();
+    try {
+        const signMint = (wallet, token, amount) => {
+            const payload = {
+                type: 'mint',
+                from: wallet.accountId,
+                to: 'acct_mint_target',
+                amount,
+                nonce: ledger.getNonce(wallet.accountId) + 1,
+                timestamp: Date.now(),
+                meta: { token }
+            };
+            const signature = signPayload(wallet.privateKeyPem, payload);
+            return { ...payload, pubkeyPem: wallet.publicKeyPem, signature, txId: `tx_mint_${wallet.accountId}_${payload.nonce}_${token}` };
+        };
+        if (ledger.getBalance(master.accountId, 'GOLD') !== 50 || ledger.getBalance(master.accountId) !== 1000) {
+            throw new Error('Genesis should hold the configured supply of each token');
+        }
+        const authorized = ledger.appendAsMaster(signMint(minter, 'GOLD', 25));
+        if (!authorized.accepted || ledger.getBalance('acct_mint_target', 'GOLD') !== 25 || ledger.getToken('GOLD').supply !== 75) {
+            throw new Error(`Authorized mint should be accepted: ${authorized.reason}`);
+        }
+        if (ledger.getBalance('acct_mint_target') !== 0) {
+            throw new Error('Minting GOLD should not touch the default token balance');
+        }
+        const unauthorized = ledger.appendAsMaster(signMint(outsider, 'GOLD', 25));
+        if (unauthorized.accepted || unauthorized.reason !== 'Not an authorized minter') {
+            throw new Error('Mint by a non-minter should be rejected');
+        }
+        if (ledger.appendAsMaster(signMint(minter, 'CLAW', 25)).accepted) {
+            throw new Error('Only the master may mint the default token');
+        }
+        if (ledger.appendAsMaster(signMint(minter, 'SILVER', 25)).accepted) {
+            throw new Error('Undefined token should not be mintable');
+        }
+
+        // 副本通过同步日志学到代币定义和 minters
+        for (const entry of ledger.getTxLogSince(0)) {
+            if (!replica.applyLogEntry(entry).applied) {
+                throw new Error(`Replica failed to apply ${entry.txId}`);
+            }
+        }
+        if (replica.getBalance('acct_mint_target', 'GOLD') !== 25 || replica.getToken('GOLD').minters[0] !== minter.accountId) {
+            throw new Error('Replica should rebuild token state from the log');
+        }
+
+        // 环境变量中的代币定义格式错误时给出指明变量的配置错误
+        const previous = process.env.OPENCLAW_GENESIS_TOKENS;
+        try {
+            for (const raw of ['[{ symbol: GOLD }]', '{"symbol":"GOLD"}']) {
+                process.env.OPENCLAW_GENESIS_TOKENS = raw;
+                let message = null;
+                try {
+                    new OpenClawMesh({ nodeId: 'node_bad_tokens' });
+                } catch (e) {
+                    message = e.message;
+                }
+                if (!message || !message.startsWith('Invalid OPENCLAW_GENESIS_TOKENS')) {
+                    throw new Error(`Malformed OPENCLAW_GENESIS_TOKENS should be a config error, got ${message}`);
+                }
+            }
+        } finally {
+            if (previous === undefined) delete process.env.OPENCLAW_GENESIS_TOKENS;
+            else process.env.OPENCLAW_GENESIS_TOKENS = previous;
+        }
+    } finally {
+        ledger.close();
+        replica.close();
+    }
+});
+
+// 测试44: /api/version 返回版本、构建、协议版本与能力
+runner.test('GET /api/version - reports versions and capabilities matching the handshake', async () => {
+    const mesh = new OpenClawMesh({ nodeId: 'node_version', topics: ['ml'] });
+    mesh.node = new MeshNode({ nodeId: 'node_version', topics: ['ml'], disabledMessageTypes: ['capsule'] });
+    const web = new WebUIServer({ port: 0, mesh });
+    const info = await callApi(web, { method: 'GET', url: '/api/version', headers: {} });
+    for (const field of ['version', 'build', 'protocolVersion', 'capabilities', 'messageTypes']) {
+        if (!(field in info)) {
+            throw new Error(`Missing field ${field}`);
+        }
+    }
+    if (info.version !== require('../package.json').version) {
+        throw new Error('version should come from package.json');
+    }
+    if (info.protocolVersion !== mesh.node.buildHandshake(null).protocolVersion) {
+        throw new Error('protocolVersion should match the handshake');
+    }
+    if (!info.capabilities.includes('topics') || info.messageTypes.includes('capsule') || !info.messageTypes.includes('task')) {
+        throw new Error(`Unexpected capabilities: ${info.capabilities.join(',')} / ${info.messageTypes.join(',')}`);
+    }
+});
+
+// 测试45: 取消查询立即清理等待状态
+runner.test('MeshNode.queryMemories() - cancelled lookup releases its waiter immediately', async () => {
+    const node = new MeshNode({ nodeId: 'node_query_cancel', maxConcurrentQueries: 1, queryTimeoutMs: 60000 });
+    const sent = [];
+    node.broadcastAll = (message) => sent.push(message);
+    const running = new AbortController();
+    const queued = new AbortController();
+    const first = node.queryMemories({}, { signal: running.signal }).catch(e => e);
+    const second = node.queryMemories({}, { signal: queued.signal }).catch(e => e);
+    await new Promise(resolve => setImmediate(resolve));
+    const event = `query_response:${sent[0].requestId}`;
+    if (node.pendingQueries.size !== 1 || node.listenerCount(event) !== 1 || node.queryWaiters.length !== 1) {
+        throw new Error('Expected one lookup in flight and one queued');
+    }
+
+    queued.abort();
+    if ((await second).code !== 'ABORTED' || node.queryWaiters.length !== 0) {
+        throw new Error('Cancelled queued lookup should leave the wait queue');
+    }
+    const startedAt = Date.now();
+    running.abort();
+    const error = await first;
+    if (error.code !== 'ABORTED' || Date.now() - startedAt > 1000) {
+        throw new Error('Cancelled lookup should reject promptly');
+    }
+    if (node.pendingQueries.size !== 0 || node.listenerCount(event) !== 0 || node.activeQueries !== 0) {
+        throw new Error('Cancelled lookup should remove its waiter and release the slot');
+    }
+    if (sent.length !== 1) {
+        throw new Error('Cancelled queued lookup should never be broadcast');
+    }
+});
+
+// 测试46: RTT相同的候选中优先选择容量大的节点
+runner.test('MeshNode.selectPeers() - higher-capacity peer wins among equidistant candidates', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_capacity', capacityWeight: 2, reconnectPeers: false });
+    if (node.buildHandshake(null).capacity !== 2) {
+        throw new Error('Handshake should advertise the capacity weight');
+    }
+    const connectInbound = (nodeId, ip, capacity) => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: ip, remotePort: 50000, writable: true, destroyed: false });
+        socket.write = () => {};
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        socket.emit('data', Buffer.from(JSON.stringify({ type: 'handshake', nodeId, port: 4000, capacity }) + '\n'));
+    };
+    connectInbound('node_small', '10.0.1.1', 1);
+    connectInbound('node_large', '10.0.1.2', 8);
+    connectInbound('node_near', '10.0.1.3', 1);
+    node.peerStats.set('node_small', { rtt: 42, lastSeen: Date.now() });
+    node.peerStats.set('node_large', { rtt: 44, lastSeen: Date.now() });
+    const capsule = { type: 'capsule', payload: { asset_id: 'sha256:cap' } };
+
+    const picked = node.selectPeers(1, 'node_near', capsule).map(p => p.peerId);
+    if (picked[0] !== 'node_large') {
+        throw new Error(`Expected the higher-capacity peer, got ${picked.join(',')}`);
+    }
+    node.peerStats.set('node_near', { rtt: 3, lastSeen: Date.now() });
+    const ordered = node.selectPeers(3, null, capsule).map(p => p.peerId);
+    if (ordered.join(',') !== 'node_near,node_large,node_small') {
+        throw new Error(`RTT should stay the primary ordering: ${ordered.join(',')}`);
+    }
+    // 虚报的容量按上限计
+    connectInbound('node_huge', '10.0.1.4', 1e9);
+    if (node.peerCapacity.get('node_huge') !== node.maxPeerCapacity) {
+        throw new Error(`Advertised capacity should be clamped, got ${node.peerCapacity.get('node_huge')}`);
+    }
+    await node.stop();
+});
+
+// 测试47: WebSocket客户端消息协议与结构化错误
+runner.test('WebUIServer WebSocket protocol - handles each message type and reports malformed input', async () => {
+    const EventEmitter = require('events');
+    const ws = new EventEmitter();
+    ws.readyState = 1;
+    ws.sent = [];
+    ws.send = (line) => ws.sent.push(JSON.parse(line));
+    ws.ping = () => {};
+    const queried = [];
+    const mesh = {
+        getStats: () => ({ nodeId: 'node_ws_proto' }),
+        memoryStore: {
+            queryCapsules: (filter) => {
+                queried.push(filter);
+                return [{ asset_id: 'sha256:ws', content: { secret: true } }];
+            }
+        }
+    };
+    const web = new WebUIServer({ mesh, wsMinStatusIntervalMs: 10, wsStatusIntervalMs: 1000, wsPingIntervalMs: 1000 });
+    web.handleWebSocket(ws, { url: '/' });
+    const reply = (raw) => {
+        ws.sent = [];
+        ws.emit('message', Buffer.from(typeof raw === 'string' ? raw : JSON.stringify(raw)));
+        return ws.sent[ws.sent.length - 1];
+    };
+    try {
+        const pong = reply({ type: 'ping', id: 1 });
+        if (pong.type !== 'pong' || pong.id !== 1) {
+            throw new Error('ping should be answered with pong');
+        }
+        const subscribed = reply({ type: 'subscribe', id: 2, statusIntervalMs: 50 });
+        if (subscribed.type !== 'subscribed' || subscribed.statusIntervalMs !== 50 || !ws.statusTimer) {
+            throw new Error('subscribe should restart status pushes');
+        }
+        const unsubscribed = reply({ type: 'unsubscribe', id: 3 });
+        if (unsubscribed.type !== 'unsubscribed' || ws.statusTimer !== null) {
+            throw new Error('unsubscribe should stop status pushes');
+        }
+        const result = reply({ type: 'query', id: 4, filter: { tags: ['a'], limit: 1000 } });
+        if (result.type !== 'query_result' || result.id !== 4 || result.items[0].asset_id !== 'sha256:ws' || result.items[0].content !== null) {
+            throw new Error('query should return redacted local capsules');
+        }
+        if (queried[0].limit !== 200) {
+            throw new Error('query limit should be capped');
+        }
+        const cases = [
+            ['{not json', 'INVALID_JSON'],
+            ['[1,2]', 'INVALID_MESSAGE'],
+            [{ id: 5 }, 'INVALID_MESSAGE'],
+            [{ type: 'query', filter: { tags: 'a' } }, 'INVALID_QUERY'],
+            [{ type: 'launch', id: 6 }, 'UNKNOWN_TYPE']
+        ];
+        for (const [raw, code] of cases) {
+            const error = reply(raw);
+            if (error?.type !== 'error' || error.code !== code) {
+                throw new Error(`Expected ${code} for ${JSON.stringify(raw)}, got ${JSON.stringify(error)}`);
+            }
+        }
+        if (reply({ type: 'launch', id: 6 }).id !== 6) {
+            throw new Error('Errors should echo the request id');
+        }
+    } finally {
+        ws.emit('close');
+    }
+});
+
+// 测试48: 低于最低置信度的胶囊既不存储也不转发
+runner.test('MeshNode minAcceptConfidence - drops low-confidence capsules before storing or relaying', async () => {
+    const origin = new MeshNode({ nodeId: 'node_conf_origin' });
+    const curator = new MeshNode({ nodeId: 'node_conf_curator', minAcceptConfidence: 0.6 });
+    const originToCurator = makeSocket();
+    const curatorToNext = makeSocket();
+    origin.peers.set('node_conf_curator', originToCurator);
+    curator.peers.set('node_conf_origin', makeSocket());
+    curator.peers.set('node_conf_next', curatorToNext);
+    const stored = [];
+    curator.on('memory:received', capsule => stored.push(capsule.asset_id));
+
+    await origin.broadcastCapsule({ asset_id: 'sha256:low', content: { capsule: { confidence: 0.3 } } });
+    await origin.broadcastCapsule({ asset_id: 'sha256:high', content: { capsule: { confidence: 0.9 } } });
+    await origin.broadcastCapsule({ asset_id: 'sha256:unrated', content: {} });
+    for (const message of originToCurator.sent) {
+        curator.handleMessage(message, 'node_conf_origin', null);
+    }
+
+    const relayed = curatorToNext.sent.map(message => message.payload.asset_id);
+    if (stored.join(',') !== 'sha256:high' || relayed.join(',') !== 'sha256:high') {
+        throw new Error(`Only the above-threshold capsule should pass, stored=${stored.join(',')} relayed=${relayed.join(',')}`);
+    }
+    if (curator.getRelayStats().droppedLowConfidence !== 2) {
+        throw new Error('Dropped capsules should be counted');
+    }
+});
+
+// 测试49: 获胜节点分块上传结果包，中断后续传，再下载
+runner.test('POST /api/tasks/:id/upload - resumable winner upload is served by download', async () => {
+    const fs = require('fs');
+    const crypto = require('crypto');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dir = TEST_CONFIG.dataDir + '/upload_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const wallet = loadOrCreateWallet(dir);
+    const taskId = 'task_upload_1';
+    const mesh = {
+        options: { nodeId: 'node_uploader' },
+        wallet,
+        node: { peerAuth: new Map() },
+        taskBazaar: { getTask: (id) => (id === taskId ? { taskId, assignedTo: 'node_uploader' } : null) }
+    };
+    const web = new WebUIServer({ port: 9989, mesh, taskWorkspaceDir: dir + '/workspace' });
+    await web.start();
+    const api = 'http://127.0.0.1:9989';
+    const zipPath = dir + '/result.zip';
+    const payload = crypto.randomBytes(5000);
+    fs.writeFileSync(zipPath, payload);
+    const worker = { nodeId: 'node_uploader', mesh };
+    const upload = (options) => TaskWorker.prototype.uploadPackage.call(worker, api, taskId, zipPath, options);
+    const realFetch = global.fetch;
+    try {
+        // 第三块发送时断线
+        let posts = 0;
+        global.fetch = (url, init) => {
+            if (init?.method === 'POST' && ++posts > 2) return Promise.reject(new Error('connection reset'));
+            return realFetch(url, init);
+        };
+        let interrupted = null;
+        try {
+            await upload({ chunkSize: 1024 });
+        } catch (e) {
+            interrupted = e;
+        }
+        global.fetch = realFetch;
+        const status = await fetch(`${api}/api/tasks/${taskId}/upload`, { headers: { 'x-node-id': 'node_uploader' } }).then(r => r.json());
+        if (!interrupted || status.offset !== 2048) {
+            throw new Error(`Expected an interrupted upload at offset 2048, got ${status.offset}`);
+        }
+        const intruder = await fetch(`${api}/api/tasks/${taskId}/upload`, { headers: { 'x-node-id': 'node_other' } }).then(r => r.json());
+        if (!intruder.error) {
+            throw new Error('Only the assigned winner may upload');
+        }
+        const forged = await fetch(`${api}/api/tasks/${taskId}/upload`, {
+            method: 'POST',
+            headers: { 'x-node-id': 'node_uploader', 'x-upload-offset': '2048', 'x-upload-total': '5000', 'x-signature': 'bogus' },
+            body: payload.subarray(2048, 3072)
+        }).then(r => r.json());
+        if (forged.error !== 'Invalid upload signature') {
+            throw new Error('Unsigned chunks should be rejected');
+        }
+
+        await upload({ chunkSize: 1024 });
+        const response = await fetch(`${api}/api/tasks/${taskId}/download`);
+        const downloaded = Buffer.from(await response.arrayBuffer());
+        if (response.status !== 200 || !downloaded.equals(payload)) {
+            throw new Error('Downloaded package should match the uploaded bytes');
+        }
+    } finally {
+        global.fetch = realFetch;
+        await web.stop();
+    }
+});
+
+// 测试50: 重复/跳数耗尽的消息计入丢弃统计，开启时限速打印
+runner.test('MeshNode drop logging - duplicates are counted and logged only when enabled', async () => {
+    const captureLogs = async (fn) => {
+        const lines = [];
+        const original = console.log;
+        console.log = (...args) => lines.push(args.join(' '));
+        try {
+            await fn();
+        } finally {
+            console.log = original;
+        }
+        return lines.filter(line => line.includes('drop '));
+    };
+    const message = { type: 'capsule', messageId: 'msg_dup', hopsLeft: 2, payload: { asset_id: 'sha256:dup' } };
+
+    const quiet = new MeshNode({ nodeId: 'node_drop_quiet' });
+    const quietLogs = await captureLogs(() => {
+        quiet.handleMessage({ ...message }, 'node_peer', null);
+        quiet.handleMessage({ ...message }, 'node_peer', null);
+    });
+    if (quiet.getRelayStats().dropped.duplicate !== 1 || quietLogs.length !== 0) {
+        throw new Error('Duplicate should be counted without logging by default');
+    }
+
+    const verbose = new MeshNode({ nodeId: 'node_drop_verbose', logDrops: true, maxDropLogsPerSec: 2 });
+    const verboseLogs = await captureLogs(() => {
+        verbose.handleMessage({ ...message }, 'node_peer', null);
+        for (let i = 0; i < 5; i++) {
+            verbose.handleMessage({ ...message }, 'node_peer', null);
+        }
+        verbose.handleMessage({ ...message, messageId: 'msg_last_hop', hopsLeft: 0 }, 'node_peer', null);
+    });
+    const dropped = verbose.getRelayStats().dropped;
+    if (dropped.duplicate !== 5 || dropped.hopsExhausted !== 1) {
+        throw new Error(`Unexpected drop counters: ${JSON.stringify(dropped)}`);
+    }
+    if (verboseLogs.length !== 2 || !verboseLogs[0].includes('duplicate capsule msg_dup')) {
+        throw new Error(`Drop logging should be rate limited: ${verboseLogs.join(' | ')}`);
+    }
+});
+
+// 测试51: 胶囊访问控制列表：允许的节点、不允许的节点与创建者
+runner.test('Capsule ACL - listed nodes and the creator see content, others get it redacted', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dir = TEST_CONFIG.dataDir + '/acl_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const store = new MemoryStore(dir, { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    const broadcasts = [];
+    const creator = {
+        options: { nodeId: 'node_acl_creator' },
+        wallet: loadOrCreateWallet(dir),
+        memoryStore: store,
+        node: { broadcast: (message) => broadcasts.push(message) }
+    };
+    for (const name of ['createProvenance', 'signCapsuleAcl', 'updateCapsuleAcl']) {
+        creator[name] = OpenClawMesh.prototype[name].bind(creator);
+    }
+    try {
+        const assetId = 'sha256:acl_capsule';
+        await store.storeCapsule({
+            asset_id: assetId,
+            content: { gene: { solution: 'private fix' } },
+            attribution: { creator: 'node_acl_creator' },
+            provenance: creator.createProvenance(assetId, []),
+            ...creator.signCapsuleAcl(assetId, ['node_acl_allowed'])
+        });
+        const web = new WebUIServer({ port: 0, mesh: { options: { nodeId: null, isGenesisNode: true }, memoryStore: store } });
+        const view = (nodeId) => new Promise((resolve) => {
+            web.mesh.options.nodeId = nodeId;
+            web.handleAPI({ method: 'GET', url: `/api/memory/${assetId}`, headers: {} }, makeResponse(resolve));
+        });
+        if ((await view('node_acl_allowed')).content?.gene?.solution !== 'private fix') {
+            throw new Error('Listed node should see the full content');
+        }
+        if ((await view('node_acl_creator')).content?.gene?.solution !== 'private fix') {
+            throw new Error('Creator should always see the full content');
+        }
+        const denied = await view('node_acl_other');
+        if (!denied.redacted || denied.content?.gene?.solution) {
+            throw new Error('Unlisted node should get redacted content even on a genesis node');
+        }
+
+        // 修改名单需要管理员权限，否则任何能访问 Web API 的人都能替创建者签名授权
+        web.mesh.updateCapsuleAcl = () => { throw new Error('ACL should not change without admin access'); };
+        const unauthorized = await callApi(web, { method: 'POST', url: `/api/memory/${assetId}/acl`, headers: {}, socket: { remoteAddress: '10.0.0.9' } });
+        if (unauthorized.error !== 'Not authorized') {
+            throw new Error(`Remote ACL change without an admin token should be rejected: ${JSON.stringify(unauthorized)}`);
+        }
+
+        // 创建者授权后生效并广播签名的变更
+        await creator.updateCapsuleAcl(assetId, { grant: ['node_acl_other'], revoke: ['node_acl_allowed'] });
+        if ((await view('node_acl_other')).redacted || !(await view('node_acl_allowed')).redacted) {
+            throw new Error('Grant and revoke should take effect');
+        }
+        if (broadcasts[0]?.type !== 'capsule_acl') {
+            throw new Error('ACL change should be broadcast');
+        }
+        const forged = { ...broadcasts[0].payload, acl: ['node_acl_intruder'], aclUpdatedAt: Date.now() + 1000 };
+        if ((await store.applyAclUpdate(forged)).applied) {
+            throw new Error('ACL update without the creator signature should be rejected');
+        }
+        let notCreator = null;
+        try {
+            await OpenClawMesh.prototype.updateCapsuleAcl.call({ ...creator, options: { nodeId: 'node_acl_other' } }, assetId, { grant: ['x'] });
+        } catch (e) {
+            notCreator = e;
+        }
+        if (!notCreator) {
+            throw new Error('Only the creator may change the ACL');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试52: 启动同步屏障：追上可信节点账本高度前报告未就绪并拒绝写操作
+runner.test('Startup sync barrier - not ready until the trusted peer head is reached', async () => {
+    const mesh = new OpenClawMesh({ nodeId: 'node_barrier', syncBarrierPeer: 'node_trusted', syncBarrierTimeoutMs: 0 });
+    const sent = [];
+    mesh.node = new MeshNode({ nodeId: 'node_barrier' });
+    mesh.node.getPeers = () => [];
+    mesh.node.sendToPeer = (peerId, message) => { sent.push({ peerId, message }); return true; };
+    let lastSeq = 0;
+    mesh.ledger = {
+        getLastSeq: () => lastSeq,
+        applyLogEntry: (entry) => { lastSeq = Math.max(lastSeq, entry.seq); },
+        getHeadHash: () => null
+    };
+    mesh.setupEventHandlers();
+    mesh.startSyncBarrier();
+    const web = new WebUIServer({ port: 0, mesh });
+    const call = (method, url) => new Promise((resolve) => {
+        web.handleAPI({ method, url, headers: {}, on(event, cb) { if (event === 'end') setImmediate(cb); } }, makeResponse((body, status) => resolve({ status, body })));
+    });
+
+    try {
+        if ((await call('GET', '/api/ready')).status !== 503) {
+            throw new Error('Node should report not ready before syncing');
+        }
+        const blocked = await call('POST', '/api/memory/publish');
+        if (blocked.status !== 503 || blocked.body.sync?.ready !== false) {
+            throw new Error('Mutating requests should get 503 while syncing');
+        }
+
+        // 非可信节点的头部不影响屏障
+        mesh.node.emit('peer:connected', 'node_other');
+        mesh.node.emit('ledger:head_response', { lastSeq: 0 }, 'node_other');
+        mesh.node.emit('peer:connected', 'node_trusted');
+        if (!sent.some(s => s.peerId === 'node_trusted' && s.message.type === 'ledger_head_request')) {
+            throw new Error('Should ask the trusted peer for its ledger head');
+        }
+        if (sent.some(s => s.peerId === 'node_other' && s.message.type === 'ledger_head_request')) {
+            throw new Error('Should not ask untrusted peers for the head');
+        }
+        mesh.node.emit('ledger:head_response', { lastSeq: 3 }, 'node_trusted');
+        mesh.node.emit('tx:log_batch', { entries: [{ seq: 1 }, { seq: 2 }], lastSeq: 2, hasMore: true }, 'node_trusted');
+        if (mesh.isSyncReady()) {
+            throw new Error('Should stay not ready below the target seq');
+        }
+        mesh.node.emit('tx:log_batch', { entries: [{ seq: 3 }], lastSeq: 3, hasMore: false }, 'node_trusted');
+        const ready = await call('GET', '/api/ready');
+        if (ready.status !== 200 || ready.body.reason !== 'synced' || ready.body.lastSeq !== 3) {
+            throw new Error(`Node should be ready after reaching the target: ${JSON.stringify(ready.body)}`);
+        }
+        if ((await call('POST', '/api/memory/publish')).status === 503) {
+            throw new Error('Mutating requests should pass once synced');
+        }
+
+        // 不接受 '*'：任意 peer 都能虚报高度
+        const wildcard = new OpenClawMesh({ nodeId: 'node_barrier_wildcard', syncBarrierPeer: '*', syncBarrierTimeoutMs: 0 });
+        wildcard.node = { getPeers: () => [], sendToPeer: () => true };
+        wildcard.ledger = { getLastSeq: () => 0 };
+        wildcard.startSyncBarrier();
+        if (wildcard.getSyncStatus().required || wildcard.isSyncBarrierPeer('node_liar')) {
+            throw new Error('Wildcard barrier peers should be ignored');
+        }
+
+        // 超时后兜底放行
+        const timed = new OpenClawMesh({ nodeId: 'node_barrier_timeout', syncBarrierPeer: 'node_a, node_b', syncBarrierTimeoutMs: 20 });
+        timed.node = { getPeers: () => [], sendToPeer: () => true };
+        timed.ledger = { getLastSeq: () => 0 };
+        timed.startSyncBarrier();
+        if (timed.isSyncReady() || timed.getSyncStatus().peers.join(',') !== 'node_a,node_b') {
+            throw new Error('Barrier should start not ready with every named peer trusted');
+        }
+        await new Promise(r => setTimeout(r, 50));
+        if (!timed.isSyncReady() || timed.getSyncStatus().reason !== 'timeout') {
+            throw new Error('Barrier should open after the timeout');
+        }
+    } finally {
+        mesh.node.removeAllListeners();
+    }
+});
+
+// 测试53: 胶囊统计按类型、标签、置信度与存储大小聚合
+runner.test('GET /api/memory/stats - breaks down capsules by type and tag', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/capsule_stats_' + Date.now();
+    const store = new MemoryStore(dataDir, { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    try {
+        await store.storeCapsule({ asset_id: 'sha256:stats1', content: { capsule: { type: 'repair', confidence: 0.9, blast_radius: ['net', 'tcp'] } } });
+        await store.storeCapsule({ asset_id: 'sha256:stats2', content: { capsule: { type: 'repair', confidence: 0.5, blast_radius: ['net'] } } });
+        await store.storeCapsule({ asset_id: 'sha256:stats3', content: { capsule: { type: 'skill', confidence: 0.7, blast_radius: ['ui'] } } });
+        const web = new WebUIServer({ port: 0, mesh: { memoryStore: store } });
+        const fetchStats = (query = '') => callApi(web, { method: 'GET', url: '/api/memory/stats' + query, headers: {} });
+
+        const stats = await fetchStats('?top=2');
+        if (stats.total !== 3 || stats.byType.repair !== 2 || stats.byType.skill !== 1) {
+            throw new Error(`Unexpected type breakdown: ${JSON.stringify(stats.byType)}`);
+        }
+        if (stats.tagCount !== 3 || stats.topTags.length !== 2 || stats.topTags[0].tag !== 'net' || stats.topTags[0].count !== 2) {
+            throw new Error(`Unexpected tag breakdown: ${JSON.stringify(stats.topTags)}`);
+        }
+        if (Math.abs(stats.avgConfidence - 0.7) > 1e-9) {
+            throw new Error(`Unexpected average confidence: ${stats.avgConfidence}`);
+        }
+        const expectedBytes = Array.from(store.capsules.values()).reduce((sum, c) => sum + Buffer.byteLength(JSON.stringify(c)), 0);
+        if (stats.storageBytes !== expectedBytes) {
+            throw new Error('storageBytes should sum the serialized capsules');
+        }
+
+        // 写入后缓存失效
+        await store.removeCapsule('sha256:stats3');
+        const after = await fetchStats();
+        if (after.total !== 2 || after.byType.skill || after.topTags.some(t => t.tag === 'ui')) {
+            throw new Error('Stats should refresh after a write');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试54: 排空阶段拒绝新工作，进行中的任务在节点退出前完成
+runner.test('OpenClawMesh.drain() - an in-progress task finishes before the node exits', async () => {
+    const mesh = new OpenClawMesh({ nodeId: 'node_drain', drainTimeoutMs: 2000 });
+    mesh.initialized = true;
+    const worker = new TaskWorker(mesh);
+    mesh.taskWorker = worker;
+    const events = [];
+    worker.processTaskWithOpenClaw = async (task) => {
+        await new Promise(resolve => setTimeout(resolve, 150));
+        events.push(`completed:${task.taskId}`);
+    };
+    const running = worker.startWorkingOnTask({ taskId: 'task_drain_1', description: 'drain test' });
+    await new Promise(resolve => setImmediate(resolve));
+
+    const stopping = mesh.stop().then(() => events.push('stopped'));
+    await new Promise(resolve => setTimeout(resolve, 20));
+    if (!mesh.isDraining()) {
+        throw new Error('Node should be draining during stop');
+    }
+    let rejected = null;
+    try {
+        await mesh.publishTask({ description: 'late task', bounty: { amount: 1 } });
+    } catch (e) {
+        rejected = e;
+    }
+    if (rejected?.code !== 'DRAINING') {
+        throw new Error('New tasks should be rejected while draining');
+    }
+    await worker.startWorkingOnTask({ taskId: 'task_drain_2', description: 'late work' });
+    if (worker.activeTasks.has('task_drain_2')) {
+        throw new Error('No new work should start while draining');
+    }
+    await Promise.all([running, stopping]);
+    if (events.join(',') !== 'completed:task_drain_1,stopped') {
+        throw new Error(`Task should finish before the node exits: ${events.join(',')}`);
+    }
+    if (mesh.drainController.signal.aborted) {
+        throw new Error('Drain that finished in time should not cancel work');
+    }
+
+    // 超时后取消剩余工作
+    const slow = new OpenClawMesh({ nodeId: 'node_drain_slow' });
+    slow.taskWorker = { activeTasks: new Map([['task_stuck', {}]]), stopAutoBidding() {} };
+    const result = await slow.drain(30);
+    if (result.drained || result.activeTasks[0] !== 'task_stuck' || !slow.drainController.signal.aborted) {
+        throw new Error('Drain timeout should report leftover work and cancel it');
+    }
+});
+
+// 测试55: 综合评分让表现良好的peer排在不稳定的peer之前
+runner.test('MeshNode.getPeerScore() - a well-behaved peer ranks above a flaky one', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_scoring', reconnectPeers: false });
+    const connectInbound = (nodeId, ip) => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: ip, remotePort: 50000, writable: true, destroyed: false });
+        socket.write = () => {};
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        socket.emit('data', Buffer.from(JSON.stringify({ type: 'handshake', nodeId, port: 4000 }) + '\n'));
+        return socket;
+    };
+    const good = connectInbound('node_steady', '10.0.2.1');
+    const flaky = connectInbound('node_flaky', '10.0.2.2');
+    for (let i = 0; i < 20; i++) {
+        good.emit('data', Buffer.from(JSON.stringify({ type: 'ledger_head_request', messageId: `steady_${i}`, payload: {} }) + '\n'));
+        flaky.emit('data', Buffer.from(JSON.stringify({ type: 'ledger_head_request', messageId: `steady_${i}`, payload: {} }) + '\n'));
+    }
+    node.peerStats.set('node_steady', { rtt: 30, lastSeen: Date.now() });
+    node.peerStats.set('node_flaky', { rtt: 20, lastSeen: Date.now() });
+    node.peerConnectedAt.set('node_steady', Date.now() - 10 * 60 * 1000);
+    node.recordViolation('node_flaky', 'invalid message');
+    node.recordViolation('node_flaky', 'invalid message');
+
+    const steadyScore = node.getPeerScore('node_steady');
+    const flakyScore = node.getPeerScore('node_flaky');
+    if (!(steadyScore.score > flakyScore.score) || flakyScore.usefulness >= steadyScore.usefulness || flakyScore.compliance >= 1) {
+        throw new Error(`Unexpected scores: ${JSON.stringify({ steadyScore, flakyScore })}`);
+    }
+    const ordered = node.selectPeers(2, null).map(p => p.peerId);
+    if (ordered.join(',') !== 'node_steady,node_flaky') {
+        throw new Error(`Selection should follow the score: ${ordered.join(',')}`);
+    }
+    const listed = node.getPeers().find(p => p.nodeId === 'node_steady');
+    if (listed?.score?.score !== steadyScore.score) {
+        throw new Error('getPeers() should expose the score');
+    }
+
+    // 只看RTT时，RTT更低的peer排在前面
+    node.peerScoreWeights = { rtt: 1, usefulness: 0, compliance: 0, uptime: 0 };
+    if (node.selectPeers(1, null)[0].peerId !== 'node_flaky') {
+        throw new Error('Weights should be configurable');
+    }
+    await node.stop();
+});
+
+// 测试56: 本地不认识的账户通过mesh向持有账本的节点查询签名余额
+runner.test('GET /api/account/:id/balance - routes to a remote node and verifies the signed statement', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/remote_balance_' + Date.now();
+    const makeMesh = (name) => {
+        fs.mkdirSync(`${dataDir}/${name}`, { recursive: true });
+        const mesh = new OpenClawMesh({ nodeId: `node_balance_${name}` });
+        mesh.wallet = loadOrCreateWallet(`${dataDir}/${name}`);
+        mesh.ledger = new LedgerStore(`${dataDir}/${name}`);
+        mesh.node = new MeshNode({ nodeId: mesh.options.nodeId });
+        mesh.node.balanceHandler = ({ accountId, token }) => mesh.createBalanceStatement(accountId, token);
+        return mesh;
+    };
+    const home = makeMesh('home');
+    const remote = makeMesh('remote');
+    home.ledger.init({
+        isGenesis: true,
+        genesisAccountId: home.wallet.accountId,
+        genesisSupply: 500,
+        genesisPublicKeyPem: home.wallet.publicKeyPem,
+        genesisPrivateKeyPem: home.wallet.privateKeyPem
+    });
+    remote.ledger.init();
+    const link = (from, to) => ({
+        writable: true,
+        destroyed: false,
+        write(line) { setImmediate(() => to.node.handleMessage(JSON.parse(line), from.options.nodeId, null)); },
+        destroy() { this.destroyed = true; }
+    });
+    home.node.peers.set(remote.options.nodeId, link(home, remote));
+    remote.node.peers.set(home.options.nodeId, link(remote, home));
+    const web = new WebUIServer({ port: 0, mesh: remote });
+    const fetchBalance = (accountId, query = '') => callApi(web, { method: 'GET', url: `/api/account/${accountId}/balance${query}`, headers: {} });
+
+    try {
+        // 副本落后且还不认识主节点公钥：声明无法核对，不被接受
+        const unverified = await fetchBalance(home.wallet.accountId);
+        if (unverified.code !== 'BALANCE_UNVERIFIED') {
+            throw new Error(`A statement at another height should not be trusted: ${JSON.stringify(unverified)}`);
+        }
+
+        // 同步到同一高度后，声明与本地账本逐项一致
+        for (const entry of home.ledger.getTxLogSince(0)) {
+            remote.ledger.applyLogEntry(entry);
+        }
+        const local = await fetchBalance(home.wallet.accountId);
+        if (local.source !== 'local' || local.balance !== 500) {
+            throw new Error('Known accounts should be answered locally');
+        }
+        const verified = await fetchBalance(home.wallet.accountId, '?remote=true');
+        if (verified.source !== 'remote' || verified.verification?.ledger !== 'match' || verified.statement?.nodeId !== home.options.nodeId) {
+            throw new Error(`remote=true should query the mesh and match local entries: ${JSON.stringify(verified.verification)}`);
+        }
+
+        // 主节点账本领先时，主节点公钥签名的声明仍可信
+        home.ledger.appendAsMaster(home.ledger.createMintTx({
+            to: home.wallet.accountId,
+            amount: 10,
+            nonce: 2,
+            publicKeyPem: home.wallet.publicKeyPem,
+            privateKeyPem: home.wallet.privateKeyPem
+        }));
+        const ahead = await fetchBalance(home.wallet.accountId, '?remote=true');
+        if (ahead.verification?.ledger !== 'master' || ahead.balance !== 510) {
+            throw new Error(`A master-signed statement should be accepted at any height: ${JSON.stringify(ahead)}`);
+        }
+
+        // 其他节点声称更高的 lastSeq 也不能凭自签名报出任意余额
+        fs.mkdirSync(`${dataDir}/other`, { recursive: true });
+        const other = loadOrCreateWallet(`${dataDir}/other`);
+        home.node.balanceHandler = ({ accountId, token }) => {
+            const { publicKeyPem, signature, ...statement } = home.createBalanceStatement(accountId, token);
+            const claimed = { ...statement, balance: 9999, lastSeq: statement.lastSeq + 5, nodeId: 'node_balance_other' };
+            return { ...claimed, publicKeyPem: other.publicKeyPem, signature: signPayload(other.privateKeyPem, claimed) };
+        };
+        const inflated = await fetchBalance(home.wallet.accountId, '?remote=true');
+        if (inflated.code !== 'BALANCE_UNVERIFIED') {
+            throw new Error(`A self-signed statement ahead of the local ledger should be rejected: ${JSON.stringify(inflated)}`);
+        }
+
+        // 篡改的声明不被接受
+        home.node.balanceHandler = ({ accountId, token }) => ({ ...home.createBalanceStatement(accountId, token), balance: 9999 });
+        const forged = await fetchBalance(home.wallet.accountId, '?remote=true');
+        if (forged.code !== 'BALANCE_UNVERIFIED') {
+            throw new Error('A statement with a broken signature should be rejected');
+        }
+        const unknown = await fetchBalance('acct_nobody');
+        if (unknown.code !== 'ACCOUNT_NOT_FOUND') {
+            throw new Error('Accounts unknown everywhere should be reported as not found');
+        }
+    } finally {
+        home.ledger.close();
+        remote.ledger.close();
+    }
+});
+
+// 测试57: 落后的节点从peer补齐账本，冲突的条目被报告为分叉
+runner.test('Ledger replay - a lagging node catches up from a peer and forks are reported', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/ledger_replay_' + Date.now();
+    for (const dir of ['home', 'lagging', 'forked']) {
+        fs.mkdirSync(`${dataDir}/${dir}`, { recursive: true });
+    }
+    const master = loadOrCreateWallet(`${dataDir}/home`);
+    const transfer = (nonce, to, amount) => {
+        const payload = { type: 'transfer', from: master.accountId, to, amount, nonce, timestamp: Date.now() };
+        return { ...payload, pubkeyPem: master.publicKeyPem, signature: signPayload(master.privateKeyPem, payload), txId: `tx_replay_${to}_${nonce}` };
+    };
+    const makeMesh = (name, ledger) => {
+        const mesh = new OpenClawMesh({ nodeId: `node_replay_${name}` });
+        mesh.ledger = ledger;
+        mesh.node = new MeshNode({ nodeId: mesh.options.nodeId });
+        mesh.setupEventHandlers();
+        return mesh;
+    };
+    const link = (a, b) => {
+        const socket = (from, to) => ({
+            writable: true,
+            destroyed: false,
+            write(line) { setImmediate(() => to.node.handleMessage(JSON.parse(line), from.options.nodeId, null)); },
+            destroy() { this.destroyed = true; }
+        });
+        a.node.peers.set(b.options.nodeId, socket(a, b));
+        b.node.peers.set(a.options.nodeId, socket(b, a));
+    };
+
+    const homeLedger = new LedgerStore(`${dataDir}/home`);
+    homeLedger.init({ isGenesis: true, genesisAccountId: master.accountId, genesisSupply: 1000, genesisPublicKeyPem: master.publicKeyPem, genesisPrivateKeyPem: master.privateKeyPem });
+    const laggingLedger = new LedgerStore(`${dataDir}/lagging`);
+    laggingLedger.init();
+    const forkedLedger = new LedgerStore(`${dataDir}/forked`);
+    forkedLedger.init();
+    const meshes = [];
+    try {
+        const [genesisEntry] = homeLedger.getTxLogSince(0);
+        laggingLedger.applyLogEntry(genesisEntry);
+        forkedLedger.applyLogEntry(genesisEntry);
+        for (let nonce = 2; nonce <= 4; nonce++) {
+            if (!homeLedger.appendAsMaster(transfer(nonce, 'acct_replay_bob', 10 * nonce)).accepted) {
+                throw new Error('Home transfer should be accepted');
+            }
+        }
+        // 分叉：同一位置写入了不同的交易
+        forkedLedger.appendAsMaster(transfer(2, 'acct_replay_carol', 7));
+
+        const gap = laggingLedger.applyLogEntry(homeLedger.getTxLogSince(2)[0]);
+        if (gap.reason !== 'Gap' || gap.expectedSeq !== 2) {
+            throw new Error('Entries past a gap should not be applied');
+        }
+
+        const home = makeMesh('home', homeLedger);
+        const lagging = makeMesh('lagging', laggingLedger);
+        const forked = makeMesh('forked', forkedLedger);
+        meshes.push(home, lagging, forked);
+        link(home, lagging);
+        link(home, forked);
+
+        lagging.node.sendToPeer(home.options.nodeId, { type: 'tx_log_request', payload: { sinceSeq: laggingLedger.getLastSeq(), limit: 1 } });
+        const deadline = Date.now() + 20000;
+        while (laggingLedger.getLastSeq() < homeLedger.getLastSeq() && Date.now() < deadline) {
+            await new Promise(resolve => setTimeout(resolve, 20));
+        }
+        if (laggingLedger.getHeadHash() !== homeLedger.getHeadHash() || laggingLedger.getBalance('acct_replay_bob') !== 90) {
+            throw new Error('Lagging node should reconverge with the same head and balances');
+        }
+
+        forked.requestLedgerSince(home.options.nodeId, 0);
+        while (forkedLedger.getForks().length === 0 && Date.now() < deadline) {
+            await new Promise(resolve => setTimeout(resolve, 20));
+        }
+        const [fork] = forkedLedger.getForks();
+        if (fork?.seq !== 2 || fork.remoteTxId !== 'tx_replay_acct_replay_bob_2' || fork.peerId !== home.options.nodeId) {
+            throw new Error(`Conflicting entry should be reported as a fork: ${JSON.stringify(fork)}`);
+        }
+        if (forkedLedger.getBalance('acct_replay_carol') !== 7 || forkedLedger.getBalance('acct_replay_bob') !== 0) {
+            throw new Error('A fork must not be merged automatically');
+        }
+        const web = new WebUIServer({ port: 0, mesh: forked });
+        const report = await callApi(web, { method: 'GET', url: '/api/ledger/forks', headers: {} });
+        if (report.items?.length !== 1) {
+            throw new Error('/api/ledger/forks should list the fork');
+        }
+    } finally {
+        for (const mesh of meshes) mesh.node.removeAllListeners();
+        homeLedger.close();
+        laggingLedger.close();
+        forkedLedger.close();
+    }
+});
+
+// 测试58: 已存有的胶囊不再存储也不再转发
+runner.test('MeshNode stored capsule suppression - a duplicate capsule is not relayed', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/suppress_' + Date.now(), { useLance: false });
+    await store.init();
+    const run = async (suppressStoredCapsules) => {
+        const node = new MeshNode({ nodeId: 'node_suppress', suppressStoredCapsules, hasCapsule: (capsule) => store.hasCapsule(capsule) });
+        const downstream = makeSocket();
+        node.peers.set('node_up', makeSocket());
+        node.peers.set('node_down', downstream);
+        const received = [];
+        node.on('memory:received', async (capsule) => {
+            received.push(capsule);
+            await store.storeCapsule(capsule);
+        });
+        const content = { capsule: { type: 'skill', confidence: 0.8, gene: { solution: `suppress-${suppressStoredCapsules}` } } };
+        node.handleMessage({ type: 'capsule', messageId: `cap_${suppressStoredCapsules}_1`, hopsLeft: 2, payload: { content } }, 'node_up');
+        await new Promise(resolve => setImmediate(resolve));
+        // 同一内容换了 messageId（例如经另一条路径到达），没有 asset_id 时按内容识别
+        node.handleMessage({ type: 'capsule', messageId: `cap_${suppressStoredCapsules}_2`, hopsLeft: 2, payload: { content } }, 'node_up');
+        await new Promise(resolve => setImmediate(resolve));
+        return { node, received, relayed: downstream.sent.filter(m => m.type === 'capsule') };
+    };
+
+    try {
+        const suppressed = await run(true);
+        if (suppressed.received.length !== 1 || suppressed.relayed.length !== 1) {
+            throw new Error(`Only the new capsule should be stored and relayed: stored=${suppressed.received.length} relayed=${suppressed.relayed.length}`);
+        }
+        if (suppressed.node.getRelayStats().dropped.storedCapsule !== 1) {
+            throw new Error('The suppressed duplicate should be counted');
+        }
+        const legacy = await run(false);
+        if (legacy.relayed.length !== 2) {
+            throw new Error('Suppression should be configurable');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试59: 账户索引缺失条目时按账户记录重建
+runner.test('MemoryStore reconcileAccountIndex - missing index entry is rebuilt', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/reconcile_' + Date.now();
+    const store = new MemoryStore(dataDir, { useLance: false });
+    await store.init();
+    const account = store.ensureAccount('node_x');
+    store.accountIndex.delete('node_x');
+    store.accountIndex.set('node_ghost', 'acct_missing');
+    await store.saveAccountsToDisk();
+
+    const dry = await store.reconcileAccountIndex({ dryRun: true });
+    if (dry.consistent) {
+        throw new Error('Dry run should report the inconsistency');
+    }
+    if (store.accountIndex.has('node_x')) {
+        throw new Error('Dry run should not change the index');
+    }
+
+    const result = await store.reconcileAccountIndex();
+    if (!result.changes.some(c => c.nodeId === 'node_x' && c.action === 'added')) {
+        throw new Error('Missing entry should be added');
+    }
+    if (!result.changes.some(c => c.nodeId === 'node_ghost' && c.action === 'removed')) {
+        throw new Error('Stale entry should be removed');
+    }
+    if (store.getAccountByNodeId('node_x')?.accountId !== account.accountId) {
+        throw new Error('Index should point to the existing account');
+    }
+    if (!(await store.reconcileAccountIndex({ dryRun: true })).consistent) {
+        throw new Error('Index should now be consistent');
+    }
+
+    // 重启时自动修复
+    store.accountIndex.delete('node_x');
+    await store.saveAccountsToDisk();
+    await store.close();
+    const reopened = new MemoryStore(dataDir, { useLance: false });
+    await reopened.init();
+    if (!reopened.lastAccountIndexReconcile) {
+        throw new Error('Startup should report the repair');
+    }
+    if (reopened.getAccountByNodeId('node_x')?.accountId !== account.accountId) {
+        throw new Error('Startup should rebuild the index');
+    }
+    if (reopened.accounts.size !== store.accounts.size) {
+        throw new Error('No duplicate account should be created');
+    }
+    await reopened.close();
+});
+
+// 测试60: 存储时指定的保留时长覆盖全局空闲清理时长
+runner.test('MemoryStore per-capsule TTL - long-TTL capsule survives a sweep that evicts a short-TTL one', async () => {
+    const dayMs = 24 * 60 * 60 * 1000;
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/ttl_' + Date.now(), {
+        useLance: false,
+        pruneIntervalMs: 0,
+        pruneIdleMs: 7 * dayMs
+    });
+    await store.init();
+    try {
+        const seed = (id, options) => store.storeCapsule({
+            asset_id: id,
+            content: { capsule: { type: 'skill', confidence: 0.9, blast_radius: ['ttl'] } }
+        }, options);
+        await seed('sha256:routine');
+        await seed('sha256:critical', { ttlMs: 90 * dayMs });
+        await seed('sha256:pinned', { ttlMs: 0 });
+        // 收到的胶囊自带的保留时长不生效
+        await store.storeCapsule({
+            asset_id: 'sha256:remote',
+            retentionMs: 0,
+            content: { capsule: { type: 'skill', confidence: 0.9, blast_radius: ['ttl'] } }
+        });
+        // 未指定保留时长的重新存储（如 gossip 回声）沿用已有记录的值
+        await seed('sha256:critical');
+        if (store.getCapsuleTtl(store.capsules.get('sha256:critical')) !== 90 * dayMs) {
+            throw new Error('Re-storing without ttlMs should keep the existing retention');
+        }
+
+        const result = await store.pruneCapsules(Date.now() + 30 * dayMs);
+        const remaining = store.queryCapsules({ tags: ['ttl'] }).map(c => c.asset_id).sort();
+        if (remaining.join(',') !== 'sha256:critical,sha256:pinned') {
+            throw new Error(`Unexpected survivors: ${remaining.join(',')}`);
+        }
+        if (result.idle !== 2) {
+            throw new Error(`Unexpected prune stats: ${JSON.stringify(result)}`);
+        }
+
+        // 重新发布只延长不缩短
+        if (await store.extendCapsuleTtl('sha256:critical', dayMs)) {
+            throw new Error('A shorter TTL should not replace a longer one');
+        }
+        if (!await store.extendCapsuleTtl('sha256:critical', 0) || store.getCapsuleTtl(store.capsules.get('sha256:critical')) !== 0) {
+            throw new Error('Re-publishing with no expiry should extend the TTL');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试61: relay 角色既不启动任务处理器也不提供写接口，并在握手中声明角色
+runner.test('OpenClawMesh roles - relay role starts neither the worker nor mutating endpoints', async () => {
+    const legacy = new OpenClawMesh({ nodeId: 'node_roles_legacy', isGenesisNode: true, enableWorker: false });
+    if (legacy.options.roles.join(',') !== 'genesis,storage' || !legacy.options.isGenesisNode) {
+        throw new Error(`Legacy flags should map to roles: ${legacy.options.roles.join(',')}`);
+    }
+    let threw = false;
+    try {
+        new OpenClawMesh({ nodeId: 'node_roles_bad', roles: ['miner'] });
+    } catch (e) {
+        threw = true;
+    }
+    if (!threw) {
+        throw new Error('Unknown roles should be rejected');
+    }
+
+    const mesh = createTestMesh('roles', 9988, { nodeId: 'node_roles_relay', roles: ['relay'] });
+    await mesh.init();
+    try {
+        if (mesh.taskWorker.checkTimer || mesh.taskWorker.votingTimer) {
+            throw new Error('Relay node should not start the task worker');
+        }
+        if (mesh.getStats().roles.join(',') !== 'relay' || mesh.options.isGenesisNode) {
+            throw new Error('Roles should be exposed in status');
+        }
+        if (mesh.node.buildHandshake(null).roles?.join(',') !== 'relay') {
+            throw new Error('Handshake should advertise roles');
+        }
+        const call = (method, url) => new Promise((resolve) => {
+            mesh.webUI.handleAPI({ method, url, headers: {}, on(event, cb) { if (event === 'end') setImmediate(cb); } }, makeResponse((body, status) => resolve({ status, body })));
+        });
+        const publish = await call('POST', '/api/memory/publish');
+        if (publish.status !== 403 || !/relay-only/.test(publish.body.error)) {
+            throw new Error(`Publish should be rejected on a relay node: ${JSON.stringify(publish)}`);
+        }
+        const status = await call('GET', '/api/status');
+        if (status.status !== 200 || status.body.roles?.join(',') !== 'relay') {
+            throw new Error('Read endpoints should stay available');
+        }
+    } finally {
+        await mesh.stop();
+    }
+});
+
+// 测试62: 相似度查询返回部分命中的胶囊并按得分排序
+runner.test('MemoryStore similarity query - partially matching capsule ranks and appears', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/similar_' + Date.now(), { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    try {
+        const seed = (id, summary, tags = []) => store.storeCapsule({
+            asset_id: id,
+            content: { capsule: { type: 'skill', confidence: 0.5, blast_radius: tags, summary } }
+        });
+        await seed('sha256:full', 'fix json parse error in api response', ['json']);
+        await seed('sha256:partial', 'retry api request on timeout', ['network']);
+        await seed('sha256:unrelated', 'render chart with svg', ['ui']);
+
+        const query = 'json parse error api';
+        const results = store.queryCapsules({ mode: 'similarity', query });
+        const ids = results.map(c => c.asset_id);
+        if (ids.join(',') !== 'sha256:full,sha256:partial') {
+            throw new Error(`Unexpected similarity ranking: ${ids.join(',')}`);
+        }
+        if (!(results[0].similarity > results[1].similarity && results[1].similarity > 0)) {
+            throw new Error('Results should carry descending similarity scores');
+        }
+        if (store.capsules.get('sha256:full').similarity !== undefined) {
+            throw new Error('Scores should not be written onto stored capsules');
+        }
+
+        await store.removeCapsule('sha256:full');
+        const after = store.queryCapsules({ mode: 'similarity', query, limit: 5 }).map(c => c.asset_id);
+        if (after.join(',') !== 'sha256:partial' || store.tokenIndex.has('json')) {
+            throw new Error('Removed capsules should leave the token index');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试63: 任务过期无人完成时托管转给备用账户，未设置备用账户时退回发布者
+runner.test('OpenClawMesh.expireTasks() - releases escrow to the fallback account or refunds', async () => {
+    const mesh = createTestMesh('fallback', 9987, {
+        isGenesisNode: true,
+        taskPublishFee: 0,
+        taskExpiryIntervalMs: 0
+    });
+    await mesh.init();
+    try {
+        const accountId = mesh.wallet.accountId;
+        const before = mesh.ledger.getBalance(accountId);
+        const deadline = new Date(Date.now() + 60000).toISOString();
+        const { taskId: fallbackTaskId } = await mesh.publishTask({
+            description: 'fallback bounty',
+            bounty: { amount: 10, token: 'CLAW' },
+            deadline,
+            fallbackAccountId: 'acct_charity'
+        });
+        const { taskId: refundTaskId } = await mesh.publishTask({
+            description: 'refund bounty',
+            bounty: { amount: 5, token: 'CLAW' },
+            deadline
+        });
+        if (mesh.expireTasks().length !== 0) {
+            throw new Error('Tasks should not expire before their deadline');
+        }
+
+        const expired = mesh.expireTasks(Date.now() + 120000);
+        if (expired.length !== 2) {
+            throw new Error(`Both tasks should expire: ${expired.length}`);
+        }
+        const fallbackTask = mesh.taskBazaar.getTask(fallbackTaskId);
+        const refundTask = mesh.taskBazaar.getTask(refundTaskId);
+        if (fallbackTask.status !== 'expired' || fallbackTask.escrowDisposition.type !== 'fallback' || !fallbackTask.escrowDisposition.accepted) {
+            throw new Error(`Unexpected fallback disposition: ${JSON.stringify(fallbackTask.escrowDisposition)}`);
+        }
+        if (refundTask.escrowDisposition.type !== 'refund' || refundTask.escrowDisposition.to !== accountId) {
+            throw new Error(`Unexpected refund disposition: ${JSON.stringify(refundTask.escrowDisposition)}`);
+        }
+        if (mesh.ledger.getBalance('acct_charity') !== 10 || mesh.ledger.getBalance(fallbackTask.escrowAccountId) !== 0) {
+            throw new Error('Fallback account should receive the escrow');
+        }
+        if (mesh.ledger.getBalance(accountId) !== before - 10 || mesh.ledger.getBalance(refundTask.escrowAccountId) !== 0) {
+            throw new Error('Unset fallback should refund the publisher');
+        }
+        if (mesh.ledger.getTxById(fallbackTask.escrowDisposition.txId)?.meta?.memo !== `task:${fallbackTaskId}:fallback`) {
+            throw new Error('Disposition should be recorded in the ledger');
+        }
+        if (mesh.expireTasks(Date.now() + 240000).length !== 0) {
+            throw new Error('Expired tasks should not be settled twice');
+        }
+
+        // 备用账户来自签名托管证明：转发时改写任务字段或证明内容都不能改变去向
+        const relayView = new TaskBazaar({ nodeId: 'node_fallback_relay', dataDir: mesh.options.dataDir, ledger: mesh.ledger });
+        const rewritten = { ...fallbackTask, status: 'open', fallbackAccountId: 'acct_attacker' };
+        if (relayView.markExpired(rewritten, Date.now(), 'node_x').escrowDisposition.to !== 'acct_charity') {
+            throw new Error('An unsigned fallbackAccountId should not redirect the escrow');
+        }
+        const tampered = {
+            ...fallbackTask,
+            status: 'open',
+            escrowProof: { ...fallbackTask.escrowProof, statement: { ...fallbackTask.escrowProof.statement, fallbackAccountId: 'acct_attacker' } }
+        };
+        const disposition = relayView.markExpired(tampered, Date.now(), 'node_x').escrowDisposition;
+        if (disposition.to !== null) {
+            throw new Error(`A tampered escrow proof should not name any payee: ${JSON.stringify(disposition)}`);
+        }
+    } finally {
+        await mesh.stop();
+    }
+});
+
+// 测试64: peer 列表附带去重的地址，拨号失败的地址在冷却期内不再拨号
+runner.test('MeshNode address book - a repeatedly advertised dead address is not re-dialed within its cooldown', async () => {
+    const node = new MeshNode({ nodeId: 'node_pex', sharePeerList: true, addressCooldownMs: 60000, maxPexAddresses: 2 });
+    const dials = [];
+    node.connectToPeer = async (address) => {
+        dials.push(address);
+        throw new Error('ECONNREFUSED');
+    };
+    const advertise = () => node.messageHandlers.get('peer_list')({
+        payload: {
+            nodeId: 'node_gossiper',
+            peers: ['node_dead'],
+            addresses: [{ nodeId: 'node_dead', address: '10.0.0.9:4001' }, '10.0.0.9:4001']
+        }
+    }, 'node_gossiper');
+    const flush = () => new Promise(resolve => setImmediate(resolve));
+
+    advertise();
+    await flush();
+    if (dials.length !== 1 || node.getAddressBook().length !== 1) {
+        throw new Error(`Dead address should be dialed once: ${dials.join(',')}`);
+    }
+    const firstSeen = node.addressBook.get('10.0.0.9:4001').lastSeen;
+    for (let i = 0; i < 3; i++) {
+        advertise();
+        await flush();
+    }
+    if (dials.length !== 1) {
+        throw new Error(`Dead address should not be re-dialed within its cooldown: ${dials.length}`);
+    }
+    const entry = node.addressBook.get('10.0.0.9:4001');
+    if (entry.failures !== 1 || entry.lastSeen < firstSeen) {
+        throw new Error('Address book should track failures and last-seen time');
+    }
+    node.dialKnownAddresses(Date.now() + 60001);
+    await flush();
+    if (dials.length !== 2) {
+        throw new Error('Address should be dialed again after the cooldown');
+    }
+    node.pruneAddressBook(Date.now() + node.addressBookTtlMs + 1);
+    if (node.addressBook.size !== 0) {
+        throw new Error('Stale addresses should expire');
+    }
+
+    // 发送方的地址列表按地址去重并受数量上限约束
+    for (const [id, address] of [['node_a', '10.0.0.1:4001'], ['node_b', '10.0.0.1:4001'], ['node_c', '10.0.0.3:4001'], ['node_d', '10.0.0.4:4001']]) {
+        node.peers.set(id, { writable: true, destroyed: false, write() {}, destroy() {} });
+        node.peerAddresses.set(id, address);
+    }
+    const shared = node.getPexAddresses().map(entry => entry.address);
+    if (shared.join(',') !== '10.0.0.1:4001,10.0.0.3:4001') {
+        throw new Error(`Unexpected PEX payload: ${shared.join(',')}`);
+    }
+});
+
+// 测试65: 存储锁被长时间占用时写请求返回 503，熔断打开后不再等待，锁释放后自动恢复
+runner.test('WebUIServer store circuit breaker - a held store lock causes 503s and recovers', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/breaker_' + Date.now(), { useLance: false });
+    await store.init();
+    const web = new WebUIServer({
+        port: 0,
+        mesh: { options: {}, memoryStore: store },
+        storeLockTimeoutMs: 30,
+        storeBreakerThreshold: 2,
+        storeBreakerCooldownMs: 100
+    });
+    const call = (method, url) => new Promise((resolve) => {
+        const started = Date.now();
+        web.handleAPI({ method, url, headers: {}, on(event, cb) { if (event === 'end') setImmediate(cb); } }, makeResponse((body, status) => resolve({ status, body, elapsed: Date.now() - started })));
+    });
+    try {
+        let release;
+        store.withStoreLock(() => new Promise(resolve => { release = resolve; }));
+
+        const first = await call('POST', '/api/memory/publish');
+        if (first.status !== 503 || first.body.store?.state !== 'closed') {
+            throw new Error(`Held lock should time out with 503: ${JSON.stringify(first)}`);
+        }
+        const second = await call('POST', '/api/memory/publish');
+        if (second.status !== 503 || second.body.store?.state !== 'open') {
+            throw new Error('Breaker should open after repeated timeouts');
+        }
+        const shortCircuited = await call('POST', '/api/memory/publish');
+        if (shortCircuited.status !== 503 || shortCircuited.elapsed >= 30) {
+            throw new Error('Open breaker should reject without waiting for the lock');
+        }
+        if ((await call('GET', '/api/ready')).status !== 503) {
+            throw new Error('Node should report not ready while the breaker is open');
+        }
+        if ((await call('GET', '/api/memories')).status !== 200) {
+            throw new Error('Read requests should not wait on the store lock');
+        }
+
+        release();
+        await new Promise(resolve => setTimeout(resolve, 120));
+        const recovered = await call('POST', '/api/memory/publish');
+        if (recovered.status === 503 || web.storeBreaker.state !== 'closed') {
+            throw new Error(`Breaker should close once the store responds: ${JSON.stringify(recovered)}`);
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试66: 任务生命周期时间戳统一为毫秒，兼容旧的 ISO 字符串
+runner.test('TaskBazaar timestamps - old ISO and new millis formats load as millis', async () => {
+    const fs = require('fs');
+    const dataDir = TEST_CONFIG.dataDir + '/timestamps_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const iso = '2025-01-02T03:04:05.000Z';
+    const ms = Date.parse(iso);
+    fs.writeFileSync(dataDir + '/tasks.json', JSON.stringify([
+        { taskId: 'task_old', description: 'old', bounty: { amount: 1 }, status: 'completed', published_at: iso, votingStartedAt: ms, assignedAt: ms, completedAt: iso },
+        { taskId: 'task_new', description: 'new', bounty: { amount: 1 }, status: 'completed', published_at: ms, assignedAt: ms, completedAt: ms + 1000 }
+    ]));
+    const bazaar = new TaskBazaar({ nodeId: 'node_ts', dataDir });
+    const old = bazaar.getTask('task_old');
+    const fresh = bazaar.getTask('task_new');
+    for (const field of ['published_at', 'votingStartedAt', 'assignedAt', 'completedAt']) {
+        if (old[field] !== ms) {
+            throw new Error(`Old ${field} should be converted to millis: ${old[field]}`);
+        }
+    }
+    if (fresh.published_at !== ms || fresh.completedAt !== ms + 1000) {
+        throw new Error('New millis timestamps should be kept as-is');
+    }
+
+    await bazaar.handleNewTask({ taskId: 'task_inbound', description: 'inbound', bounty: { amount: 1 }, published_at: iso, deadline: iso });
+    const inbound = bazaar.getTask('task_inbound');
+    if (inbound.published_at !== ms || inbound.deadline !== ms) {
+        throw new Error('Inbound ISO timestamps should be normalized');
+    }
+    const taskId = await bazaar.publishTask({ description: 'local', bounty: { amount: 1 } });
+    bazaar.updateTask(taskId, { cancelledAt: iso });
+    const local = bazaar.getTask(taskId);
+    if (typeof local.published_at !== 'number' || local.cancelledAt !== ms) {
+        throw new Error('Published and updated timestamps should be millis');
+    }
+});
+
+// 测试67: 竞标数超过上限时只保留最有竞争力的，更差的迟到竞标被拒绝
+runner.test('TaskBazaar.addBid() - keeps only the best bids beyond the cap', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/max_bids_' + Date.now();
+    require('fs').mkdirSync(dataDir, { recursive: true });
+    const bazaar = new TaskBazaar({ nodeId: 'node_bids', dataDir, maxBidsPerTask: 3 });
+    const worker = new TaskWorker({ options: { nodeId: 'node_bids' } });
+    bazaar.compareBids = (a, b, taskId) => worker.compareBids(a, b, taskId);
+    const taskId = await bazaar.publishTask({ description: 'capped', bounty: { amount: 100 } });
+
+    for (const [nodeId, amount] of [['node_a', 80], ['node_b', 60], ['node_c', 90]]) {
+        if (!bazaar.addBid(taskId, { nodeId, amount, timestamp: Date.now() }).accepted) {
+            throw new Error(`Bid from ${nodeId} should be accepted under the cap`);
+        }
+    }
+    if (bazaar.getTask(taskId).status !== 'voting') {
+        throw new Error('First bid should move the task to voting');
+    }
+    const better = bazaar.addBid(taskId, { nodeId: 'node_d', amount: 70, timestamp: Date.now() });
+    if (!better.accepted || better.dropped.map(b => b.nodeId).join(',') !== 'node_c') {
+        throw new Error(`A better bid should evict the worst one: ${JSON.stringify(better)}`);
+    }
+    const worse = bazaar.addBid(taskId, { nodeId: 'node_e', amount: 95, timestamp: Date.now() });
+    if (worse.accepted || worse.reason !== 'Bid not competitive') {
+        throw new Error('A worse late bid should be rejected');
+    }
+    if (bazaar.addBid(taskId, { nodeId: 'node_b', amount: 10 }).reason !== 'Duplicate bid') {
+        throw new Error('Duplicate bidders should be rejected');
+    }
+    const bids = bazaar.getTask(taskId).bids;
+    if (bids.map(b => b.nodeId).join(',') !== 'node_b,node_d,node_a') {
+        throw new Error(`Unexpected retained bids: ${bids.map(b => b.nodeId).join(',')}`);
+    }
+    if (worker.determineWinner({ taskId, bids }).nodeId !== 'node_b') {
+        throw new Error('Retained bids should still contain the winner');
+    }
+
+    await bazaar.handleNewTask({ taskId: 'task_gossiped', description: 'big', bounty: { amount: 10 }, bids: [1, 2, 3, 4, 5].map(n => ({ nodeId: 'node_' + n, amount: 10 - n })) });
+    if (bazaar.getTask('task_gossiped').bids.length !== 3) {
+        throw new Error('Inbound tasks should be trimmed to the cap');
+    }
+});
+
+// 测试68: 签名不符的消息在转发前被丢弃
+runner.test('MeshNode message signatures - spoofed or tampered messages are dropped before relay', async () => {
+    const crypto = require('crypto');
+    const { nodeIdFromPublicKey } = require('../src/wallet');
+    const makeIdentity = () => {
+        const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
+        return {
+            publicKeyPem: publicKey.export({ type: 'spki', format: 'pem' }),
+            privateKeyPem: privateKey.export({ type: 'pkcs8', format: 'pem' })
+        };
+    };
+    const idA = makeIdentity();
+    const idB = makeIdentity();
+    let mismatched = false;
+    try {
+        new MeshNode({ nodeId: 'node_custom', identity: idA, signMessages: true });
+    } catch (e) {
+        mismatched = true;
+    }
+    if (!mismatched) {
+        throw new Error('Non-derived nodeId should be rejected when signing');
+    }
+
+    const nodeA = new MeshNode({ nodeId: nodeIdFromPublicKey(idA.publicKeyPem), identity: idA, signMessages: true });
+    const nodeB = new MeshNode({ nodeId: nodeIdFromPublicKey(idB.publicKeyPem), identity: idB, signMessages: true });
+    const toB = makeSocket();
+    nodeA.peers.set(nodeB.nodeId, toB);
+    const downstream = makeSocket();
+    nodeB.peers.set('node_downstream', downstream);
+    const received = [];
+    nodeB.messageHandlers.set('task', message => received.push(message.payload.taskId));
+
+    nodeA.broadcastTask({ taskId: 'task_signed' });
+    const signed = toB.sent[0];
+    if (signed.origin !== nodeA.nodeId || !signed.signature) {
+        throw new Error('Outbound message should be signed by its origin');
+    }
+
+    const tampered = { ...signed, messageId: 'm2', payload: { taskId: 'task_tampered' } };
+    const spoofed = { ...signed, messageId: 'm3', origin: 'node_victim' };
+    const unsigned = { type: 'task', payload: { taskId: 'task_unsigned' }, messageId: 'm4', hopsLeft: 2 };
+    for (const message of [tampered, spoofed, unsigned]) {
+        nodeB.handleMessage(message, nodeA.nodeId, toB);
+    }
+    if (received.length !== 0 || downstream.sent.length !== 0) {
+        throw new Error('Invalid messages should not be handled or relayed');
+    }
+    if (nodeB.getRelayStats().dropped.badSignature !== 3) {
+        throw new Error('Invalid messages should be counted as badSignature drops');
+    }
+
+    nodeB.handleMessage(signed, nodeA.nodeId, toB);
+    if (received[0] !== 'task_signed' || downstream.sent.length !== 1) {
+        throw new Error('Valid message should be handled and relayed');
+    }
+    const relayed = downstream.sent[0];
+    if (relayed.signature !== signed.signature || !nodeB.verifyMessageSignature(relayed)) {
+        throw new Error('Relayed message should keep the original signature');
+    }
+
+    // 签名有效但替其他节点出价的竞价同样被丢弃
+    const bids = [];
+    nodeB.messageHandlers.set('task_bid', message => bids.push(message.payload.bid.nodeId));
+    const forBid = makeSocket();
+    nodeA.peers.clear();
+    nodeA.peers.set(nodeB.nodeId, forBid);
+    nodeA.broadcast({ type: 'task_bid', payload: { taskId: 'task_signed', bid: { nodeId: 'node_victim', amount: 1 } } });
+    nodeA.broadcast({ type: 'task_bid', payload: { taskId: 'task_signed', bid: { nodeId: nodeA.nodeId, amount: 2 } } });
+    for (const message of forBid.sent) {
+        nodeB.handleMessage(message, nodeA.nodeId, forBid);
+    }
+    if (bids.join(',') !== nodeA.nodeId || nodeB.getRelayStats().dropped.badSignature !== 4) {
+        throw new Error(`Bids should only be accepted from the bidder itself: ${JSON.stringify(bids)}`);
+    }
+});
+
+// 测试69: 胶囊评分聚合、防重复评分，并参与查询排序
+runner.test('Capsule ratings - signed ratings aggregate, dedupe per node and affect ranking', async () => {
+    const fs = require('fs');
+    const crypto = require('crypto');
+    const RatingStore = require('../src/rating-store');
+    const { loadOrCreateWallet, signPayload, nodeIdFromPublicKey } = require('../src/wallet');
+    const dir = TEST_CONFIG.dataDir + '/capsule_rating_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const store = new MemoryStore(dir, { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    const ratingStore = new RatingStore(dir);
+    ratingStore.init();
+    store.capsuleRatings = () => ratingStore.getCapsuleRatings();
+    const broadcasts = [];
+    const mesh = {
+        options: { nodeId: 'node_rater_local' },
+        wallet: loadOrCreateWallet(dir),
+        memoryStore: store,
+        ratingStore,
+        node: { broadcast: (message) => broadcasts.push(message), peerAuth: new Map() }
+    };
+    for (const name of ['rateCapsule', 'applyCapsuleRating', 'verifyCapsuleRating']) {
+        mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+    }
+    const raters = new Map();
+    const remoteRating = (name, assetId, thumb, score, raterNodeId = null) => {
+        if (!raters.has(name)) {
+            const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
+            raters.set(name, {
+                publicKeyPem: publicKey.export({ type: 'spki', format: 'pem' }),
+                privateKeyPem: privateKey.export({ type: 'pkcs8', format: 'pem' })
+            });
+        }
+        const { publicKeyPem, privateKeyPem } = raters.get(name);
+        const statement = { assetId, raterNodeId: raterNodeId || nodeIdFromPublicKey(publicKeyPem), thumb, score, ratedAt: Date.now() };
+        return { ...statement, publicKeyPem, signature: signPayload(privateKeyPem, statement) };
+    };
+    try {
+        await store.storeCapsule({ asset_id: 'sha256:rated_high', confidence: 0.8, content: { capsule: { type: 'skill' } } });
+        await store.storeCapsule({ asset_id: 'sha256:rated_low', confidence: 0.85, content: { capsule: { type: 'skill' } } });
+
+        const local = mesh.rateCapsule('sha256:rated_high', { thumb: 'up', score: 5 });
+        if (local.rating.count !== 1 || broadcasts[0]?.type !== 'capsule_rating') {
+            throw new Error('Local rating should be stored and broadcast');
+        }
+        if (!mesh.applyCapsuleRating(remoteRating('node_rater_b', 'sha256:rated_high', 'up', 3)).ok) {
+            throw new Error('Valid remote rating should be accepted');
+        }
+        if (!mesh.applyCapsuleRating(remoteRating('node_rater_c', 'sha256:rated_low', 'down', 4)).ok) {
+            throw new Error('Valid remote down-vote should be accepted');
+        }
+
+        // 同一节点重复评分、伪造签名都被拒绝
+        let duplicate = null;
+        try {
+            mesh.rateCapsule('sha256:rated_high', { thumb: 'down', score: 1 });
+        } catch (e) {
+            duplicate = e;
+        }
+        if (!duplicate) {
+            throw new Error('Double rating by the same node should be rejected');
+        }
+        const repeat = remoteRating('node_rater_b', 'sha256:rated_high', 'down', 1);
+        if (mesh.applyCapsuleRating(repeat).ok) {
+            throw new Error('Remote node should not rate the same capsule twice');
+        }
+        const forged = { ...remoteRating('node_rater_d', 'sha256:rated_high', 'up', 2), score: 5 };
+        if (mesh.applyCapsuleRating(forged).reason !== 'Invalid signature') {
+            throw new Error('Tampered rating should fail signature verification');
+        }
+        // 未开启消息签名时也不能用自己的钥匙冒充其他节点评分
+        const impostor = remoteRating('node_rater_e', 'sha256:rated_high', 'up', 5, 'node_rater_b');
+        if (mesh.applyCapsuleRating(impostor).reason !== 'Signer key does not match the rater') {
+            throw new Error('Rating with a rater ID not derived from the signer key should be rejected');
+        }
+
+        const aggregate = ratingStore.getCapsuleRating('sha256:rated_high');
+        if (aggregate.count !== 2 || aggregate.up !== 2 || aggregate.down !== 0 || aggregate.averageScore !== 4 || aggregate.value !== 0.8) {
+            throw new Error(`Unexpected aggregate: ${JSON.stringify(aggregate)}`);
+        }
+
+        // 评分修正让置信度略低但口碑好的胶囊排在前面
+        const results = store.queryCapsules({ limit: 10 });
+        if (results[0].asset_id !== 'sha256:rated_high' || results[0].rating?.count !== 2) {
+            throw new Error('Ratings should be included and factored into ranking');
+        }
+        if (store.getCapsule('sha256:rated_high').rating) {
+            throw new Error('Stored capsule should not be modified by ratings');
+        }
+    } finally {
+        ratingStore.close();
+        await store.close();
+    }
+});
+
+// 测试70: 按 key 的路由追踪经过已知拓扑中的中间节点
+runner.test('MeshNode key route trace - path walks through intermediate nodes of a known topology', async () => {
+    const node = new MeshNode({ nodeId: 'node_trace_a' });
+    node.peers.set('node_trace_b', { destroyed: false });
+    const report = (peers) => ({ peers, roles: [], receivedAt: Date.now() });
+    node.peerLists.set('node_trace_b', report(['node_trace_a', 'node_trace_c', 'node_trace_d']));
+    node.peerLists.set('node_trace_c', report(['node_trace_b', 'node_trace_e']));
+    const web = new WebUIServer({ port: 0, mesh: { options: { nodeId: 'node_trace_a' }, node } });
+    const trace = await callApi(web, { method: 'GET', url: '/api/network/trace?key=node_trace_e', headers: {} });
+    const visited = trace.path.map(step => step.nodeId);
+    if (visited[0] !== 'node_trace_b' || !visited.includes('node_trace_c') || !visited.includes('node_trace_e')) {
+        throw new Error(`Unexpected routing path: ${visited.join(' -> ')}`);
+    }
+    const viaB = trace.path[0].closest;
+    if (!viaB.includes('node_trace_c') || viaB.includes('node_trace_a')) {
+        throw new Error('Each step should report the closest set returned by that node');
+    }
+    if (trace.path.find(step => step.nodeId === 'node_trace_d').closest !== null) {
+        throw new Error('Nodes without a known peer list should report no closest set');
+    }
+    if (trace.closest[0] !== 'node_trace_e' || trace.rounds !== 3) {
+        throw new Error('Lookup should converge on the key owner');
+    }
+});
+
+// 测试71: 胶囊数量上限按最近访问淘汰，重启后向最近的peer重新通告
+runner.test('Capsule cap and re-announce - LRU eviction over the cap, restart announces to closest peers', async () => {
+    const dir = TEST_CONFIG.dataDir + '/capsule_cap_' + Date.now();
+    const store = new MemoryStore(dir, { useLance: false, capsuleFlushIntervalMs: 0, maxCapsules: 2 });
+    await store.init();
+    const capsule = (id, status) => ({ asset_id: id, status, content: { capsule: { type: 'skill' } } });
+    await store.storeCapsule(capsule('sha256:cap_old'));
+    await store.storeCapsule(capsule('sha256:cap_used'));
+    store.touchCapsules([store.getCapsule('sha256:cap_used')], Date.now() + 1000);
+    await store.storeCapsule(capsule('sha256:cap_new'));
+    if (store.getCapsule('sha256:cap_old') || !store.getCapsule('sha256:cap_used') || !store.getCapsule('sha256:cap_new')) {
+        throw new Error('Least recently used capsule should be evicted first');
+    }
+    await store.close();
+
+    const reopened = new MemoryStore(dir, { useLance: false, capsuleFlushIntervalMs: 0, maxCapsules: 2 });
+    await reopened.init();
+    if (reopened.getCount() !== 2 || reopened.getStats().eviction.maxCapsules !== 2) {
+        throw new Error('Capsules under the cap should survive a restart');
+    }
+
+    const node = new MeshNode({ nodeId: 'node_reannounce' });
+    const sent = new Map();
+    for (const peerId of ['node_peer_1', 'node_peer_2', 'node_peer_3', 'node_peer_4']) {
+        const socket = { destroyed: false, writable: true, write(line) { sent.set(peerId, [...(sent.get(peerId) || []), JSON.parse(line)]); } };
+        node.peers.set(peerId, socket);
+    }
+    const mesh = { options: { reannounceK: 2 }, memoryStore: reopened, node };
+    for (const name of ['reannounceCapsules', 'buildCapsuleAnnouncement']) {
+        mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+    }
+    const result = mesh.reannounceCapsules();
+    if (result.capsules !== 2 || result.sent !== 4) {
+        throw new Error(`Each capsule should go to its 2 closest peers: ${JSON.stringify(result)}`);
+    }
+    const expected = node.getClosestPeers('sha256:cap_new', 2);
+    for (const peerId of expected) {
+        const message = (sent.get(peerId) || []).find(m => m.payload.asset_id === 'sha256:cap_new');
+        if (!message || message.hopsLeft !== 0 || message.payload.content !== null) {
+            throw new Error('Closest peers should receive a non-relayed announcement without content');
+        }
+    }
+
+    // 分批通告：每批之后等待间隔再发下一批
+    sent.clear();
+    mesh.options = { reannounceK: 2, reannounceBatchSize: 1, reannounceBatchIntervalMs: 10 };
+    const first = mesh.reannounceCapsules();
+    if (first.capsules !== 1 || first.sent !== 2 || first.remaining !== 1 || !mesh.reannounceTimer) {
+        throw new Error(`Re-announce should send one batch and schedule the rest: ${JSON.stringify(first)}`);
+    }
+    await new Promise(resolve => setTimeout(resolve, 50));
+    const total = Array.from(sent.values()).reduce((sum, messages) => sum + messages.length, 0);
+    if (total !== 4 || mesh.reannounceTimer) {
+        throw new Error(`Later batches should follow after the interval: ${total}`);
+    }
+    await reopened.close();
+
+    // 本节点发布的胶囊不会被收到的胶囊挤掉，收到的胶囊自带的本地标记无效
+    const flooded = new MemoryStore(dir + '_flood', { useLance: false, capsuleFlushIntervalMs: 0, maxCapsules: 2 });
+    await flooded.init();
+    await flooded.storeCapsule(capsule('sha256:cap_mine'), { localOrigin: true });
+    await flooded.storeCapsule({ ...capsule('sha256:cap_claimed'), localOrigin: true });
+    for (let i = 0; i < 5; i++) {
+        await flooded.storeCapsule(capsule('sha256:cap_remote_' + i));
+    }
+    if (!flooded.getCapsule('sha256:cap_mine') || flooded.getCapsule('sha256:cap_claimed') || flooded.getCount() !== 2) {
+        throw new Error('Remote capsules should evict each other, not locally published ones');
+    }
+    if ('localOrigin' in flooded.redactCapsule(flooded.getCapsule('sha256:cap_mine'))) {
+        throw new Error('Local origin flag should not leave the node');
+    }
+    await flooded.close();
+});
+
+// 测试72: 长度前缀分帧：多行JSON可传输，超大帧断开连接，旧的按行协议仍可用
+runner.test('MeshNode framing - length-prefixed frames, oversized frames and line fallback', async () => {
+    const net = require('net');
+    const server = new MeshNode({ nodeId: 'node_frame_server', maxMessageBytes: 64 * 1024 });
+    const client = new MeshNode({ nodeId: 'node_frame_client', wireFraming: 'length' });
+    await server.init();
+    const received = [];
+    server.messageHandlers.set('capsule', (message) => received.push(message.payload));
+    const waitFor = async (check, label) => {
+        for (let i = 0; i < 100 && !check(); i++) {
+            await new Promise(resolve => setTimeout(resolve, 20));
+        }
+        if (!check()) throw new Error(label);
+    };
+    try {
+        await client.connectToPeer(`127.0.0.1:${server.port}`);
+        await waitFor(() => server.peers.has('node_frame_client'), 'Length-framed handshake should be accepted');
+        const toServer = client.peers.get('node_frame_server') || client.peers.get(`127.0.0.1:${server.port}`);
+        if (server.peers.get('node_frame_client').meshFraming !== 'length') {
+            throw new Error('Server should detect and reply with the framing the client used');
+        }
+
+        // 多行（格式化）JSON 在长度前缀分帧下完整传输
+        const pretty = Buffer.from(JSON.stringify({ type: 'capsule', payload: { asset_id: 'sha256:pretty', content: 'line1\nline2' } }, null, 2));
+        const header = Buffer.alloc(4);
+        header.writeUInt32BE(pretty.length, 0);
+        toServer.write(Buffer.concat([header, pretty.subarray(0, 10)]));
+        toServer.write(pretty.subarray(10));
+        await waitFor(() => received.length === 1, 'Pretty-printed frame should be delivered');
+        if (received[0].content !== 'line1\nline2') {
+            throw new Error('Embedded newlines should survive framing');
+        }
+
+        // 旧节点仍按行发送
+        const legacy = net.createConnection({ host: '127.0.0.1', port: server.port });
+        await new Promise(resolve => legacy.on('connect', resolve));
+        legacy.write(JSON.stringify({ type: 'capsule', payload: { asset_id: 'sha256:legacy' } }) + '\n');
+        await waitFor(() => received.length === 2, 'Line-framed messages should still be accepted');
+        legacy.destroy();
+
+        // 超大帧直接断开
+        const attacker = net.createConnection({ host: '127.0.0.1', port: server.port });
+        await new Promise(resolve => attacker.on('connect', resolve));
+        const closed = new Promise(resolve => attacker.on('close', resolve));
+        const huge = Buffer.alloc(4);
+        huge.writeUInt32BE(64 * 1024 + 1, 0);
+        attacker.write(huge);
+        await closed;
+        if (!(server.violations.get('127.0.0.1')?.length > 0)) {
+            throw new Error('Oversized frame should be recorded as a violation');
+        }
+    } finally {
+        await client.stop();
+        await server.stop();
+    }
+});
+
+// 测试73: 按内容类型索引：JSON 胶囊只索引指定字段，Markdown 去掉链接地址
+runner.test('MemoryStore content-type indexing - JSON capsules index selected fields only', async () => {
+    const capsule = {
+        asset_id: 'sha256:json_indexed',
+        content: {
+            content_type: 'application/json',
+            gene: { solution: 'Restart the stalled worker', retryPolicy: 'exponential' },
+            meta: { requestId: 'req_7f3a9c', endpoint: 'internal-metrics' }
+        }
+    };
+    const naive = new MemoryStore(TEST_CONFIG.dataDir + '/index_naive', { useLance: false, indexMode: 'naive' });
+    const aware = new MemoryStore(TEST_CONFIG.dataDir + '/index_aware', { useLance: false });
+    const naiveTokens = new Set(naive.getCapsuleTokens(capsule));
+    const awareTokens = new Set(aware.getCapsuleTokens(capsule));
+    for (const token of ['restart', 'stalled', 'worker']) {
+        if (!naiveTokens.has(token) || !awareTokens.has(token)) {
+            throw new Error(`Both modes should index the solution text (${token})`);
+        }
+    }
+    for (const token of ['application', 'json', '7f3a9c', 'metrics', 'exponential']) {
+        if (awareTokens.has(token)) {
+            throw new Error(`Field-aware indexing should skip ${token}`);
+        }
+    }
+    if (!naiveTokens.has('application') || !naiveTokens.has('metrics') || awareTokens.size >= naiveTokens.size) {
+        throw new Error('Naive indexing should keep every string value');
+    }
+
+    const custom = { ...capsule, content: { ...capsule.content, index_fields: ['meta.endpoint'] } };
+    if (aware.getCapsuleTokens(custom).join(' ') !== 'internal metrics') {
+        throw new Error('Capsule index_fields should override the default paths');
+    }
+    const markdown = aware.getCapsuleTokens({ content: { content_type: 'text/markdown', text: '## Fix\nSee [the runbook](https://example.com/runbook)' } });
+    if (!markdown.includes('runbook') || markdown.includes('https') || markdown.includes('example')) {
+        throw new Error('Markdown indexing should keep link text but drop the URL');
+    }
+
+    await aware.storeCapsule(capsule);
+    if (aware.queryCapsules({ mode: 'similarity', query: 'metrics endpoint' }).length !== 0
+        || aware.queryCapsules({ mode: 'similarity', query: 'stalled worker' })[0]?.asset_id !== 'sha256:json_indexed') {
+        throw new Error('Similarity search should only match indexed fields');
+    }
+});
+
+// 测试74: 页面模板缺少标记时返回诊断页而不是残缺页面
+runner.test('WebUIServer page - broken template yields a diagnostic page instead of garbage', async () => {
+    const web = new WebUIServer({ port: 0 });
+    const serve = () => {
+        const res = { status: null, body: '', setHeader() {}, writeHead(code) { this.status = code; }, end(chunk) { this.body = chunk; } };
+        web.serveHTML(res);
+        return res;
+    };
+    const ok = serve();
+    if (ok.status !== 200 || web.validateHTML(ok.body) !== null) {
+        throw new Error('Real page should validate and be served');
+    }
+
+    const cases = [
+        ['', 'empty'],
+        ['<div>dashboard</div>', '<!DOCTYPE html>'],
+        ['<!DOCTYPE html>\n<html><body>cut off', '<html> ... </html>'],
+        [undefined, 'empty']
+    ];
+    for (const [template, expected] of cases) {
+        web.generateHTML = () => template;
+        const res = serve();
+        if (res.status !== 500 || !res.body.includes('Web UI unavailable') || !res.body.includes(expected.replace(/</g, '&lt;').replace(/>/g, '&gt;'))) {
+            throw new Error(`Broken template should produce a diagnostic page (${expected})`);
+        }
+    }
+    web.generateHTML = () => { throw new Error('boom'); };
+    if (!serve().body.includes('template failed to render: boom')) {
+        throw new Error('Rendering errors should be reported on the diagnostic page');
+    }
+});
+
+// 测试75: bootstrap 节点失败或断开后按指数退避一直重试，普通peer达到次数后放弃
+runner.test('MeshNode bootstrap reconnect - retries bootstrap forever with backoff, gives up on discovered peers', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({
+        nodeId: 'node_bootstrap_retry',
+        bootstrapNodes: ['10.0.0.1:4000'],
+        reconnectBaseDelayMs: 2,
+        reconnectMaxDelayMs: 16,
+        maxReconnectAttempts: 2
+    });
+    const dialed = [];
+    let bootstrapFailures = 6;
+    node.connectToPeer = async (address) => {
+        dialed.push({ address, at: Date.now() });
+        if (address === '10.0.0.1:4000' && bootstrapFailures-- > 0) throw new Error('connection refused');
+        if (address !== '10.0.0.1:4000') throw new Error('connection refused');
+    };
+    await node.connectToBootstrapNodes();
+    await new Promise(resolve => setTimeout(resolve, 200));
+    const bootstrapDials = dialed.filter(d => d.address === '10.0.0.1:4000');
+    if (bootstrapDials.length !== 7 || node.bootstrapTimers.size !== 0) {
+        throw new Error(`Bootstrap should be retried past maxReconnectAttempts until it connects, got ${bootstrapDials.length} dials`);
+    }
+    const gaps = bootstrapDials.slice(1).map((d, i) => d.at - bootstrapDials[i].at);
+    if (gaps[gaps.length - 1] < 16) {
+        throw new Error(`Backoff should grow up to the cap, got gaps ${gaps.join(',')}`);
+    }
+
+    // 已连接的 bootstrap peer 断开后重新拨号（即使不是高价值peer）；普通peer重试两次后放弃
+    const connectInbound = (nodeId, ip, port) => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: '::ffff:' + ip, remotePort: 50000, writable: true, destroyed: false });
+        socket.write = () => {};
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        socket.emit('data', Buffer.from(JSON.stringify({ type: 'handshake', nodeId, port }) + '\n'));
+        return socket;
+    };
+    dialed.length = 0;
+    const seed = connectInbound('node_seed', '10.0.0.1', 4000);
+    const discovered = connectInbound('node_found', '10.0.0.9', 4100);
+    node.peerStats.set('node_found', { rtt: 10, lastSeen: Date.now() });
+    seed.emit('close');
+    discovered.emit('close');
+    await new Promise(resolve => setTimeout(resolve, 100));
+    if (!dialed.some(d => d.address === '10.0.0.1:4000')) {
+        throw new Error('Lost bootstrap peer should be re-dialed');
+    }
+    if (dialed.filter(d => d.address === '10.0.0.9:4100').length !== 2 || node.reconnectTimers.size !== 0) {
+        throw new Error('Discovered peers should give up after maxReconnectAttempts');
+    }
+    await node.stop();
+});
+
+// 测试76: 握手后交换地址样本，接收方拨号未知地址
+runner.test('MeshNode peer exchange - new peers get an address sample on handshake and dial unknown addresses', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_pex_b', sharePeerList: true, pexTargetPeers: 5 });
+    const dialed = [];
+    node.connectToPeer = async (address) => { dialed.push(address); };
+    const connectInbound = (nodeId, ip, port) => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: '::ffff:' + ip, remotePort: 50000, writable: true, destroyed: false, sent: [] });
+        socket.write = (line) => { socket.sent.push(JSON.parse(line)); };
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        socket.emit('data', Buffer.from(JSON.stringify({ type: 'handshake', nodeId, port }) + '\n'));
+        return socket;
+    };
+
+    // B 已连着 C；A 连上 B 后应得知 C 的地址
+    connectInbound('node_pex_c', '10.0.1.3', 4003);
+    node.learnAddresses([{ nodeId: 'node_pex_d', address: '10.0.1.4:4004' }]);
+    const socketA = connectInbound('node_pex_a', '10.0.1.1', 4001);
+    const exchange = socketA.sent.find(m => m.type === 'peer_exchange');
+    const shared = (exchange?.payload.addresses || []).map(entry => entry.address).sort();
+    if (shared.join(',') !== '10.0.1.3:4003,10.0.1.4:4004') {
+        throw new Error(`New peer should receive known addresses but not its own, got ${shared.join(',')}`);
+    }
+    const peerC = node.getPeers().find(p => p.nodeId === 'node_pex_c');
+    if (peerC?.address !== '10.0.1.3:4003') {
+        throw new Error('getPeers() should expose the learned address');
+    }
+
+    // 收到地址样本后拨号未知地址，已连接的不重复拨号，且不转发
+    node.handleMessage({
+        type: 'peer_exchange',
+        payload: { nodeId: 'node_pex_a', addresses: [
+            { nodeId: 'node_pex_c', address: '10.0.1.3:4003' },
+            { nodeId: 'node_pex_e', address: '10.0.1.5:4005' }
+        ] }
+    }, 'node_pex_a', socketA);
+    await new Promise(resolve => setImmediate(resolve));
+    if (!dialed.includes('10.0.1.5:4005') || !dialed.includes('10.0.1.4:4004') || dialed.includes('10.0.1.3:4003')) {
+        throw new Error(`Only unknown addresses should be dialed, got ${dialed.join(',')}`);
+    }
+    await node.stop();
+});
+
+// 测试77: 批量发布时胶囊广播按配置速率排队发出
+runner.test('MeshNode publish throttling - a burst of capsule broadcasts is spread over time', async () => {
+    const node = new MeshNode({ nodeId: 'node_publish_throttle', maxPublishPerSec: 50 });
+    const sentAt = [];
+    node.broadcast = (message) => { sentAt.push({ at: Date.now(), id: message.payload.asset_id }); return 1; };
+    for (let i = 0; i < 10; i++) {
+        await node.broadcastCapsule({ asset_id: `sha256:burst_${i}` });
+    }
+    const queued = node.getRelayStats().publishQueue;
+    if (sentAt.length !== 1 || queued.depth !== 9 || queued.queued !== 10) {
+        throw new Error(`Burst should be queued behind the rate limit: ${JSON.stringify(queued)}`);
+    }
+    for (let i = 0; i < 100 && node.publishQueue.length > 0; i++) {
+        await new Promise(resolve => setTimeout(resolve, 20));
+    }
+    const stats = node.getPublishQueueStats();
+    if (stats.depth !== 0 || stats.sent !== 10 || sentAt.map(s => s.id).join(',') !== Array.from({ length: 10 }, (_, i) => `sha256:burst_${i}`).join(',')) {
+        throw new Error('All queued capsules should be sent in order');
+    }
+    const span = sentAt[9].at - sentAt[0].at;
+    if (span < 175) {
+        throw new Error(`10 broadcasts at 50/s should take about 180ms, took ${span}ms`);
+    }
+
+    // 队列满时以 BUSY 拒绝，不再无限堆积
+    const bounded = new MeshNode({ nodeId: 'node_publish_bounded', maxPublishPerSec: 1, maxPublishQueue: 3 });
+    bounded.broadcast = () => 1;
+    for (let i = 0; i < 4; i++) await bounded.broadcastCapsule({ asset_id: `sha256:bounded_${i}` });
+    let busy = null;
+    try {
+        await bounded.broadcastCapsule({ asset_id: 'sha256:bounded_over' });
+    } catch (e) {
+        busy = e;
+    }
+    if (busy?.code !== 'BUSY' || bounded.getPublishQueueStats().depth !== 3 || bounded.getPublishQueueStats().rejected !== 1) {
+        throw new Error(`A full publish queue should reject with BUSY: ${JSON.stringify(bounded.getPublishQueueStats())}`);
+    }
+    await bounded.stop();
+
+    // 不限速时立即发出
+    const unlimited = new MeshNode({ nodeId: 'node_publish_unlimited' });
+    let direct = 0;
+    unlimited.broadcast = () => { direct++; return 1; };
+    for (let i = 0; i < 5; i++) await unlimited.broadcastCapsule({ asset_id: `sha256:direct_${i}` });
+    if (direct !== 5 || unlimited.getPublishQueueStats().depth !== 0) {
+        throw new Error('Without a limit capsules should be broadcast immediately');
+    }
+    await node.stop();
+    await unlimited.stop();
+});
+
+// 测试78: 一致性哈希放置让 key 在节点间分布均匀，副本取环上后继的不同节点
+runner.test('MeshNode ring placement - keys spread evenly and replicas are distinct successors', () => {
+    const node = new MeshNode({ nodeId: 'node_ring_self', keyPlacement: 'ring' });
+    const peerIds = Array.from({ length: 8 }, (_, i) => `node_ring_${i}`);
+    peerIds.forEach(id => node.peers.set(id, {}));
+
+    const counts = new Map(peerIds.map(id => [id, 0]));
+    const keyCount = 8000;
+    for (let i = 0; i < keyCount; i++) {
+        const [owner] = node.getClosestPeers(`sha256:ring_key_${i}`, 1);
+        counts.set(owner, counts.get(owner) + 1);
+    }
+    const mean = keyCount / peerIds.length;
+    for (const [id, count] of counts) {
+        if (Math.abs(count - mean) > mean * 0.35) {
+            throw new Error(`Ring placement is uneven: ${id} owns ${count} keys (mean ${mean})`);
+        }
+    }
+
+    const replicas = node.getClosestPeers('sha256:ring_replica', 3);
+    if (replicas.length !== 3 || new Set(replicas).size !== 3) {
+        throw new Error(`Replicas should be 3 distinct nodes: ${JSON.stringify(replicas)}`);
+    }
+    // 主副本离开后，原来的第一个后继成为主副本
+    node.peers.delete(replicas[0]);
+    const after = node.getClosestPeers('sha256:ring_replica', 2);
+    if (after[0] !== replicas[1] || after[1] !== replicas[2]) {
+        throw new Error(`Successors should take over in order: ${JSON.stringify({ replicas, after })}`);
+    }
+
+    const xor = new MeshNode({ nodeId: 'node_ring_self' });
+    if (xor.keyPlacement !== 'xor' || xor.getClosestPeers('sha256:ring_replica', 3).length !== 0) {
+        throw new Error('XOR placement should remain the default');
+    }
+});
+
+// 测试79: 连接数达到上限后，新入站连接顶替匿名连接或评分最低的慢peer（bootstrap 除外），否则被拒绝
+runner.test('MeshNode max peers - inbound connections are bounded and evict the weakest slot', () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_max_peers', maxPeers: 2, peerScoreRttRefMs: 100 });
+    let port = 40000;
+    const inbound = (ip = '10.0.0.9') => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: ip, remotePort: port++, writable: true, destroyed: false });
+        socket.write = () => true;
+        socket.destroy = () => { socket.destroyed = true; };
+        node.handleConnection(socket);
+        return socket;
+    };
+    const known = (nodeId, rtt) => {
+        const socket = { writable: true, destroyed: false, destroy() { this.destroyed = true; } };
+        node.peers.set(nodeId, socket);
+        node.peerStats.set(nodeId, { rtt, lastSeen: Date.now() });
+        return socket;
+    };
+
+    const fast = known('node_fast', 20);
+    const anon = inbound();
+    const fresh = inbound();
+    if (!anon.destroyed || fresh.destroyed || node.peers.size !== 2 || !node.peers.has('node_fast')) {
+        throw new Error('A full node should drop the oldest anonymous connection before known peers');
+    }
+
+    node.peers.delete('10.0.0.9:' + fresh.remotePort);
+    const slow = known('node_slow', 800);
+    const replacing = inbound();
+    if (!slow.destroyed || node.peers.has('node_slow') || replacing.destroyed || fast.destroyed) {
+        throw new Error('The slowest known peer should be evicted for a healthier newcomer');
+    }
+
+    node.peers.delete('10.0.0.9:' + replacing.remotePort);
+    known('node_ok', 50);
+    const rejected = inbound();
+    const stats = node.getConnectionStats();
+    if (!rejected.destroyed || stats.current !== 2 || stats.limit !== 2 || stats.rejected !== 1 || stats.evicted !== 2) {
+        throw new Error(`Newcomer should be rejected when every peer is healthy: ${JSON.stringify(stats)}`);
+    }
+
+    node.violations.set('10.0.0.66', [Date.now()]);
+    node.peerStats.set('node_ok', { rtt: 900, lastSeen: Date.now() });
+    if (!inbound('10.0.0.66').destroyed || !node.peers.has('node_ok')) {
+        throw new Error('A newcomer with recent violations should not displace a known peer');
+    }
+
+    // bootstrap 地址上的 peer 不被顶替；从未测得RTT的 peer 按最差处理
+    node.bootstrapNodes = ['10.0.0.5:4000'];
+    node.peerAddresses.set('node_ok', '10.0.0.5:4000');
+    if (!inbound('10.0.0.7').destroyed || !node.peers.has('node_ok')) {
+        throw new Error('A peer reached through a bootstrap address should not be evicted');
+    }
+    node.peers.delete('node_fast');
+    const silent = { writable: true, destroyed: false, destroy() { this.destroyed = true; } };
+    node.peers.set('node_silent', silent);
+    const afterSilent = inbound('10.0.0.8');
+    if (!silent.destroyed || afterSilent.destroyed || !node.peers.has('node_ok')) {
+        throw new Error('A peer that never reported an RTT should be evictable');
+    }
+
+    // 多个慢 peer 时按评分取最低者，而不是只看RTT
+    node.peers.delete('10.0.0.8:' + afterSilent.remotePort);
+    node.peers.delete('node_ok');
+    const slower = known('node_slower', 400);
+    const flaky = known('node_flaky', 300);
+    node.violations.set('node_flaky', [Date.now(), Date.now()]);
+    inbound('10.0.0.10');
+    if (!flaky.destroyed || slower.destroyed) {
+        throw new Error('The lowest-scoring slow peer should be evicted first');
+    }
+});
+
+// 测试80: /api/ledger 按 offset/limit 分页返回账本条目和总数
+runner.test('WebUIServer ledger pagination - /api/ledger returns a page and the total count', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/ledger_page_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: wallet.accountId,
+        genesisSupply: 1000,
+        genesisPublicKeyPem: wallet.publicKeyPem,
+        genesisPrivateKeyPem: wallet.privateKeyPem
+    });
+    try {
+        for (let i = 0; i < 4; i++) {
+            const payload = {
+                type: 'transfer',
+                from: wallet.accountId,
+                to: 'acct_page_target',
+                amount: 1,
+                nonce: ledger.getNonce(wallet.accountId) + 1,
+                timestamp: Date.now()
+            };
+            const signature = signPayload(wallet.privateKeyPem, payload);
+            if (!ledger.appendAsMaster({ ...payload, pubkeyPem: wallet.publicKeyPem, signature, txId: 'tx_page_' + i }).accepted) {
+                throw new Error('Transfer should be accepted');
+            }
+        }
+        const web = new WebUIServer({ port: 0, mesh: { ledger } });
+        const get = (url) => callApi(web, { method: 'GET', url, headers: {} });
+
+        const page = await get('/api/ledger?offset=1&limit=2');
+        if (page.total !== 5 || page.items.length !== 2 || page.items[0].seq !== 2 || page.items[1].txId !== 'tx_page_1') {
+            throw new Error(`Unexpected page: ${JSON.stringify(page)}`);
+        }
+        const first = await get('/api/ledger');
+        if (first.limit !== 50 || first.offset !== 0 || first.items.length !== 5 || first.items[0].seq !== 1) {
+            throw new Error(`Defaults should return the first 50 entries: ${JSON.stringify({ limit: first.limit, count: first.items.length })}`);
+        }
+        const clamped = await get('/api/ledger?limit=100000&offset=-3');
+        if (clamped.limit !== 500 || clamped.offset !== 0) {
+            throw new Error(`limit should be capped at 500: ${clamped.limit}`);
+        }
+    } finally {
+        ledger.close();
+    }
+});
+
+// 测试81: 收益排行榜按 SQLite 账本中的托管释放统计收益和完成数，支持时间窗口并缓存结果
+runner.test('LedgerStore leaderboard - accounts are ranked by released escrow earnings from tx_log', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/leaderboard_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: wallet.accountId,
+        genesisSupply: 10000,
+        genesisPublicKeyPem: wallet.publicKeyPem,
+        genesisPrivateKeyPem: wallet.privateKeyPem
+    });
+    const append = (type, from, to, amount, meta = null, timestamp = Date.now()) => {
+        const payload = { type, from, to, amount, nonce: ledger.getNonce(from) + 1, timestamp, ...(meta ? { meta } : {}) };
+        const signature = signPayload(wallet.privateKeyPem, payload);
+        const tx = { ...payload, pubkeyPem: wallet.publicKeyPem, signature, txId: `tx_lb_${type}_${from}_${payload.nonce}` };
+        const result = ledger.appendAsMaster(tx);
+        if (!result.accepted) throw new Error(`${type} rejected: ${result.reason}`);
+    };
+    const complete = (taskId, winner, amount, kind = 'award', timestamp = Date.now()) => {
+        append('transfer', wallet.accountId, `escrow_${taskId}`, amount);
+        append('escrow_release', `escrow_${taskId}`, winner, amount, { memo: `task:${taskId}:${kind}` }, timestamp);
+    };
+    try {
+        complete('task_lb_1', 'acct_lb_alice', 30);
+        complete('task_lb_2', 'acct_lb_bob', 50, 'award', Date.now() - 3600000);
+        complete('task_lb_3', 'acct_lb_carol', 20);
+        complete('task_lb_4', 'acct_lb_carol', 30);
+        complete('task_lb_5', 'acct_lb_alice', 20);
+        // 退款和回退账户的释放不算收益
+        complete('task_lb_refund', 'acct_lb_publisher', 500, 'refund');
+        complete('task_lb_fallback', 'acct_lb_fallback', 500, 'fallback');
+
+        const board = ledger.getLeaderboard();
+        const order = board.items.map(item => `${item.accountId}:${item.earnings}:${item.tasksCompleted}`);
+        // alice 与 carol 收益相同，完成数也相同时按账户排序；bob 收益相同但完成数少，排在后面
+        if (order.join(',') !== 'acct_lb_alice:50:2,acct_lb_carol:50:2,acct_lb_bob:50:1' || board.items[2].rank !== 3) {
+            throw new Error(`Unexpected ranking: ${order.join(',')}`);
+        }
+
+        // 同一链头在缓存期内返回缓存结果，新条目使缓存失效
+        const cachedAt = board.computedAt;
+        if (ledger.getLeaderboard({ now: cachedAt + 1000 }).computedAt !== cachedAt) {
+            throw new Error('Leaderboard should be served from cache while the ledger is unchanged');
+        }
+        complete('task_lb_6', 'acct_lb_bob', 5);
+        if (ledger.getLeaderboard({ now: cachedAt + 1000 }).items[0].accountId !== 'acct_lb_bob') {
+            throw new Error('A new ledger entry should invalidate the cached leaderboard');
+        }
+
+        // 时间窗口之外的收益不计入
+        const recent = ledger.getLeaderboard({ windowMs: 60000, limit: 1 });
+        if (recent.total !== 3 || recent.items.length !== 1 || recent.items[0].accountId !== 'acct_lb_alice') {
+            throw new Error(`Window should exclude older releases: ${JSON.stringify(recent.items)}`);
+        }
+
+        const accounts = new Map([['acct_lb_bob', { accountId: 'acct_lb_bob', nodeId: 'node_lb_bob' }]]);
+        const web = new WebUIServer({ port: 0, mesh: { ledger, memoryStore: { accounts }, ratingStore: null } });
+        const viaApi = await callApi(web, { method: 'GET', url: '/api/leaderboard?limit=2', headers: {} });
+        if (viaApi.items?.length !== 2 || viaApi.items[0].nodeId !== 'node_lb_bob' || viaApi.items[1].nodeId !== null || viaApi.items[0].reputation !== null) {
+            throw new Error(`/api/leaderboard should return the ranking: ${JSON.stringify(viaApi)}`);
+        }
+    } finally {
+        ledger.close();
+    }
+});
+
+// 测试82: 账本链校验返回第一处断裂的位置
+runner.test('MemoryStore ledger verification - the first broken index is reported', async () => {
+    const fs = require('fs');
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/verify_ledger_' + Date.now(), { useLance: false });
+    await store.init();
+    store.credit('node_verify_a', 100);
+    store.credit('node_verify_b', 50);
+    store.debit('node_verify_a', 10, { reason: 'test' });
+    const intact = store.verifyPersistedLedger();
+    if (!intact.valid || intact.source !== 'disk' || intact.length !== store.ledger.length || intact.headHash !== store.getLedgerHeadHash()) {
+        throw new Error(`Intact ledger should verify: ${JSON.stringify(intact)}`);
+    }
+
+    // 篡改磁盘上的一条记录
+    const onDisk = JSON.parse(fs.readFileSync(store.getLedgerPath(), 'utf8'));
+    const tamperedIndex = onDisk.findIndex(entry => entry.type === 'credit' && entry.nodeId === 'node_verify_b');
+    onDisk[tamperedIndex].amount = 5000;
+    fs.writeFileSync(store.getLedgerPath(), JSON.stringify(onDisk));
+    const broken = store.verifyPersistedLedger();
+    if (broken.valid || broken.brokenIndex !== tamperedIndex || broken.reason !== 'hash mismatch' || broken.headHash !== onDisk[tamperedIndex - 1].hash) {
+        throw new Error(`Tampered entry should be reported: ${JSON.stringify(broken)}`);
+    }
+
+    // 删除一条记录后，后续记录的 index 不再连续
+    const gapped = store.ledger.filter((_, i) => i !== 1);
+    const gap = store.verifyLedger(gapped);
+    if (gap.valid || gap.brokenIndex !== 1 || gap.reason !== 'index mismatch') {
+        throw new Error(`Missing entry should break the chain: ${JSON.stringify(gap)}`);
+    }
+
+    const web = new WebUIServer({ port: 0, mesh: { memoryStore: store } });
+    const viaApi = await callApi(web, { method: 'GET', url: '/api/ledger/verify', headers: {} });
+    if (viaApi.valid !== false || viaApi.brokenIndex !== tamperedIndex) {
+        throw new Error(`/api/ledger/verify should report the broken index: ${JSON.stringify(viaApi)}`);
+    }
+
+    // SQLite tx_log 的链hash同样校验
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const wallet = loadOrCreateWallet(store.dataDir);
+    const ledger = new LedgerStore(store.dataDir);
+    ledger.init({ isGenesis: true, genesisAccountId: wallet.accountId, genesisSupply: 1000, genesisPublicKeyPem: wallet.publicKeyPem, genesisPrivateKeyPem: wallet.privateKeyPem });
+    try {
+        const withTxLog = store.verifyPersistedLedger(ledger);
+        if (!withTxLog.txLog?.valid || withTxLog.txLog.length !== ledger.getLastSeq() || withTxLog.txLog.headHash !== ledger.getHeadHash()) {
+            throw new Error(`Intact tx_log should verify: ${JSON.stringify(withTxLog.txLog)}`);
+        }
+        ledger.db.prepare('UPDATE tx_log SET tx_id = ? WHERE seq = 1').run('tx_forged');
+        const offline = new LedgerStore(store.dataDir);
+        if (!offline.openExisting()) {
+            throw new Error('Existing ledger database should open for verification');
+        }
+        const forged = offline.verifyChain();
+        offline.close();
+        if (forged.valid || forged.brokenSeq !== 1 || forged.reason !== 'head hash mismatch' || forged.headHash !== '') {
+            throw new Error(`Edited tx_log row should break the chain: ${JSON.stringify(forged)}`);
+        }
+        const web2 = new WebUIServer({ port: 0, mesh: { memoryStore: store, ledger } });
+        const viaApi2 = await callApi(web2, { method: 'GET', url: '/api/ledger/verify', headers: {} });
+        if (viaApi2.valid !== false || viaApi2.txLog?.brokenSeq !== 1) {
+            throw new Error(`/api/ledger/verify should report the broken tx_log seq: ${JSON.stringify(viaApi2)}`);
+        }
+    } finally {
+        ledger.close();
+    }
+});
+
+// 测试83: 配置了 schema 的入站胶囊/任务不符合时既不交给上层也不转发
+runner.test('MeshNode inbound schema - non-conforming capsules and tasks are rejected', () => {
+    const { validateSchema } = require('../src/schema');
+    const capsuleSchema = {
+        type: 'object',
+        required: ['asset_id', 'content'],
+        properties: {
+            asset_id: { type: 'string', pattern: '^sha256:' },
+            confidence: { type: 'number', minimum: 0, maximum: 1 },
+            tags: { type: 'array', items: { type: 'string' } }
+        }
+    };
+    const taskSchema = {
+        type: 'object',
+        required: ['taskId', 'description'],
+        properties: {
+            description: { type: 'string', minLength: 5 },
+            bounty: { type: 'object', required: ['amount'], properties: { amount: { type: 'integer', minimum: 1 } } }
+        },
+        additionalProperties: true
+    };
+    const node = new MeshNode({ nodeId: 'node_schema', messageSchemas: { capsule: capsuleSchema, task: taskSchema } });
+    const capsules = [];
+    const tasks = [];
+    const relayed = [];
+    node.on('memory:received', capsule => capsules.push(capsule.asset_id));
+    node.on('task:received', task => tasks.push(task.taskId));
+    node.relayMessage = (message) => relayed.push(message.messageId);
+    let seq = 0;
+    const deliver = (type, payload) => node.handleMessage({ type, payload, messageId: `msg_schema_${seq++}`, hopsLeft: 3 }, 'node_schema_peer');
+
+    deliver('capsule', { asset_id: 'sha256:ok', content: {}, confidence: 0.9, tags: ['a'] });
+    deliver('capsule', { asset_id: 'sha256:no_content', confidence: 0.9 });
+    deliver('capsule', { asset_id: 'md5:wrong', content: {}, tags: [1] });
+    deliver('task', { taskId: 'task_schema_ok', description: 'Summarise logs', bounty: { amount: 10 } });
+    deliver('task', { taskId: 'task_schema_bad', description: 'hi', bounty: { amount: 1.5 } });
+    // 未开启压测模式时 bench 字段不能绕过 schema，任务消息更不行
+    deliver('capsule', { asset_id: 'md5:bench', bench: { benchId: 'b', seq: 1, origin: 'node_x' } });
+    deliver('task', { taskId: 'task_schema_bench', description: 'hi', bench: { benchId: 'b' } });
+
+    if (capsules.join(',') !== 'sha256:ok' || tasks.join(',') !== 'task_schema_ok') {
+        throw new Error(`Only conforming payloads should reach the upper layer: ${JSON.stringify({ capsules, tasks })}`);
+    }
+    if (relayed.length !== 2 || node.getRelayStats().dropped.schema !== 5) {
+        throw new Error(`Rejected payloads should not be relayed: ${JSON.stringify({ relayed, dropped: node.getRelayStats().dropped })}`);
+    }
+
+    const benchNode = new MeshNode({ nodeId: 'node_schema_bench', messageSchemas: { capsule: capsuleSchema, task: taskSchema }, benchMode: true });
+    benchNode.relayMessage = () => {};
+    benchNode.ackBenchCapsule = () => {};
+    const benchTasks = [];
+    benchNode.on('task:received', task => benchTasks.push(task.taskId));
+    benchNode.handleMessage({ type: 'capsule', payload: { asset_id: 'md5:bench', bench: { benchId: 'b', seq: 1, origin: 'node_x' } }, messageId: 'msg_schema_bench_1', hopsLeft: 3 }, 'node_schema_peer');
+    benchNode.handleMessage({ type: 'task', payload: { taskId: 'task_schema_bench', description: 'hi', bench: {} }, messageId: 'msg_schema_bench_2', hopsLeft: 3 }, 'node_schema_peer');
+    if (benchNode.getRelayStats().dropped.schema !== 1 || benchTasks.length !== 0) {
+        throw new Error('In bench mode only bench capsules should skip the schema check');
+    }
+
+    const errors = validateSchema(taskSchema, { taskId: 7, description: 'hi', bounty: { amount: 1.5 } });
+    if (errors.join('|') !== '$.description: shorter than 5|$.bounty.amount: expected integer, got number') {
+        throw new Error(`Unexpected validation errors: ${errors.join('|')}`);
+    }
+
+    // 未配置 schema 时全部接受
+    const open = new MeshNode({ nodeId: 'node_schema_open' });
+    const received = [];
+    open.on('memory:received', capsule => received.push(capsule));
+    open.relayMessage = () => {};
+    open.handleMessage({ type: 'capsule', payload: { anything: true }, messageId: 'msg_schema_open', hopsLeft: 3 }, 'node_schema_peer');
+    if (received.length !== 1) {
+        throw new Error('Without schemas every payload should be accepted');
+    }
+});
+
+// 测试84: 修复篡改了最后一条记录的账本：备份原文件，隔离尾部，余额按剩余记录重算
+runner.test('MemoryStore ledger repair - a tampered tail is quarantined and balances recomputed', async () => {
+    const fs = require('fs');
+    const dataDir = TEST_CONFIG.dataDir + '/repair_ledger_' + Date.now();
+    const store = new MemoryStore(dataDir, { useLance: false });
+    await store.init();
+    store.credit('node_repair', 100);
+    store.credit('node_repair', 40);
+    const accountId = store.accountIndex.get('node_repair');
+    const intact = await store.repairLedger();
+    if (intact.repaired !== false) {
+        throw new Error('An intact ledger should not be repaired');
+    }
+
+    const onDisk = JSON.parse(fs.readFileSync(store.getLedgerPath(), 'utf8'));
+    onDisk[onDisk.length - 1].amount = 4000;
+    const tamperedText = JSON.stringify(onDisk);
+    fs.writeFileSync(store.getLedgerPath(), tamperedText);
+
+    const result = await store.repairLedger();
+    if (!result.repaired || result.kept !== onDisk.length - 1 || result.quarantined !== 1 || result.reason !== 'hash mismatch') {
+        throw new Error(`Unexpected repair result: ${JSON.stringify(result)}`);
+    }
+    if (fs.readFileSync(result.backupPath, 'utf8') !== tamperedText) {
+        throw new Error('The original ledger should be backed up unchanged');
+    }
+    const quarantined = JSON.parse(fs.readFileSync(result.quarantinePath, 'utf8'));
+    if (quarantined.length !== 1 || quarantined[0].amount !== 4000) {
+        throw new Error('The corrupt tail should be quarantined');
+    }
+    if (result.balances[accountId] !== 100 || store.computeBalance(accountId) !== 100) {
+        throw new Error(`Balance should be recomputed from the kept prefix: ${JSON.stringify(result.balances)}`);
+    }
+    const after = store.verifyPersistedLedger();
+    if (!after.valid || after.length !== result.kept || after.headHash !== result.headHash || result.headHash !== onDisk[onDisk.length - 2].hash) {
+        throw new Error(`Repaired ledger should verify with the rebuilt head: ${JSON.stringify(after)}`);
+    }
+
+    // 新记录接在修复后的链头之后
+    store.credit('node_repair', 5);
+    if (!store.verifyPersistedLedger().valid || store.computeBalance(accountId) !== 105) {
+        throw new Error('Appends after repair should chain off the repaired head');
+    }
+});
+
+// 测试85: 发布者取消 open 任务并退回托管，取消通知经签名校验后在其他节点生效
+runner.test('Task cancellation - publisher cancels an open task and peers apply the signed notice', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/cancel_' + Date.now();
+    fs.mkdirSync(dataDir + '/remote', { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const store = new MemoryStore(dataDir + '/store', { useLance: false });
+    await store.init();
+
+    const broadcasts = [];
+    const makeMesh = (nodeId, bazaarDir, memoryStore, meshWallet) => {
+        const mesh = {
+            options: { nodeId, isGenesisNode: false },
+            wallet: meshWallet,
+            taskBazaar: new TaskBazaar({ nodeId, memoryStore: null, dataDir: bazaarDir }),
+            memoryStore,
+            node: { broadcast: (message) => broadcasts.push(message) },
+            taskWorker: { biddingTasks: new Map() }
+        };
+        for (const name of ['cancelTask', 'applyTaskCancellation', 'refundCancelledTask']) {
+            mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+        }
+        return mesh;
+    };
+    const publisher = makeMesh('node_cancel_pub', dataDir, store, wallet);
+    const remote = makeMesh('node_cancel_remote', dataDir + '/remote', { refundEscrow: () => ({ refunded: 0 }) }, null);
+
+    const cancelId = 'task_cancel_' + Date.now();
+    const taskId = await publisher.taskBazaar.publishTask({
+        taskId: cancelId,
+        description: 'Cancel me',
+        bounty: { amount: 0 },
+        publisherAccountId: wallet.accountId,
+        escrowProof: signEscrowProof(wallet, cancelId)
+    });
+    const assignedId = await publisher.taskBazaar.publishTask({
+        description: 'Already assigned',
+        bounty: { amount: 0 },
+        publisherAccountId: wallet.accountId
+    });
+    publisher.taskBazaar.updateTask(assignedId, { status: 'assigned' });
+    remote.taskBazaar.tasks.set(taskId, { ...publisher.taskBazaar.getTask(taskId) });
+    remote.taskWorker.biddingTasks.set(taskId, {});
+    store.credit('node_cancel_pub', 100);
+    store.lockEscrow(taskId, 'node_cancel_pub', 60);
+
+    if (publisher.taskBazaar.cancelTask(taskId, 'node_cancel_other').success) {
+        throw new Error('Only the publisher may cancel');
+    }
+    let assignedError = null;
+    try {
+        publisher.cancelTask(assignedId);
+    } catch (e) {
+        assignedError = e.message;
+    }
+    if (!/assigned/.test(assignedError || '')) {
+        throw new Error(`An assigned task should not be cancellable: ${assignedError}`);
+    }
+
+    const result = publisher.cancelTask(taskId, { reason: 'no bids' });
+    const task = publisher.taskBazaar.getTask(taskId);
+    if (result.status !== 'cancelled' || task.cancelledBy !== 'node_cancel_pub' || task.escrowDisposition.to !== wallet.accountId) {
+        throw new Error(`Task should be cancelled with a refund disposition: ${JSON.stringify(task)}`);
+    }
+    const accountId = store.accountIndex.get('node_cancel_pub');
+    if (result.refund.refunded !== 60 || store.computeBalance(accountId) !== 100 || store.escrows.has(taskId)) {
+        throw new Error(`Escrow should be refunded to the publisher: ${JSON.stringify(result.refund)}`);
+    }
+    const [notice] = broadcasts;
+    if (notice?.type !== 'task_cancelled' || notice.payload.statement.taskId !== taskId) {
+        throw new Error('A task_cancelled message should be broadcast');
+    }
+
+    // 篡改后的通知被拒绝，原始通知在其他节点生效
+    const forged = { ...notice.payload, statement: { ...notice.payload.statement, reason: 'forged' } };
+    if (remote.applyTaskCancellation(forged).success || remote.taskBazaar.getTask(taskId).status !== 'open') {
+        throw new Error('A forged cancellation should be rejected');
+    }
+    const applied = remote.applyTaskCancellation(notice.payload);
+    const remoteTask = remote.taskBazaar.getTask(taskId);
+    if (!applied.success || remoteTask.status !== 'cancelled' || remoteTask.cancelReason !== 'no bids' || remote.taskWorker.biddingTasks.has(taskId)) {
+        throw new Error(`Remote node should apply the signed cancellation: ${JSON.stringify(applied)}`);
+    }
+});
+
+// 测试86: 发布者离线时，排名第一的节点在宽限期后宣布过期，主节点据此退款且只退一次
+runner.test('Task expiry authority - a fallback node expires an orphaned task and escrow is refunded once', async () => {
+    const stamp = Date.now();
+    const grace = 30000;
+    const makeMesh = (name, extra) => new OpenClawMesh({
+        ...TEST_CONFIG,
+        nodeId: `node_expiry_${name}_${stamp}`,
+        dataDir: `${TEST_CONFIG.dataDir}/expiry_${name}_${stamp}`,
+        taskPublishFee: 0,
+        taskExpiryIntervalMs: 0,
+        taskExpiryGraceMs: grace,
+        ...extra
+    });
+    const genesis = makeMesh('genesis', { isGenesisNode: true, webPort: 9986 });
+    const worker = makeMesh('worker', { webPort: 9985 });
+    await genesis.init();
+    await worker.init();
+    const link = (a, b) => {
+        a.node.peers.set(b.options.nodeId, {
+            writable: true,
+            destroyed: false,
+            write(line) {
+                setImmediate(() => {
+                    if (!this.destroyed) b.node.handleMessage(JSON.parse(line), a.options.nodeId, null);
+                });
+            },
+            destroy() { this.destroyed = true; }
+        });
+    };
+    link(genesis, worker);
+    link(worker, genesis);
+    const links = [genesis.node.peers.get(worker.options.nodeId), worker.node.peers.get(genesis.options.nodeId)];
+    const settle = () => new Promise(resolve => setTimeout(resolve, 50));
+    try {
+        const accountId = genesis.wallet.accountId;
+        const before = genesis.ledger.getBalance(accountId);
+        // 截止时间设在过去，收到通知的节点按真实时间校验；扫描时间由参数模拟
+        const deadlineMs = Date.now() - 10 * grace;
+        // 以离线节点的名义发布，发布者不会参与过期
+        const { taskId } = await genesis.publishTask({
+            description: 'orphaned task',
+            bounty: { amount: 8, token: 'CLAW' },
+            deadline: new Date(deadlineMs).toISOString(),
+            publisher: 'node_expiry_offline_publisher'
+        });
+        await settle();
+        if (!worker.taskBazaar.getTask(taskId)) {
+            throw new Error('Worker should have received the task');
+        }
+
+        // 宽限期内，发布者之外的节点都不能宣布过期
+        if (genesis.expireTasks(deadlineMs + 1).length + worker.expireTasks(deadlineMs + 1).length !== 0) {
+            throw new Error('Nobody but the publisher may expire a task before the grace period');
+        }
+        // 参与者（投标节点）先于其他节点接手，接手时间不随连接情况变化
+        const probe = { ...genesis.taskBazaar.getTask(taskId), bids: [{ nodeId: worker.options.nodeId, amount: 1 }] };
+        if (worker.getExpiryTakeoverAt(probe) !== deadlineMs + grace
+            || genesis.getExpiryTakeoverAt(probe) !== worker.getExpiryTakeoverAt(probe, genesis.options.nodeId)
+            || genesis.getExpiryTakeoverAt(probe) < deadlineMs + 2 * grace) {
+            throw new Error('Bidders should rank before other nodes, and every node should agree on the order');
+        }
+
+        const task = genesis.taskBazaar.getTask(taskId);
+        const first = genesis.getExpiryTakeoverAt(task) < worker.getExpiryTakeoverAt(task) ? genesis : worker;
+        const second = first === genesis ? worker : genesis;
+        const afterGrace = first.getExpiryTakeoverAt(task);
+        if (second.isExpiryAuthority(second.taskBazaar.getTask(taskId), afterGrace)) {
+            throw new Error('Exactly one node should hold expiry authority when the first one takes over');
+        }
+        if (second.expireTasks(afterGrace).length !== 0) {
+            throw new Error('The second-ranked node should wait another grace period');
+        }
+        const expired = first.expireTasks(afterGrace);
+        await settle();
+        if (expired.length !== 1 || expired[0].expiredBy !== first.options.nodeId) {
+            throw new Error('The first-ranked node should expire the task');
+        }
+        for (const mesh of [genesis, worker]) {
+            const task = mesh.taskBazaar.getTask(taskId);
+            if (task.status !== 'expired' || task.expiredBy !== first.options.nodeId) {
+                throw new Error(`${mesh.options.nodeId} should converge on the expiry: ${task.status}`);
+            }
+        }
+        const refund = genesis.taskBazaar.getTask(taskId).escrowDisposition;
+        if (!refund.accepted || refund.type !== 'refund' || genesis.ledger.getBalance(accountId) !== before) {
+            throw new Error(`Escrow should be refunded to the publisher account: ${JSON.stringify(refund)}`);
+        }
+
+        // 后续的扫描和重复的通知都不会再次退款
+        const lastSeq = genesis.ledger.getLastSeq();
+        second.expireTasks(deadlineMs + 3 * grace);
+        genesis.applyTaskExpiry({ taskId, expiredAt: afterGrace, expiredBy: 'node_expiry_replay' });
+        await settle();
+        if (genesis.ledger.getLastSeq() !== lastSeq || genesis.ledger.getBalance(accountId) !== before) {
+            throw new Error('Escrow must not be refunded twice');
+        }
+    } finally {
+        // 关闭前断开模拟链路，避免排队中的消息送到已关闭的账本
+        links.forEach(socket => socket.destroy());
+        await worker.stop();
+        await genesis.stop();
+    }
+});
+
+// 测试87: deadlineMs 换算成截止时间，过期后由发布者退回本地托管并广播 task_expired
+runner.test('Task deadlineMs - an expired task refunds the local escrow on the publisher only', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/deadline_' + Date.now();
+    const store = new MemoryStore(dataDir + '/store', { useLance: false });
+    await store.init();
+    const broadcasts = [];
+    const makeMesh = (nodeId) => {
+        const mesh = {
+            options: { nodeId, isGenesisNode: false, taskExpiryGraceMs: 60000 },
+            taskBazaar: new TaskBazaar({ nodeId, memoryStore: null, dataDir: `${dataDir}/${nodeId}` }),
+            memoryStore: store,
+            node: { broadcast: (message) => broadcasts.push({ from: nodeId, message }), getConnectedNodeIds: () => [] }
+        };
+        for (const name of ['expireTasks', 'isExpiryAuthority', 'getExpiryTakeoverAt', 'refundExpiredTask']) {
+            mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+        }
+        return mesh;
+    };
+    require('fs').mkdirSync(dataDir + '/node_deadline_pub', { recursive: true });
+    require('fs').mkdirSync(dataDir + '/node_deadline_peer', { recursive: true });
+    const publisher = makeMesh('node_deadline_pub');
+    const peer = makeMesh('node_deadline_peer');
+
+    const publishedAt = Date.now();
+    const taskId = await publisher.taskBazaar.publishTask({
+        description: 'short-lived task',
+        bounty: { amount: 0 },
+        published_at: publishedAt,
+        deadlineMs: 5000,
+        publisherAccountId: 'acct_deadline_pub'
+    });
+    const task = publisher.taskBazaar.getTask(taskId);
+    if (task.deadline !== publishedAt + 5000) {
+        throw new Error(`deadlineMs should set the deadline: ${task.deadline}`);
+    }
+    let invalid = null;
+    try {
+        await publisher.taskBazaar.publishTask({ description: 'bad deadline', bounty: { amount: 0 }, deadlineMs: -1 });
+    } catch (e) {
+        invalid = e.message;
+    }
+    if (!/deadlineMs/.test(invalid || '')) {
+        throw new Error('A non-positive deadlineMs should be rejected');
+    }
+
+    peer.taskBazaar.tasks.set(taskId, { ...task });
+    store.credit('node_deadline_pub', 50);
+    store.lockEscrow(taskId, 'node_deadline_pub', 20);
+    const accountId = store.accountIndex.get('node_deadline_pub');
+
+    if (publisher.expireTasks(publishedAt + 4000).length !== 0) {
+        throw new Error('Tasks should not expire before deadlineMs elapses');
+    }
+    // 宽限期内非发布者不处理，也不会退回发布者的托管
+    if (peer.expireTasks(publishedAt + 6000).length !== 0 || !store.escrows.has(taskId)) {
+        throw new Error('Only the publisher should sweep before the grace period');
+    }
+    const [expired] = publisher.expireTasks(publishedAt + 6000);
+    if (expired?.status !== 'expired' || store.escrows.has(taskId) || store.computeBalance(accountId) !== 50) {
+        throw new Error('The publisher should expire the task and refund its escrow');
+    }
+    if (!store.ledger.some(entry => entry.type === 'escrow_refunded' && entry.taskId === taskId)) {
+        throw new Error('The refund should be recorded in the ledger');
+    }
+    const notice = broadcasts.find(b => b.message.type === 'task_expired');
+    if (notice?.from !== 'node_deadline_pub' || notice.message.payload.taskId !== taskId) {
+        throw new Error('task_expired should be broadcast by the publisher');
+    }
+    if (publisher.expireTasks(publishedAt + 600000).length !== 0 || store.computeBalance(accountId) !== 50) {
+        throw new Error('An expired task should not be refunded twice');
+    }
+});
+
+// 测试88: 入站与出站连接都按配置设置 TCP_NODELAY 和 keepalive
+runner.test('MeshNode socket options - nodelay and keepalive are applied to inbound and outbound sockets', async () => {
+    const EventEmitter = require('events');
+    const net = require('net');
+    const fakeSocket = () => {
+        const socket = new EventEmitter();
+        Object.assign(socket, { remoteAddress: '10.0.0.7', remotePort: 41000, writable: true, destroyed: false, calls: [] });
+        socket.setNoDelay = (value) => socket.calls.push(['noDelay', value]);
+        socket.setKeepAlive = (enable, delay) => socket.calls.push(['keepAlive', enable, delay]);
+        socket.write = () => true;
+        socket.destroy = () => { socket.destroyed = true; };
+        return socket;
+    };
+
+    const defaults = new MeshNode({ nodeId: 'node_sockopt_default' });
+    const inbound = fakeSocket();
+    defaults.handleConnection(inbound);
+    if (JSON.stringify(inbound.calls) !== JSON.stringify([['noDelay', true], ['keepAlive', true, 60000]])) {
+        throw new Error(`Defaults should enable nodelay and keepalive: ${JSON.stringify(inbound.calls)}`);
+    }
+
+    const tuned = new MeshNode({ nodeId: 'node_sockopt_tuned', tcpNoDelay: false, tcpKeepAliveMs: 0 });
+    const tunedSocket = fakeSocket();
+    tuned.handleConnection(tunedSocket);
+    if (JSON.stringify(tunedSocket.calls) !== JSON.stringify([['noDelay', false], ['keepAlive', false, undefined]])) {
+        throw new Error(`Options should be configurable: ${JSON.stringify(tunedSocket.calls)}`);
+    }
+
+    // 出站连接使用真实套接字
+    const accepted = [];
+    const server = net.createServer(socket => {
+        socket.on('error', () => {});
+        accepted.push(socket);
+    });
+    await new Promise(resolve => server.listen(0, '127.0.0.1', resolve));
+    const dialer = new MeshNode({ nodeId: 'node_sockopt_dialer', tcpKeepAliveMs: 15000 });
+    const applied = [];
+    const original = dialer.applySocketOptions.bind(dialer);
+    dialer.applySocketOptions = (socket) => {
+        original(socket);
+        applied.push(socket.meshSocketOptions);
+    };
+    const address = `127.0.0.1:${server.address().port}`;
+    try {
+        await dialer.connectToPeer(address);
+        if (applied.length !== 1 || applied[0].noDelay !== true || applied[0].keepAliveMs !== 15000) {
+            throw new Error(`Outbound socket options not applied: ${JSON.stringify(applied)}`);
+        }
+    } finally {
+        dialer.reconnectPeers = false;
+        dialer.stopping = true;
+        dialer.peers.get(address)?.destroy();
+        accepted.forEach(socket => socket.destroy());
+        await new Promise(resolve => server.close(resolve));
+    }
+});
+
+// 测试89: 出价策略与标签过滤
+runner.test('TaskWorker bid strategy - fixed, random and match-tags pricing with tag filtering', async () => {
+    const task = { taskId: 'task_bid_strategy', bounty: { amount: 1000 }, tags: ['Rust', 'wasm'] };
+
+    const fixed = new TaskWorker({ options: { nodeId: 'node_fixed', bidStrategy: 'fixed', bidPercent: 0.8 } });
+    if (fixed.computeBidAmount(task) !== 800) {
+        throw new Error(`Fixed strategy should bid 80%: ${fixed.computeBidAmount(task)}`);
+    }
+    const fallback = new TaskWorker({ options: { nodeId: 'node_unknown', bidStrategy: 'bogus' } });
+    if (fallback.bidStrategy !== 'fixed' || fallback.computeBidAmount(task) !== 900) {
+        throw new Error('Unknown strategy should fall back to fixed 90%');
+    }
+
+    const random = new TaskWorker({ options: { nodeId: 'node_random', bidStrategy: 'random', bidMinPercent: 0.5, bidMaxPercent: 0.6 } });
+    for (let i = 0; i < 20; i++) {
+        const amount = random.computeBidAmount(task);
+        if (amount < 500 || amount > 600) {
+            throw new Error(`Random bid out of range: ${amount}`);
+        }
+    }
+
+    const options = { bidStrategy: 'match-tags', bidMinPercent: 0.6, bidMaxPercent: 0.9 };
+    const specialist = new TaskWorker({ options: { ...options, nodeId: 'node_spec', workerTags: ['rust', 'WASM'] } });
+    const partial = new TaskWorker({ options: { ...options, nodeId: 'node_partial', workerTags: ['rust', 'python'] } });
+    const outsider = new TaskWorker({ options: { ...options, nodeId: 'node_out', workerTags: ['python'] } });
+    if (specialist.computeBidAmount(task) !== 600 || partial.computeBidAmount(task) !== 750) {
+        throw new Error(`Match-tags should price by overlap: ${specialist.computeBidAmount(task)} / ${partial.computeBidAmount(task)}`);
+    }
+    if (outsider.matchesWorkerTags(task) || !outsider.matchesWorkerTags({ ...task, tags: [] })) {
+        throw new Error('Disjoint tags should be skipped, untagged tasks should not');
+    }
+
+    // checkTasks 只对标签相交的任务出价
+    const bids = [];
+    outsider.mesh = {
+        options: outsider.mesh.options,
+        taskBazaar: {
+            getTasks: () => [
+                { ...task, status: 'open' },
+                { taskId: 'task_python', bounty: { amount: 100 }, tags: ['python'], status: 'open' }
+            ],
+            addBid: (taskId, bid) => {
+                bids.push({ taskId, ...bid });
+                return { accepted: true };
+            }
+        }
+    };
+    await outsider.checkTasks();
+    if (bids.length !== 1 || bids[0].taskId !== 'task_python' || bids[0].amount !== 60) {
+        throw new Error(`Only the matching task should get a bid: ${JSON.stringify(bids)}`);
+    }
+});
+
+// 测试90: 严格标签匹配要求覆盖任务的全部标签
+runner.test('TaskWorker requireAllTags - strict matching needs every task tag', async () => {
+    const gpuTask = { taskId: 'task_gpu', bounty: { amount: 100 }, tags: ['gpu', 'python'] };
+    const loose = new TaskWorker({ options: { nodeId: 'node_loose', workerTags: ['python'] } });
+    const strict = new TaskWorker({ options: { nodeId: 'node_strict', workerTags: ['python'], requireAllTags: true } });
+    const capable = new TaskWorker({ options: { nodeId: 'node_capable', workerTags: ['GPU', 'python', 'cuda'], requireAllTags: true } });
+    const untagged = new TaskWorker({ options: { nodeId: 'node_untagged', requireAllTags: true } });
+    if (!loose.matchesWorkerTags(gpuTask)) {
+        throw new Error('Overlap mode should accept a partial match');
+    }
+    if (strict.matchesWorkerTags(gpuTask) || untagged.matchesWorkerTags(gpuTask)) {
+        throw new Error('Strict mode should reject a task with an uncovered tag');
+    }
+    if (!capable.matchesWorkerTags(gpuTask)) {
+        throw new Error('Strict mode should accept when all tags are covered');
+    }
+    if (!strict.matchesWorkerTags({ ...gpuTask, tags: [] }) || !untagged.matchesWorkerTags({ ...gpuTask, tags: undefined })) {
+        throw new Error('Untagged tasks should always match');
+    }
+});
+
+// 测试91: 胶囊导出清单可发现篡改与截断
+runner.test('MemoryStore capsule export - manifest verifies bundle and catches tampering', async () => {
+    const crypto = require('crypto');
+    const { accountIdFromPublicKey } = require('../src/wallet');
+    const { publicKey, privateKey } = crypto.generateKeyPairSync('ed25519');
+    const signer = {
+        publicKeyPem: publicKey.export({ type: 'spki', format: 'pem' }),
+        privateKeyPem: privateKey.export({ type: 'pkcs8', format: 'pem' })
+    };
+    const base = TEST_CONFIG.dataDir + '/capsule_export_' + Date.now();
+    const source = new MemoryStore(base + '/source', { useLance: false, capsuleFlushIntervalMs: 0 });
+    await source.init();
+    for (const summary of ['parse retry', 'cache warmup', 'rate limiter']) {
+        await source.storeCapsule({ content: { capsule: { type: 'skill', summary, confidence: 0.8 } }, tags: ['export'] }, { ttlMs: 60000 });
+    }
+    const { bundle, manifest } = source.exportCapsules({ signer });
+    if (bundle.capsules.length !== 3 || manifest.count !== 3 || manifest.exporter !== accountIdFromPublicKey(signer.publicKeyPem)) {
+        throw new Error(`Unexpected export: ${JSON.stringify(manifest)}`);
+    }
+    if (bundle.capsules.some(capsule => capsule.retentionMs !== undefined)) {
+        throw new Error('Local retention should not be exported');
+    }
+
+    // 有效的包
+    const target = new MemoryStore(base + '/target', { useLance: false, capsuleFlushIntervalMs: 0 });
+    await target.init();
+    const exporter = accountIdFromPublicKey(signer.publicKeyPem);
+    const selfSigned = await target.importCapsules(JSON.parse(JSON.stringify(bundle)), manifest, { requireSignature: true });
+    if (selfSigned.valid || selfSigned.reason !== 'Signature required but no trusted exporters configured' || target.getCount() !== 0) {
+        throw new Error(`Requiring a signature without trusted exporters should be rejected: ${JSON.stringify(selfSigned)}`);
+    }
+    if (target.verifyCapsuleBundle(bundle, manifest, { requireSignature: true, trustedExporters: ['acct_someone_else'] }).valid) {
+        throw new Error('Untrusted exporter should be rejected');
+    }
+    const ok = await target.importCapsules(JSON.parse(JSON.stringify(bundle)), manifest, { requireSignature: true, trustedExporters: [exporter] });
+    if (!ok.valid || ok.imported !== 3 || ok.skipped !== 0 || target.getCount() !== 3) {
+        throw new Error(`Valid bundle should import: ${JSON.stringify(ok)}`);
+    }
+    const again = await target.importCapsules(JSON.parse(JSON.stringify(bundle)), manifest);
+    if (again.imported !== 0 || again.skipped !== 3) {
+        throw new Error('Existing capsules should be skipped');
+    }
+
+    // 被篡改的胶囊：内容与记录元数据
+    const empty = new MemoryStore(base + '/empty', { useLance: false, capsuleFlushIntervalMs: 0 });
+    await empty.init();
+    const corrupted = JSON.parse(JSON.stringify(bundle));
+    corrupted.capsules[1].content.capsule.summary = 'rm -rf /';
+    const bad = await empty.importCapsules(corrupted, manifest);
+    if (bad.valid || bad.imported !== 0 || empty.getCount() !== 0) {
+        throw new Error('Corrupted bundle should be rejected before applying');
+    }
+    if (bad.errors.length !== 1 || bad.errors[0].asset_id !== bundle.capsules[1].asset_id || bad.errors[0].reason !== 'Content hash mismatch') {
+        throw new Error(`Corrupted capsule should be reported: ${JSON.stringify(bad.errors)}`);
+    }
+    const relabelled = JSON.parse(JSON.stringify(bundle));
+    relabelled.capsules[0].attribution.creator = 'node_mallory';
+    if (empty.verifyCapsuleBundle(relabelled, manifest).errors[0]?.reason !== 'Record hash mismatch') {
+        throw new Error('Tampered metadata should fail the record hash');
+    }
+
+    // 截断、伪造清单、未签名
+    const truncated = { ...bundle, capsules: bundle.capsules.slice(0, 2) };
+    if (empty.verifyCapsuleBundle(truncated, manifest).valid) {
+        throw new Error('Truncated bundle should be rejected');
+    }
+    const forged = { ...manifest, count: 2, entries: manifest.entries.slice(0, 2) };
+    if (empty.verifyCapsuleBundle(truncated, forged).reason !== 'Manifest signature is invalid') {
+        throw new Error('Edited manifest should fail its signature');
+    }
+    const unsigned = source.exportCapsules();
+    if (!empty.verifyCapsuleBundle(unsigned.bundle, unsigned.manifest).valid
+        || empty.verifyCapsuleBundle(unsigned.bundle, unsigned.manifest, { requireSignature: true, trustedExporters: [exporter] }).valid) {
+        throw new Error('Unsigned manifests should only pass when signatures are optional');
+    }
+
+    await source.close();
+    await target.close();
+    await empty.close();
+});
+
+// 测试92: 卡住的 peer 的 gossip 被丢弃，队列不会无限增长
+runner.test('MeshNode send queue - stalled peer drops oldest gossip, keeps control messages, then disconnects', async () => {
+    const EventEmitter = require('events');
+    const node = new MeshNode({ nodeId: 'node_sendq', maxSendQueue: 10, sendQueueStallMs: 5000 });
+    const socket = new EventEmitter();
+    Object.assign(socket, { writable: true, destroyed: false, accepting: false, written: [] });
+    socket.write = (frame) => {
+        socket.written.push(JSON.parse(frame));
+        return socket.accepting;
+    };
+    socket.destroy = () => { socket.destroyed = true; };
+    node.peers.set('node_slow', socket);
+
+    node.sendToPeer('node_slow', { type: 'capsule', payload: { n: 0 } });
+    for (let n = 1; n <= 200; n++) {
+        node.sendToPeer('node_slow', { type: 'capsule', payload: { n } });
+        if (n === 100) node.sendToPeer('node_slow', { type: 'task_assigned', payload: { taskId: 'task_q' } });
+    }
+    const queue = socket.meshSendQueue;
+    if (socket.written.length !== 1 || queue.length !== 10) {
+        throw new Error(`Queue should stay bounded: written=${socket.written.length} queued=${queue.length}`);
+    }
+    if (!queue.some(item => item.message.type === 'task_assigned')) {
+        throw new Error('Task messages should never be dropped');
+    }
+    const gossip = queue.filter(item => item.message.type === 'capsule').map(item => item.message.payload.n);
+    if (gossip[gossip.length - 1] !== 200 || gossip[0] !== 192) {
+        throw new Error(`Oldest gossip should be dropped first: ${gossip}`);
+    }
+    const stats = node.getConnectionStats().sendQueues;
+    if (stats.dropped !== 191 || stats.droppedByPeer.node_slow !== 191 || stats.queued.node_slow !== 10) {
+        throw new Error(`Drops should be tracked per peer: ${JSON.stringify(stats)}`);
+    }
+
+    // drain 后按顺序发出
+    socket.accepting = true;
+    socket.emit('drain');
+    const flushed = socket.written.slice(1);
+    if (queue.length !== 0 || flushed.length !== 10 || flushed[8].payload.n !== 199 || socket.meshSendQueueFullSince) {
+        throw new Error('Queue should flush in order on drain');
+    }
+
+    // 队列持续满员超时后断开
+    socket.accepting = false;
+    node.sendToPeer('node_slow', { type: 'ping' });
+    for (let n = 0; n < 11; n++) node.sendToPeer('node_slow', { type: 'capsule', payload: { n } });
+    const fullSince = socket.meshSendQueueFullSince;
+    if (!fullSince || socket.destroyed) {
+        throw new Error('Queue should be marked full');
+    }
+    node.checkStalledSendQueues(fullSince + 4999);
+    if (socket.destroyed) {
+        throw new Error('Peer should not be disconnected before the timeout');
+    }
+    // 没有新消息入队时由心跳检查断开
+    node.checkStalledSendQueues(fullSince + 5000);
+    const after = node.getConnectionStats().sendQueues;
+    if (!socket.destroyed || node.peers.has('node_slow') || after.stalled !== 1) {
+        throw new Error('Peer stalled beyond the timeout should be disconnected');
+    }
+    if ('node_slow' in after.droppedByPeer || after.dropped !== 192) {
+        throw new Error(`Per-peer drop stats should be pruned on disconnect: ${JSON.stringify(after)}`);
+    }
+});
+
+// 测试93: 命令执行器产出解和产物，并接入任务完成流程
+runner.test('ShellExecutor - runs the task command and feeds completeTask', async () => {
+    const fsp = require('fs').promises;
+    const path = require('path');
+    const { ShellExecutor, buildCommandArgs } = require('../src/task-executor');
+    const base = path.resolve(TEST_CONFIG.dataDir, 'executor_' + Date.now());
+    const workDir = path.join(base, 'task_exec');
+    await fsp.mkdir(workDir, { recursive: true });
+    const script = path.join(base, 'solve.js');
+    await fsp.writeFile(script, [
+        "const fs = require('fs');",
+        "fs.writeFileSync('artifact.txt', process.argv[3]);",
+        "if (process.env.OPENCLAW_TASK_DESCRIPTION === 'fail') { console.error('no solver for this'); process.exit(3); }",
+        "console.log(JSON.stringify({ taskId: process.argv[2], description: process.env.OPENCLAW_TASK_DESCRIPTION }));"
+    ].join('\n'));
+
+    const task = { taskId: 'task_exec', description: 'sum; rm -rf /', tags: ['js', 'math'], bounty: { amount: 10 } };
+    const args = buildCommandArgs(`node "${script}" {taskId} {tags}`, task, workDir);
+    if (JSON.stringify(args) !== JSON.stringify(['node', script, 'task_exec', 'js,math'])) {
+        throw new Error(`Unexpected argv: ${JSON.stringify(args)}`);
+    }
+
+    const executor = new ShellExecutor({ command: `${process.execPath} ${script} {taskId} {tags}` });
+    const result = await executor.execute(task, workDir);
+    if (!result.success || result.solutionFile !== 'solution.json' || JSON.parse(result.solution).description !== 'sum; rm -rf /') {
+        throw new Error(`Command output should become the solution: ${JSON.stringify(result)}`);
+    }
+    const names = result.outputFiles.map(f => f.name).sort();
+    if (JSON.stringify(names) !== JSON.stringify(['artifact.txt', 'solution.json'])) {
+        throw new Error(`Artifacts should be collected: ${names}`);
+    }
+    const failed = await executor.execute({ ...task, description: 'fail' }, workDir);
+    if (failed.success || !failed.error.includes('no solver')) {
+        throw new Error(`Non-zero exit should fail with stderr: ${JSON.stringify(failed)}`);
+    }
+
+    // 远端字段：描述只走环境变量，替换后像选项的参数拒绝执行
+    let envOnly = null;
+    try {
+        new ShellExecutor({ command: `${process.execPath} ${script} {description}` });
+    } catch (e) {
+        envOnly = e.message;
+    }
+    if (!envOnly || !envOnly.includes('OPENCLAW_TASK_DESCRIPTION')) {
+        throw new Error('{description} should not be accepted in the command template');
+    }
+    const injected = await executor.execute({ ...task, tags: ['--eval=process.exit(0)'] }, workDir);
+    if (injected.success || !injected.error.includes('would be passed as an option')) {
+        throw new Error(`Option-like task fields should be refused: ${JSON.stringify(injected)}`);
+    }
+
+    // 超长输出完整写入 solution.txt，返回的解按上限截断
+    const loud = path.join(base, 'loud.js');
+    await fsp.writeFile(loud, "process.stdout.write('x'.repeat(200 * 1024));");
+    const loudDir = path.join(base, 'task_loud');
+    await fsp.mkdir(loudDir, { recursive: true });
+    const big = await new ShellExecutor({ command: `${process.execPath} ${loud}` }).execute({ taskId: 'task_loud' }, loudDir);
+    const bigFile = await fsp.stat(path.join(loudDir, 'solution.txt'));
+    if (!big.success || big.solution.length !== 64 * 1024 || big.solutionFile !== 'solution.txt' || bigFile.size !== 200 * 1024) {
+        throw new Error(`Large output should be capped in memory and kept in full on disk: ${big.solution?.length} / ${bigFile.size}`);
+    }
+
+    // 接入 TaskWorker：完成后产物移到 completed/<nodeId>_<taskId>/
+    const completions = [];
+    const worker = new TaskWorker({
+        options: { nodeId: 'node_exec', taskCommand: `${process.execPath} ${script} {taskId} {tags}` },
+        taskBazaar: { completeTask: (taskId, completion) => completions.push({ taskId, completion }) }
+    });
+    worker.workDir = path.join(base, 'workspace');
+    worker.createDownloadPackage = async (taskId, dir) => fsp.writeFile(path.join(dir, taskId + '.zip'), 'zip');
+    await worker.startWorkingOnTask({ ...task, description: 'real work' });
+    const completedDir = path.join(worker.workDir, 'completed', 'node_exec_task_exec');
+    const solution = JSON.parse(await fsp.readFile(path.join(completedDir, 'solution.json'), 'utf8'));
+    const manifest = JSON.parse(await fsp.readFile(path.join(completedDir, 'manifest.json'), 'utf8'));
+    if (solution.description !== 'real work' || manifest.processedBy !== 'shell') {
+        throw new Error('Worker should finalize the executor output');
+    }
+    await fsp.access(path.join(completedDir, 'task_exec.zip'));
+    if (completions.length !== 1 || completions[0].completion.result.source !== 'shell' || !worker.completedTasks.has('task_exec')) {
+        throw new Error('Executor result should be submitted through completeTask');
+    }
+});
+
+// 测试94: 评选模式收集多个解，由发布者评选后才释放托管
+runner.test('Task review mode - collects competing submissions and releases escrow only on award', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet, nodeIdFromPublicKey, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/review_' + Date.now();
+    fs.mkdirSync(dataDir + '/remote', { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const store = new MemoryStore(dataDir + '/store', { useLance: false });
+    await store.init();
+
+    const broadcasts = [];
+    const makeMesh = (nodeId, bazaarDir, memoryStore, meshWallet) => {
+        const mesh = {
+            options: { nodeId, isGenesisNode: false },
+            wallet: meshWallet,
+            taskBazaar: new TaskBazaar({ nodeId, memoryStore: null, dataDir: bazaarDir }),
+            memoryStore,
+            node: { broadcast: (message) => broadcasts.push(message) }
+        };
+        for (const name of ['submitSolution', 'awardTask', 'applyTaskSubmission', 'applyTaskAward', 'payoutAwardedTask']) {
+            mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+        }
+        return mesh;
+    };
+    const publisher = makeMesh('node_review_pub', dataDir, store, wallet);
+    const remote = makeMesh('node_review_remote', dataDir + '/remote', { releaseEscrow: () => ({ released: 0 }) }, null);
+
+    let rejected = false;
+    try {
+        await publisher.taskBazaar.publishTask({ description: 'Bad mode', bounty: { amount: 0 }, selectionMode: 'lottery' });
+    } catch (e) {
+        rejected = e.message.includes('selectionMode');
+    }
+    if (!rejected) throw new Error('Unknown selection modes should be rejected');
+
+    // 默认仍是第一个有效解获胜
+    const firstId = await publisher.taskBazaar.publishTask({ description: 'First wins', bounty: { amount: 0 } });
+    const first = await publisher.taskBazaar.submitSolution(firstId, { description: 'done' }, 'node_fast');
+    if (!first.winner || publisher.taskBazaar.getTask(firstId).status !== 'completed') {
+        throw new Error('First-valid mode should complete on the first solution');
+    }
+
+    rejected = false;
+    try {
+        await publisher.taskBazaar.publishTask({ description: 'No deadline', type: 'review', bounty: { amount: 0 } });
+    } catch (e) {
+        rejected = e.message.includes('deadline');
+    }
+    if (!rejected) throw new Error('Review tasks without a deadline should be rejected');
+
+    const reviewId = 'task_review_' + Date.now();
+    const taskId = await publisher.taskBazaar.publishTask({
+        taskId: reviewId,
+        description: 'Review me',
+        type: 'review',
+        bounty: { amount: 0 },
+        deadlineMs: 3600000,
+        publisherAccountId: wallet.accountId,
+        escrowProof: signEscrowProof(wallet, reviewId)
+    });
+    if (publisher.taskBazaar.getTask(taskId).selectionMode !== 'review') {
+        throw new Error('Review tasks should default to review mode');
+    }
+    store.credit('node_review_pub', 100);
+    store.lockEscrow(taskId, 'node_review_pub', 60);
+
+    // 两个解题节点提交（nodeId 由钱包公钥派生），广播的签名解在发布者处汇总
+    const solvers = [];
+    for (const name of ['solver_a', 'solver_b']) {
+        fs.mkdirSync(dataDir + '/' + name, { recursive: true });
+        const solverWallet = loadOrCreateWallet(dataDir + '/' + name);
+        const solverId = nodeIdFromPublicKey(solverWallet.publicKeyPem);
+        const solver = makeMesh(solverId, dataDir + '/' + name, null, solverWallet);
+        solver.taskBazaar.tasks.set(taskId, { ...publisher.taskBazaar.getTask(taskId), submissions: [] });
+        const result = await solver.submitSolution(taskId, { description: `answer from ${name}` });
+        if (!result.underReview || result.winner) {
+            throw new Error('Review submissions should not win immediately');
+        }
+        solvers.push({ solverId, wallet: solverWallet });
+    }
+    const [solverA, solverB] = solvers;
+    let unbound = false;
+    try {
+        await makeMesh('node_not_derived', dataDir + '/solver_a', null, solverA.wallet).submitSolution(taskId, { description: 'x' });
+    } catch (e) {
+        unbound = e.message.includes('derived');
+    }
+    if (!unbound) throw new Error('Review submissions should require a wallet-derived nodeId');
+    for (const message of broadcasts.filter(m => m.type === 'task_submission')) {
+        const result = await publisher.applyTaskSubmission(message.payload);
+        if (!result.success) throw new Error(`Signed submission should be collected: ${result.reason}`);
+    }
+
+    // 冒充其他解题节点、改写收款账户、或内容无效的解都被拒绝
+    const [signedA] = broadcasts.filter(m => m.type === 'task_submission').map(m => m.payload);
+    const hijack = { ...signedA, statement: { ...signedA.statement, accountId: solverB.wallet.accountId } };
+    const resigned = { ...signedA.statement, solution: { description: 'overwrite' } };
+    const impostor = { statement: resigned, publicKeyPem: solverB.wallet.publicKeyPem, signature: signPayload(solverB.wallet.privateKeyPem, resigned) };
+    const emptyStatement = { ...signedA.statement, solution: { note: 'no code or description' } };
+    const empty = { statement: emptyStatement, publicKeyPem: solverA.wallet.publicKeyPem, signature: signPayload(solverA.wallet.privateKeyPem, emptyStatement) };
+    for (const bad of [hijack, impostor, empty]) {
+        if ((await publisher.applyTaskSubmission(bad)).success) {
+            throw new Error('Unsigned, impersonated or invalid submissions should be rejected');
+        }
+    }
+    const collected = publisher.taskBazaar.getTask(taskId);
+    if (collected.status !== 'under_review' || collected.submissions.length !== 2
+        || collected.submissions[0].solution.description !== 'answer from solver_a'
+        || collected.submissions[1].accountId !== solverB.wallet.accountId) {
+        throw new Error(`Submissions should be collected: ${JSON.stringify(collected)}`);
+    }
+    if (!store.escrows.has(taskId) || publisher.taskBazaar.isExpirable(collected)) {
+        throw new Error('Escrow should stay locked while under review');
+    }
+    if (!publisher.taskBazaar.isExpirable(collected, collected.deadline)) {
+        throw new Error('An unawarded review task should expire at its deadline');
+    }
+
+    if (publisher.taskBazaar.awardTask(taskId, 'node_other', solverA.solverId).success
+        || publisher.taskBazaar.awardTask(taskId, 'node_review_pub', 'node_nobody').success) {
+        throw new Error('Only the publisher can award, and only to a submitter');
+    }
+    remote.taskBazaar.tasks.set(taskId, { ...collected });
+    const awarded = publisher.awardTask(taskId, solverB.solverId);
+    if (awarded.status !== 'completed' || awarded.winner !== solverB.solverId || awarded.payout.released !== 60) {
+        throw new Error(`Award should complete the task and release escrow: ${JSON.stringify(awarded)}`);
+    }
+    if (store.getBalance(solverB.solverId) !== 60 || store.escrows.has(taskId)) {
+        throw new Error('Winner should be paid from escrow');
+    }
+    if (publisher.taskBazaar.getTask(taskId).escrowDisposition.to !== solverB.wallet.accountId) {
+        throw new Error('Ledger escrow should go to the winner account');
+    }
+
+    const notice = broadcasts.find(m => m.type === 'task_awarded');
+    if (notice.payload.statement.accountId !== solverB.wallet.accountId) {
+        throw new Error('The signed award should carry the winner account');
+    }
+    const forged = { ...notice.payload, statement: { ...notice.payload.statement, winner: solverA.solverId } };
+    const redirected = { ...notice.payload, statement: { ...notice.payload.statement, accountId: 'acct_attacker' } };
+    if (remote.applyTaskAward(forged).success || remote.applyTaskAward(redirected).success) {
+        throw new Error('Forged awards should be rejected');
+    }
+    // 其他节点只按签名评选结果中的账户结算，不看本地保存的提交
+    remote.taskBazaar.getTask(taskId).submissions[1].accountId = 'acct_attacker';
+    if (!remote.applyTaskAward(notice.payload).success || remote.taskBazaar.getTask(taskId).winner !== solverB.solverId
+        || remote.taskBazaar.getTask(taskId).escrowDisposition.to !== solverB.wallet.accountId) {
+        throw new Error('Signed award should apply on other nodes');
+    }
+    await store.close();
+});
+
+// 测试95: 能力索引只返回匹配的任务，worker 通过索引取任务
+runner.test('TaskBazaar capability index - workers only see tasks matching their capabilities', async () => {
+    const fs = require('fs');
+    const dataDir = TEST_CONFIG.dataDir + '/capindex_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const bazaar = new TaskBazaar({ nodeId: 'node_capindex', memoryStore: null, dataDir, archiveAfterMs: 1000 });
+    const publish = (description, extra) => bazaar.publishTask({ description, bounty: { amount: 100 }, ...extra });
+    const gpuId = await publish('Train model', { tags: ['GPU', 'ml'] });
+    const webId = await publish('Build site', { tags: ['web'] });
+    const typedId = await publish('Port to wasm', { type: 'rust', tags: ['wasm'] });
+    const genericId = await publish('Anything', { tags: [] });
+    const ids = (tasks) => tasks.map(t => t.taskId).sort().join(',');
+
+    if (ids(bazaar.getTasksForCapabilities(['gpu'])) !== [gpuId, genericId].sort().join(',')) {
+        throw new Error('gpu capability should match the gpu task and untagged tasks');
+    }
+    if (ids(bazaar.getTasksForCapabilities(['Rust'], { includeUntagged: false })) !== typedId) {
+        throw new Error('Task type should be indexed as a capability');
+    }
+    bazaar.updateTask(webId, { tags: ['gpu'] });
+    bazaar.updateTask(gpuId, { status: 'assigned' });
+    if (ids(bazaar.getTasksForCapabilities(['gpu'], { status: 'open', includeUntagged: false })) !== webId
+        || bazaar.getTasksForCapabilities(['web']).some(t => t.taskId === webId)) {
+        throw new Error('Index should follow tag updates and filter by status');
+    }
+
+    // worker 使用索引，不再扫描全部任务
+    const worker = new TaskWorker({ options: { nodeId: 'node_gpu_worker', workerTags: ['gpu'] } });
+    const bids = [];
+    bazaar.getTasks = () => { throw new Error('Worker should not scan all tasks'); };
+    bazaar.verifyEscrowProof = () => ({ valid: true });
+    bazaar.addBid = (taskId) => {
+        bids.push(taskId);
+        return { accepted: true };
+    };
+    worker.mesh = { options: worker.mesh.options, taskBazaar: bazaar };
+    await worker.checkTasks();
+    if (bids.sort().join(',') !== [webId, genericId].sort().join(',')) {
+        throw new Error(`Worker should only bid on indexed matches: ${bids}`);
+    }
+
+    // 归档后移出索引
+    bazaar.updateTask(genericId, { status: 'completed', completedAt: Date.now() - 5000 });
+    if (bazaar.archiveTasks() !== 1 || bazaar.untaggedTaskIds.has(genericId) || bazaar.getTasksForCapabilities([]).length !== 0) {
+        throw new Error('Archived tasks should leave the index');
+    }
+});
+
+// 测试96: 乱序到达的过期状态更新被忽略
+runner.test('TaskBazaar versions - out-of-order stale status updates are ignored', async () => {
+    const fs = require('fs');
+    const dataDir = TEST_CONFIG.dataDir + '/taskversion_' + Date.now();
+    fs.mkdirSync(dataDir, { recursive: true });
+    const bazaar = new TaskBazaar({ nodeId: 'node_version', memoryStore: null, dataDir });
+    const taskId = await bazaar.publishTask({ description: 'Versioned', bounty: { amount: 0 } });
+    if (bazaar.getTask(taskId).version !== 1) {
+        throw new Error('New tasks should start at version 1');
+    }
+
+    // 只接受发布者或被分配节点的签名更新：未签名或其他节点发来的 failed 不能终结任务
+    if (bazaar.applyRemoteUpdate(taskId, { status: 'failed' }, 2).applied
+        || bazaar.applyRemoteUpdate(taskId, { status: 'failed' }, 2, 'node_evil').applied
+        || bazaar.applyRemoteUpdate(taskId, { status: 'assigned', assignedTo: 'node_evil' }, 2, 'node_evil').applied
+        || bazaar.getTask(taskId).status !== 'open') {
+        throw new Error('Status updates from unsigned or unrelated senders should be ignored');
+    }
+
+    // 先收到 completed，再收到更早发出的 assigned；版本号最多前进 1
+    const completed = bazaar.applyRemoteUpdate(taskId, { status: 'completed', completedBy: 'node_b' }, 3, 'node_version');
+    if (!completed.applied || completed.task.version !== 2) {
+        throw new Error(`Newer update should apply with a capped version: ${JSON.stringify(completed)}`);
+    }
+    const stale = bazaar.applyRemoteUpdate(taskId, { status: 'assigned', assignedTo: 'node_a' }, 2, 'node_version');
+    const task = bazaar.getTask(taskId);
+    if (stale.applied || task.status !== 'completed' || task.assignedTo || task.version !== 2) {
+        throw new Error(`Stale assigned update should be ignored: ${JSON.stringify(task)}`);
+    }
+    if (bazaar.applyRemoteUpdate(taskId, { status: 'completed', completedBy: 'node_old' }, 2, 'node_version').applied
+        || bazaar.getTask(taskId).completedBy !== 'node_b') {
+        throw new Error('Same-status updates that are not newer should be ignored');
+    }
+    if (bazaar.updateTask(taskId, { status: 'failed' }).version !== 3) {
+        throw new Error('Local status changes should bump the version');
+    }
+
+    // 夸大的版本号被截断，被分配节点之后的正常更新仍然生效
+    const assignedId = await bazaar.publishTask({ description: 'Inflated', bounty: { amount: 0 } });
+    const inflated = bazaar.applyRemoteUpdate(assignedId, { status: 'assigned', assignedTo: 'node_worker' }, 1e9, 'node_version');
+    if (!inflated.applied || inflated.task.version !== 2) {
+        throw new Error(`An inflated version should be capped: ${JSON.stringify(inflated.task)}`);
+    }
+    if (bazaar.applyRemoteUpdate(assignedId, { status: 'completed' }, 3, 'node_other').applied
+        || !bazaar.applyRemoteUpdate(assignedId, { status: 'completed' }, 3, 'node_worker').applied) {
+        throw new Error('Completion should be accepted only from the assigned node');
+    }
+});
+
+// 测试97: 信誉持久化，只计签名的被分配节点报告的真实状态转换，且不影响获胜者
+runner.test('RatingStore reputation - outcomes persist, count only signed assignee transitions and never pick the winner', async () => {
+    const fs = require('fs');
+    const RatingStore = require('../src/rating-store');
+    const dir = TEST_CONFIG.dataDir + '/reputation_' + Date.now();
+    fs.mkdirSync(dir, { recursive: true });
+    const ratingStore = new RatingStore(dir);
+    ratingStore.init();
+    ratingStore.recordOutcome('node_reliable', true);
+    ratingStore.recordOutcome('node_reliable', true);
+    ratingStore.recordOutcome('node_flaky', true);
+    ratingStore.recordOutcome('node_flaky', false);
+    ratingStore.close();
+
+    const reopened = new RatingStore(dir);
+    reopened.init();
+    if (reopened.getReputation('node_reliable') !== 2 || reopened.getReputation('node_flaky') !== 0 || reopened.getReputation('node_new') !== 0) {
+        throw new Error('Reputation should persist across restarts');
+    }
+    const listed = reopened.getReputations();
+    if (listed[0].nodeId !== 'node_reliable' || listed[1].failures !== 1) {
+        throw new Error(`Reputations should be listed by score: ${JSON.stringify(listed)}`);
+    }
+
+    const task = {
+        taskId: 'task_reputation',
+        bids: [{ nodeId: 'node_flaky', amount: 89 }, { nodeId: 'node_reliable', amount: 90 }]
+    };
+    const judge = new TaskWorker({ options: { nodeId: 'node_judge', reputationWeight: 1 }, ratingStore: reopened });
+    if (judge.determineWinner(task).nodeId !== 'node_flaky') {
+        throw new Error('The lowest bid should win regardless of local reputation');
+    }
+    reopened.close();
+
+    const EventEmitter = require('events');
+    const outcomes = [];
+    const mesh = new OpenClawMesh({ nodeId: 'node_rep_judge' });
+    mesh.node = new EventEmitter();
+    mesh.taskBazaar = new TaskBazaar({ nodeId: 'node_rep_judge', memoryStore: null, dataDir: dir });
+    mesh.ratingStore = {
+        recordOutcome: (nodeId, success) => outcomes.push([nodeId, success]),
+        recordCompletion: () => {},
+        recordFailure: () => {}
+    };
+    mesh.setupEventHandlers();
+    const settle = () => new Promise(resolve => setImmediate(resolve));
+    for (const taskId of ['task_rep_done', 'task_rep_failed']) {
+        mesh.taskBazaar.tasks.set(taskId, { taskId, publisher: 'node_rep_pub', status: 'assigned', assignedTo: 'node_rep_worker', version: 2 });
+    }
+
+    mesh.node.emit('task:failed', { taskId: 'task_unknown', nodeId: 'node_rep_worker', version: 3 }, 'node_rep_worker');
+    mesh.node.emit('task:failed', { taskId: 'task_rep_failed', nodeId: 'node_rep_worker', version: 3 }, null);
+    mesh.node.emit('task:completed', { taskId: 'task_rep_done', nodeId: 'node_rep_other', version: 3 }, 'node_rep_other');
+    await settle();
+    if (outcomes.length !== 0) {
+        throw new Error(`Unknown, unsigned or foreign reports should not count: ${JSON.stringify(outcomes)}`);
+    }
+    mesh.node.emit('task:failed', { taskId: 'task_rep_failed', nodeId: 'node_rep_worker', version: 3 }, 'node_rep_pub');
+    await settle();
+    if (mesh.taskBazaar.getTask('task_rep_failed').status !== 'failed' || outcomes.length !== 0) {
+        throw new Error('A failure reported by the publisher should apply but not count against the assignee');
+    }
+    mesh.node.emit('task:completed', { taskId: 'task_rep_done', nodeId: 'node_rep_worker', version: 3 }, 'node_rep_worker');
+    await settle();
+    mesh.node.emit('task:completed', { taskId: 'task_rep_done', nodeId: 'node_rep_worker', version: 4 }, 'node_rep_worker');
+    await settle();
+    if (JSON.stringify(outcomes) !== JSON.stringify([['node_rep_worker', true]])) {
+        throw new Error(`Only the assignee's first signed completion should count: ${JSON.stringify(outcomes)}`);
+    }
+});
+
+// 测试98: 查询结果数上限，超出时截断并标记
+runner.test('MeshNode maxQueryResults - a query with many candidates is capped and flagged truncated', async () => {
+    const candidates = Array.from({ length: 50 }, (_, i) => ({ asset_id: `sha256:cap_${i}` }));
+    const requested = [];
+    const responder = new MeshNode({ nodeId: 'node_query_cap_peer', maxQueryResults: 10 });
+    responder.queryHandler = (filter) => {
+        requested.push(filter.limit);
+        return candidates.slice(0, filter.limit);
+    };
+    const response = await responder.handleQuery({ type: 'memories', filter: { tags: ['x'] } });
+    if (response.memories.length !== 10 || !response.truncated || requested[0] !== 11) {
+        throw new Error(`Responder should stop at the cap and flag truncation: ${response.memories.length}/${response.truncated}/${requested[0]}`);
+    }
+    const small = await responder.handleQuery({ type: 'memories', filter: { limit: 100 } });
+    const exact = await new MeshNode({ nodeId: 'node_query_cap_exact', maxQueryResults: 50 }).handleQuery({ type: 'memories', filter: {} });
+    if (small.memories.length !== 10 || exact.truncated) {
+        throw new Error('Requested limits should not exceed the cap, and an exact fit is not truncated');
+    }
+    for (const limit of [-1, 0, -1000]) {
+        const clamped = await responder.handleQuery({ type: 'memories', filter: { limit } });
+        if (clamped.memories.length !== 1 || !clamped.truncated || requested[requested.length - 1] !== 2) {
+            throw new Error(`Non-positive limit ${limit} should be clamped to 1, got ${clamped.memories.length}`);
+        }
+    }
+
+    // 发起方对不遵守上限的 peer 同样截断
+    const node = new MeshNode({ nodeId: 'node_query_cap', maxQueryResults: 5, queryTimeoutMs: 1000 });
+    node.broadcastAll = (message) => {
+        setImmediate(() => node.emit(`query_response:${message.requestId}`, { memories: candidates }));
+    };
+    const result = await node.queryMemoriesResult({});
+    const page = await node.queryMemoriesPage({}, { limit: 3 });
+    if (result.memories.length !== 5 || !result.truncated || page.total !== 5 || !page.truncated) {
+        throw new Error(`Requester should cap oversized responses: ${result.memories.length}/${page.total}`);
+    }
+});
+
+// 测试99: 每个peer的入站限速，持续超速的peer被封禁
+runner.test('MeshNode inbound rate limit - floods are dropped per peer and sustained abuse bans the peer', async () => {
+    const node = new MeshNode({ nodeId: 'node_inbound_limit', maxInboundPerSec: 5, banThreshold: 3 });
+    const flooder = { remoteAddress: '10.0.0.9', destroy() { this.destroyed = true; } };
+    const polite = { remoteAddress: '10.0.0.10', destroy() {} };
+    node.peers.set('node_flooder', flooder);
+    node.peers.set('node_polite', polite);
+    const gossip = { type: 'capsule', payload: {} };
+    let now = 1000000;
+    let allowed = 0;
+    for (let i = 0; i < 20; i++) {
+        if (node.allowInbound(flooder, 'node_flooder', gossip, now)) allowed++;
+    }
+    if (allowed !== 5 || node.inboundDrops.get('node_flooder').dropped !== 15) {
+        throw new Error(`Only the burst budget should pass: ${allowed}`);
+    }
+    if (!node.allowInbound(flooder, 'node_flooder', { type: 'handshake', nodeId: 'node_flooder' }, now)) {
+        throw new Error('The first handshake should not be rate limited');
+    }
+    if (node.allowInbound(flooder, 'node_flooder', { type: 'handshake', nodeId: 'node_flooder' }, now)) {
+        throw new Error('Repeated handshakes should count against the budget');
+    }
+    if (!node.allowInbound(polite, 'node_polite', gossip, now)) {
+        throw new Error('Each peer should have its own budget');
+    }
+    now += 400;
+    if (!node.allowInbound(flooder, 'node_flooder', gossip, now) || !node.allowInbound(flooder, 'node_flooder', gossip, now)
+        || node.allowInbound(flooder, 'node_flooder', gossip, now)) {
+        throw new Error('Budget should refill at the configured rate');
+    }
+    const peers = node.getPeers();
+    if (peers.find(p => p.nodeId === 'node_flooder').inboundDropped !== 17 || peers.find(p => p.nodeId === 'node_polite').inboundDropped !== 0) {
+        throw new Error('Per-peer drops should be exposed in getPeers');
+    }
+    if (node.isBanned('node_flooder')) {
+        throw new Error('A short burst should not ban the peer');
+    }
+    for (let second = 1; second <= 2; second++) {
+        now += 1000;
+        for (let i = 0; i < 10; i++) node.allowInbound(flooder, 'node_flooder', gossip, now);
+    }
+    if (!node.isBanned('node_flooder') || !flooder.destroyed || node.peers.has('node_flooder')) {
+        throw new Error('Sustained flooding should ban and disconnect the peer');
+    }
+    if (node.getRelayStats().dropped.rateLimited !== 27) {
+        throw new Error('Rate-limited drops should be counted in relay stats');
+    }
+
+    // 断开后删除该 peer 的丢弃计数
+    const EventEmitter = require('events');
+    const socket = new EventEmitter();
+    Object.assign(socket, { remoteAddress: '10.0.0.11', remotePort: 50000, writable: true, destroyed: false });
+    socket.write = () => {};
+    socket.destroy = () => { socket.destroyed = true; };
+    const limited = new MeshNode({ nodeId: 'node_inbound_prune', maxInboundPerSec: 1, banThreshold: 100 });
+    limited.handleConnection(socket);
+    const lines = [{ type: 'handshake', nodeId: 'node_chatty', port: 4000 }, gossip, gossip, gossip];
+    socket.emit('data', Buffer.from(lines.map(line => JSON.stringify(line)).join('\n') + '\n'));
+    if (!(limited.inboundDrops.get('node_chatty')?.dropped > 0)) {
+        throw new Error('The chatty peer should have drops recorded');
+    }
+    socket.emit('close');
+    if (limited.inboundDrops.size !== 0) {
+        throw new Error('Inbound drop stats should be pruned on disconnect');
+    }
+    await limited.stop();
+});
+
+// 测试100: 停用账户阻止转账并保留账本历史
+runner.test('MemoryStore deactivateAccount - blocks transfers, sweeps the balance and keeps history', async () => {
+    const dataDir = TEST_CONFIG.dataDir + '/deactivate_' + Date.now();
+    const store = new MemoryStore(dataDir, { useLance: false });
+    await store.init();
+    const retiring = store.ensureAccount('node_retiring');
+    const keeper = store.ensureAccount('node_keeper');
+    store.credit('node_retiring', 40);
+    store.transfer(retiring.accountId, keeper.accountId, 15);
+    const historyBefore = store.ledger.length;
+
+    const result = store.deactivateAccount(retiring.accountId, { sweepTo: keeper.accountId });
+    if (result.status !== 'inactive' || result.swept.amount !== 25 || store.getBalance('node_keeper') !== 40 || result.balance !== 0) {
+        throw new Error(`Balance should be swept to the designated account: ${JSON.stringify(result)}`);
+    }
+    const blocked = [
+        () => store.transfer(retiring.accountId, keeper.accountId, 1),
+        () => store.transfer(keeper.accountId, retiring.accountId, 1),
+        () => store.debit('node_retiring', 1),
+        () => store.lockEscrow('task_after_deactivate', 'node_retiring', 1)
+    ];
+    for (const attempt of blocked) {
+        let error = null;
+        try {
+            attempt();
+        } catch (e) {
+            error = e;
+        }
+        if (!error || !error.message.includes('inactive')) {
+            throw new Error('Inactive accounts should not send or receive transfers');
+        }
+    }
+    if (store.ledger.length !== historyBefore + 2 || !store.verifyLedger().valid
+        || !store.ledger.some(e => e.type === 'transfer' && e.from === retiring.accountId && e.amount === 15)) {
+        throw new Error('Deactivation should append to the ledger without rewriting history');
+    }
+    const listed = store.listAccounts();
+    if (listed.find(a => a.accountId === retiring.accountId).status !== 'inactive'
+        || listed.find(a => a.accountId === keeper.accountId).status !== 'active') {
+        throw new Error('Listings should flag inactive accounts');
+    }
+    let again = null;
+    try {
+        store.deactivateAccount(retiring.accountId);
+    } catch (e) {
+        again = e;
+    }
+    if (!again) throw new Error('Deactivating twice should fail');
+
+    await store.saveAccountsToDisk();
+    await store.close();
+    const reopened = new MemoryStore(dataDir, { useLance: false });
+    await reopened.init();
+    if (reopened.isAccountActive(reopened.accounts.get(retiring.accountId))) {
+        throw new Error('Inactive status should survive a restart');
+    }
+    await reopened.close();
+});
+
+// 测试101: 已见消息按时间顺序淘汰，布隆过滤器接住被挤出的ID
+runner.test('MeshNode seen messages - oldest entry is evicted first and the bloom layer still dedups it', async () => {
+    const node = new MeshNode({ nodeId: 'node_seen_order', maxSeenMessages: 3 });
+    node.markMessageSeen('m1');
+    node.markMessageSeen('m2');
+    node.markMessageSeen('m3');
+    // 再次见到 m1 会刷新它的时间，最早的变为 m2
+    node.markMessageSeen('m1');
+    node.markMessageSeen('m4');
+    if (Array.from(node.seenMessages.keys()).join(',') !== 'm3,m1,m4') {
+        throw new Error(`Oldest entry should be evicted first: ${Array.from(node.seenMessages.keys())}`);
+    }
+    if (node.shouldProcessMessage({ type: 'capsule', messageId: 'm1' }) || !node.shouldProcessMessage({ type: 'capsule', messageId: 'm2' })) {
+        throw new Error('Without a bloom filter only the exact window dedups');
+    }
+
+    const ttl = new MeshNode({ nodeId: 'node_seen_ttl', maxSeenMessages: 100, seenTtlMs: 1000 });
+    ttl.seenMessages.set('old', Date.now() - 5000);
+    ttl.markMessageSeen('fresh');
+    if (ttl.seenMessages.has('old') || !ttl.seenMessages.has('fresh')) {
+        throw new Error('Expired entries should be removed from the old end');
+    }
+
+    const bloom = new MeshNode({ nodeId: 'node_seen_bloom', maxSeenMessages: 2, seenBloomBits: 1 << 16 });
+    for (const id of ['b1', 'b2', 'b3']) {
+        bloom.shouldProcessMessage({ type: 'capsule', messageId: id });
+    }
+    if (bloom.seenMessages.has('b1') || bloom.shouldProcessMessage({ type: 'capsule', messageId: 'b1' })) {
+        throw new Error('Evicted ids should still be deduplicated by the bloom filter');
+    }
+    if (!bloom.shouldProcessMessage({ type: 'capsule', messageId: 'b_new' })) {
+        throw new Error('Unseen ids should pass the bloom filter');
+    }
+});
+
+// 测试102: gossip 转发策略在固定拓扑上以更少的完整消息达到同样的送达
+runner.test('MeshNode relay strategy - gossip delivers like flood with fewer full copies', async () => {
+    const simulate = async (relayStrategy, extra = {}) => {
+        const count = 12;
+        const nodes = Array.from({ length: count }, (_, i) => new MeshNode({
+            nodeId: `node_relay_${relayStrategy}_${i}`, relayStrategy, gossipIwantDelayMs: 5, fanout: 6, defaultHops: 3, ...extra
+        }));
+        const traffic = { full: 0, control: 0, bytes: 0 };
+        const link = (a, b) => {
+            a.peers.set(b.nodeId, {
+                writable: true,
+                destroyed: false,
+                write(line) {
+                    const message = JSON.parse(line);
+                    if (message.type === 'capsule') traffic.full++; else traffic.control++;
+                    traffic.bytes += line.length;
+                    setImmediate(() => b.handleMessage(message, a.nodeId, null));
+                },
+                destroy() { this.destroyed = true; }
+            });
+        };
+        // 环上相距 1、2、4 的节点互连，每个节点 6 个邻居
+        for (let i = 0; i < count; i++) {
+            for (const step of [1, 2, 4]) {
+                const j = (i + step) % count;
+                link(nodes[i], nodes[j]);
+                link(nodes[j], nodes[i]);
+            }
+        }
+        const message = { type: 'capsule', payload: { asset_id: 'sha256:relay', content: 'x'.repeat(2000) }, timestamp: Date.now() };
+        nodes[0].broadcast(message, { fanout: 6, hopsLeft: 3 });
+        await new Promise(resolve => setTimeout(resolve, 150));
+        const delivered = nodes.filter(node => node.hasSeenMessage(message.messageId)).length;
+        await Promise.all(nodes.map(node => { node.peers.clear(); return node.stop(); }));
+        return { delivered, ...traffic, served: nodes.reduce((sum, node) => sum + node.gossipStats.served, 0) };
+    };
+
+    const flood = await simulate('flood');
+    const gossip = await simulate('gossip');
+    if (flood.delivered !== 12 || gossip.delivered !== 12) {
+        throw new Error(`Both strategies should reach every node: flood=${flood.delivered} gossip=${gossip.delivered}`);
+    }
+    if (gossip.full >= flood.full || gossip.bytes >= flood.bytes) {
+        throw new Error(`Gossip should send fewer full copies: flood=${flood.full}/${flood.bytes}B gossip=${gossip.full}/${gossip.bytes}B`);
+    }
+    if (flood.control !== 0 || gossip.control === 0) {
+        throw new Error('Only gossip should exchange ihave/iwant messages');
+    }
+    // 不做完整推送时，未直接收到的节点全部通过 iwant 取回
+    const lazy = await simulate('gossip', { gossipEagerFanout: 0 });
+    if (lazy.delivered !== 12 || lazy.served === 0) {
+        throw new Error(`Announced messages should be fetched with iwant: ${JSON.stringify(lazy)}`);
+    }
+    if (new MeshNode({ nodeId: 'node_relay_default' }).relayStrategy !== 'flood') {
+        throw new Error('Flood should stay the default strategy');
+    }
+
+    // 缓存按字节预算淘汰最早的消息
+    const budget = new MeshNode({ nodeId: 'node_relay_budget', gossipCacheMaxBytes: 5000 });
+    for (let i = 0; i < 5; i++) {
+        budget.cacheGossipMessage({ type: 'capsule', messageId: `big_${i}`, payload: { content: 'x'.repeat(2000) } });
+    }
+    if (budget.gossipCacheBytes > 5000 || budget.gossipCache.has('big_0') || !budget.gossipCache.has('big_4')) {
+        throw new Error(`Gossip cache should stay within its byte budget: ${budget.gossipCacheBytes}B, ${Array.from(budget.gossipCache.keys()).join(',')}`);
+    }
+
+    // 索取超时后改向其他通告者索取
+    const fetcher = new MeshNode({ nodeId: 'node_relay_fetch', gossipIwantDelayMs: 5, gossipIwantTimeoutMs: 30 });
+    const asked = [];
+    for (const peerId of ['node_announcer_a', 'node_announcer_b']) {
+        fetcher.peers.set(peerId, {
+            writable: true,
+            destroyed: false,
+            write(line) { asked.push({ peerId, message: JSON.parse(line) }); },
+            destroy() { this.destroyed = true; }
+        });
+    }
+    fetcher.handleIHave(['msg_slow'], 'node_announcer_a');
+    fetcher.handleIHave(['msg_slow'], 'node_announcer_b');
+    await new Promise(resolve => setTimeout(resolve, 20));
+    if (asked.map(a => a.peerId).join(',') !== 'node_announcer_a') {
+        throw new Error(`Only the first announcer should be asked at first: ${asked.map(a => a.peerId).join(',')}`);
+    }
+    await new Promise(resolve => setTimeout(resolve, 40));
+    if (asked.map(a => a.peerId).join(',') !== 'node_announcer_a,node_announcer_b' || asked[1].message.type !== 'iwant') {
+        throw new Error(`An unanswered iwant should fall back to the next announcer: ${asked.map(a => a.peerId).join(',')}`);
+    }
+    await new Promise(resolve => setTimeout(resolve, 40));
+    if (asked.length !== 2 || fetcher.gossipWants.size !== 0) {
+        throw new Error('Fetching should stop once every announcer was asked');
+    }
+    await fetcher.stop();
+});
+
+// 测试103: 超过 maxSeenMessages 时淘汰的是真正最早的ID
+runner.test('MeshNode seen messages - 10001 increasing ids evict exactly the first one', async () => {
+    const node = new MeshNode({ nodeId: 'node_seen_regression' });
+    const base = Date.now() - 20000;
+    for (let i = 0; i <= 10000; i++) {
+        node.markMessageSeen(`id_${i}`, base + i);
+    }
+    if (node.seenMessages.size !== 10000 || node.seenMessages.has('id_0') || !node.seenMessages.has('id_1') || !node.seenMessages.has('id_10000')) {
+        throw new Error(`Only the first id should be dropped (size ${node.seenMessages.size})`);
+    }
+    // 时钟回拨：迟到的条目不会排到更早的条目前面被先淘汰
+    node.markMessageSeen('id_clock_skew', base - 60000);
+    node.markMessageSeen('id_after_skew', base + 10001);
+    if (!node.seenMessages.has('id_clock_skew') || node.seenMessages.has('id_1') || node.seenMessages.has('id_2')) {
+        throw new Error('Entries should be evicted in the order they were seen');
+    }
+
+    // 未来的时间被截到当前时间，不会把后续条目的时间推到未来
+    node.markMessageSeen('id_future', Date.now() + 3600000);
+    if (node.seenMessages.get('id_future') > Date.now() || node.seenLatestAt > Date.now()) {
+        throw new Error('Future timestamps should be clamped to now');
+    }
+});
+
+// 测试104: 置顶的胶囊不会被清理、空闲过期或容量淘汰
+runner.test('MemoryStore pinned capsules - survive pruning and eviction', async () => {
+    const dayMs = 24 * 60 * 60 * 1000;
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/pin_' + Date.now(), {
+        useLance: false,
+        capsuleFlushIntervalMs: 0,
+        pruneMinConfidence: 0.3,
+        pruneIdleMs: 30 * dayMs,
+        maxCapsules: 3
+    });
+    await store.init();
+    try {
+        const seed = (id, confidence) => store.storeCapsule({
+            asset_id: id,
+            content: { capsule: { type: 'skill', confidence, blast_radius: ['pin'] } }
+        });
+        await seed('sha256:pinned_low', 0.1);
+        await seed('sha256:pinned_idle', 0.9);
+        await seed('sha256:low', 0.1);
+        await store.setCapsulePinned('sha256:pinned_low', true);
+        await store.setCapsulePinned('sha256:pinned_idle', true);
+
+        // 重新存储不会丢失置顶状态
+        await seed('sha256:pinned_low', 0.1);
+        if (!store.isPinned(store.capsules.get('sha256:pinned_low'))) {
+            throw new Error('Re-storing a capsule should keep its pin');
+        }
+
+        const result = await store.pruneCapsules(Date.now() + 31 * dayMs);
+        const remaining = store.queryCapsules({ tags: ['pin'] }).map(c => c.asset_id).sort();
+        if (remaining.join(',') !== 'sha256:pinned_idle,sha256:pinned_low' || result.pruned !== 1) {
+            throw new Error(`Unexpected survivors: ${remaining.join(',')}`);
+        }
+
+        await seed('sha256:fresh_a', 0.9);
+        await seed('sha256:fresh_b', 0.9);
+        if (!store.capsules.has('sha256:pinned_low') || !store.capsules.has('sha256:pinned_idle') || store.capsules.has('sha256:fresh_a')) {
+            throw new Error('Eviction over the cap should skip pinned capsules');
+        }
+
+        await store.setCapsulePinned('sha256:pinned_low', false);
+        const unpinned = await store.pruneCapsules(Date.now() + 31 * dayMs);
+        if (!unpinned.assetIds.includes('sha256:pinned_low') || (await store.setCapsulePinned('sha256:missing', true)) !== null) {
+            throw new Error('Unpinned capsules should be prunable again');
+        }
+    } finally {
+        await store.close();
+    }
+});
+
+// 测试105: 停止节点时关闭心跳、所有peer和监听端口
+runner.test('MeshNode stop - clears the heartbeat, closes peers and reports how many', async () => {
+    const node = new MeshNode({ nodeId: 'node_shutdown', port: 0 });
+    await node.init();
+    let destroyed = 0;
+    for (const peerId of ['node_a', 'node_b']) {
+        node.peers.set(peerId, { destroyed: false, destroy() { destroyed++; this.destroyed = true; } });
+    }
+    // 握手后同一连接同时以地址和 nodeId 为键
+    node.peers.set('10.0.0.1:4001', node.peers.get('node_a'));
+    if (!node.heartbeatTimer) {
+        throw new Error('Heartbeat should start with the node');
+    }
+    const result = await node.stop();
+    if (result.peersClosed !== 2 || destroyed !== 2 || node.peers.size !== 0) {
+        throw new Error(`Expected 2 peers closed, got ${JSON.stringify(result)}`);
+    }
+    if (node.heartbeatTimer || node.server.listening) {
+        throw new Error('Heartbeat and listener should be stopped');
+    }
+});
+
+// 测试106: 转账写入中途失败时整体回滚，总供应量不变
+runner.test('LedgerStore atomic transfer - a failure between writes rolls back and conserves supply', async () => {
+    const fs = require('fs');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/atomic_' + Date.now();
+    fs.mkdirSync(dataDir + '/replica', { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: wallet.accountId,
+        genesisSupply: 1000,
+        genesisPublicKeyPem: wallet.publicKeyPem,
+        genesisPrivateKeyPem: wallet.privateKeyPem
+    });
+    const replica = new LedgerStore(dataDir + '/replica');
+    replica.init();
+    try {
+        const totalSupply = (store) => store.db.prepare('SELECT COALESCE(SUM(balance), 0) AS total FROM accounts_state').get().total;
+        const payload = {
+            type: 'transfer',
+            from: wallet.accountId,
+            to: 'acct_atomic_target',
+            amount: 100,
+            nonce: ledger.getNonce(wallet.accountId) + 1,
+            timestamp: Date.now()
+        };
+        const tx = { ...payload, pubkeyPem: wallet.publicKeyPem, signature: signPayload(wallet.privateKeyPem, payload), txId: 'tx_atomic' };
+        const seqBefore = ledger.getLastSeq();
+        const headBefore = ledger.getHeadHash();
+
+        // 余额已经写入、链头hash还没更新时失败
+        const setMeta = ledger.setMeta.bind(ledger);
+        ledger.setMeta = () => { throw new Error('injected failure'); };
+        let failed = null;
+        try {
+            ledger.appendAsMaster(tx);
+        } catch (e) {
+            failed = e;
+        }
+        ledger.setMeta = setMeta;
+        if (!failed || ledger.getTxById('tx_atomic') || ledger.getLastSeq() !== seqBefore || ledger.getHeadHash() !== headBefore) {
+            throw new Error('Failed transfer should leave no ledger entry behind');
+        }
+        if (ledger.getBalance(wallet.accountId) !== 1000 || ledger.getBalance('acct_atomic_target') !== 0 || totalSupply(ledger) !== 1000) {
+            throw new Error('Failed transfer should not move any balance');
+        }
+
+        if (!ledger.appendAsMaster(tx).accepted || ledger.getBalance('acct_atomic_target') !== 100 || totalSupply(ledger) !== 1000) {
+            throw new Error('Retried transfer should apply once and conserve supply');
+        }
+
+        // 副本在两边余额写入后失败，同样整体回滚
+        const entries = ledger.getTxLogSince(0);
+        replica.applyLogEntry(entries[0]);
+        const applyToState = replica.applyToState.bind(replica);
+        replica.applyToState = (entryTx) => {
+            applyToState(entryTx);
+            throw new Error('injected failure');
+        };
+        let replicaFailed = null;
+        try {
+            replica.applyLogEntry(entries[1]);
+        } catch (e) {
+            replicaFailed = e;
+        }
+        replica.applyToState = applyToState;
+        if (!replicaFailed || replica.getLastSeq() !== 1 || replica.getBalance('acct_atomic_target') !== 0 || totalSupply(replica) !== 1000) {
+            throw new Error('Replica should roll back a partially applied entry');
+        }
+        if (!replica.applyLogEntry(entries[1]).applied || totalSupply(replica) !== 1000) {
+            throw new Error('Replica should apply the entry on retry');
+        }
+    } finally {
+        ledger.close();
+        replica.close();
+    }
+});
+
+// 测试107: 停用账户后经 /api/account/transfer 转出和转入都被账本拒绝，其他代币一并转走，副本据签名交易停用本地账户
+runner.test('LedgerStore deactivation - /api/account/transfer from and to an inactive account is rejected', async () => {
+    const fs = require('fs');
+    const EventEmitter = require('events');
+    const LedgerStore = require('../src/ledger-store');
+    const { loadOrCreateWallet } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/ledger_deactivate_' + Date.now();
+    for (const dir of ['user', 'keeper', 'replica']) fs.mkdirSync(`${dataDir}/${dir}`, { recursive: true });
+    const master = loadOrCreateWallet(dataDir);
+    const user = loadOrCreateWallet(dataDir + '/user');
+    const keeper = loadOrCreateWallet(dataDir + '/keeper');
+    const ledger = new LedgerStore(dataDir);
+    ledger.init({
+        isGenesis: true,
+        genesisAccountId: master.accountId,
+        genesisSupply: 1000,
+        genesisTokens: [{ symbol: 'GEM', supply: 50 }],
+        genesisPublicKeyPem: master.publicKeyPem,
+        genesisPrivateKeyPem: master.privateKeyPem
+    });
+    const replica = new LedgerStore(dataDir + '/replica');
+    replica.init();
+    // 两个 WebUI 都直接写主账本：一个以主节点钱包签名，一个以被停用账户的钱包签名
+    const makeServer = (wallet) => {
+        const mesh = {
+            options: { isGenesisNode: true },
+            wallet,
+            ledger,
+            node: { broadcastAll() {} },
+            memoryStore: { accounts: new Map(), isAccountActive: account => Boolean(account) }
+        };
+        for (const name of ['createSignedTransfer', 'submitTx', 'deactivateAccount']) {
+            mesh[name] = OpenClawMesh.prototype[name].bind(mesh);
+        }
+        return new WebUIServer({ port: 0, mesh, adminToken: 'secret' });
+    };
+    const call = (server, url, body) => new Promise((resolve) => {
+        const req = new EventEmitter();
+        Object.assign(req, { method: 'POST', url, headers: { 'x-admin-token': 'secret' }, socket: { remoteAddress: '::ffff:10.0.0.5' } });
+        server.handleAPI(req, makeResponse(resolve));
+        req.emit('data', JSON.stringify(body));
+        req.emit('end');
+    });
+    const masterServer = makeServer(master);
+    const userServer = makeServer(user);
+    try {
+        const funded = await call(masterServer, '/api/account/transfer', { toAccountId: user.accountId, amount: 100 });
+        if (!funded.result?.accepted) {
+            throw new Error(`Funding transfer rejected: ${JSON.stringify(funded)}`);
+        }
+        const gems = await call(masterServer, '/api/account/transfer', { toAccountId: user.accountId, amount: 20, token: 'GEM' });
+        if (!gems.result?.accepted) {
+            throw new Error(`Token funding transfer rejected: ${JSON.stringify(gems)}`);
+        }
+        const deactivated = await call(masterServer, `/api/account/${user.accountId}/deactivate`, { sweepTo: keeper.accountId });
+        if (!deactivated.success || deactivated.swept.amount !== 100 || ledger.getBalance(user.accountId) !== 0 || ledger.getBalance(keeper.accountId) !== 100) {
+            throw new Error(`Deactivation should sweep the ledger balance: ${JSON.stringify(deactivated)}`);
+        }
+        if (deactivated.swept.tokens?.GEM !== 20 || ledger.getBalance(user.accountId, 'GEM') !== 0 || ledger.getBalance(keeper.accountId, 'GEM') !== 20) {
+            throw new Error(`Other token balances should be swept too: ${JSON.stringify(deactivated.swept)}`);
+        }
+
+        const incoming = await call(masterServer, '/api/account/transfer', { toAccountId: user.accountId, amount: 5 });
+        if (incoming.result?.accepted !== false || incoming.result.reason !== 'Recipient account is inactive') {
+            throw new Error(`Transfers to an inactive account should be rejected: ${JSON.stringify(incoming)}`);
+        }
+        const outgoing = await call(userServer, '/api/account/transfer', { toAccountId: keeper.accountId, amount: 1 });
+        if (outgoing.result?.accepted !== false || outgoing.result.reason !== 'Account is inactive') {
+            throw new Error(`Transfers from an inactive account should be rejected: ${JSON.stringify(outgoing)}`);
+        }
+        const genesis = await call(masterServer, `/api/account/${master.accountId}/deactivate`, {});
+        if (!genesis.error) {
+            throw new Error('The genesis account must not be deactivatable');
+        }
+
+        // 同步后副本同样认定该账户已停用，本地账户记录也随之停用
+        const replicaStore = new MemoryStore(dataDir + '/replica', { useLance: false });
+        await replicaStore.init();
+        replicaStore.accounts.set(user.accountId, { accountId: user.accountId, nodeId: 'node_retired_user' });
+        const replicaMesh = { ledger: replica, memoryStore: replicaStore };
+        const applyEntry = OpenClawMesh.prototype.applyLedgerEntry.bind(replicaMesh);
+        for (const entry of ledger.getTxLogSince(0)) {
+            if (!applyEntry(entry, 'node_master').applied) throw new Error(`Replica rejected seq ${entry.seq}`);
+        }
+        if (replica.isAccountActive(user.accountId) || replica.getBalance(keeper.accountId) !== 100 || replica.getBalance(keeper.accountId, 'GEM') !== 20) {
+            throw new Error('Replica should learn the deactivation from the signed ledger tx');
+        }
+        if (replicaStore.isAccountActive(replicaStore.accounts.get(user.accountId))) {
+            throw new Error('Replica should mark the local account record inactive');
+        }
+        await replicaStore.close();
+    } finally {
+        ledger.close();
+        replica.close();
+    }
+});
+
+// 测试108: WebUI 关闭时不会被一直不结束的请求卡住
+runner.test('WebUIServer stop - a hanging request is cut off after shutdownTimeoutMs', async () => {
+    const http = require('http');
+    const web = new WebUIServer({ port: 0, shutdownTimeoutMs: 200 });
+    web.server = http.createServer(() => {});
+    await new Promise(resolve => web.server.listen(0, resolve));
+    const request = http.get({ port: web.server.address().port, path: '/api/slow' });
+    const aborted = new Promise(resolve => request.on('error', resolve));
+    await new Promise(resolve => web.server.once('request', resolve));
+    const started = Date.now();
+    await web.stop();
+    await aborted;
+    const elapsed = Date.now() - started;
+    if (elapsed < 150 || elapsed > 2000 || web.server.listening) {
+        throw new Error(`Shutdown should wait for the timeout and then close, took ${elapsed}ms`);
+    }
+});
+
+// 测试109: 退款和发布者通知只认签名托管证明中的发布者账户，改写 publisherAccountId 的任务被丢弃
+runner.test('Task publisher account - refunds and notices trust only the signed escrow proof', async () => {
+    const fs = require('fs');
+    const { loadOrCreateWallet, signPayload } = require('../src/wallet');
+    const dataDir = TEST_CONFIG.dataDir + '/publisher_acct_' + Date.now();
+    fs.mkdirSync(dataDir + '/attacker', { recursive: true });
+    const wallet = loadOrCreateWallet(dataDir);
+    const attacker = loadOrCreateWallet(dataDir + '/attacker');
+    const bazaar = new TaskBazaar({ nodeId: 'node_pubacct_remote', memoryStore: null, dataDir });
+    const makeTask = (taskId, extra = {}) => ({
+        taskId,
+        description: 'Guarded escrow',
+        bounty: { amount: 50, token: 'CLAW' },
+        publisher: 'node_pubacct_pub',
+        publisherAccountId: wallet.accountId,
+        escrowProof: signEscrowProof(wallet, taskId, 50),
+        ...extra
+    });
+
+    // 转发时改写 publisherAccountId 的任务不入库
+    await bazaar.handleNewTask(makeTask('task_rewritten', { publisherAccountId: attacker.accountId }));
+    if (bazaar.getTask('task_rewritten')) {
+        throw new Error('A task whose publisherAccountId differs from the escrow proof should be dropped');
+    }
+    if (bazaar.verifyEscrowProof(makeTask('task_check', { publisherAccountId: attacker.accountId })).valid) {
+        throw new Error('Escrow proof should not validate a rewritten publisher account');
+    }
+
+    await bazaar.handleNewTask(makeTask('task_guarded', { escrowDisposition: { type: 'refund', to: attacker.accountId } }));
+    if (!bazaar.getTask('task_guarded') || bazaar.getTask('task_guarded').escrowDisposition) {
+        throw new Error('An intact task should be stored without a gossiped escrow disposition');
+    }
+    const statement = { taskId: 'task_guarded', publisher: 'node_pubacct_pub', cancelledAt: Date.now(), reason: 'mine now' };
+    const sign = (signer) => ({ statement, publicKeyPem: signer.publicKeyPem, signature: signPayload(signer.privateKeyPem, statement) });
+    if (bazaar.verifyTaskCancellation(sign(attacker)).valid) {
+        throw new Error('A cancellation signed by another key should be rejected');
+    }
+    if (!bazaar.verifyTaskCancellation(sign(wallet)).valid) {
+        throw new Error('A cancellation signed by the escrow publisher should be accepted');
+    }
+
+    // 本地字段被改写也不影响退款去向
+    bazaar.getTask('task_guarded').publisherAccountId = attacker.accountId;
+    const cancelled = bazaar.cancelTask('task_guarded', 'node_pubacct_pub');
+    if (!cancelled.success || cancelled.task.escrowDisposition.to !== null) {
+        throw new Error(`A task with a mismatched publisher account should not refund anywhere: ${JSON.stringify(cancelled.task?.escrowDisposition)}`);
+    }
+    await bazaar.handleNewTask(makeTask('task_refund'));
+    const refunded = bazaar.cancelTask('task_refund', 'node_pubacct_pub');
+    if (refunded.task.escrowDisposition.to !== wallet.accountId) {
+        throw new Error('The refund should go to the signed escrow publisher');
+    }
+});
+
+// 测试110: 10k 个共享标签的胶囊：倒排表只追加不重写，查询结果与全量扫描一致
+runner.test('MemoryStore tag postings - 10k capsules sharing a tag are appended without rewriting and query like a full scan', async () => {
+    const store = new MemoryStore(TEST_CONFIG.dataDir + '/postings_' + Date.now(), { useLance: false, capsuleFlushIntervalMs: 0 });
+    await store.init();
+    const put = (i, tags) => {
+        const capsule = { asset_id: 'sha256:post' + i, content: { capsule: { type: 'skill', blast_radius: tags } } };
+        store.capsules.set(capsule.asset_id, capsule);
+        store.indexCapsule(capsule);
+    };
+    const scan = (tags) => Array.from(store.capsules.values())
+        .filter(c => tags.some(tag => (c.content?.capsule?.blast_radius || []).includes(tag)))
+        .map(c => c.asset_id);
+    const started = Date.now();
+    for (let i = 0; i < 10000; i++) put(i, ['shared', 'mod' + (i % 10)]);
+    const indexMs = Date.now() - started;
+    const shared = store.tagIndex.get('shared');
+    if (!(shared.seqs instanceof Uint32Array) || shared.length !== 10000 || shared.seqs.length > 16384) {
+        throw new Error('Postings should be a compact append-only array of insertion numbers');
+    }
+    if (indexMs > 3000) {
+        throw new Error(`Indexing 10k capsules took ${indexMs}ms`);
+    }
+
+    // 追加一条不会复制已有条目
+    const buffer = shared.seqs.buffer;
+    put(10000, ['shared']);
+    if (shared.seqs.buffer !== buffer || shared.length !== 10001) {
+        throw new Error('Appending a capsule should not rewrite the posting list');
+    }
+
+    // 改标签、删除、再加回原标签后仍与全量扫描一致，失效条目过半后压缩
+    for (let i = 0; i < 6000; i++) put(i, ['mod' + (i % 10)]);
+    for (let i = 6000; i < 6100; i++) await store.removeCapsule('sha256:post' + i);
+    put(5, ['shared', 'mod5']);
+    for (const tags of [['shared'], ['mod3'], ['mod1', 'shared'], ['missing']]) {
+        if (JSON.stringify(store.getIndexedIds(tags)) !== JSON.stringify(scan(tags))) {
+            throw new Error(`Postings should match a full scan for ${tags.join(',')}`);
+        }
+    }
+    if (shared.length > shared.size * 2 || shared.size !== 3902) {
+        throw new Error(`Dead postings should be compacted: length=${shared.length} size=${shared.size}`);
+    }
+    await store.close();
+});
+
 // 运行测试
 runner.run().then(success => {
     process.exit(success ? 0 : 1);
@@ -3,9 +3,12 @@
  */
 
 const http = require('http');
+const https = require('https');
 const fs = require('fs');
 const path = require('path');
+const crypto = require('crypto');
 const WebSocket = require('ws');
+const { verifyPayload } = require('../src/wallet');
 
 class WebUIServer {
     constructor(options = {}) {
@@ -13,24 +16,73 @@ class WebUIServer {
         this.mesh = options.mesh;
         this.server = null;
         this.wss = null;
+        // WebSocket状态推送/心跳间隔，客户端可在下限之上自行调整
+        this.wsMinStatusIntervalMs = options.wsMinStatusIntervalMs || 1000;
+        this.wsMinPingIntervalMs = options.wsMinPingIntervalMs || 5000;
+        this.wsStatusIntervalMs = this.clampInterval(options.wsStatusIntervalMs, 5000, this.wsMinStatusIntervalMs);
+        this.wsPingIntervalMs = this.clampInterval(options.wsPingIntervalMs, 20000, this.wsMinPingIntervalMs);
+        // TLS：配置证书后使用HTTPS；再配置CA时，修改类接口要求该CA签发的客户端证书（mTLS）
+        this.tls = options.tls && options.tls.certPath && options.tls.keyPath ? options.tls : null;
+        this.requireClientCert = Boolean(this.tls?.caPath);
+        // 操作审计：记录所有修改类API调用，内存环形缓冲，可选追加到 audit.jsonl
+        this.adminToken = options.adminToken || null;
+        this.maxAuditEntries = options.maxAuditEntries || 1000;
+        this.auditLogPath = options.auditLogPath || null;
+        this.auditLog = [];
+        this.auditSeq = 0;
+        this.loadAuditLog();
+        // 任务结果包：获胜节点分块上传到 uploads/，完整后移入 completed/ 供下载
+        this.taskWorkspaceDir = options.taskWorkspaceDir || path.join(path.resolve(__dirname, '..'), 'task-workspace');
+        this.maxUploadBytes = options.maxUploadBytes || 100 * 1024 * 1024;
+        // 存储熔断：写请求先在限定时间内获取存储锁；连续超时达到阈值后熔断，冷却期内直接返回 503，之后放行一次探测
+        this.storeLockTimeoutMs = options.storeLockTimeoutMs || 5000;
+        this.storeBreakerThreshold = options.storeBreakerThreshold || 3;
+        this.storeBreakerCooldownMs = options.storeBreakerCooldownMs || 30000;
+        this.storeBreaker = { state: 'closed', failures: 0, openedAt: null, probing: false };
+        // 关闭时等待进行中请求的上限，超时后强制断开剩余连接
+        this.shutdownTimeoutMs = options.shutdownTimeoutMs ?? 5000;
+    }
+
+    clampInterval(value, fallback, min) {
+        const ms = Number(value);
+        if (!Number.isFinite(ms) || ms <= 0) return Math.max(fallback, min);
+        return Math.max(Math.floor(ms), min);
     }
     
     async start() {
-        // 创建HTTP服务器
-        this.server = http.createServer((req, res) => {
+        // 创建HTTP(S)服务器
+        const handler = (req, res) => {
             this.handleRequest(req, res);
-        });
+        };
+        if (this.tls) {
+            const tlsOptions = {
+                cert: fs.readFileSync(this.tls.certPath),
+                key: fs.readFileSync(this.tls.keyPath)
+            };
+            if (this.requireClientCert) {
+                // 只读接口不要求客户端证书，由 handleAPI 按请求校验
+                tlsOptions.ca = fs.readFileSync(this.tls.caPath);
+                tlsOptions.requestCert = true;
+                tlsOptions.rejectUnauthorized = false;
+            }
+            this.server = https.createServer(tlsOptions, handler);
+        } else {
+            this.server = http.createServer(handler);
+        }
         
         // 创建WebSocket服务器
-        this.wss = new WebSocket.Server({ server: this.server });
-        this.wss.on('connection', (ws) => {
-            this.handleWebSocket(ws);
+        this.wss = new WebSocket.Server({
+            server: this.server,
+            verifyClient: (info, done) => this.verifyWebSocketClient(info, done)
+        });
+        this.wss.on('connection', (ws, req) => {
+            this.handleWebSocket(ws, req);
         });
         
         // 启动服务器
         return new Promise((resolve) => {
             this.server.listen(this.port, () => {
-                console.log(`🌐 WebUI server started on port ${this.port}`);
+                console.log(`🌐 WebUI server started on port ${this.port}${this.tls ? ' (TLS' + (this.requireClientCert ? ', mTLS for writes)' : ')') : ''}`);
                 resolve();
             });
         });
@@ -56,17 +108,78 @@ class WebUIServer {
         res.end('Not Found');
     }
     
-    handleAPI(req, res) {
+    handleAPI(req, res, storeChecked = false) {
         const url = req.url;
+
+        if (!storeChecked && this.isMutatingRequest(req) && this.mesh?.memoryStore?.probeStoreLock) {
+            this.checkStoreHealth().then(health => {
+                if (health.ok) {
+                    this.handleAPI(req, res, true);
+                    return;
+                }
+                res.setHeader('Content-Type', 'application/json');
+                res.setHeader('Access-Control-Allow-Origin', '*');
+                this.trackMutation(req, res);
+                res.writeHead(503);
+                res.end(JSON.stringify({ error: 'Store unavailable', store: health }));
+            });
+            return;
+        }
         
         // 设置CORS
         res.setHeader('Content-Type', 'application/json');
         res.setHeader('Access-Control-Allow-Origin', '*');
+        this.trackMutation(req, res);
         
         let data = {};
+        const viewer = this.getViewerNodeId(req);
+
+        if (this.requireClientCert && this.isMutatingRequest(req) && !req.socket?.authorized) {
+            res.writeHead(403);
+            res.end(JSON.stringify({ error: 'Client certificate required' }));
+            return;
+        }
+
+        // 只转发的节点不提供写接口（peer 管理与维护操作除外）
+        if (this.isMutatingRequest(req) && this.mesh?.isRelayOnly?.() && !/^\/api\/(peers|maintenance)\//.test(url)) {
+            res.writeHead(403);
+            res.end(JSON.stringify({ error: 'Not available on a relay-only node', roles: this.mesh.options.roles }));
+            return;
+        }
+
+        // 启动同步屏障未满足前拒绝写操作
+        if (this.isMutatingRequest(req) && this.mesh?.isSyncReady && !this.mesh.isSyncReady()) {
+            res.writeHead(503);
+            res.end(JSON.stringify({ error: 'Node is syncing', sync: this.mesh.getSyncStatus() }));
+            return;
+        }
         
-        if (url === '/api/status') {
+        if (url === '/api/ready') {
+            const sync = this.mesh?.getSyncStatus ? this.mesh.getSyncStatus() : { ready: false };
+            const store = this.getStoreBreakerStatus();
+            const ready = sync.ready && store.state !== 'open';
+            res.writeHead(ready ? 200 : 503);
+            res.end(JSON.stringify({ ...sync, ready, store }));
+            return;
+        } else if (url === '/api/status') {
             data = this.mesh ? this.mesh.getStats() : { error: 'Mesh not initialized' };
+        } else if (url === '/api/version') {
+            data = this.mesh ? this.mesh.getVersionInfo() : { error: 'Mesh not initialized' };
+        } else if (/^\/api\/account\/[^/?]+\/balance(\?|$)/.test(url)) {
+            const [pathname, query] = url.split('?');
+            const accountId = decodeURIComponent(pathname.split('/')[3]);
+            const params = new URLSearchParams(query || '');
+            if (!this.mesh) {
+                data = { error: 'Mesh not initialized' };
+            } else {
+                this.mesh.getAccountBalance(accountId, { remote: params.get('remote') === 'true', token: params.get('token') })
+                    .catch(e => ({ error: e.message, code: e.code }))
+                    .then(result => {
+                        res.writeHead(200);
+                        res.end(JSON.stringify(result));
+                    });
+                return;
+            }
         } else if (url.startsWith('/api/account/balance')) {
             const query = url.split('?')[1] || '';
             const params = new URLSearchParams(query);
@@ -102,6 +215,7 @@ class WebUIServer {
                     exportedAt: new Date().toISOString(),
                     account: {
                         accountId,
+                        algorithm: this.mesh.wallet?.algorithm || 'ed25519',
                         publicKeyPem: this.mesh.wallet?.publicKeyPem || null,
                         privateKeyPem: this.mesh.wallet?.privateKeyPem || null,
                         balance: this.mesh.ledger?.getBalance(accountId) || 0,
@@ -111,11 +225,41 @@ class WebUIServer {
             } else {
                 data = { error: 'Mesh not initialized' };
             }
-        } else if (url === '/api/memories') {
-            data = this.mesh ? this.sanitizeCapsules(this.mesh.memoryStore.queryCapsules({ limit: 50 })) : [];
-        } else if (url === '/api/tasks') {
+        } else if (url === '/api/memories/network' || url.startsWith('/api/memories/network?')) {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            const filter = {};
+            if (params.get('tags')) filter.tags = params.get('tags').split(',').map(t => t.trim()).filter(Boolean);
+            if (params.get('type')) filter.type = params.get('type');
+            if (params.get('minConfidence')) filter.minConfidence = Number(params.get('minConfidence'));
+            if (!this.mesh) {
+                data = { error: 'Mesh not initialized' };
+            } else {
+                // 客户端提前断开时取消网络查询，释放等待状态
+                const controller = new AbortController();
+                res.on?.('close', () => {
+                    if (!res.writableFinished) controller.abort();
+                });
+                this.mesh.node.queryMemoriesPage(filter, { limit: params.get('limit'), cursor: params.get('cursor'), signal: controller.signal })
+                    .then(page => ({ ...page, items: this.sanitizeCapsules(page.items, viewer) }))
+                    .catch(e => ({ error: e.message, code: e.code }))
+                    .then(result => {
+                        if (controller.signal.aborted) return;
+                        res.writeHead(200);
+                        res.end(JSON.stringify(result));
+                    });
+                return;
+            }
+        } else if (url === '/api/memories' || url.startsWith('/api/memories?')) {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            const fields = this.parseFields(params.get('fields'));
+            const filter = params.get('mode') === 'similarity'
+                ? { mode: 'similarity', query: params.get('q') || '', limit: Math.min(Math.max(Number(params.get('limit')) || 10, 1), 200) }
+                : { limit: 50 };
+            data = this.mesh ? this.projectCapsules(this.sanitizeCapsules(this.mesh.memoryStore.queryCapsules(filter), viewer), fields) : [];
+        } else if (url === '/api/tasks' || url.startsWith('/api/tasks?')) {
             if (this.mesh) {
-                const tasks = this.mesh.taskBazaar.getTasks();
+                const params = new URLSearchParams(url.split('?')[1] || '');
+                const tasks = this.mesh.taskBazaar.getTasks({ includeArchived: params.get('include_archived') === 'true' });
                 data = tasks.map(t => ({
                     ...t,
                     voted: this.mesh.ratingStore?.hasVote?.(t.taskId) || false
@@ -125,9 +269,122 @@ class WebUIServer {
             }
         } else if (url === '/api/peers') {
             data = this.mesh ? this.mesh.node.getPeers() : [];
-        } else if (url.startsWith('/api/memory/')) {
-            const assetId = url.split('/').pop();
-            data = this.mesh ? this.sanitizeCapsule(this.mesh.memoryStore.getCapsule(assetId)) : null;
+        } else if (url === '/api/network/graph') {
+            data = this.mesh ? this.mesh.node.getNetworkGraph() : { error: 'Mesh not initialized' };
+        } else if (url.startsWith('/api/network/trace')) {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            if (!this.mesh) {
+                data = { error: 'Mesh not initialized' };
+            } else if (!params.get('key')) {
+                data = { error: 'Missing key' };
+            } else {
+                data = this.mesh.node.traceKeyRoute(params.get('key'), { alpha: params.get('alpha'), k: params.get('k') });
+            }
+        } else if (url === '/api/network/addresses') {
+            data = this.mesh ? this.mesh.node.getAddressBook() : { error: 'Mesh not initialized' };
+        } else if (url === '/api/peers/rtt') {
+            data = this.mesh ? this.mesh.node.getRttSummary() : { error: 'Mesh not initialized' };
+        } else if (url === '/api/peers/bans') {
+            data = this.mesh ? { items: this.mesh.node.getBans() } : { error: 'Mesh not initialized' };
+        } else if ((url === '/api/peers/ban' || url === '/api/peers/unban') && req.method === 'POST') {
+            if (!this.isAdminRequest(req)) {
+                res.writeHead(200);
+                res.end(JSON.stringify({ error: 'Not authorized' }));
+                return;
+            }
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    if (!this.mesh) {
+                        data = { error: 'Mesh not initialized' };
+                    } else if (!payload.peerId) {
+                        data = { error: 'Missing peerId' };
+                    } else if (url === '/api/peers/ban') {
+                        const durationMs = Number(payload.durationMs) > 0 ? Number(payload.durationMs) : this.mesh.node.banDurationMs;
+                        const ban = this.mesh.node.banPeer(payload.peerId, durationMs, payload.reason || 'manual');
+                        data = { success: true, ban };
+                    } else {
+                        data = { success: this.mesh.node.unbanPeer(payload.peerId) };
+                    }
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url.startsWith('/api/memory/stats') && req.method === 'GET') {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            data = this.mesh
+                ? this.mesh.memoryStore.getCapsuleStats({ topTags: Math.min(Number(params.get('top') || 10), 100) })
+                : { error: 'Mesh not initialized' };
+        } else if (url.startsWith('/api/memory/') && url.endsWith('/acl') && req.method === 'POST') {
+            const assetId = url.split('/')[3];
+            if (!this.isAdminRequest(req)) {
+                res.writeHead(200);
+                res.end(JSON.stringify({ error: 'Not authorized' }));
+                return;
+            }
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', async () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    data = this.mesh
+                        ? { success: true, ...(await this.mesh.updateCapsuleAcl(assetId, {
+                            grant: Array.isArray(payload.grant) ? payload.grant : [],
+                            revoke: Array.isArray(payload.revoke) ? payload.revoke : []
+                        })) }
+                        : { error: 'Mesh not initialized' };
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url.startsWith('/api/memory/') && (url.endsWith('/pin') || url.endsWith('/unpin')) && req.method === 'POST') {
+            const assetId = url.split('/')[3];
+            if (!this.isAdminRequest(req)) {
+                data = { error: 'Not authorized' };
+            } else if (!this.mesh) {
+                data = { error: 'Mesh not initialized' };
+            } else {
+                this.mesh.memoryStore.setCapsulePinned(assetId, url.endsWith('/pin'))
+                    .then(capsule => capsule
+                        ? { success: true, assetId, pinned: this.mesh.memoryStore.isPinned(capsule) }
+                        : { error: 'Capsule not found' })
+                    .catch(e => ({ error: e.message }))
+                    .then(result => {
+                        res.writeHead(200);
+                        res.end(JSON.stringify(result));
+                    });
+                return;
+            }
+        } else if (url.startsWith('/api/memory/') && url.endsWith('/rate') && req.method === 'POST') {
+            const assetId = url.split('/')[3];
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    data = this.mesh
+                        ? { success: true, ...this.mesh.rateCapsule(assetId, { thumb: payload.thumb, score: payload.score }) }
+                        : { error: 'Mesh not initialized' };
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url.startsWith('/api/memory/') && req.method !== 'POST') {
+            const [pathname, query] = url.split('?');
+            const assetId = pathname.split('/').pop();
+            const fields = this.parseFields(new URLSearchParams(query || '').get('fields'));
+            data = this.mesh ? this.projectCapsule(this.sanitizeCapsule(this.mesh.memoryStore.getCapsule(assetId), viewer), fields) : null;
         } else if (url === '/api/stats') {
             const platformAccountId = this.mesh?.getPlatformAccountId?.();
             const rating = this.mesh?.ratingStore?.ensureNode?.(this.mesh?.options?.nodeId) || null;
@@ -140,8 +397,49 @@ class WebUIServer {
                 taskPublishFee: this.mesh?.options?.taskPublishFee || 0,
                 capsulePublishFee: this.mesh?.options?.capsulePublishFee || 0,
                 rating,
-                ratingRules
+                ratingRules,
+                rtt: this.mesh ? this.mesh.node.getRttSummary() : null,
+                relay: this.mesh ? this.mesh.node.getRelayStats() : null
             };
+        } else if (url === '/api/ledger' || url.startsWith('/api/ledger?')) {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            const offset = Math.max(Math.floor(Number(params.get('offset'))) || 0, 0);
+            const limit = Math.min(Math.max(Math.floor(Number(params.get('limit'))) || 50, 1), 500);
+            data = this.mesh?.ledger
+                ? this.mesh.ledger.getTxPage(offset, limit)
+                : { error: 'Mesh not initialized' };
+        } else if (url === '/api/leaderboard' || url.startsWith('/api/leaderboard?')) {
+            const params = new URLSearchParams(url.split('?')[1] || '');
+            if (!this.mesh?.ledger) {
+                data = { error: 'Mesh not initialized' };
+            } else {
+                data = this.mesh.ledger.getLeaderboard({
+                    windowMs: Math.max(Number(params.get('window')) || 0, 0),
+                    limit: Math.min(Math.max(Number(params.get('limit')) || 20, 1), 200),
+                    token: params.get('token') || 'CLAW'
+                });
+                // 附上账户所属的节点（本地认识时）及其当前评分（reputation），便于界面同时展示
+                const ratingStore = this.mesh.ratingStore;
+                data.items = data.items.map(item => {
+                    const nodeId = this.mesh.memoryStore?.accounts?.get(item.accountId)?.nodeId || null;
+                    const row = nodeId ? ratingStore?.getNode?.(nodeId) : null;
+                    return { ...item, nodeId, reputation: row ? ratingStore.computeScore(row) : null };
+                });
+            }
+        } else if (url === '/api/reputation') {
+            data = this.mesh?.ratingStore
+                ? { items: this.mesh.ratingStore.getReputations() }
+                : { error: 'Mesh not initialized' };
+        } else if (url === '/api/ledger/verify') {
+            try {
+                data = this.mesh ? this.mesh.memoryStore.verifyPersistedLedger(this.mesh.ledger?.db ? this.mesh.ledger : null) : { error: 'Mesh not initialized' };
+            } catch (e) {
+                data = { error: e.message };
+            }
+        } else if (url === '/api/ledger/forks') {
+            data = this.mesh?.ledger
+                ? { headSeq: this.mesh.ledger.getLastSeq(), headHash: this.mesh.ledger.getHeadHash(), items: this.mesh.ledger.getForks() }
+                : { error: 'Mesh not initialized' };
         } else if (url.startsWith('/api/tx/status')) {
             const query = url.split('?')[1] || '';
             const params = new URLSearchParams(query);
@@ -198,20 +496,73 @@ class WebUIServer {
                 });
                 return;
             }
-        } else if (url === '/api/snapshot') {
+        } else if (/^\/api\/account\/[^/?]+\/deactivate$/.test(url) && req.method === 'POST') {
+            const accountId = decodeURIComponent(url.split('/')[3]);
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    if (!this.isAdminRequest(req)) {
+                        data = { error: 'Not authorized' };
+                    } else if (!this.mesh) {
+                        data = { error: 'Mesh not initialized' };
+                    } else {
+                        data = { success: true, ...this.mesh.deactivateAccount(accountId, { sweepTo: payload.sweepTo }) };
+                    }
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url === '/api/accounts') {
+            if (!this.isAdminRequest(req)) {
+                data = { error: 'Not authorized' };
+            } else {
+                data = this.mesh ? { items: this.mesh.memoryStore.listAccounts() } : { error: 'Mesh not initialized' };
+            }
+        } else if (url.startsWith('/api/maintenance/reconcile-accounts') && req.method === 'POST') {
+            if (!this.isAdminRequest(req)) {
+                data = { error: 'Not authorized' };
+            } else if (!this.mesh) {
+                data = { error: 'Mesh not initialized' };
+            } else {
+                const params = new URLSearchParams(url.split('?')[1] || '');
+                this.mesh.memoryStore.reconcileAccountIndex({ dryRun: params.get('dryRun') === 'true' })
+                    .catch(e => ({ error: e.message }))
+                    .then(result => {
+                        res.writeHead(200);
+                        res.end(JSON.stringify(result));
+                    });
+                return;
+            }
+        } else if (url === '/api/audit' || url.startsWith('/api/audit?')) {
+            if (!this.isAdminRequest(req)) {
+                data = { error: 'Not authorized' };
+            } else {
+                const params = new URLSearchParams(url.split('?')[1] || '');
+                data = { items: this.getAuditLog(Number(params.get('limit') || 100)) };
+            }
+        } else if (url === '/api/snapshot' || url.startsWith('/api/snapshot?')) {
             if (this.mesh?.options?.isGenesisNode) {
-                data = this.mesh.memoryStore.getSnapshot();
+                const params = new URLSearchParams(url.split('?')[1] || '');
+                const include = this.parseFields(params.get('include'));
+                data = this.mesh.memoryStore.getSnapshot(include && include.map(name => name === 'account_index' ? 'accountIndex' : name));
             } else {
                 data = { error: 'Not authorized' };
             }
+        } else if (url.startsWith('/api/tasks/') && url.split('?')[0].endsWith('/upload')) {
+            this.handleTaskUpload(req, res, url.split('?')[0].split('/')[3]);
+            return;
         } else if (url.startsWith('/api/tasks/') && url.endsWith('/download')) {
             // Handle task package download
             const parts = url.split('/');
             const taskId = parts[3];
             
             try {
-                const fs = require('fs');
-                const completedBasePath = path.join(path.resolve(__dirname, '..'), 'task-workspace', 'completed');
+                const completedBasePath = path.join(this.taskWorkspaceDir, 'completed');
                 
                 // Search for the task in all node directories
                 let zipPath = null;
@@ -256,7 +607,12 @@ class WebUIServer {
                             description: payload.description,
                             bounty: { amount: payload.bounty || 100, token: 'CLAW' },
                             tags: payload.tags || [],
-                            publisher: payload.publisher
+                            publisher: payload.publisher,
+                            deadline: payload.deadline,
+                            deadlineMs: payload.deadlineMs,
+                            type: payload.type,
+                            selectionMode: payload.selectionMode,
+                            fallbackAccountId: payload.fallbackAccountId
                         });
                         const task = this.mesh.taskBazaar.getTask(taskId.taskId || taskId);
                         data = { success: true, task, taskId: taskId.taskId || taskId, txReceipts: taskId.txReceipts || [] };
@@ -270,6 +626,78 @@ class WebUIServer {
                 res.end(JSON.stringify(data));
             });
             return;
+        } else if (url.startsWith('/api/task/') && url.endsWith('/cancel') && req.method === 'POST') {
+            const taskId = decodeURIComponent(url.split('/')[3]);
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    data = this.mesh
+                        ? { success: true, ...this.mesh.cancelTask(taskId, { reason: payload.reason }) }
+                        : { error: 'Mesh not initialized' };
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url.startsWith('/api/task/') && url.endsWith('/award') && req.method === 'POST') {
+            const taskId = decodeURIComponent(url.split('/')[3]);
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    const solverId = payload.solverId || payload.winner;
+                    if (!solverId) {
+                        data = { error: 'solverId is required' };
+                    } else {
+                        data = this.mesh
+                            ? { success: true, ...this.mesh.awardTask(taskId, solverId) }
+                            : { error: 'Mesh not initialized' };
+                    }
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url === '/api/bench/gossip' && req.method === 'POST') {
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', async () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    data = this.mesh ? await this.mesh.runGossipBench(payload) : { error: 'Mesh not initialized' };
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url === '/api/tasks/import' && req.method === 'POST') {
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', async () => {
+                try {
+                    const payload = JSON.parse(body || '[]');
+                    if (this.mesh) {
+                        const specs = Array.isArray(payload) ? payload : payload.tasks;
+                        data = { success: true, ...(await this.mesh.importTasks(specs)) };
+                    } else {
+                        data = { error: 'Mesh not initialized' };
+                    }
+                } catch (e) {
+                    data = { error: e.message };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
         } else if (url === '/api/task/vote' && req.method === 'POST') {
             let body = '';
             req.on('data', chunk => body += chunk);
@@ -339,6 +767,34 @@ class WebUIServer {
                 res.end(JSON.stringify(data));
             });
             return;
+        } else if (url === '/api/tokens') {
+            data = { tokens: this.mesh?.ledger?.getTokens() || [] };
+        } else if (url === '/api/ledger/mint' && req.method === 'POST') {
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    const amount = Number(payload.amount);
+                    if (!this.isAdminRequest(req)) {
+                        data = { error: 'Not authorized' };
+                    } else if (!this.mesh) {
+                        data = { error: 'Mesh not initialized' };
+                    } else if (!payload.token || !payload.toAccountId || !Number.isFinite(amount) || amount <= 0) {
+                        data = { error: 'Invalid mint payload' };
+                    } else {
+                        const result = this.mesh.mintToken(payload.token, payload.toAccountId, amount);
+                        data = result.accepted === false
+                            ? { error: result.reason, result }
+                            : { success: true, result, txId: result.txId };
+                    }
+                } catch (e) {
+                    data = { error: e.message, code: e.code };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
         } else if (url === '/api/account/transfer' && req.method === 'POST') {
             let body = '';
             req.on('data', chunk => body += chunk);
@@ -351,7 +807,7 @@ class WebUIServer {
                         if (!toAccountId || !Number.isFinite(amount) || amount <= 0) {
                             data = { error: 'Invalid transfer payload' };
                         } else {
-                            const tx = this.mesh.createSignedTransfer(toAccountId, amount);
+                            const tx = this.mesh.createSignedTransfer(toAccountId, amount, { memo: payload.memo, token: payload.token });
                             const result = this.mesh.submitTx(tx);
                             data = { success: true, result, txId: tx.txId };
                         }
@@ -372,18 +828,32 @@ class WebUIServer {
                 try {
                     const payload = JSON.parse(body);
                     if (this.mesh) {
-                        const assetId = await this.mesh.publishCapsule({
-                            content: payload.content,
-                            type: payload.type || 'repair',
-                            tags: payload.tags || [],
-                            price: payload.price,
-                            attribution: payload.publisher ? { creator: payload.publisher } : undefined
-                        });
+                        const assetId = await this.mesh.publishCapsule(this.toPublishCapsule(payload), { ttlMs: payload.ttlMs });
                         const capsule = this.mesh.memoryStore.getCapsule(assetId.assetId || assetId);
-                        data = { success: true, capsule, assetId: assetId.assetId || assetId, txReceipts: assetId.txReceipts || [] };
+                        data = { success: true, capsule, assetId: assetId.assetId || assetId, txReceipts: assetId.txReceipts || [], alreadyExists: Boolean(assetId.alreadyExists) };
                     } else {
                         data = { error: 'Mesh not initialized' };
                     }
+                } catch (e) {
+                    data = { error: e.message, code: e.code };
+                }
+                res.writeHead(200);
+                res.end(JSON.stringify(data));
+            });
+            return;
+        } else if (url === '/api/memory/publish-batch' && req.method === 'POST') {
+            let body = '';
+            req.on('data', chunk => body += chunk);
+            req.on('end', async () => {
+                try {
+                    const payload = JSON.parse(body || '{}');
+                    if (!this.mesh) {
+                        data = { error: 'Mesh not initialized' };
+                    } else if (!Array.isArray(payload.capsules) || payload.capsules.length === 0) {
+                        data = { error: 'capsules must be a non-empty array' };
+                    } else {
+                        data = { success: true, ...(await this.mesh.publishCapsules(payload.capsules.map(item => this.toPublishCapsule(item || {})), { ttlMs: payload.ttlMs })) };
+                    }
                 } catch (e) {
                     data = { error: e.message };
                 }
@@ -416,75 +886,433 @@ class WebUIServer {
         res.end(JSON.stringify(data));
     }
 
-    sanitizeCapsules(capsules) {
-        if (!Array.isArray(capsules)) return [];
-        return capsules.map(capsule => this.sanitizeCapsule(capsule));
+    getRequestIdentity(req) {
+        const ip = (req.socket?.remoteAddress || '').replace('::ffff:', '') || null;
+        if (this.hasValidAdminToken(req)) {
+            return { ip, identity: 'admin' };
+        }
+        if (req.socket?.authorized && typeof req.socket.getPeerCertificate === 'function') {
+            const subject = req.socket.getPeerCertificate()?.subject;
+            return { ip, identity: subject?.CN ? `cert:${subject.CN}` : null };
+        }
+        return { ip, identity: null };
     }
 
-    sanitizeCapsule(capsule) {
-        if (!capsule) return null;
-        if (this.mesh?.options?.isGenesisNode) {
-            return capsule;
+    // GET 返回已接收的字节数（断点续传的起点）；POST 追加一块，x-upload-offset 必须等于已接收字节数。
+    // 每块由获胜节点签名 { taskId, nodeId, offset, total, sha256 }，收满 x-upload-total 字节后移入 completed/
+    handleTaskUpload(req, res, taskId) {
+        const reply = (result) => {
+            res.writeHead(200);
+            res.end(JSON.stringify(result));
+        };
+        const nodeId = req.headers?.['x-node-id'];
+        const task = this.mesh?.taskBazaar?.getTask(taskId);
+        if (!task) return reply({ error: 'Task not found', taskId });
+        const winner = task.assignedTo || task.winner;
+        if (!nodeId || nodeId !== winner) return reply({ error: 'Only the assigned winner may upload', taskId });
+        if (!/^[\w.-]+$/.test(`${nodeId}${taskId}`)) return reply({ error: 'Invalid task or node id' });
+        const partPath = path.join(this.taskWorkspaceDir, 'uploads', `${nodeId}_${taskId}.zip.part`);
+        const received = () => (fs.existsSync(partPath) ? fs.statSync(partPath).size : 0);
+        if (req.method === 'GET') return reply({ taskId, offset: received() });
+        if (req.method !== 'POST') return reply({ error: 'Unsupported method' });
+
+        const chunks = [];
+        let size = 0;
+        req.on('data', chunk => {
+            size += chunk.length;
+            if (size <= this.maxUploadBytes) chunks.push(chunk);
+        });
+        req.on('end', () => {
+            try {
+                const body = Buffer.concat(chunks);
+                const offset = Number(req.headers['x-upload-offset'] || 0);
+                const total = Number(req.headers['x-upload-total']);
+                const current = received();
+                if (!Number.isInteger(total) || total <= 0 || total > this.maxUploadBytes || size > this.maxUploadBytes) {
+                    return reply({ error: 'Invalid or oversized upload', maxUploadBytes: this.maxUploadBytes });
+                }
+                if (offset !== current) {
+                    return reply({ error: 'Offset mismatch', offset: current });
+                }
+                if (offset + body.length > total) {
+                    return reply({ error: 'Chunk exceeds declared total', offset: current });
+                }
+                const statement = {
+                    taskId,
+                    nodeId,
+                    offset,
+                    total,
+                    sha256: crypto.createHash('sha256').update(body).digest('hex')
+                };
+                const publicKeyPem = this.getNodePublicKey(nodeId);
+                let signed = false;
+                try {
+                    signed = Boolean(publicKeyPem) && verifyPayload(publicKeyPem, statement, req.headers['x-signature']);
+                } catch (e) {
+                    signed = false;
+                }
+                if (!signed) return reply({ error: 'Invalid upload signature' });
+
+                fs.mkdirSync(path.dirname(partPath), { recursive: true });
+                fs.appendFileSync(partPath, body);
+                const offsetAfter = current + body.length;
+                if (offsetAfter < total) return reply({ success: true, taskId, offset: offsetAfter, complete: false });
+                const completedDir = path.join(this.taskWorkspaceDir, 'completed', `${nodeId}_${taskId}`);
+                fs.mkdirSync(completedDir, { recursive: true });
+                fs.renameSync(partPath, path.join(completedDir, `${taskId}.zip`));
+                reply({ success: true, taskId, offset: offsetAfter, complete: true });
+            } catch (e) {
+                reply({ error: e.message });
+            }
+        });
+    }
+
+    // 查看者：带有效客户端证书（CN 为节点ID）的请求按证书身份，否则视为本节点
+    getViewerNodeId(req) {
+        const identity = this.getRequestIdentity(req).identity;
+        if (identity && identity.startsWith('cert:node_')) return identity.slice(5);
+        return this.mesh?.options?.nodeId || null;
+    }
+
+    // 上传者公钥：本节点用自己的钱包，其他节点用握手挑战中验证过的公钥
+    getNodePublicKey(nodeId) {
+        if (nodeId === this.mesh?.options?.nodeId) return this.mesh.wallet?.publicKeyPem || null;
+        const auth = this.mesh?.node?.peerAuth?.get(nodeId);
+        return auth?.verified ? auth.publicKeyPem : null;
+    }
+
+    async checkStoreHealth(now = Date.now()) {
+        const breaker = this.storeBreaker;
+        const status = () => ({ ok: false, ...this.getStoreBreakerStatus() });
+        if (breaker.state === 'open') {
+            if (now - breaker.openedAt < this.storeBreakerCooldownMs) return status();
+            breaker.state = 'half_open';
+        }
+        if (breaker.state === 'half_open') {
+            if (breaker.probing) return status();
+            breaker.probing = true;
+        }
+        const ok = await this.mesh.memoryStore.probeStoreLock(this.storeLockTimeoutMs);
+        breaker.probing = false;
+        if (ok) {
+            if (breaker.state !== 'closed') {
+                console.log('✅ Store responsive again, circuit breaker closed');
+            }
+            breaker.state = 'closed';
+            breaker.failures = 0;
+            breaker.openedAt = null;
+            return { ok: true, ...this.getStoreBreakerStatus() };
+        }
+        breaker.failures++;
+        if (breaker.state === 'half_open' || breaker.failures >= this.storeBreakerThreshold) {
+            if (breaker.state !== 'open') {
+                console.warn(`⚠️  Store lock not acquired within ${this.storeLockTimeoutMs}ms, circuit breaker open`);
+            }
+            breaker.state = 'open';
+            breaker.openedAt = Date.now();
+        }
+        return status();
+    }
+
+    getStoreBreakerStatus() {
+        const { state, failures, openedAt } = this.storeBreaker;
+        return { state, failures, openedAt, lockTimeoutMs: this.storeLockTimeoutMs };
+    }
+
+    isMutatingRequest(req) {
+        return ['POST', 'PUT', 'PATCH', 'DELETE'].includes(req.method);
+    }
+
+    getAdminToken(req) {
+        const header = req.headers?.authorization || '';
+        return header.startsWith('Bearer ') ? header.slice(7) : (req.headers?.['x-admin-token'] || null);
+    }
+
+    // 比较两者的 SHA-256 摘要：等长缓冲区上用 timingSafeEqual，耗时与令牌内容和长度都无关
+    hasValidAdminToken(req) {
+        const token = this.getAdminToken(req);
+        if (!this.adminToken || typeof token !== 'string' || !token) return false;
+        const digest = (value) => crypto.createHash('sha256').update(String(value)).digest();
+        return crypto.timingSafeEqual(digest(token), digest(this.adminToken));
+    }
+
+    // 配置了 adminToken 时校验令牌，否则只允许本机访问
+    isAdminRequest(req) {
+        if (this.adminToken) {
+            return this.hasValidAdminToken(req);
+        }
+        const ip = this.getRequestIdentity(req).ip;
+        return ip === '127.0.0.1' || ip === '::1';
+    }
+
+    trackMutation(req, res) {
+        if (!this.isMutatingRequest(req)) return;
+        const end = res.end.bind(res);
+        res.end = (chunk, ...rest) => {
+            let result = null;
+            try {
+                result = typeof chunk === 'string' ? JSON.parse(chunk) : null;
+            } catch (e) {
+                result = null;
+            }
+            this.recordAudit({
+                actor: this.getRequestIdentity(req),
+                method: req.method,
+                action: req.url.split('?')[0],
+                statusCode: res.statusCode,
+                result: result?.error || res.statusCode >= 400 ? 'error' : 'ok',
+                error: result?.error || undefined,
+                target: result ? (result.assetId || result.taskId || result.txId || undefined) : undefined
+            });
+            return end(chunk, ...rest);
+        };
+    }
+
+    recordAudit(entry) {
+        const record = { id: ++this.auditSeq, at: new Date().toISOString(), ...entry };
+        this.auditLog.push(record);
+        if (this.auditLog.length > this.maxAuditEntries) {
+            this.auditLog.splice(0, this.auditLog.length - this.maxAuditEntries);
+        }
+        if (this.auditLogPath) {
+            try {
+                fs.appendFileSync(this.auditLogPath, JSON.stringify(record) + '\n');
+            } catch (e) {
+                console.error('Failed to append audit log:', e.message);
+            }
         }
+        return record;
+    }
+
+    getAuditLog(limit = 100) {
+        const count = Number.isFinite(limit) && limit > 0 ? Math.floor(limit) : 100;
+        return this.auditLog.slice(-count).reverse();
+    }
+
+    // 启动时读回最近的记录并压缩文件，保留上限与内存一致
+    loadAuditLog() {
+        if (!this.auditLogPath || !fs.existsSync(this.auditLogPath)) return;
+        try {
+            const records = fs.readFileSync(this.auditLogPath, 'utf8')
+                .split('\n')
+                .filter(line => line.trim())
+                .map(line => { try { return JSON.parse(line); } catch (e) { return null; } })
+                .filter(Boolean)
+                .slice(-this.maxAuditEntries);
+            this.auditLog = records;
+            this.auditSeq = records.length > 0 ? records[records.length - 1].id || records.length : 0;
+            fs.writeFileSync(this.auditLogPath, records.map(r => JSON.stringify(r)).join('\n') + (records.length ? '\n' : ''));
+        } catch (e) {
+            console.error('Failed to load audit log:', e.message);
+        }
+    }
+
+    toPublishCapsule(payload) {
         return {
-            ...capsule,
-            content: null
+            content: payload.content,
+            type: payload.type || 'repair',
+            tags: payload.tags || [],
+            price: payload.price,
+            derivedFrom: payload.derivedFrom,
+            acl: payload.acl,
+            attribution: payload.publisher ? { creator: payload.publisher } : undefined
         };
     }
+
+    parseFields(raw) {
+        if (!raw) return null;
+        const fields = raw.split(',').map(f => f.trim()).filter(Boolean);
+        return fields.length > 0 ? fields : null;
+    }
+
+    // 只返回请求的顶层字段（默认返回完整胶囊）
+    projectCapsule(capsule, fields) {
+        if (!capsule || !fields) return capsule;
+        const projected = {};
+        for (const field of fields) {
+            if (Object.prototype.hasOwnProperty.call(capsule, field)) {
+                projected[field] = capsule[field];
+            }
+        }
+        return projected;
+    }
+
+    projectCapsules(capsules, fields) {
+        if (!Array.isArray(capsules)) return [];
+        return capsules.map(capsule => this.projectCapsule(capsule, fields));
+    }
+
+    sanitizeCapsules(capsules, viewer = this.mesh?.options?.nodeId) {
+        if (!Array.isArray(capsules)) return [];
+        return capsules.map(capsule => this.sanitizeCapsule(capsule, viewer));
+    }
+
+    // 胶囊 acl 优先于全局规则：不在名单里的查看者即使在主节点上也只能看到脱敏内容
+    sanitizeCapsule(capsule, viewer = this.mesh?.options?.nodeId) {
+        if (!capsule) return null;
+        const restricted = this.mesh?.memoryStore?.canRead ? !this.mesh.memoryStore.canRead(capsule, viewer) : false;
+        const pinned = capsule.pinned === true;
+        if (this.mesh?.options?.isGenesisNode && !restricted) {
+            return { ...capsule, pinned };
+        }
+        return this.mesh?.memoryStore?.redactCapsule
+            ? { ...this.mesh.memoryStore.redactCapsule(capsule), pinned }
+            : { ...capsule, content: null, redacted: true, pinned };
+    }
     
-    handleWebSocket(ws) {
-        console.log('🔌 WebSocket client connected');
-        
-        // 发送初始数据
-        if (this.mesh) {
-            ws.send(JSON.stringify({
-                type: 'status',
-                data: this.mesh.getStats()
-            }));
+    // 启用 mTLS 时，WebSocket 升级同样要求 CA 签发的客户端证书
+    verifyWebSocketClient(info, done) {
+        if (this.requireClientCert && !info?.req?.socket?.authorized) {
+            done(false, 403, 'Client certificate required');
+            return;
         }
+        done(true);
+    }
+
+    handleWebSocket(ws, req = null) {
+        console.log('🔌 WebSocket client connected');
         
-        // 定期更新
-        const interval = setInterval(() => {
+        const sendStatus = () => {
             if (ws.readyState === WebSocket.OPEN && this.mesh) {
                 ws.send(JSON.stringify({
                     type: 'status',
                     data: this.mesh.getStats()
                 }));
             }
-        }, 5000);
+        };
+        
+        // 发送初始数据
+        sendStatus();
+        
+        // 定期更新（连接URL可带 ?interval=ms）
+        const params = new URLSearchParams((req?.url || '').split('?')[1] || '');
+        ws.statusIntervalMs = this.clampInterval(params.get('interval'), this.wsStatusIntervalMs, this.wsMinStatusIntervalMs);
+        ws.statusTimer = setInterval(sendStatus, ws.statusIntervalMs);
+        ws.sendStatus = sendStatus;
+        
+        const pingTimer = setInterval(() => {
+            if (ws.readyState === WebSocket.OPEN && typeof ws.ping === 'function') {
+                ws.ping();
+            }
+        }, this.wsPingIntervalMs);
         
         ws.on('close', () => {
-            clearInterval(interval);
+            clearInterval(ws.statusTimer);
+            clearInterval(pingTimer);
             console.log('🔌 WebSocket client disconnected');
         });
         
         ws.on('message', (message) => {
+            let data;
             try {
-                const data = JSON.parse(message);
-                this.handleWebSocketMessage(ws, data);
+                data = JSON.parse(message);
             } catch (e) {
-                console.error('Invalid WebSocket message:', e);
+                this.sendWebSocketError(ws, 'INVALID_JSON', 'Message is not valid JSON');
+                return;
             }
+            this.handleWebSocketMessage(ws, data);
         });
     }
     
+    // 客户端消息协议：{ type, id? }，回复中原样带回 id；无法处理的消息返回 type: 'error'
     handleWebSocketMessage(ws, data) {
+        if (!data || typeof data !== 'object' || Array.isArray(data) || typeof data.type !== 'string') {
+            this.sendWebSocketError(ws, 'INVALID_MESSAGE', 'Message must be an object with a string type');
+            return;
+        }
+        const id = data.id ?? null;
         switch (data.type) {
-            case 'publish':
-                // 处理发布请求
+            case 'subscribe': {
+                // 客户端调整状态推送频率
+                const intervalMs = this.clampInterval(data.statusIntervalMs, this.wsStatusIntervalMs, this.wsMinStatusIntervalMs);
+                clearInterval(ws.statusTimer);
+                ws.statusIntervalMs = intervalMs;
+                ws.statusTimer = setInterval(ws.sendStatus, intervalMs);
+                ws.send(JSON.stringify({ type: 'subscribed', id, statusIntervalMs: intervalMs }));
                 break;
-            case 'search':
-                // 处理搜索请求
+            }
+            case 'unsubscribe':
+                // 停止状态推送，之后可重新 subscribe
+                clearInterval(ws.statusTimer);
+                ws.statusTimer = null;
+                ws.send(JSON.stringify({ type: 'unsubscribed', id }));
                 break;
+            case 'ping':
+                ws.send(JSON.stringify({ type: 'pong', id, timestamp: Date.now() }));
+                break;
+            case 'query': {
+                if (!this.mesh?.memoryStore) {
+                    this.sendWebSocketError(ws, 'NOT_READY', 'Mesh not initialized', id);
+                    break;
+                }
+                const filter = data.filter && typeof data.filter === 'object' ? data.filter : {};
+                if (filter.tags !== undefined && !Array.isArray(filter.tags)) {
+                    this.sendWebSocketError(ws, 'INVALID_QUERY', 'filter.tags must be an array', id);
+                    break;
+                }
+                const items = this.mesh.memoryStore.queryCapsules({
+                    mode: filter.mode,
+                    query: filter.query,
+                    tags: filter.tags,
+                    type: filter.type,
+                    minConfidence: filter.minConfidence,
+                    limit: Math.min(Math.max(Number(filter.limit) || 50, 1), 200)
+                });
+                ws.send(JSON.stringify({ type: 'query_result', id, items: this.sanitizeCapsules(items) }));
+                break;
+            }
+            default:
+                this.sendWebSocketError(ws, 'UNKNOWN_TYPE', `Unknown message type: ${data.type}`, id);
         }
     }
+
+    sendWebSocketError(ws, code, message, id = null) {
+        if (ws.readyState !== WebSocket.OPEN) return;
+        ws.send(JSON.stringify({ type: 'error', id, code, message }));
+    }
     
     serveHTML(res) {
-        const html = this.generateHTML();
+        let html = null;
+        let problem = null;
+        try {
+            html = this.generateHTML();
+            problem = this.validateHTML(html);
+        } catch (e) {
+            problem = `template failed to render: ${e.message}`;
+        }
         res.setHeader('Content-Type', 'text/html');
+        if (problem) {
+            // 模板损坏时给出明确的诊断页，而不是返回空白或残缺的页面
+            console.error(`❌ Web UI page unavailable: ${problem}`);
+            res.writeHead(500);
+            res.end(this.generateDiagnosticHTML(problem));
+            return;
+        }
         res.writeHead(200);
         res.end(html);
     }
+
+    // 返回问题描述；页面正常时返回 null
+    validateHTML(html) {
+        if (typeof html !== 'string' || html.trim() === '') return 'generated page is empty';
+        if (!/^\s*<!DOCTYPE html>/i.test(html)) return 'generated page is missing the <!DOCTYPE html> marker';
+        if (!/<html[\s>]/i.test(html) || !/<\/html>\s*$/i.test(html)) return 'generated page is missing the <html> ... </html> markers';
+        if (!/<body[\s>]/i.test(html)) return 'generated page has no <body>';
+        return null;
+    }
+
+    generateDiagnosticHTML(problem) {
+        const escaped = String(problem).replace(/[&<>"]/g, ch => ({ '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;' })[ch]);
+        return `<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="UTF-8"><title>OpenClaw Mesh - Web UI unavailable</title></head>
+<body>
+    <h1>Web UI unavailable</h1>
+    <p>The dashboard page could not be rendered: <code>${escaped}</code></p>
+    <p>The node and its API (<a href="/api/status">/api/status</a>) are still running. Check the node log and <code>web/server.js</code>.</p>
+</body>
+</html>`;
+    }
     
     generateHTML() {
         return `<!DOCTYPE html>
@@ -1479,6 +2307,8 @@ class WebUIServer {
                 const data = JSON.parse(event.data);
                 if (data.type === 'status') {
                     updateUI(data.data);
+                } else if (data.type === 'error') {
+                    console.warn('WebSocket error:', data.code, data.message);
                 }
             };
             
@@ -1560,7 +2390,7 @@ class WebUIServer {
             const tbody = document.querySelector('#memoriesTable tbody');
             tbody.innerHTML = memories.slice(0, 10).map(m => \`
                 <tr>
-                    <td>\${m.asset_id.slice(0, 20)}...</td>
+                    <td title="\${(m.preview || '').replace(/&/g, '&amp;').replace(/"/g, '&quot;').replace(/</g, '&lt;')}">\${m.asset_id.slice(0, 20)}...</td>
                     <td>\${m.type}</td>
                     <td>\${(m.confidence * 100).toFixed(0)}%</td>
                     <td>\${m.attribution.creator.slice(0, 10)}...</td>
@@ -2064,12 +2894,29 @@ class WebUIServer {
 </html>`;
     }
     
+    // 停止接受新连接，关闭 WebSocket 客户端和空闲的 keep-alive 连接，等待进行中的请求结束；
+    // 超过 shutdownTimeoutMs 仍未结束的连接被强制断开
     async stop() {
         if (this.wss) {
+            for (const client of this.wss.clients) {
+                client.close(1001, 'Server shutting down');
+            }
             this.wss.close();
         }
         if (this.server) {
-            this.server.close();
+            const closed = new Promise(resolve => this.server.close(() => resolve()));
+            this.server.closeIdleConnections?.();
+            let timer = null;
+            const deadline = new Promise(resolve => {
+                timer = setTimeout(() => {
+                    console.warn(`⚠️  WebUI requests still open after ${this.shutdownTimeoutMs}ms, closing connections`);
+                    this.server.closeAllConnections?.();
+                    resolve();
+                }, this.shutdownTimeoutMs);
+            });
+            await Promise.race([closed, deadline]);
+            clearTimeout(timer);
+            await closed;
         }
         console.log('🌐 WebUI server stopped');
     }